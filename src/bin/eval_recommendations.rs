@@ -0,0 +1,217 @@
+// Offline evaluation for the recommendation engine. Replays the anonymized selection sets in
+// data/usage.json: feeds are split into folds by origin, the co-occurrence model is trained on
+// the other folds, and for every held-out feed each comic is hidden in turn to see whether the remaining
+// selection brings it back in the top k. Reports precision@k and hit rate@k per weighting.
+
+use std::collections::HashMap;
+
+use clap::Parser;
+use panels::data;
+use panels::recommend::{CoOccurrence, RecommendOptions, Recommender, Weights};
+use panels::usage::UsageStats;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "eval_recommendations",
+    about = "Hold-out evaluation of panels recommendations"
+)]
+struct Args {
+    #[arg(long, default_value = "data", env = "PANELS_DATA_DIR")]
+    data_dir: String,
+
+    /// Number of recommendations considered per query
+    #[arg(short, long, default_value = "10")]
+    k: usize,
+
+    #[arg(long, default_value = "5")]
+    folds: usize,
+
+    #[arg(long, default_value = "1.0", env = "PANELS_REC_CONTENT_WEIGHT")]
+    content_weight: f64,
+
+    #[arg(long, default_value = "1.0", env = "PANELS_REC_COLLABORATIVE_WEIGHT")]
+    collaborative_weight: f64,
+
+    #[arg(long, default_value = "0.1", env = "PANELS_REC_POPULARITY_WEIGHT")]
+    popularity_weight: f64,
+}
+
+#[derive(Default)]
+struct Score {
+    queries: usize,
+    hits: usize,
+}
+
+impl Score {
+    fn precision(&self, k: usize) -> f64 {
+        if self.queries == 0 {
+            0.0
+        } else {
+            self.hits as f64 / (self.queries * k) as f64
+        }
+    }
+
+    fn hit_rate(&self) -> f64 {
+        if self.queries == 0 {
+            0.0
+        } else {
+            self.hits as f64 / self.queries as f64
+        }
+    }
+}
+
+/// For each basket, the index of the largest basket containing it. A reader selecting one comic
+/// at a time leaves every step behind as a basket, and all of them share an origin, so they
+/// land in the same fold instead of training the model on the answer.
+fn origins(baskets: &[Vec<String>]) -> Vec<usize> {
+    let mut holding: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, basket) in baskets.iter().enumerate() {
+        for item in basket {
+            holding.entry(item.as_str()).or_default().push(i);
+        }
+    }
+    baskets
+        .iter()
+        .enumerate()
+        .map(|(i, basket)| {
+            // Any basket containing this one holds its rarest comic
+            let candidates = basket
+                .iter()
+                .map(|item| &holding[item.as_str()])
+                .min_by_key(|c| c.len())
+                .map_or(&[][..], Vec::as_slice);
+            candidates
+                .iter()
+                .copied()
+                .filter(|&j| basket.iter().all(|item| baskets[j].contains(item)))
+                .max_by_key(|&j| (baskets[j].len(), std::cmp::Reverse(j)))
+                .unwrap_or(i)
+        })
+        .collect()
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    anyhow::ensure!(args.k > 0, "--k must be at least 1");
+    anyhow::ensure!(args.folds >= 2, "--folds must be at least 2");
+
+    let comics = data::load_comics(&args.data_dir)?;
+    let tags = data::load_tags(&args.data_dir)?;
+    let taxonomy = data::load_taxonomy(&args.data_dir, &tags)?;
+    let usage = UsageStats::open(&args.data_dir)?;
+    let (baskets, readers): (Vec<Vec<String>>, Vec<u64>) =
+        usage.snapshot().basket_items().into_iter().unzip();
+
+    if baskets.is_empty() {
+        println!("No selection data in {}/usage.json yet", args.data_dir);
+        return Ok(());
+    }
+
+    let configured = Weights {
+        content: args.content_weight,
+        collaborative: args.collaborative_weight,
+        popularity: args.popularity_weight,
+    };
+    let variants = [
+        (
+            "content only",
+            Weights {
+                content: 1.0,
+                collaborative: 0.0,
+                popularity: 0.0,
+            },
+        ),
+        (
+            "collaborative only",
+            Weights {
+                content: 0.0,
+                collaborative: 1.0,
+                popularity: 0.0,
+            },
+        ),
+        ("blended", configured),
+    ];
+    let mut scores: Vec<Score> = variants.iter().map(|_| Score::default()).collect();
+
+    let origins = origins(&baskets);
+    for fold in 0..args.folds {
+        let training: Vec<(&[String], u64)> = baskets
+            .iter()
+            .zip(&readers)
+            .zip(&origins)
+            .filter(|(_, origin)| *origin % args.folds != fold)
+            .map(|((b, &n), _)| (b.as_slice(), n))
+            .collect();
+        let train = CoOccurrence::build(training.iter().copied());
+        // Strip views would leak the held-out feeds, so popularity is counted from training
+        let mut views: HashMap<String, u64> = HashMap::new();
+        for (basket, n) in &training {
+            for item in *basket {
+                *views.entry(item.clone()).or_insert(0) += n;
+            }
+        }
+        let test = baskets
+            .iter()
+            .zip(&origins)
+            .filter(|(_, origin)| *origin % args.folds == fold)
+            .map(|(b, _)| b);
+
+        for basket in test {
+            for held_out in basket {
+                let selected: Vec<&str> = basket
+                    .iter()
+                    .filter(|e| *e != held_out)
+                    .map(String::as_str)
+                    .collect();
+
+                for ((_, weights), score) in variants.iter().zip(scores.iter_mut()) {
+                    let recommender = Recommender {
+                        comics: &comics,
+                        tags: &tags,
+                        taxonomy: &taxonomy,
+                        model: &train,
+                        views: &views,
+                        weights: *weights,
+                    };
+                    let recommended =
+                        recommender.recommend(&selected, &RecommendOptions::default(), args.k);
+                    score.queries += 1;
+                    if recommended
+                        .iter()
+                        .any(|r| &r.comic.comic.endpoint == held_out)
+                    {
+                        score.hits += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    println!(
+        "{} feeds, {} folds, k = {}, blended weights: content {}, collaborative {}, popularity {}",
+        baskets.len(),
+        args.folds,
+        args.k,
+        configured.content,
+        configured.collaborative,
+        configured.popularity
+    );
+    println!(
+        "{:<20} {:>8} {:>14} {:>12}",
+        "variant",
+        "queries",
+        format!("precision@{}", args.k),
+        format!("hit rate@{}", args.k)
+    );
+    for ((name, _), score) in variants.iter().zip(&scores) {
+        println!(
+            "{:<20} {:>8} {:>14.4} {:>12.4}",
+            name,
+            score.queries,
+            score.precision(args.k),
+            score.hit_rate()
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,363 @@
+// Maintenance commands for the catalog in the data directory: `validate` the catalog files,
+// `sync` them with the GoComics listing as a patch for review, draw missing `badges`, list
+// `duplicates` among proxied strips, `export` a range of strips, run a `mirror` pass, and
+// `hash` saved placeholder images. The work is done by the library; this only parses the
+// arguments and prints what comes back.
+
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use panels::cache::Caches;
+use panels::catalog::Catalog;
+use panels::catalog_sync;
+use panels::export::{ExportFormat, export_to_file};
+use panels::http_client::build_client;
+use panels::image_hashes::{DUPLICATE_DISTANCE, find_duplicates};
+use panels::imaging::badge::{BADGE_SIZE, generate_badges, plan_badges};
+use panels::imaging::hash::Placeholder;
+use panels::mirror::MirrorStore;
+use panels::mirror::sync::{SYNC_BATCH, SyncOptions, status, sync_all};
+use panels::sources::SourceRegistry;
+use panels::sources::gocomics::discovery::A_TO_Z_URL;
+use panels::validation::validate_data_dir;
+
+#[derive(Parser, Debug)]
+#[command(name = "panels-catalog", about = "Maintain the panels comic catalog")]
+struct Args {
+    #[arg(long, default_value = "data", env = "PANELS_DATA_DIR", global = true)]
+    data_dir: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check the catalog files for orphans, duplicates, missing badges, unknown sources
+    /// and malformed dates
+    Validate {
+        /// Treat warnings as failures too
+        #[arg(long)]
+        strict: bool,
+
+        #[arg(long, value_enum, default_value = "text")]
+        format: Format,
+    },
+    /// Compare the catalog with the GoComics A-to-Z listing and write the differences as a patch
+    Sync {
+        #[arg(long, default_value = A_TO_Z_URL)]
+        listing: String,
+
+        /// Where to write the patch for comics.json
+        #[arg(long, default_value = "comics-sync.patch")]
+        out: String,
+
+        /// Feature pages fetched at once while probing
+        #[arg(long, default_value = "4")]
+        concurrency: usize,
+    },
+    /// Generate badges for comics without one from their latest strip
+    Badges {
+        /// Only these comics; their badges are redrawn even if they exist
+        endpoints: Vec<String>,
+
+        /// Badge width and height in pixels
+        #[arg(long, default_value_t = BADGE_SIZE)]
+        size: u32,
+
+        /// List what would be generated without fetching anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// List strips whose image repeats on another date, from the hashes the server recorded
+    Duplicates {
+        /// Only this comic
+        #[arg(long)]
+        endpoint: Option<String>,
+
+        /// Bits the hashes may differ by
+        #[arg(long, default_value_t = DUPLICATE_DISTANCE)]
+        max_distance: u32,
+
+        #[arg(long, value_enum, default_value = "text")]
+        format: Format,
+    },
+    /// Download a range of strips as a CBZ or EPUB
+    Export {
+        endpoint: String,
+
+        /// First date, YYYY-MM-DD
+        #[arg(long)]
+        from: chrono::NaiveDate,
+
+        /// Last date, YYYY-MM-DD
+        #[arg(long)]
+        to: chrono::NaiveDate,
+
+        #[arg(long, value_enum, default_value = "cbz")]
+        format: ExportFormat,
+
+        /// Defaults to <endpoint>-<from>-<to>.<format>
+        #[arg(long)]
+        out: Option<String>,
+    },
+    /// Download new and older strips of mirrored comics into the local mirror
+    Mirror {
+        /// Comics to mirror
+        #[arg(env = "PANELS_MIRROR", value_delimiter = ',', required = true)]
+        endpoints: Vec<String>,
+
+        /// Dates to check upstream per comic
+        #[arg(long, default_value_t = SYNC_BATCH)]
+        batch: usize,
+
+        /// Don't mirror strips before this date
+        #[arg(long, env = "PANELS_MIRROR_SINCE")]
+        since: Option<chrono::NaiveDate>,
+
+        /// Re-check every stored image's checksum and fetch the bad ones again
+        #[arg(long)]
+        verify: bool,
+
+        /// Only show what is mirrored so far
+        #[arg(long)]
+        status: bool,
+    },
+    /// Print placeholder_hashes.json entries for saved placeholder images
+    Hash {
+        #[arg(required = true)]
+        files: Vec<String>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Format {
+    Text,
+    Json,
+}
+
+/// The live sources, for commands that fetch strips.
+fn live_sources(catalog: &Arc<Catalog>) -> SourceRegistry {
+    SourceRegistry::standard(
+        build_client(),
+        Arc::clone(catalog),
+        Caches::new(100, 600, 0),
+    )
+}
+
+fn exit(ok: bool) -> ExitCode {
+    if ok {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn validate(data_dir: &str, strict: bool, format: Format) -> anyhow::Result<ExitCode> {
+    let report = validate_data_dir(data_dir);
+    match format {
+        Format::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        Format::Text => println!("{}", report),
+    }
+    if strict && report.errors() == 0 && !report.passed(strict) {
+        eprintln!("warnings are failures with --strict");
+    }
+    Ok(exit(report.passed(strict)))
+}
+
+async fn sync(
+    data_dir: &str,
+    listing: &str,
+    out: &str,
+    concurrency: usize,
+) -> anyhow::Result<ExitCode> {
+    let run = catalog_sync::sync(&build_client(), data_dir, listing, concurrency).await?;
+    println!("{} features listed at {}", run.listed, listing);
+    for (endpoint, e) in &run.unprobed {
+        eprintln!("skipping {}: {}", endpoint, e);
+    }
+    for endpoint in &run.empty {
+        eprintln!("{} is listed but shows no strip; not adding it", endpoint);
+    }
+    if run.plan.is_empty() {
+        println!("catalog is up to date");
+        return Ok(ExitCode::SUCCESS);
+    }
+    catalog_sync::write_patch(data_dir, &run.plan, out)?;
+    print!("{}", run.plan);
+    println!(
+        "wrote {}; review it, then apply with `git apply {}`",
+        out, out
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn badges(
+    data_dir: &str,
+    only: Vec<String>,
+    size: u32,
+    dry_run: bool,
+) -> anyhow::Result<ExitCode> {
+    let catalog = Arc::new(Catalog::load(data_dir)?);
+    let plan = plan_badges(data_dir, &catalog.get(), only)?;
+    for orphan in &plan.orphans {
+        println!("orphan   {}.png has no matching comic", orphan);
+    }
+    if plan.missing.is_empty() {
+        println!("every available comic has a badge");
+        return Ok(ExitCode::SUCCESS);
+    }
+    if dry_run {
+        for endpoint in &plan.missing {
+            println!("missing  {}", endpoint);
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let results = generate_badges(&live_sources(&catalog), data_dir, &plan.missing, size).await?;
+    let mut failed = 0;
+    for (endpoint, result) in &results {
+        match result {
+            Ok((path, date)) => println!("wrote    {} (from {})", path.display(), date),
+            Err(e) => {
+                failed += 1;
+                println!("failed   {}: {}", endpoint, e);
+            }
+        }
+    }
+    println!(
+        "{} badges written, {} failed",
+        results.len() - failed,
+        failed
+    );
+    Ok(exit(failed == 0))
+}
+
+fn duplicates(
+    data_dir: &str,
+    endpoint: Option<&str>,
+    max_distance: u32,
+    format: Format,
+) -> anyhow::Result<ExitCode> {
+    let groups = find_duplicates(data_dir, endpoint, max_distance)?;
+    match format {
+        Format::Json => println!("{}", serde_json::to_string_pretty(&groups)?),
+        Format::Text => {
+            for group in &groups {
+                println!("{}: {}", group.endpoint, group.dates.join(", "));
+            }
+            println!("{} groups of repeated strips", groups.len());
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn export_range(
+    data_dir: &str,
+    endpoint: &str,
+    range: (chrono::NaiveDate, chrono::NaiveDate),
+    format: ExportFormat,
+    out: Option<String>,
+) -> anyhow::Result<ExitCode> {
+    let catalog = Arc::new(Catalog::load(data_dir)?);
+    let sources = live_sources(&catalog);
+    let (out, summary) =
+        export_to_file(&catalog.get(), &sources, endpoint, range, format, out).await?;
+    for date in &summary.missing {
+        println!("missing  {}", date);
+    }
+    println!(
+        "wrote {} strips to {} ({} missing)",
+        summary.pages,
+        out,
+        summary.missing.len()
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn mirror(
+    data_dir: &str,
+    endpoints: &[String],
+    options: SyncOptions,
+    status_only: bool,
+) -> anyhow::Result<ExitCode> {
+    let store = MirrorStore::open(data_dir, endpoints)?;
+    if !status_only {
+        let catalog = Arc::new(Catalog::load(data_dir)?);
+        let reports = sync_all(&store, &live_sources(&catalog), &catalog, &options).await;
+        let mut failed = false;
+        for report in reports {
+            match report {
+                Ok(report) => println!("synced   {}", report),
+                Err(e) => {
+                    failed = true;
+                    println!("failed   {}", e);
+                }
+            }
+        }
+        if failed {
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+    for line in status(&store).await {
+        println!("{}", line);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn hash(files: &[String]) -> anyhow::Result<ExitCode> {
+    let entries = files
+        .iter()
+        .map(|file| Placeholder::from_file(Path::new(file)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    println!("{}", serde_json::to_string_pretty(&entries)?);
+    Ok(ExitCode::SUCCESS)
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<ExitCode> {
+    let args = Args::parse();
+    match args.command {
+        Command::Validate { strict, format } => validate(&args.data_dir, strict, format),
+        Command::Sync {
+            listing,
+            out,
+            concurrency,
+        } => sync(&args.data_dir, &listing, &out, concurrency).await,
+        Command::Badges {
+            endpoints,
+            size,
+            dry_run,
+        } => badges(&args.data_dir, endpoints, size, dry_run).await,
+        Command::Duplicates {
+            endpoint,
+            max_distance,
+            format,
+        } => duplicates(&args.data_dir, endpoint.as_deref(), max_distance, format),
+        Command::Export {
+            endpoint,
+            from,
+            to,
+            format,
+            out,
+        } => export_range(&args.data_dir, &endpoint, (from, to), format, out).await,
+        Command::Mirror {
+            endpoints,
+            batch,
+            since,
+            verify,
+            status,
+        } => {
+            let options = SyncOptions {
+                batch,
+                since,
+                verify,
+                ..SyncOptions::default()
+            };
+            mirror(&args.data_dir, &endpoints, options, status).await
+        }
+        Command::Hash { files } => hash(&files),
+    }
+}
@@ -0,0 +1,85 @@
+// Builds the archives of discontinued comics served by the wayback source. Each comic in
+// data/wayback.json names the URL pattern of its strip pages, the date format in it and the
+// selectors for the image and title. A bulk CDX query lists every capture of those pages,
+// the missing dates are fetched a few at a time, and the results are saved periodically to
+// data/wayback/<endpoint>.json in the same format as dilbert_cache.json. Outcomes go to an
+// append-only journal that is compacted into the archive at the end, and dates that fail are
+// kept with the reason in <endpoint>.failures.json for a later --retry-failed run.
+
+use std::process::ExitCode;
+
+use clap::Parser;
+use panels::data;
+use panels::http_client::build_client;
+use panels::sources::wayback::archive::{BuildOptions, build_archive};
+use panels::sources::wayback::load_wayback_comics;
+use tracing::{error, info};
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "scrape_wayback",
+    about = "Build archives of comics from the Wayback Machine"
+)]
+struct Args {
+    /// Comics from wayback.json to build; all of them when omitted
+    endpoints: Vec<String>,
+
+    #[arg(long, default_value = "data", env = "PANELS_DATA_DIR")]
+    data_dir: String,
+
+    #[command(flatten)]
+    build: BuildOptions,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<ExitCode> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "panels=info,scrape_wayback=info".parse().unwrap()),
+        )
+        .init();
+
+    let args = Args::parse();
+    let configured = load_wayback_comics(&args.data_dir)?;
+    let comics = data::load_comics(&args.data_dir)?;
+    if let Some(unknown) = args
+        .endpoints
+        .iter()
+        .find(|e| !configured.iter().any(|c| &c.endpoint == *e))
+    {
+        eprintln!("{} is not configured in wayback.json", unknown);
+        return Ok(ExitCode::FAILURE);
+    }
+
+    let client = build_client();
+    let mut failed = false;
+    for comic in configured
+        .iter()
+        .filter(|c| args.endpoints.is_empty() || args.endpoints.contains(&c.endpoint))
+    {
+        let title = comics
+            .iter()
+            .find(|c| c.endpoint == comic.endpoint)
+            .map_or(comic.endpoint.as_str(), |c| c.title.as_str());
+        let path = comic.archive_path(&args.data_dir);
+        match build_archive(&client, comic, title, &path, &args.build).await {
+            Ok(report) => info!(
+                endpoint = %comic.endpoint,
+                fetched = report.fetched,
+                failed = report.failed,
+                total = report.total,
+                "archive built"
+            ),
+            Err(e) => {
+                error!(endpoint = %comic.endpoint, "archive build failed: {:#}", e);
+                failed = true;
+            }
+        }
+    }
+    Ok(if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
@@ -0,0 +1,583 @@
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use rand::Rng;
+use rand::distributions::Alphanumeric;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::error::{PanelsError, Result};
+use crate::html;
+use crate::models::ComicStrip;
+
+const BOOKMARKS_FILE: &str = "bookmarks.json";
+const IMAGES_DIR: &str = "images";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Bookmark {
+    pub id: String,
+    pub strip: ComicStrip,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub note: Option<String>,
+    #[serde(default)]
+    pub collections: Vec<String>,
+    pub created_at: String,
+    pub image_file: Option<String>,
+    pub image_content_type: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Collection {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub share_token: String,
+    pub created_at: String,
+}
+
+impl Collection {
+    pub fn share_url(&self) -> String {
+        format!("/shared/{}", self.share_token)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectionExport {
+    #[serde(flatten)]
+    pub collection: Collection,
+    pub share_url: String,
+    pub bookmarks: Vec<Bookmark>,
+}
+
+/// A bookmark as a share link shows it, without the ids the rest of the API takes.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SharedBookmark {
+    pub strip: ComicStrip,
+    pub tags: Vec<String>,
+    pub note: Option<String>,
+    pub image_url: String,
+}
+
+/// A collection as its share link shows it. Neither the collection's id nor its bookmarks'
+/// are included, so a share link can't be used to edit or delete anything.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SharedCollection {
+    pub name: String,
+    pub description: Option<String>,
+    pub bookmarks: Vec<SharedBookmark>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct BookmarkPatch {
+    pub tags: Option<Vec<String>>,
+    pub note: Option<String>,
+    pub collections: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CollectionPatch {
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BookmarkFile {
+    #[serde(default)]
+    bookmarks: Vec<Bookmark>,
+    #[serde(default)]
+    collections: Vec<Collection>,
+}
+
+pub struct BookmarkStore {
+    root: PathBuf,
+    file: RwLock<BookmarkFile>,
+}
+
+fn new_id(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// From the bytes, like export pages; upstream content types are not always right.
+fn image_extension(bytes: &[u8]) -> &'static str {
+    image::guess_format(bytes)
+        .ok()
+        .and_then(|format| format.extensions_str().first().copied())
+        .unwrap_or("bin")
+}
+
+fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    let mut tags: Vec<String> = tags
+        .into_iter()
+        .map(|t| t.trim().to_lowercase())
+        .filter(|t| !t.is_empty())
+        .collect();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+impl BookmarkStore {
+    pub fn open(data_dir: &str) -> anyhow::Result<Self> {
+        let root = Path::new(data_dir).join("bookmarks");
+        let path = root.join(BOOKMARKS_FILE);
+        let file = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| anyhow::anyhow!("failed to parse {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BookmarkFile::default(),
+            Err(e) => anyhow::bail!("failed to read {}: {}", path.display(), e),
+        };
+        info!(
+            bookmarks = file.bookmarks.len(),
+            collections = file.collections.len(),
+            "loaded bookmarks"
+        );
+        Ok(Self {
+            root,
+            file: RwLock::new(file),
+        })
+    }
+
+    async fn write_image(&self, name: &str, bytes: Vec<u8>) -> anyhow::Result<()> {
+        let dir = self.root.join(IMAGES_DIR);
+        tokio::fs::create_dir_all(&dir).await?;
+        tokio::fs::write(dir.join(name), bytes).await?;
+        Ok(())
+    }
+
+    async fn persist(&self, file: &BookmarkFile) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(&self.root).await?;
+        let json = serde_json::to_string_pretty(file)?;
+        let path = self.root.join(BOOKMARKS_FILE);
+        let tmp = self.root.join(format!("{}.tmp", BOOKMARKS_FILE));
+        tokio::fs::write(&tmp, json).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    pub async fn list(&self, collection: Option<&str>, tag: Option<&str>) -> Vec<Bookmark> {
+        let file = self.file.read().await;
+        let tag = tag.map(|t| t.to_lowercase());
+        file.bookmarks
+            .iter()
+            .filter(|b| collection.is_none_or(|c| b.collections.iter().any(|id| id == c)))
+            .filter(|b| tag.as_ref().is_none_or(|t| b.tags.contains(t)))
+            .cloned()
+            .collect()
+    }
+
+    pub async fn get(&self, id: &str) -> Option<Bookmark> {
+        let file = self.file.read().await;
+        file.bookmarks.iter().find(|b| b.id == id).cloned()
+    }
+
+    pub async fn add(
+        &self,
+        strip: ComicStrip,
+        tags: Vec<String>,
+        note: Option<String>,
+        collections: Vec<String>,
+        image: Option<(Vec<u8>, String)>,
+    ) -> Result<Bookmark> {
+        let mut file = self.file.write().await;
+        check_collections(&file, &collections)?;
+
+        let id = new_id(12);
+        let (image_file, image_content_type) = match image {
+            Some((bytes, content_type)) => {
+                let name = format!("{}.{}", id, image_extension(&bytes));
+                self.write_image(&name, bytes).await?;
+                (Some(name), Some(content_type))
+            }
+            None => {
+                warn!(endpoint = %strip.endpoint, date = %strip.date, "bookmarked strip without a local image");
+                (None, None)
+            }
+        };
+
+        let bookmark = Bookmark {
+            id,
+            strip,
+            tags: normalize_tags(tags),
+            note,
+            collections,
+            created_at: Utc::now().to_rfc3339(),
+            image_file,
+            image_content_type,
+        };
+        file.bookmarks.push(bookmark.clone());
+        if let Err(e) = self.persist(&file).await {
+            file.bookmarks.pop();
+            if let Some(ref name) = bookmark.image_file {
+                let _ = tokio::fs::remove_file(self.root.join(IMAGES_DIR).join(name)).await;
+            }
+            return Err(e.into());
+        }
+        Ok(bookmark)
+    }
+
+    pub async fn update(&self, id: &str, patch: BookmarkPatch) -> Result<Option<Bookmark>> {
+        let mut file = self.file.write().await;
+        if let Some(ref collections) = patch.collections {
+            check_collections(&file, collections)?;
+        }
+
+        let Some(bookmark) = file.bookmarks.iter_mut().find(|b| b.id == id) else {
+            return Ok(None);
+        };
+        if let Some(tags) = patch.tags {
+            bookmark.tags = normalize_tags(tags);
+        }
+        if let Some(note) = patch.note {
+            bookmark.note = Some(note).filter(|n| !n.is_empty());
+        }
+        if let Some(collections) = patch.collections {
+            bookmark.collections = collections;
+        }
+        let updated = bookmark.clone();
+        self.persist(&file).await?;
+        Ok(Some(updated))
+    }
+
+    pub async fn remove(&self, id: &str) -> Result<bool> {
+        let mut file = self.file.write().await;
+        let Some(idx) = file.bookmarks.iter().position(|b| b.id == id) else {
+            return Ok(false);
+        };
+        let removed = file.bookmarks.remove(idx);
+        self.persist(&file).await?;
+        if let Some(name) = removed.image_file {
+            let _ = tokio::fs::remove_file(self.root.join(IMAGES_DIR).join(name)).await;
+        }
+        Ok(true)
+    }
+
+    pub async fn image(&self, id: &str) -> Option<(Vec<u8>, String)> {
+        let bookmark = self.get(id).await?;
+        let name = bookmark.image_file?;
+        let bytes = tokio::fs::read(self.root.join(IMAGES_DIR).join(name))
+            .await
+            .ok()?;
+        Some((bytes, bookmark.image_content_type.unwrap_or_default()))
+    }
+
+    pub async fn list_collections(&self) -> Vec<Collection> {
+        self.file.read().await.collections.clone()
+    }
+
+    pub async fn create_collection(
+        &self,
+        name: String,
+        description: Option<String>,
+    ) -> Result<Collection> {
+        let mut file = self.file.write().await;
+        let collection = Collection {
+            id: new_id(8),
+            name,
+            description,
+            share_token: new_id(24),
+            created_at: Utc::now().to_rfc3339(),
+        };
+        file.collections.push(collection.clone());
+        self.persist(&file).await?;
+        Ok(collection)
+    }
+
+    pub async fn update_collection(
+        &self,
+        id: &str,
+        patch: CollectionPatch,
+    ) -> Result<Option<Collection>> {
+        let mut file = self.file.write().await;
+        let Some(collection) = file.collections.iter_mut().find(|c| c.id == id) else {
+            return Ok(None);
+        };
+        if let Some(name) = patch.name {
+            collection.name = name;
+        }
+        if let Some(description) = patch.description {
+            collection.description = Some(description).filter(|d| !d.is_empty());
+        }
+        let updated = collection.clone();
+        self.persist(&file).await?;
+        Ok(Some(updated))
+    }
+
+    pub async fn remove_collection(&self, id: &str) -> Result<bool> {
+        let mut file = self.file.write().await;
+        let before = file.collections.len();
+        file.collections.retain(|c| c.id != id);
+        if file.collections.len() == before {
+            return Ok(false);
+        }
+        for bookmark in &mut file.bookmarks {
+            bookmark.collections.retain(|c| c != id);
+        }
+        self.persist(&file).await?;
+        Ok(true)
+    }
+
+    pub async fn export_collection(&self, id: &str) -> Option<CollectionExport> {
+        let file = self.file.read().await;
+        let collection = file.collections.iter().find(|c| c.id == id)?;
+        Some(build_export(&file, collection))
+    }
+
+    /// The collection with share token `token`, as its share link shows it.
+    pub async fn export_shared(&self, token: &str) -> Option<SharedCollection> {
+        let file = self.file.read().await;
+        let collection = file.collections.iter().find(|c| c.share_token == token)?;
+        Some(build_shared(&file, collection))
+    }
+
+    /// The collection with id `id`, as its share link shows it.
+    pub async fn shared_view(&self, id: &str) -> Option<SharedCollection> {
+        let file = self.file.read().await;
+        let collection = file.collections.iter().find(|c| c.id == id)?;
+        Some(build_shared(&file, collection))
+    }
+
+    /// The `index`th bookmark of the collection shared as `token`, for its image.
+    pub async fn shared_bookmark(&self, token: &str, index: usize) -> Option<Bookmark> {
+        let file = self.file.read().await;
+        let collection = file.collections.iter().find(|c| c.share_token == token)?;
+        members(&file, collection).nth(index).cloned()
+    }
+}
+
+fn check_collections(file: &BookmarkFile, ids: &[String]) -> Result<()> {
+    match ids
+        .iter()
+        .find(|id| !file.collections.iter().any(|c| &c.id == *id))
+    {
+        Some(missing) => Err(PanelsError::InvalidParam(format!(
+            "unknown collection: {}",
+            missing
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn members<'a>(
+    file: &'a BookmarkFile,
+    collection: &'a Collection,
+) -> impl Iterator<Item = &'a Bookmark> {
+    file.bookmarks
+        .iter()
+        .filter(|b| b.collections.contains(&collection.id))
+}
+
+fn build_export(file: &BookmarkFile, collection: &Collection) -> CollectionExport {
+    let bookmarks = members(file, collection).cloned().collect();
+    CollectionExport {
+        collection: collection.clone(),
+        share_url: collection.share_url(),
+        bookmarks,
+    }
+}
+
+/// Images are numbered by position in the whole collection, so safe mode leaving some out
+/// doesn't move the rest.
+fn build_shared(file: &BookmarkFile, collection: &Collection) -> SharedCollection {
+    let bookmarks = members(file, collection)
+        .enumerate()
+        .map(|(index, bookmark)| SharedBookmark {
+            strip: bookmark.strip.clone(),
+            tags: bookmark.tags.clone(),
+            note: bookmark.note.clone(),
+            image_url: format!("/api/shared/{}/images/{}", collection.share_token, index),
+        })
+        .collect();
+    SharedCollection {
+        name: collection.name.clone(),
+        description: collection.description.clone(),
+        bookmarks,
+    }
+}
+
+pub fn render_collection_html(shared: &SharedCollection) -> String {
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"UTF-8\">\n");
+    out.push_str(&format!(
+        "<title>{} - panels</title>\n</head>\n<body>\n<h1>{}</h1>\n",
+        html::escape(&shared.name),
+        html::escape(&shared.name)
+    ));
+    if let Some(ref description) = shared.description {
+        out.push_str(&format!("<p>{}</p>\n", html::escape(description)));
+    }
+
+    for bookmark in &shared.bookmarks {
+        let strip = &bookmark.strip;
+        out.push_str("<figure>\n");
+        out.push_str(&format!(
+            "<img src=\"{}\" alt=\"{}\" title=\"{}\" loading=\"lazy\">\n",
+            html::escape(&bookmark.image_url),
+            html::escape(&strip.title),
+            html::escape(strip.metadata.alt_text.as_deref().unwrap_or(""))
+        ));
+        out.push_str(&format!(
+            "<figcaption><a href=\"{}\">{} ({})</a>",
+            html::escape(&strip.source_url),
+            html::escape(&strip.title),
+            html::escape(&strip.date)
+        ));
+        if !bookmark.tags.is_empty() {
+            out.push_str(&format!(" [{}]", html::escape(&bookmark.tags.join(", "))));
+        }
+        if let Some(ref note) = bookmark.note {
+            out.push_str(&format!("<br>{}", html::escape(note)));
+        }
+        out.push_str("</figcaption>\n</figure>\n");
+    }
+
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::StripMetadata;
+
+    fn strip(endpoint: &str, date: &str) -> ComicStrip {
+        ComicStrip {
+            endpoint: endpoint.into(),
+            title: "Garfield".into(),
+            date: date.into(),
+            image_url: "https://example.com/img.gif".into(),
+            source_url: "https://www.gocomics.com/garfield".into(),
+            prev_date: None,
+            next_date: None,
+            metadata: StripMetadata::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn bookmarks_persist_with_local_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().to_str().unwrap();
+
+        let store = BookmarkStore::open(data_dir).unwrap();
+        let collection = store
+            .create_collection("Mondays".into(), None)
+            .await
+            .unwrap();
+        let bookmark = store
+            .add(
+                strip("garfield", "1993-05-12"),
+                vec!["Lasagna".into(), "lasagna".into()],
+                Some("classic".into()),
+                vec![collection.id.clone()],
+                Some((vec![1, 2, 3], "image/png".into())),
+            )
+            .await
+            .unwrap();
+        assert_eq!(bookmark.tags, vec!["lasagna".to_string()]);
+
+        let reopened = BookmarkStore::open(data_dir).unwrap();
+        let (bytes, content_type) = reopened.image(&bookmark.id).await.unwrap();
+        assert_eq!(bytes, vec![1, 2, 3]);
+        assert_eq!(content_type, "image/png");
+
+        let shared = reopened
+            .export_shared(&collection.share_token)
+            .await
+            .unwrap();
+        assert_eq!(shared.bookmarks.len(), 1);
+        assert_eq!(shared.bookmarks[0].strip.date, "1993-05-12");
+    }
+
+    #[tokio::test]
+    async fn removing_collection_clears_membership() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = BookmarkStore::open(dir.path().to_str().unwrap()).unwrap();
+        let collection = store.create_collection("xkcd".into(), None).await.unwrap();
+        let bookmark = store
+            .add(
+                strip("xkcd", "#927"),
+                vec![],
+                None,
+                vec![collection.id.clone()],
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert!(store.remove_collection(&collection.id).await.unwrap());
+        let bookmark = store.get(&bookmark.id).await.unwrap();
+        assert!(bookmark.collections.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unknown_collection_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = BookmarkStore::open(dir.path().to_str().unwrap()).unwrap();
+        let result = store
+            .add(
+                strip("garfield", "2024-01-15"),
+                vec![],
+                None,
+                vec!["nope".into()],
+                None,
+            )
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn shared_collections_give_away_no_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = BookmarkStore::open(dir.path().to_str().unwrap()).unwrap();
+        let collection = store.create_collection("Faves".into(), None).await.unwrap();
+        let bookmark = store
+            .add(
+                strip("garfield", "1993-05-12"),
+                vec![],
+                Some("<script>".into()),
+                vec![collection.id.clone()],
+                None,
+            )
+            .await
+            .unwrap();
+
+        let shared = store.export_shared(&collection.share_token).await.unwrap();
+        let json = serde_json::to_string(&shared).unwrap();
+        assert!(!json.contains(&collection.id));
+        assert!(!json.contains(&bookmark.id));
+        let html = render_collection_html(&shared);
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(html.contains(&format!("/api/shared/{}/images/0", collection.share_token)));
+        assert!(!html.contains(&bookmark.id));
+
+        let found = store
+            .shared_bookmark(&collection.share_token, 0)
+            .await
+            .unwrap();
+        assert_eq!(found.id, bookmark.id);
+        assert!(
+            store
+                .shared_bookmark(&collection.share_token, 1)
+                .await
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn image_extensions_come_from_the_bytes() {
+        assert_eq!(image_extension(b"\x89PNG\r\n\x1a\n"), "png");
+        assert_eq!(image_extension(b"GIF89a"), "gif");
+        assert_eq!(image_extension(b"not an image"), "bin");
+    }
+}
@@ -1,21 +1,51 @@
+use std::sync::Arc;
 use std::time::Duration;
 
+use axum::body::Bytes;
 use moka::future::Cache;
 
+use crate::imaging::panels::PanelLayout;
 use crate::models::ComicStrip;
 
+const PANEL_LAYOUT_MAX: u64 = 10_000;
+const IMAGE_VARIANT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A resized or re-encoded strip image.
+#[derive(Clone)]
+pub struct ImageVariant {
+    pub bytes: Bytes,
+    pub content_type: &'static str,
+}
+
 #[derive(Clone)]
 pub struct Caches {
     pub strips: Cache<String, ComicStrip>,
+    /// Keyed by image URL and variant; bounded by total bytes rather than entries
+    pub image_variants: Cache<String, ImageVariant>,
+    /// Detected panels, keyed by image URL
+    pub panel_layouts: Cache<String, Arc<PanelLayout>>,
 }
 
 impl Caches {
-    pub fn new(strip_max: u64, strip_ttl_secs: u64) -> Self {
+    pub fn new(strip_max: u64, strip_ttl_secs: u64, image_variant_max_bytes: u64) -> Self {
         Self {
             strips: Cache::builder()
                 .max_capacity(strip_max)
                 .time_to_live(Duration::from_secs(strip_ttl_secs))
                 .build(),
+            image_variants: Cache::builder()
+                .max_capacity(image_variant_max_bytes)
+                .weigher(|key: &String, value: &ImageVariant| {
+                    (key.len() + value.bytes.len())
+                        .try_into()
+                        .unwrap_or(u32::MAX)
+                })
+                .time_to_live(IMAGE_VARIANT_TTL)
+                .build(),
+            panel_layouts: Cache::builder()
+                .max_capacity(PANEL_LAYOUT_MAX)
+                .time_to_live(IMAGE_VARIANT_TTL)
+                .build(),
         }
     }
 }
@@ -23,10 +53,11 @@ impl Caches {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::StripMetadata;
 
     #[tokio::test]
     async fn strip_cache_insert_and_get() {
-        let caches = Caches::new(100, 60);
+        let caches = Caches::new(100, 60, 1024 * 1024);
         let strip = ComicStrip {
             endpoint: "garfield".into(),
             title: "Garfield".into(),
@@ -35,6 +66,7 @@ mod tests {
             source_url: "https://www.gocomics.com/garfield".into(),
             prev_date: Some("2024-01-14".into()),
             next_date: Some("2024-01-16".into()),
+            metadata: StripMetadata::default(),
         };
         caches
             .strips
@@ -0,0 +1,311 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use arc_swap::ArcSwap;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::data;
+use crate::imaging::hash::Placeholder;
+use crate::models::Comic;
+use crate::sources::dilbert::{DilbertCacheEntry, load_dilbert_cache};
+use crate::sources::scrape::{CompiledDefinition, definition_paths, load_definitions};
+use crate::sources::script::{Script, load_scripts, script_paths};
+use crate::sources::wayback::{WaybackArchive, load_wayback};
+use crate::taxonomy::Taxonomy;
+
+const WATCHED_FILES: [&str; 6] = [
+    "comics.json",
+    "tags.json",
+    "tag_definitions.json",
+    "dilbert_cache.json",
+    "placeholder_hashes.json",
+    "wayback.json",
+];
+
+/// Everything loaded from the data directory that can change without a restart.
+#[derive(Debug, Default)]
+pub struct CatalogData {
+    pub comics: Vec<Comic>,
+    pub tags: HashMap<String, Vec<String>>,
+    pub taxonomy: Taxonomy,
+    pub dilbert: HashMap<String, DilbertCacheEntry>,
+    pub placeholders: Vec<Placeholder>,
+    /// Comics rebuilt from the Wayback Machine, with their archives
+    pub wayback: Vec<WaybackArchive>,
+    /// Sites scraped as described in `data/sources/`
+    pub scrapers: Vec<Arc<CompiledDefinition>>,
+    /// Source adapters compiled from `data/scripts/`
+    pub scripts: Vec<Script>,
+}
+
+impl CatalogData {
+    pub fn load(data_dir: &str) -> anyhow::Result<Self> {
+        let comics = data::load_comics(data_dir)?;
+        let tags = data::load_tags(data_dir)?;
+        let taxonomy = data::load_taxonomy(data_dir, &tags)?;
+        let dilbert = load_dilbert_cache(data_dir)?;
+        let placeholders = data::load_placeholders(data_dir)?;
+        let wayback = load_wayback(data_dir)?;
+        let scrapers = load_definitions(data_dir)?;
+        let scripts = load_scripts(data_dir)?;
+
+        let data = Self {
+            comics,
+            tags,
+            taxonomy,
+            dilbert,
+            placeholders,
+            wayback,
+            scrapers,
+            scripts,
+        };
+        data.validate()?;
+        Ok(data)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        let mut seen = BTreeSet::new();
+        for comic in &self.comics {
+            anyhow::ensure!(
+                !comic.endpoint.trim().is_empty(),
+                "comics.json has a comic without an endpoint"
+            );
+            anyhow::ensure!(
+                !comic.title.trim().is_empty(),
+                "comic {} has an empty title",
+                comic.endpoint
+            );
+            anyhow::ensure!(
+                seen.insert(comic.endpoint.as_str()),
+                "comic {} appears more than once in comics.json",
+                comic.endpoint
+            );
+        }
+        Ok(())
+    }
+
+    pub fn comic(&self, endpoint: &str) -> Option<&Comic> {
+        self.comics.iter().find(|c| c.endpoint == endpoint)
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CatalogDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// Comics whose catalog entry or tags differ
+    pub changed: Vec<String>,
+    pub dilbert_strips_before: usize,
+    pub dilbert_strips_after: usize,
+}
+
+impl CatalogDiff {
+    pub fn between(old: &CatalogData, new: &CatalogData) -> Self {
+        let mut diff = Self {
+            dilbert_strips_before: old.dilbert.len(),
+            dilbert_strips_after: new.dilbert.len(),
+            ..Self::default()
+        };
+        for comic in &new.comics {
+            match old.comic(&comic.endpoint) {
+                None => diff.added.push(comic.endpoint.clone()),
+                Some(previous) => {
+                    if previous != comic
+                        || old.tags.get(&comic.endpoint) != new.tags.get(&comic.endpoint)
+                    {
+                        diff.changed.push(comic.endpoint.clone());
+                    }
+                }
+            }
+        }
+        for comic in &old.comics {
+            if new.comic(&comic.endpoint).is_none() {
+                diff.removed.push(comic.endpoint.clone());
+            }
+        }
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && self.dilbert_strips_before == self.dilbert_strips_after
+    }
+}
+
+/// The live catalog. Readers take a snapshot with [`Catalog::get`]; a reload validates the
+/// files on disk and swaps the whole snapshot at once, so no reader sees a half-loaded state.
+pub struct Catalog {
+    data_dir: String,
+    current: ArcSwap<CatalogData>,
+    reloading: Mutex<()>,
+}
+
+impl Catalog {
+    pub fn new(data_dir: &str, data: CatalogData) -> Self {
+        Self {
+            data_dir: data_dir.to_string(),
+            current: ArcSwap::from_pointee(data),
+            reloading: Mutex::new(()),
+        }
+    }
+
+    pub fn load(data_dir: &str) -> anyhow::Result<Self> {
+        let data = CatalogData::load(data_dir)?;
+        info!(
+            comics = data.comics.len(),
+            tags = data.tags.len(),
+            dilbert = data.dilbert.len(),
+            "loaded catalog"
+        );
+        Ok(Self::new(data_dir, data))
+    }
+
+    pub fn data_dir(&self) -> &str {
+        &self.data_dir
+    }
+
+    pub fn get(&self) -> Arc<CatalogData> {
+        self.current.load_full()
+    }
+
+    /// Reloads every catalog file. Nothing is swapped in if any file fails to load or validate.
+    pub fn reload(&self) -> anyhow::Result<CatalogDiff> {
+        let _guard = self.reloading.lock().unwrap();
+        let data = CatalogData::load(&self.data_dir)?;
+        let diff = CatalogDiff::between(&self.get(), &data);
+        self.current.store(Arc::new(data));
+        info!(
+            added = diff.added.len(),
+            removed = diff.removed.len(),
+            changed = diff.changed.len(),
+            "reloaded catalog"
+        );
+        Ok(diff)
+    }
+
+    /// Modification times of the catalog files, of the configured wayback archives and of
+    /// the scrape definitions and scripts, with their directories so added and removed ones
+    /// count.
+    fn modified_times(&self) -> Vec<Option<SystemTime>> {
+        let archives: Vec<PathBuf> = self
+            .get()
+            .wayback
+            .iter()
+            .map(|a| a.comic.archive_path(&self.data_dir))
+            .chain(std::iter::once(Path::new(&self.data_dir).join("sources")))
+            .chain(definition_paths(&self.data_dir))
+            .chain(std::iter::once(Path::new(&self.data_dir).join("scripts")))
+            .chain(script_paths(&self.data_dir))
+            .collect();
+        WATCHED_FILES
+            .iter()
+            .map(|file| Path::new(&self.data_dir).join(file))
+            .chain(archives)
+            .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+
+    /// Polls the catalog files' modification times and reloads when any of them changes.
+    pub fn spawn_watcher(self: &Arc<Self>, interval: Duration) {
+        let catalog = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut seen = catalog.modified_times();
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let current = catalog.modified_times();
+                if current == seen {
+                    continue;
+                }
+                seen = current;
+                let reloading = Arc::clone(&catalog);
+                match tokio::task::spawn_blocking(move || reloading.reload()).await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => warn!("catalog change rejected, keeping previous catalog: {}", e),
+                    Err(e) => warn!("catalog reload task failed: {}", e),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn copy_data_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for file in WATCHED_FILES {
+            std::fs::copy(Path::new("data").join(file), dir.path().join(file)).unwrap();
+        }
+        dir
+    }
+
+    fn edit_comics(dir: &Path, edit: impl FnOnce(&mut Vec<serde_json::Value>)) {
+        let path = dir.join("comics.json");
+        let mut comics: Vec<serde_json::Value> =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        edit(&mut comics);
+        std::fs::write(&path, serde_json::to_string(&comics).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn reload_reports_diff() {
+        let dir = copy_data_dir();
+        let data_dir = dir.path().to_str().unwrap();
+        let catalog = Catalog::load(data_dir).unwrap();
+        let before = catalog.get();
+
+        edit_comics(dir.path(), |comics| {
+            let removed = comics.remove(0);
+            comics[0]["title"] = "Renamed".into();
+            comics.push(serde_json::json!({
+                "endpoint": "brand-new",
+                "title": "Brand New",
+                "author": null,
+                "available": true,
+                "startDate": null
+            }));
+            assert_eq!(removed["endpoint"], before.comics[0].endpoint.as_str());
+        });
+
+        let diff = catalog.reload().unwrap();
+        assert_eq!(diff.added, ["brand-new"]);
+        assert_eq!(diff.removed, [before.comics[0].endpoint.clone()]);
+        assert_eq!(diff.changed, [before.comics[1].endpoint.clone()]);
+        assert!(catalog.get().comic("brand-new").is_some());
+        // Snapshots taken before the reload stay intact
+        assert!(before.comic("brand-new").is_none());
+    }
+
+    #[test]
+    fn invalid_files_are_not_swapped_in() {
+        let dir = copy_data_dir();
+        let data_dir = dir.path().to_str().unwrap();
+        let catalog = Catalog::load(data_dir).unwrap();
+        let count = catalog.get().comics.len();
+
+        edit_comics(dir.path(), |comics| {
+            let duplicate = comics[0].clone();
+            comics.push(duplicate);
+        });
+        assert!(catalog.reload().is_err());
+
+        std::fs::copy("data/comics.json", dir.path().join("comics.json")).unwrap();
+        std::fs::write(
+            dir.path().join("tags.json"),
+            r#"{"garfield": ["no-such-tag"]}"#,
+        )
+        .unwrap();
+        assert!(catalog.reload().is_err());
+
+        assert_eq!(catalog.get().comics.len(), count);
+    }
+}
@@ -0,0 +1,572 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::Serialize;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::data;
+use crate::models::Comic;
+use crate::sources::gocomics::discovery::{ListedFeature, Probe, crawl_listing, probe_feature};
+
+const CONTEXT_LINES: usize = 3;
+
+/// A GoComics feature missing from the catalog, with what the crawl learned about it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Addition {
+    pub endpoint: String,
+    pub title: String,
+    pub author: Option<String>,
+    pub start_date: Option<String>,
+}
+
+/// Catalog changes proposed by a sync. Only GoComics entries are ever touched.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncPlan {
+    pub additions: Vec<Addition>,
+    /// Available comics that are neither listed nor reachable any more
+    pub retired: Vec<String>,
+    /// Comics marked unavailable that are listed again
+    pub restored: Vec<String>,
+}
+
+impl SyncPlan {
+    pub fn is_empty(&self) -> bool {
+        self.additions.is_empty() && self.retired.is_empty() && self.restored.is_empty()
+    }
+}
+
+impl fmt::Display for SyncPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for addition in &self.additions {
+            writeln!(
+                f,
+                "add      {} ({}, {})",
+                addition.endpoint,
+                addition.title,
+                addition
+                    .start_date
+                    .as_deref()
+                    .unwrap_or("start date unknown")
+            )?;
+        }
+        for endpoint in &self.retired {
+            writeln!(f, "retire   {}", endpoint)?;
+        }
+        for endpoint in &self.restored {
+            writeln!(f, "restore  {}", endpoint)?;
+        }
+        Ok(())
+    }
+}
+
+/// Catalog GoComics entries that the listing no longer shows but are still marked available.
+/// These need probing before they can be called retired.
+pub fn unlisted<'a>(catalog: &'a [Comic], listed: &[ListedFeature]) -> Vec<&'a str> {
+    let listed: BTreeSet<&str> = listed.iter().map(|f| f.endpoint.as_str()).collect();
+    catalog
+        .iter()
+        .filter(|c| c.source == "gocomics" && c.available)
+        .filter(|c| !listed.contains(c.endpoint.as_str()))
+        .map(|c| c.endpoint.as_str())
+        .collect()
+}
+
+/// Listed features whose endpoint is not in the catalog under any source.
+pub fn new_features<'a>(catalog: &[Comic], listed: &'a [ListedFeature]) -> Vec<&'a ListedFeature> {
+    let known: BTreeSet<&str> = catalog.iter().map(|c| c.endpoint.as_str()).collect();
+    listed
+        .iter()
+        .filter(|f| !known.contains(f.endpoint.as_str()))
+        .collect()
+}
+
+/// Unavailable GoComics entries that are listed again.
+pub fn relisted(catalog: &[Comic], listed: &[ListedFeature]) -> Vec<String> {
+    let listed: BTreeSet<&str> = listed.iter().map(|f| f.endpoint.as_str()).collect();
+    catalog
+        .iter()
+        .filter(|c| c.source == "gocomics" && !c.available)
+        .filter(|c| listed.contains(c.endpoint.as_str()))
+        .map(|c| c.endpoint.clone())
+        .collect()
+}
+
+/// The changes the listing and the probes of unlisted and new features call for. Features
+/// missing from `probes`, because probing them failed, are left alone.
+pub fn plan(
+    catalog: &[Comic],
+    listed: &[ListedFeature],
+    probes: &BTreeMap<String, Probe>,
+) -> SyncPlan {
+    let mut plan = SyncPlan {
+        restored: relisted(catalog, listed),
+        ..SyncPlan::default()
+    };
+    for endpoint in unlisted(catalog, listed) {
+        if probes.get(endpoint) == Some(&Probe::Unreachable) {
+            plan.retired.push(endpoint.to_string());
+        }
+    }
+    for feature in new_features(catalog, listed) {
+        if let Some(Probe::Reachable { earliest_date }) = probes.get(&feature.endpoint) {
+            plan.additions.push(Addition {
+                endpoint: feature.endpoint.clone(),
+                title: feature.title.clone(),
+                author: feature.author.clone(),
+                start_date: earliest_date.clone(),
+            });
+        }
+    }
+    plan
+}
+
+/// JSON string literal with non-ASCII escaped, matching how `comics.json` is written.
+fn json_string(value: &str) -> String {
+    let quoted = serde_json::to_string(value).unwrap_or_default();
+    let mut out = String::with_capacity(quoted.len());
+    for c in quoted.chars() {
+        if c.is_ascii() {
+            out.push(c);
+        } else {
+            let mut units = [0u16; 2];
+            for unit in c.encode_utf16(&mut units) {
+                out.push_str(&format!("\\u{:04x}", unit));
+            }
+        }
+    }
+    out
+}
+
+fn optional_json_string(value: Option<&str>) -> String {
+    value.map(json_string).unwrap_or_else(|| "null".into())
+}
+
+fn addition_lines(addition: &Addition, trailing_comma: bool) -> Vec<String> {
+    vec![
+        "  {".into(),
+        format!("    \"endpoint\": {},", json_string(&addition.endpoint)),
+        format!("    \"title\": {},", json_string(&addition.title)),
+        format!(
+            "    \"author\": {},",
+            optional_json_string(addition.author.as_deref())
+        ),
+        "    \"available\": true,".into(),
+        format!(
+            "    \"startDate\": {}",
+            optional_json_string(addition.start_date.as_deref())
+        ),
+        if trailing_comma { "  }," } else { "  }" }.into(),
+    ]
+}
+
+/// One top-level object in `comics.json`, as a range of lines.
+struct Block {
+    endpoint: String,
+    start: usize,
+    end: usize,
+}
+
+fn find_blocks(lines: &[&str]) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut start = None;
+    let mut endpoint = None;
+    for (i, line) in lines.iter().enumerate() {
+        if *line == "  {" {
+            start = Some(i);
+            endpoint = None;
+        } else if let Some(rest) = line.trim_start().strip_prefix("\"endpoint\": ") {
+            endpoint = serde_json::from_str::<String>(rest.trim_end_matches(',')).ok();
+        } else if (*line == "  }" || *line == "  },")
+            && let (Some(s), Some(e)) = (start.take(), endpoint.take())
+        {
+            blocks.push(Block {
+                endpoint: e,
+                start: s,
+                end: i + 1,
+            });
+        }
+    }
+    blocks
+}
+
+/// Replaces `original[start..end]` with `lines`.
+struct Edit {
+    start: usize,
+    end: usize,
+    lines: Vec<String>,
+}
+
+fn availability_edit(lines: &[&str], block: &Block, available: bool) -> Option<Edit> {
+    let (from, to) = if available {
+        ("false", "true")
+    } else {
+        ("true", "false")
+    };
+    (block.start..block.end).find_map(|i| {
+        let line = lines[i];
+        let value = line.trim_start().strip_prefix("\"available\": ")?;
+        let value = value.strip_suffix(',').unwrap_or(value);
+        (value == from).then(|| Edit {
+            start: i,
+            end: i + 1,
+            lines: vec![line.replacen(from, to, 1)],
+        })
+    })
+}
+
+fn plan_edits(lines: &[&str], plan: &SyncPlan) -> anyhow::Result<Vec<Edit>> {
+    let blocks = find_blocks(lines);
+    anyhow::ensure!(
+        !blocks.is_empty(),
+        "comics.json is not laid out one object per block; nothing to patch"
+    );
+    let by_endpoint: BTreeMap<&str, &Block> =
+        blocks.iter().map(|b| (b.endpoint.as_str(), b)).collect();
+
+    let mut edits = Vec::new();
+    for (endpoints, available) in [(&plan.retired, false), (&plan.restored, true)] {
+        for endpoint in endpoints {
+            let block = by_endpoint
+                .get(endpoint.as_str())
+                .ok_or_else(|| anyhow::anyhow!("{} is not in comics.json", endpoint))?;
+            let edit = availability_edit(lines, block, available).ok_or_else(|| {
+                anyhow::anyhow!("{} has no \"available\" line to change", endpoint)
+            })?;
+            edits.push(edit);
+        }
+    }
+
+    // New entries go in endpoint order ahead of the first block sorting after them, or after
+    // the last block, which then needs a trailing comma
+    let mut additions: Vec<&Addition> = plan.additions.iter().collect();
+    additions.sort_by(|a, b| a.endpoint.cmp(&b.endpoint));
+    let mut inserts: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    let mut appended = Vec::new();
+    for addition in additions {
+        match blocks.iter().find(|b| b.endpoint > addition.endpoint) {
+            Some(block) => inserts
+                .entry(block.start)
+                .or_default()
+                .extend(addition_lines(addition, true)),
+            None => appended.push(addition),
+        }
+    }
+    for (at, new_lines) in inserts {
+        edits.push(Edit {
+            start: at,
+            end: at,
+            lines: new_lines,
+        });
+    }
+    if !appended.is_empty() {
+        let last = blocks.last().expect("checked above");
+        let mut new_lines = vec!["  },".to_string()];
+        for (i, addition) in appended.iter().enumerate() {
+            new_lines.extend(addition_lines(addition, i + 1 < appended.len()));
+        }
+        edits.push(Edit {
+            start: last.end - 1,
+            end: last.end,
+            lines: new_lines,
+        });
+    }
+
+    edits.sort_by_key(|e| (e.start, e.end));
+    Ok(edits)
+}
+
+/// Renders the plan as a unified diff against `original`, the current contents of the file
+/// at `path`. The patch applies with `git apply` or `patch -p1`.
+pub fn render_patch(original: &str, path: &str, plan: &SyncPlan) -> anyhow::Result<String> {
+    let lines: Vec<&str> = original.lines().collect();
+    let edits = plan_edits(&lines, plan)?;
+
+    let mut out = format!("--- a/{}\n+++ b/{}\n", path, path);
+    let mut offset: isize = 0;
+    let mut i = 0;
+    while i < edits.len() {
+        // Group edits whose context would overlap into one hunk
+        let mut j = i + 1;
+        while j < edits.len() && edits[j].start <= edits[j - 1].end + 2 * CONTEXT_LINES {
+            j += 1;
+        }
+        let group = &edits[i..j];
+        let old_start = group[0].start.saturating_sub(CONTEXT_LINES);
+        let old_end = (group[j - i - 1].end + CONTEXT_LINES).min(lines.len());
+
+        let mut body = Vec::new();
+        let mut cursor = old_start;
+        let mut added = 0;
+        let mut removed = 0;
+        for edit in group {
+            body.extend(lines[cursor..edit.start].iter().map(|l| format!(" {}", l)));
+            body.extend(
+                lines[edit.start..edit.end]
+                    .iter()
+                    .map(|l| format!("-{}", l)),
+            );
+            body.extend(edit.lines.iter().map(|l| format!("+{}", l)));
+            removed += edit.end - edit.start;
+            added += edit.lines.len();
+            cursor = edit.end;
+        }
+        body.extend(lines[cursor..old_end].iter().map(|l| format!(" {}", l)));
+
+        let old_len = old_end - old_start;
+        let new_len = old_len + added - removed;
+        let new_start = old_start as isize + offset;
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            old_start + 1,
+            old_len,
+            new_start + 1,
+            new_len
+        ));
+        for line in body {
+            out.push_str(&line);
+            out.push('\n');
+        }
+        offset += added as isize - removed as isize;
+        i = j;
+    }
+    Ok(out)
+}
+
+/// What a sync found: the plan, and the features it had to leave alone.
+#[derive(Debug, Default)]
+pub struct SyncRun {
+    /// Features on the listing
+    pub listed: usize,
+    pub plan: SyncPlan,
+    /// Features that couldn't be probed, with why
+    pub unprobed: Vec<(String, String)>,
+    /// New features that are listed but show no strip
+    pub empty: Vec<String>,
+}
+
+/// Probes `endpoints`, `concurrency` at a time. Probes that fail are returned apart: not
+/// knowing is different from unreachable.
+pub async fn probe_all(
+    client: &reqwest::Client,
+    endpoints: Vec<String>,
+    concurrency: usize,
+) -> (BTreeMap<String, Probe>, Vec<(String, String)>) {
+    let permits = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut tasks = JoinSet::new();
+    for endpoint in endpoints {
+        let client = client.clone();
+        let permits = Arc::clone(&permits);
+        tasks.spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            let probe = probe_feature(&client, &endpoint).await;
+            (endpoint, probe)
+        });
+    }
+
+    let (mut probes, mut failed) = (BTreeMap::new(), Vec::new());
+    while let Some(joined) = tasks.join_next().await {
+        let Ok((endpoint, probe)) = joined else {
+            continue;
+        };
+        match probe {
+            Ok(probe) => {
+                probes.insert(endpoint, probe);
+            }
+            Err(e) => failed.push((endpoint, e.to_string())),
+        }
+    }
+    failed.sort();
+    (probes, failed)
+}
+
+/// Crawls the listing at `listing`, probes the catalog's unlisted comics and the new
+/// features, and plans the catalog changes for comics.json in `data_dir`.
+pub async fn sync(
+    client: &reqwest::Client,
+    data_dir: &str,
+    listing: &str,
+    concurrency: usize,
+) -> anyhow::Result<SyncRun> {
+    let comics = data::load_comics(data_dir)?;
+    let listed = crawl_listing(client, listing).await?;
+    anyhow::ensure!(
+        !listed.is_empty(),
+        "found no features at {}; the listing layout may have changed",
+        listing
+    );
+
+    let new = new_features(&comics, &listed);
+    let to_probe = unlisted(&comics, &listed)
+        .iter()
+        .map(|e| e.to_string())
+        .chain(new.iter().map(|f| f.endpoint.clone()))
+        .collect();
+    let (probes, unprobed) = probe_all(client, to_probe, concurrency).await;
+    let empty = new
+        .iter()
+        .filter(|f| probes.get(&f.endpoint) == Some(&Probe::Unreachable))
+        .map(|f| f.endpoint.clone())
+        .collect();
+
+    Ok(SyncRun {
+        listed: listed.len(),
+        plan: plan(&comics, &listed, &probes),
+        unprobed,
+        empty,
+    })
+}
+
+/// Writes the plan to `out` as a patch against comics.json in `data_dir`.
+pub fn write_patch(data_dir: &str, plan: &SyncPlan, out: &str) -> anyhow::Result<()> {
+    let path = Path::new(data_dir).join("comics.json");
+    let original = std::fs::read_to_string(&path)?;
+    let label = path.to_string_lossy().trim_start_matches("./").to_string();
+    std::fs::write(out, render_patch(&original, &label, plan)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CATALOG: &str = r#"[
+  {
+    "endpoint": "garfield",
+    "title": "Garfield",
+    "author": "Jim Davis",
+    "available": true,
+    "startDate": "1978-06-19"
+  },
+  {
+    "endpoint": "nancy",
+    "title": "Nancy",
+    "author": "Olivia Jaimes",
+    "available": false,
+    "startDate": null
+  },
+  {
+    "endpoint": "xkcd",
+    "title": "xkcd",
+    "author": "Randall Munroe",
+    "available": true,
+    "startDate": null,
+    "source": "xkcd"
+  }
+]
+"#;
+
+    fn listed(endpoint: &str) -> ListedFeature {
+        ListedFeature {
+            endpoint: endpoint.into(),
+            title: endpoint.into(),
+            author: None,
+        }
+    }
+
+    /// Applies a patch produced by `render_patch`, trusting its hunk headers.
+    fn apply(original: &str, patch: &str) -> String {
+        let lines: Vec<&str> = original.lines().collect();
+        let mut out = Vec::new();
+        let mut cursor = 0;
+        for line in patch.lines().skip(2) {
+            if let Some(header) = line.strip_prefix("@@ -") {
+                let start: usize = header.split(',').next().unwrap().parse().unwrap();
+                while cursor < start - 1 {
+                    out.push(lines[cursor].to_string());
+                    cursor += 1;
+                }
+            } else if let Some(added) = line.strip_prefix('+') {
+                out.push(added.to_string());
+            } else if let Some(context) = line.strip_prefix(' ') {
+                assert_eq!(lines[cursor], context);
+                out.push(context.to_string());
+                cursor += 1;
+            } else if let Some(removed) = line.strip_prefix('-') {
+                assert_eq!(lines[cursor], removed);
+                cursor += 1;
+            }
+        }
+        out.extend(lines[cursor..].iter().map(|l| l.to_string()));
+        out.join("\n") + "\n"
+    }
+
+    #[test]
+    fn compares_listing_with_catalog() {
+        let comics: Vec<Comic> = serde_json::from_str(CATALOG).unwrap();
+        let listing = vec![listed("nancy"), listed("peanuts"), listed("xkcd")];
+
+        assert_eq!(unlisted(&comics, &listing), ["garfield"]);
+        assert_eq!(relisted(&comics, &listing), ["nancy"]);
+        let new: Vec<&str> = new_features(&comics, &listing)
+            .iter()
+            .map(|f| f.endpoint.as_str())
+            .collect();
+        assert_eq!(new, ["peanuts"]);
+    }
+
+    #[test]
+    fn only_probed_features_change() {
+        let comics: Vec<Comic> = serde_json::from_str(CATALOG).unwrap();
+        let listing = vec![listed("peanuts"), listed("xkcd")];
+
+        // Probing garfield and peanuts failed, say with a 503
+        let plan = super::plan(&comics, &listing, &BTreeMap::new());
+        assert!(plan.retired.is_empty());
+        assert!(plan.additions.is_empty());
+
+        let probes = BTreeMap::from([
+            ("garfield".to_string(), Probe::Unreachable),
+            (
+                "peanuts".to_string(),
+                Probe::Reachable {
+                    earliest_date: None,
+                },
+            ),
+        ]);
+        let plan = super::plan(&comics, &listing, &probes);
+        assert_eq!(plan.retired, ["garfield"]);
+        assert_eq!(plan.additions[0].endpoint, "peanuts");
+    }
+
+    #[test]
+    fn patch_applies_to_a_valid_catalog() {
+        let plan = SyncPlan {
+            additions: vec![
+                Addition {
+                    endpoint: "zits".into(),
+                    title: "Zits".into(),
+                    author: Some("Jerry Scott and Jim Borgman".into()),
+                    start_date: Some("1997-07-07".into()),
+                },
+                Addition {
+                    endpoint: "baldo-en-espanol".into(),
+                    title: "Baldo en Español".into(),
+                    author: None,
+                    start_date: None,
+                },
+            ],
+            retired: vec!["garfield".into()],
+            restored: vec!["nancy".into()],
+        };
+        let patch = render_patch(CATALOG, "data/comics.json", &plan).unwrap();
+        assert!(patch.starts_with("--- a/data/comics.json\n+++ b/data/comics.json\n@@ -1,"));
+        assert!(patch.contains("+    \"title\": \"Baldo en Espa\\u00f1ol\","));
+
+        let patched = apply(CATALOG, &patch);
+        let comics: Vec<Comic> = serde_json::from_str(&patched).unwrap();
+        let endpoints: Vec<&str> = comics.iter().map(|c| c.endpoint.as_str()).collect();
+        assert_eq!(
+            endpoints,
+            ["baldo-en-espanol", "garfield", "nancy", "xkcd", "zits"]
+        );
+        assert!(!comics[1].available);
+        assert!(comics[2].available);
+        assert_eq!(comics[4].start_date.as_deref(), Some("1997-07-07"));
+        assert_eq!(comics[0].title, "Baldo en Español");
+    }
+}
@@ -14,4 +14,57 @@ pub struct PanelsConfig {
 
     #[arg(long, default_value = "1800", env = "PANELS_STRIP_CACHE_TTL")]
     pub strip_cache_ttl_secs: u64,
+
+    /// Memory for resized strip images, in megabytes
+    #[arg(long, default_value = "128", env = "PANELS_IMAGE_CACHE_MB")]
+    pub image_cache_mb: u64,
+
+    #[arg(long, env = "PANELS_PUBLIC_URL")]
+    pub public_url: Option<String>,
+
+    /// Bearer token for `/api/admin/*`; the admin API is disabled when unset
+    #[arg(long, env = "PANELS_ADMIN_TOKEN")]
+    pub admin_token: Option<String>,
+
+    /// How often to check the catalog files for changes, in seconds; 0 turns the watcher off
+    #[arg(long, default_value = "5", env = "PANELS_CATALOG_POLL_SECS")]
+    pub catalog_poll_secs: u64,
+
+    /// Validate the catalog at startup and refuse to start on any error or warning
+    #[arg(long, env = "PANELS_STRICT_CATALOG")]
+    pub strict_catalog: bool,
+
+    /// Comics to keep a local mirror of, comma-separated
+    #[arg(long = "mirror", env = "PANELS_MIRROR", value_delimiter = ',')]
+    pub mirror: Vec<String>,
+
+    /// Don't mirror strips published before this date (YYYY-MM-DD)
+    #[arg(long, env = "PANELS_MIRROR_SINCE")]
+    pub mirror_since: Option<chrono::NaiveDate>,
+
+    /// How often to sync the mirror with upstream, in seconds; 0 turns syncing off
+    #[arg(long, default_value = "3600", env = "PANELS_MIRROR_SYNC_SECS")]
+    pub mirror_sync_secs: u64,
+
+    /// How often to poll feed-backed comics so their strip history keeps growing, in seconds;
+    /// 0 turns polling off
+    #[arg(long, default_value = "3600", env = "PANELS_HISTORY_POLL_SECS")]
+    pub history_poll_secs: u64,
+
+    /// Serve mirrored comics only from the mirror, never from upstream
+    #[arg(long, env = "PANELS_MIRROR_OFFLINE")]
+    pub mirror_offline: bool,
+
+    /// Hide comics not rated `general` from every route, as if each request passed `safe=1`
+    #[arg(long, env = "PANELS_SAFE_MODE")]
+    pub safe_mode: bool,
+
+    #[arg(long, default_value = "1.0", env = "PANELS_REC_CONTENT_WEIGHT")]
+    pub rec_content_weight: f64,
+
+    #[arg(long, default_value = "1.0", env = "PANELS_REC_COLLABORATIVE_WEIGHT")]
+    pub rec_collaborative_weight: f64,
+
+    #[arg(long, default_value = "0.1", env = "PANELS_REC_POPULARITY_WEIGHT")]
+    pub rec_popularity_weight: f64,
 }
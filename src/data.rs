@@ -1,7 +1,9 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::Path;
 
+use crate::imaging::hash::Placeholder;
 use crate::models::Comic;
+use crate::taxonomy::{TagDefinition, Taxonomy};
 
 pub fn load_comics(data_dir: &str) -> anyhow::Result<Vec<Comic>> {
     let path = Path::new(data_dir).join("comics.json");
@@ -21,6 +23,47 @@ pub fn load_tags(data_dir: &str) -> anyhow::Result<HashMap<String, Vec<String>>>
     Ok(tags)
 }
 
+/// Loads `tag_definitions.json` and checks every tag used in `tags` is defined there.
+pub fn load_taxonomy(
+    data_dir: &str,
+    tags: &HashMap<String, Vec<String>>,
+) -> anyhow::Result<Taxonomy> {
+    let path = Path::new(data_dir).join("tag_definitions.json");
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+    let definitions: BTreeMap<String, TagDefinition> = serde_json::from_str(&contents)
+        .map_err(|e| anyhow::anyhow!("failed to parse {}: {}", path.display(), e))?;
+    let taxonomy = Taxonomy::new(definitions)
+        .map_err(|e| anyhow::anyhow!("invalid {}: {}", path.display(), e))?;
+
+    let undefined = taxonomy.undefined_tags(tags);
+    if !undefined.is_empty() {
+        let listed: Vec<String> = undefined
+            .iter()
+            .map(|(endpoint, tag)| format!("{} ({})", tag, endpoint))
+            .collect();
+        anyhow::bail!(
+            "tags.json uses tags missing from {}: {}",
+            path.display(),
+            listed.join(", ")
+        );
+    }
+    Ok(taxonomy)
+}
+
+/// Loads `placeholder_hashes.json`, the images upstreams serve in place of a missing strip.
+/// The file is optional.
+pub fn load_placeholders(data_dir: &str) -> anyhow::Result<Vec<Placeholder>> {
+    let path = Path::new(data_dir).join("placeholder_hashes.json");
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => anyhow::bail!("failed to read {}: {}", path.display(), e),
+    };
+    serde_json::from_str(&contents)
+        .map_err(|e| anyhow::anyhow!("failed to parse {}: {}", path.display(), e))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -55,4 +98,11 @@ mod tests {
         // some comics lack startDate
         assert!(no_start.len() >= 2);
     }
+
+    #[test]
+    fn shipped_tags_are_all_defined() {
+        let tags = load_tags("data").expect("should load tags.json");
+        let taxonomy = load_taxonomy("data", &tags).expect("should load tag_definitions.json");
+        assert_eq!(taxonomy.resolve("children"), Some("kids"));
+    }
 }
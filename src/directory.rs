@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use strsim::damerau_levenshtein;
+
+use crate::models::{Comic, ComicWithTags};
+
+const TITLE_WEIGHT: f64 = 3.0;
+const ENDPOINT_WEIGHT: f64 = 2.0;
+const AUTHOR_WEIGHT: f64 = 2.0;
+const KEYWORD_WEIGHT: f64 = 1.5;
+const PHRASE_BONUS: f64 = 2.0;
+
+fn tokenize(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// How well one query token matches one candidate token, from 0 (no match) to 1.
+fn token_similarity(query: &str, candidate: &str) -> f64 {
+    if query == candidate {
+        return 1.0;
+    }
+    let query_len = query.chars().count();
+    if query_len >= 2 && candidate.starts_with(query) {
+        return 0.9;
+    }
+    if query_len >= 3 && candidate.contains(query) {
+        return 0.75;
+    }
+    if query_len < 4 {
+        return 0.0;
+    }
+
+    let allowed = if query_len >= 8 { 2 } else { 1 };
+    // Also compare against the candidate's prefix so typos in a partially typed word still match
+    let prefix: String = candidate.chars().take(query_len).collect();
+    let distance = damerau_levenshtein(query, candidate).min(damerau_levenshtein(query, &prefix));
+    if distance <= allowed {
+        0.7 - 0.15 * distance.saturating_sub(1) as f64
+    } else {
+        0.0
+    }
+}
+
+fn best_match(query: &str, tokens: &[String]) -> f64 {
+    tokens
+        .iter()
+        .map(|t| token_similarity(query, t))
+        .fold(0.0, f64::max)
+}
+
+/// Typo-tolerant relevance of a comic for a free-text query. Every query word has
+/// to match some field; `None` means the comic does not match at all.
+pub fn match_score(query: &str, comic: &Comic) -> Option<f64> {
+    let query_tokens = tokenize(query);
+    if query_tokens.is_empty() {
+        return Some(0.0);
+    }
+
+    let title = tokenize(&comic.title);
+    let endpoint = tokenize(&comic.endpoint);
+    let author = comic.author.as_deref().map(tokenize).unwrap_or_default();
+    let keywords: Vec<String> = comic.keywords.iter().flat_map(|k| tokenize(k)).collect();
+
+    let mut score = 0.0;
+    for token in &query_tokens {
+        let best = [
+            best_match(token, &title) * TITLE_WEIGHT,
+            best_match(token, &endpoint) * ENDPOINT_WEIGHT,
+            best_match(token, &author) * AUTHOR_WEIGHT,
+            best_match(token, &keywords) * KEYWORD_WEIGHT,
+        ]
+        .into_iter()
+        .fold(0.0, f64::max);
+        if best == 0.0 {
+            return None;
+        }
+        score += best;
+    }
+
+    if comic.title.to_lowercase().contains(&query_tokens.join(" ")) {
+        score += PHRASE_BONUS;
+    }
+
+    Some(score)
+}
+
+/// Whether the comic carries any of `tags` (compared case-insensitively).
+pub fn has_any_tag(comic: &ComicWithTags, tags: &[String]) -> bool {
+    comic
+        .tags
+        .iter()
+        .any(|t| tags.iter().any(|f| f.eq_ignore_ascii_case(t)))
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Facets {
+    pub tags: BTreeMap<String, usize>,
+    pub sources: BTreeMap<String, usize>,
+}
+
+/// Counts tags over comics passing every filter except the tag filter, and sources over
+/// comics passing every filter except the source filter, so each facet shows what
+/// picking one of its values would return.
+/// `tags` is the expanded tag filter; a comic passes it by carrying any of those tags.
+pub fn facets<'a, I>(comics: I, tags: Option<&[String]>, source: Option<&str>) -> Facets
+where
+    I: IntoIterator<Item = &'a ComicWithTags>,
+{
+    let mut facets = Facets::default();
+    for comic in comics {
+        let tag_ok = tags.is_none_or(|ts| has_any_tag(comic, ts));
+        let source_ok = source.is_none_or(|s| comic.comic.source.eq_ignore_ascii_case(s));
+        if source_ok {
+            for t in &comic.tags {
+                *facets.tags.entry(t.clone()).or_insert(0) += 1;
+            }
+        }
+        if tag_ok {
+            *facets
+                .sources
+                .entry(comic.comic.source.clone())
+                .or_insert(0) += 1;
+        }
+    }
+    facets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comic(endpoint: &str, title: &str, author: &str, keywords: &[&str]) -> Comic {
+        Comic {
+            endpoint: endpoint.into(),
+            title: title.into(),
+            author: Some(author.into()),
+            available: true,
+            start_date: None,
+            source: "gocomics".into(),
+            keywords: keywords.iter().map(|k| k.to_string()).collect(),
+            rating: Default::default(),
+            feed: None,
+        }
+    }
+
+    #[test]
+    fn tolerates_typos_across_words() {
+        let calvin = comic(
+            "calvinandhobbes",
+            "Calvin and Hobbes",
+            "Bill Watterson",
+            &[],
+        );
+        assert!(match_score("calvn hobbes", &calvin).is_some());
+        assert!(match_score("calvin", &calvin).is_some());
+        assert!(match_score("garfield", &calvin).is_none());
+    }
+
+    #[test]
+    fn matches_author_and_keywords() {
+        let adam = comic(
+            "adamathome",
+            "Adam@Home",
+            "Rob Harrell",
+            &["stay-at-home", "dad"],
+        );
+        assert!(match_score("harrell", &adam).is_some());
+        assert!(match_score("dad", &adam).is_some());
+        assert!(match_score("stay at home", &adam).is_some());
+    }
+
+    #[test]
+    fn exact_title_ranks_above_fuzzy_match() {
+        let peanuts = comic("peanuts", "Peanuts", "Charles Schulz", &[]);
+        let peanuts_begins = comic("peanuts-begins", "Peanuts Begins", "Charles Schulz", &[]);
+        let exact = match_score("peanuts", &peanuts).unwrap();
+        let typo = match_score("peanut", &peanuts_begins).unwrap();
+        assert!(exact > typo);
+    }
+
+    #[test]
+    fn facets_ignore_their_own_filter() {
+        let mut go = ComicWithTags {
+            comic: comic("garfield", "Garfield", "Jim Davis", &[]),
+            tags: vec!["humor".into(), "animals".into()],
+        };
+        let mut rss = go.clone();
+        rss.comic.endpoint = "blondie".into();
+        rss.comic.source = "comicsrss".into();
+        rss.tags = vec!["humor".into()];
+        go.comic.source = "gocomics".into();
+
+        let all = [go, rss];
+        let filter = vec!["animals".to_string()];
+        let facets = facets(all.iter(), Some(&filter), Some("gocomics"));
+        assert_eq!(facets.tags.get("humor"), Some(&1));
+        assert_eq!(facets.tags.get("animals"), Some(&1));
+        assert_eq!(facets.sources.get("gocomics"), Some(&1));
+        assert_eq!(facets.sources.get("comicsrss"), None);
+    }
+}
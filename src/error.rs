@@ -13,12 +13,19 @@ pub enum PanelsError {
     #[error("invalid parameter: {0}")]
     InvalidParam(String),
 
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+
     #[error("scrape failed: {0}")]
     ScrapeFailed(String),
 
     #[error("http error: {0}")]
     HttpError(#[from] reqwest::Error),
 
+    /// New catalog files that failed to load, with what validating them found
+    #[error("catalog rejected: {0}")]
+    CatalogRejected(String, crate::validation::Report),
+
     #[error("internal error: {0}")]
     Internal(#[from] anyhow::Error),
 }
@@ -29,12 +36,19 @@ impl IntoResponse for PanelsError {
             PanelsError::NotFound(msg) => (StatusCode::NOT_FOUND, msg.clone()),
             PanelsError::InvalidDate(msg) => (StatusCode::BAD_REQUEST, msg.clone()),
             PanelsError::InvalidParam(msg) => (StatusCode::BAD_REQUEST, msg.clone()),
+            PanelsError::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg.clone()),
             PanelsError::ScrapeFailed(msg) => (StatusCode::BAD_GATEWAY, msg.clone()),
             PanelsError::HttpError(e) => (StatusCode::BAD_GATEWAY, e.to_string()),
+            PanelsError::CatalogRejected(msg, _) => (StatusCode::UNPROCESSABLE_ENTITY, msg.clone()),
             PanelsError::Internal(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
         };
 
-        let body = json!({ "error": message });
+        let body = match &self {
+            PanelsError::CatalogRejected(_, report) => {
+                json!({ "error": message, "report": report })
+            }
+            _ => json!({ "error": message }),
+        };
         (status, axum::Json(body)).into_response()
     }
 }
@@ -66,10 +80,24 @@ mod tests {
         assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     }
 
+    #[test]
+    fn unauthorized_maps_to_401() {
+        let err = PanelsError::Unauthorized("bad token".into());
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
     #[test]
     fn scrape_failed_maps_to_502() {
         let err = PanelsError::ScrapeFailed("timeout".into());
         let response = err.into_response();
         assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
     }
+
+    #[test]
+    fn catalog_rejected_maps_to_422() {
+        let err = PanelsError::CatalogRejected("bad tag".into(), Default::default());
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
 }
@@ -0,0 +1,83 @@
+use std::io::Write;
+
+use chrono::Datelike;
+use zip::CompressionMethod;
+use zip::write::SimpleFileOptions;
+
+use super::{ExportRequest, Page, Zip};
+use crate::html::escape;
+
+/// Images are already compressed; storing them keeps readers fast
+fn stored() -> SimpleFileOptions {
+    SimpleFileOptions::default().compression_method(CompressionMethod::Stored)
+}
+
+/// Pages named by date, so they sort in reading order, with a ComicInfo.xml at the end.
+pub(super) struct CbzWriter {
+    zip: Zip,
+    pages: Vec<Page>,
+}
+
+impl CbzWriter {
+    pub(super) fn new(zip: Zip) -> Self {
+        Self {
+            zip,
+            pages: Vec::new(),
+        }
+    }
+
+    pub(super) fn add_page(&mut self, page: Page, bytes: &[u8]) -> anyhow::Result<()> {
+        self.zip.start_file(page.file.as_str(), stored())?;
+        self.zip.write_all(bytes)?;
+        self.pages.push(page);
+        Ok(())
+    }
+
+    pub(super) fn finish(
+        mut self,
+        request: &ExportRequest,
+        missing: &[String],
+    ) -> anyhow::Result<()> {
+        self.zip.start_file("ComicInfo.xml", stored())?;
+        self.zip
+            .write_all(comic_info(request, &self.pages, missing).as_bytes())?;
+        self.zip.finish()?;
+        Ok(())
+    }
+}
+
+/// ComicRack's metadata format, which most comic readers understand.
+fn comic_info(request: &ExportRequest, pages: &[Page], missing: &[String]) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<ComicInfo xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" xmlns:xsd=\"http://www.w3.org/2001/XMLSchema\">\n",
+    );
+    let mut field = |name: &str, value: &str| {
+        xml.push_str(&format!("  <{0}>{1}</{0}>\n", name, escape(value)));
+    };
+    field(
+        "Title",
+        &format!("{}, {} to {}", request.title, request.from, request.to),
+    );
+    field("Series", &request.title);
+    if let Some(ref author) = request.author {
+        field("Writer", author);
+    }
+    field("Year", &request.from.year().to_string());
+    field("Month", &request.from.month().to_string());
+    field("Day", &request.from.day().to_string());
+    field("PageCount", &pages.len().to_string());
+    if !missing.is_empty() {
+        field("Notes", &format!("Missing strips: {}", missing.join(", ")));
+    }
+    field("Manga", "No");
+
+    xml.push_str("  <Pages>\n");
+    for (i, page) in pages.iter().enumerate() {
+        xml.push_str(&format!(
+            "    <Page Image=\"{}\" ImageSize=\"{}\" Bookmark=\"{}\" />\n",
+            i, page.size, page.date
+        ));
+    }
+    xml.push_str("  </Pages>\n</ComicInfo>\n");
+    xml
+}
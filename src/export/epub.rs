@@ -0,0 +1,153 @@
+use std::io::Write;
+
+use zip::CompressionMethod;
+use zip::write::SimpleFileOptions;
+
+use super::{ExportRequest, Page, Zip};
+use crate::html::escape;
+
+const CONTAINER_XML: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+
+const PAGE_STYLE: &str =
+    "body{margin:0;text-align:center}img{max-width:100%;max-height:95vh}p{font-size:0.8em}";
+
+fn stored() -> SimpleFileOptions {
+    SimpleFileOptions::default().compression_method(CompressionMethod::Stored)
+}
+
+/// An EPUB 3 book with one XHTML page per strip. The package document and navigation are
+/// written last, once every page is known; readers find them through container.xml.
+pub(super) struct EpubWriter {
+    zip: Zip,
+    pages: Vec<Page>,
+}
+
+fn xhtml(title: &str, body: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<!DOCTYPE html>\n<html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" xml:lang=\"en\" lang=\"en\">\n<head><title>{}</title><style>{}</style></head>\n<body>\n{}\n</body>\n</html>\n",
+        escape(title),
+        PAGE_STYLE,
+        body
+    )
+}
+
+impl EpubWriter {
+    pub(super) fn new(mut zip: Zip) -> anyhow::Result<Self> {
+        // The mimetype must come first and uncompressed so readers can sniff it
+        zip.start_file("mimetype", stored())?;
+        zip.write_all(b"application/epub+zip")?;
+        zip.start_file("META-INF/container.xml", stored())?;
+        zip.write_all(CONTAINER_XML.as_bytes())?;
+        Ok(Self {
+            zip,
+            pages: Vec::new(),
+        })
+    }
+
+    pub(super) fn add_page(&mut self, page: Page, bytes: &[u8]) -> anyhow::Result<()> {
+        self.zip
+            .start_file(format!("OEBPS/images/{}", page.file), stored())?;
+        self.zip.write_all(bytes)?;
+        let body = format!(
+            "<img src=\"../images/{}\" alt=\"{}\" />\n<p>{}</p>",
+            page.file, page.date, page.date
+        );
+        self.zip
+            .start_file(format!("OEBPS/pages/{}.xhtml", page.date), stored())?;
+        self.zip.write_all(xhtml(&page.date, &body).as_bytes())?;
+        self.pages.push(page);
+        Ok(())
+    }
+
+    pub(super) fn finish(
+        mut self,
+        request: &ExportRequest,
+        missing: &[String],
+    ) -> anyhow::Result<()> {
+        if !missing.is_empty() {
+            let items: String = missing
+                .iter()
+                .map(|date| format!("<li>{}</li>", date))
+                .collect();
+            let body = format!("<h1>Missing strips</h1>\n<ul>{}</ul>", items);
+            self.zip.start_file("OEBPS/missing.xhtml", stored())?;
+            self.zip
+                .write_all(xhtml("Missing strips", &body).as_bytes())?;
+        }
+
+        let mut toc: String = self
+            .pages
+            .iter()
+            .map(|p| format!("<li><a href=\"pages/{0}.xhtml\">{0}</a></li>", p.date))
+            .collect();
+        if !missing.is_empty() {
+            toc.push_str("<li><a href=\"missing.xhtml\">Missing strips</a></li>");
+        }
+        let nav = format!(
+            "<nav epub:type=\"toc\" id=\"toc\"><h1>{}</h1><ol>{}</ol></nav>",
+            escape(&request.title),
+            toc
+        );
+        self.zip.start_file("OEBPS/nav.xhtml", stored())?;
+        self.zip.write_all(xhtml(&request.title, &nav).as_bytes())?;
+
+        self.zip.start_file("OEBPS/content.opf", stored())?;
+        self.zip
+            .write_all(package(request, &self.pages, !missing.is_empty()).as_bytes())?;
+        self.zip.finish()?;
+        Ok(())
+    }
+}
+
+fn package(request: &ExportRequest, pages: &[Page], has_missing: bool) -> String {
+    let mut manifest = String::from(
+        "    <item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n",
+    );
+    let mut spine = String::new();
+    for (i, page) in pages.iter().enumerate() {
+        manifest.push_str(&format!(
+            "    <item id=\"page-{0}\" href=\"pages/{1}.xhtml\" media-type=\"application/xhtml+xml\"/>\n    <item id=\"image-{0}\" href=\"images/{2}\" media-type=\"{3}\"/>\n",
+            i, page.date, page.file, page.media_type
+        ));
+        spine.push_str(&format!("    <itemref idref=\"page-{}\"/>\n", i));
+    }
+    if has_missing {
+        manifest.push_str(
+            "    <item id=\"missing\" href=\"missing.xhtml\" media-type=\"application/xhtml+xml\"/>\n",
+        );
+        spine.push_str("    <itemref idref=\"missing\"/>\n");
+    }
+    let creator = request
+        .author
+        .as_deref()
+        .map(|a| format!("    <dc:creator>{}</dc:creator>\n", escape(a)))
+        .unwrap_or_default();
+
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="book-id">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="book-id">urn:panels:{endpoint}:{from}:{to}</dc:identifier>
+    <dc:title>{title}, {from} to {to}</dc:title>
+{creator}    <dc:language>en</dc:language>
+    <meta property="dcterms:modified">{modified}</meta>
+  </metadata>
+  <manifest>
+{manifest}  </manifest>
+  <spine>
+{spine}  </spine>
+</package>
+"#,
+        endpoint = escape(&request.endpoint),
+        title = escape(&request.title),
+        from = request.from,
+        to = request.to,
+        modified = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+    )
+}
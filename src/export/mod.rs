@@ -0,0 +1,438 @@
+mod cbz;
+mod epub;
+
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::NaiveDate;
+use image::ImageFormat;
+use tracing::warn;
+use zip::ZipWriter;
+use zip::write::StreamWriter;
+
+use crate::catalog::CatalogData;
+use crate::error::{PanelsError, Result};
+use crate::imaging;
+use crate::imaging::hash::{ImageHashes, Placeholder, find_placeholder};
+use crate::models::Comic;
+use crate::sources::{ComicSource, SourceRegistry};
+
+use cbz::CbzWriter;
+use epub::EpubWriter;
+
+/// Longest range one export may cover
+pub const MAX_EXPORT_DAYS: i64 = 366;
+/// Pause between strips so a long export doesn't hammer upstream
+pub const PAGE_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// Comic book archive with ComicInfo.xml
+    Cbz,
+    /// EPUB 3 with one strip per page
+    Epub,
+}
+
+impl ExportFormat {
+    pub fn parse(format: Option<&str>) -> Result<Self> {
+        match format {
+            None | Some("cbz") => Ok(ExportFormat::Cbz),
+            Some("epub") => Ok(ExportFormat::Epub),
+            Some(other) => Err(PanelsError::InvalidParam(format!(
+                "unsupported export format: {} (expected cbz or epub)",
+                other
+            ))),
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Cbz => "application/vnd.comicbook+zip",
+            ExportFormat::Epub => "application/epub+zip",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Cbz => "cbz",
+            ExportFormat::Epub => "epub",
+        }
+    }
+}
+
+/// A range of one comic's strips to package.
+#[derive(Debug, Clone)]
+pub struct ExportRequest {
+    pub endpoint: String,
+    pub title: String,
+    pub author: Option<String>,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub format: ExportFormat,
+    /// Strips showing one of these are skipped like missing ones
+    pub placeholders: Vec<Placeholder>,
+    pub interval: Duration,
+}
+
+impl ExportRequest {
+    pub fn new(
+        comic: &Comic,
+        from: NaiveDate,
+        to: NaiveDate,
+        format: ExportFormat,
+    ) -> Result<Self> {
+        if to < from {
+            return Err(PanelsError::InvalidParam(format!(
+                "export range ends ({}) before it starts ({})",
+                to, from
+            )));
+        }
+        if (to - from).num_days() >= MAX_EXPORT_DAYS {
+            return Err(PanelsError::InvalidParam(format!(
+                "export range is limited to {} days",
+                MAX_EXPORT_DAYS
+            )));
+        }
+        Ok(Self {
+            endpoint: comic.endpoint.clone(),
+            title: comic.title.clone(),
+            author: comic.author.clone(),
+            from,
+            to,
+            format,
+            placeholders: Vec::new(),
+            interval: PAGE_INTERVAL,
+        })
+    }
+
+    pub fn file_name(&self) -> String {
+        format!(
+            "{}-{}-{}.{}",
+            self.endpoint,
+            self.from,
+            self.to,
+            self.format.extension()
+        )
+    }
+
+    fn dates(&self) -> impl Iterator<Item = String> + use<> {
+        let to = self.to;
+        self.from
+            .iter_days()
+            .take_while(move |d| *d <= to)
+            .map(|d| d.format("%Y-%m-%d").to_string())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ExportSummary {
+    pub pages: usize,
+    /// Dates without a strip, in order
+    pub missing: Vec<String>,
+}
+
+/// Bytes the archive writer has produced since they were last taken.
+#[derive(Clone, Default)]
+struct Chunks(Arc<Mutex<Vec<u8>>>);
+
+impl Chunks {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl Write for Chunks {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+type Zip = ZipWriter<StreamWriter<Chunks>>;
+
+/// A strip image stored in the archive.
+struct Page {
+    date: String,
+    file: String,
+    media_type: &'static str,
+    size: usize,
+}
+
+impl Page {
+    fn new(date: &str, bytes: &[u8], content_type: &str) -> Self {
+        let (extension, media_type) = match image::guess_format(bytes) {
+            Ok(ImageFormat::Png) => ("png", "image/png"),
+            Ok(ImageFormat::Jpeg) => ("jpg", "image/jpeg"),
+            Ok(ImageFormat::Gif) => ("gif", "image/gif"),
+            Ok(ImageFormat::WebP) => ("webp", "image/webp"),
+            Ok(ImageFormat::Avif) => ("avif", "image/avif"),
+            _ if content_type.starts_with("image/jpeg") => ("jpg", "image/jpeg"),
+            _ => ("png", "image/png"),
+        };
+        Self {
+            date: date.to_string(),
+            file: format!("{}.{}", date, extension),
+            media_type,
+            size: bytes.len(),
+        }
+    }
+}
+
+enum Archive {
+    Cbz(CbzWriter),
+    Epub(EpubWriter),
+}
+
+impl Archive {
+    fn new(format: ExportFormat, out: Chunks) -> anyhow::Result<Self> {
+        let zip = ZipWriter::new_stream(out);
+        Ok(match format {
+            ExportFormat::Cbz => Archive::Cbz(CbzWriter::new(zip)),
+            ExportFormat::Epub => Archive::Epub(EpubWriter::new(zip)?),
+        })
+    }
+
+    fn add_page(&mut self, page: Page, bytes: &[u8]) -> anyhow::Result<()> {
+        match self {
+            Archive::Cbz(cbz) => cbz.add_page(page, bytes),
+            Archive::Epub(epub) => epub.add_page(page, bytes),
+        }
+    }
+
+    fn finish(self, request: &ExportRequest, missing: &[String]) -> anyhow::Result<()> {
+        match self {
+            Archive::Cbz(cbz) => cbz.finish(request, missing),
+            Archive::Epub(epub) => epub.finish(request, missing),
+        }
+    }
+}
+
+/// The image for `date`, or `None` if there is no strip or it is a placeholder.
+async fn fetch_page(
+    source: &dyn ComicSource,
+    request: &ExportRequest,
+    date: &str,
+) -> anyhow::Result<Option<(Vec<u8>, String)>> {
+    let Some(strip) = source.fetch_strip(&request.endpoint, date).await? else {
+        return Ok(None);
+    };
+    let (bytes, content_type) = source.proxy_image(&strip.image_url).await?;
+    if request.placeholders.is_empty() {
+        return Ok(Some((bytes, content_type)));
+    }
+    let (bytes, hashes) = tokio::task::spawn_blocking(move || {
+        let hashes = imaging::decode(&bytes).map(|image| ImageHashes::of(&image));
+        (bytes, hashes)
+    })
+    .await?;
+    match hashes {
+        Ok(hashes) if find_placeholder(&request.placeholders, &hashes).is_some() => Ok(None),
+        _ => Ok(Some((bytes, content_type))),
+    }
+}
+
+/// Walks the range one date at a time and packages every strip found. The archive is handed
+/// to `emit` in pieces as it is built, one piece per date, so callers can stream it. Dates
+/// without a strip, or whose strip can't be fetched, are skipped and listed in the archive.
+pub async fn export<F>(
+    source: &dyn ComicSource,
+    request: &ExportRequest,
+    mut emit: impl FnMut(Vec<u8>) -> F,
+) -> anyhow::Result<ExportSummary>
+where
+    F: Future<Output = anyhow::Result<()>>,
+{
+    let chunks = Chunks::default();
+    let mut archive = Archive::new(request.format, chunks.clone())?;
+    let mut summary = ExportSummary::default();
+
+    for (i, date) in request.dates().enumerate() {
+        if i > 0 {
+            tokio::time::sleep(request.interval).await;
+        }
+        match fetch_page(source, request, &date).await {
+            Ok(Some((bytes, content_type))) => {
+                archive.add_page(Page::new(&date, &bytes, &content_type), &bytes)?;
+                summary.pages += 1;
+            }
+            Ok(None) => summary.missing.push(date),
+            Err(e) => {
+                warn!(endpoint = %request.endpoint, date, "skipping strip in export: {}", e);
+                summary.missing.push(date);
+            }
+        }
+        let chunk = chunks.take();
+        if !chunk.is_empty() {
+            emit(chunk).await?;
+        }
+    }
+
+    archive.finish(request, &summary.missing)?;
+    emit(chunks.take()).await?;
+    Ok(summary)
+}
+
+/// Exports a range of a catalog comic through whichever of `sources` handles it, into the
+/// file `out` or else one named by [`ExportRequest::file_name`]. Returns the file written.
+pub async fn export_to_file(
+    catalog: &CatalogData,
+    sources: &SourceRegistry,
+    endpoint: &str,
+    (from, to): (NaiveDate, NaiveDate),
+    format: ExportFormat,
+    out: Option<String>,
+) -> anyhow::Result<(String, ExportSummary)> {
+    let comic = catalog
+        .comic(endpoint)
+        .ok_or_else(|| anyhow::anyhow!("{} is not in comics.json", endpoint))?;
+    let mut request = ExportRequest::new(comic, from, to, format)?;
+    request.placeholders = catalog.placeholders.clone();
+    let source = sources
+        .find(endpoint)
+        .ok_or_else(|| anyhow::anyhow!("no source handles {}", endpoint))?;
+
+    let out = out.unwrap_or_else(|| request.file_name());
+    let mut file = std::fs::File::create(&out)
+        .map_err(|e| anyhow::anyhow!("failed to create {}: {}", out, e))?;
+    let summary = export(source, &request, |chunk| {
+        std::future::ready(file.write_all(&chunk).map_err(anyhow::Error::from))
+    })
+    .await?;
+    Ok((out, summary))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use async_trait::async_trait;
+    use zip::ZipArchive;
+
+    use super::*;
+    use crate::imaging::testing::strip;
+    use crate::models::{ComicStrip, StripMetadata};
+
+    /// Has a strip every day except 2024-01-02.
+    struct MockSource;
+
+    #[async_trait]
+    impl ComicSource for MockSource {
+        fn handles(&self, _: &str) -> bool {
+            true
+        }
+        async fn fetch_strip(&self, endpoint: &str, date: &str) -> Result<Option<ComicStrip>> {
+            Ok((date != "2024-01-02").then(|| ComicStrip {
+                endpoint: endpoint.to_string(),
+                title: "Test".to_string(),
+                date: date.to_string(),
+                image_url: format!("https://example.com/{}.png", date),
+                source_url: String::new(),
+                prev_date: None,
+                next_date: None,
+                metadata: StripMetadata::default(),
+            }))
+        }
+        async fn fetch_latest(&self, _: &str) -> Result<Option<ComicStrip>> {
+            Ok(None)
+        }
+        async fn fetch_random(&self, _: &str) -> Result<Option<ComicStrip>> {
+            Ok(None)
+        }
+        async fn proxy_image(&self, _: &str) -> Result<(Vec<u8>, String)> {
+            let png = imaging::encode(&strip(60, 20, &[(5, 5, 10, 10)]), ImageFormat::Png)
+                .map_err(PanelsError::Internal)?;
+            Ok((png, "image/png".into()))
+        }
+    }
+
+    fn comic() -> Comic {
+        Comic {
+            endpoint: "test".into(),
+            title: "Test & Co".into(),
+            author: Some("A. Uthor".into()),
+            available: true,
+            start_date: None,
+            source: "gocomics".into(),
+            keywords: Vec::new(),
+            rating: Default::default(),
+            feed: None,
+        }
+    }
+
+    fn request(format: ExportFormat) -> ExportRequest {
+        let comic = comic();
+        let from = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let to = NaiveDate::from_ymd_opt(2024, 1, 3).unwrap();
+        let mut request = ExportRequest::new(&comic, from, to, format).unwrap();
+        request.interval = Duration::ZERO;
+        request
+    }
+
+    async fn build(request: &ExportRequest) -> (ExportSummary, ZipArchive<Cursor<Vec<u8>>>, usize) {
+        let mut out = Vec::new();
+        let mut chunks = 0;
+        let summary = export(&MockSource, request, |chunk| {
+            out.extend(chunk);
+            chunks += 1;
+            std::future::ready(Ok(()))
+        })
+        .await
+        .unwrap();
+        (summary, ZipArchive::new(Cursor::new(out)).unwrap(), chunks)
+    }
+
+    fn read(archive: &mut ZipArchive<Cursor<Vec<u8>>>, name: &str) -> String {
+        let mut contents = String::new();
+        archive
+            .by_name(name)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        contents
+    }
+
+    #[tokio::test]
+    async fn cbz_skips_and_lists_missing_dates() {
+        let (summary, mut archive, chunks) = build(&request(ExportFormat::Cbz)).await;
+        assert_eq!(summary.pages, 2);
+        assert_eq!(summary.missing, vec!["2024-01-02"]);
+        assert!(chunks > 1, "archive should be emitted as it is built");
+
+        let names: Vec<&str> = archive.file_names().collect();
+        assert_eq!(names.len(), 3);
+        assert!(names.contains(&"2024-01-01.png") && names.contains(&"2024-01-03.png"));
+
+        let info = read(&mut archive, "ComicInfo.xml");
+        assert!(info.contains("<Series>Test &amp; Co</Series>"));
+        assert!(info.contains("<PageCount>2</PageCount>"));
+        assert!(info.contains("<Notes>Missing strips: 2024-01-02</Notes>"));
+    }
+
+    #[tokio::test]
+    async fn epub_has_mimetype_first_and_a_page_per_strip() {
+        let (_, mut archive, _) = build(&request(ExportFormat::Epub)).await;
+        assert_eq!(archive.by_index(0).unwrap().name(), "mimetype");
+        assert_eq!(read(&mut archive, "mimetype"), "application/epub+zip");
+
+        let package = read(&mut archive, "OEBPS/content.opf");
+        assert!(package.contains("href=\"pages/2024-01-03.xhtml\""));
+        assert!(package.contains("<dc:creator>A. Uthor</dc:creator>"));
+        assert!(package.contains("<itemref idref=\"missing\"/>"));
+        assert!(read(&mut archive, "OEBPS/missing.xhtml").contains("<li>2024-01-02</li>"));
+    }
+
+    #[test]
+    fn rejects_backwards_and_overlong_ranges() {
+        let comic = comic();
+        let day = |m, d| NaiveDate::from_ymd_opt(2024, m, d).unwrap();
+        assert!(ExportRequest::new(&comic, day(2, 1), day(1, 1), ExportFormat::Cbz).is_err());
+        assert!(ExportRequest::new(&comic, day(1, 1), day(12, 31), ExportFormat::Cbz).is_ok());
+        let next_year = NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
+        assert!(ExportRequest::new(&comic, day(1, 1), next_year, ExportFormat::Cbz).is_err());
+    }
+}
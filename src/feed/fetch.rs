@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use reqwest::header::{ACCEPT, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, USER_AGENT};
+use tracing::debug;
+
+use super::{Feed, parse};
+use crate::error::{PanelsError, Result};
+use crate::http_client::random_user_agent;
+
+const FEED_TIMEOUT: Duration = Duration::from_secs(15);
+const FEED_ACCEPT: &str =
+    "application/rss+xml, application/atom+xml, application/xml;q=0.9, text/xml;q=0.9, */*;q=0.8";
+
+/// The last copy of a feed with the validators it was served with.
+struct Cached {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    feed: Arc<Feed>,
+}
+
+/// Fetches feeds, revalidating with `If-None-Match` / `If-Modified-Since` once a feed has
+/// been seen so unchanged feeds cost a 304 rather than a download and parse.
+pub struct FeedFetcher {
+    client: reqwest::Client,
+    cached: Mutex<HashMap<String, Cached>>,
+}
+
+impl FeedFetcher {
+    pub fn new(client: reqwest::Client) -> Self {
+        Self {
+            client,
+            cached: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The feed at `url`, or `None` when there is none there.
+    pub async fn fetch(&self, url: &str) -> Result<Option<Arc<Feed>>> {
+        let mut request = self
+            .client
+            .get(url)
+            .header(USER_AGENT, random_user_agent())
+            .header(ACCEPT, FEED_ACCEPT)
+            .timeout(FEED_TIMEOUT);
+        if let Some(cached) = self.cached.lock().unwrap().get(url) {
+            if let Some(ref etag) = cached.etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(ref last_modified) = cached.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| PanelsError::ScrapeFailed(format!("failed to fetch {}: {}", url, e)))?;
+        match response.status() {
+            StatusCode::NOT_MODIFIED => {
+                debug!(url, "feed not modified");
+                return self
+                    .cached
+                    .lock()
+                    .unwrap()
+                    .get(url)
+                    .map(|c| Some(Arc::clone(&c.feed)))
+                    .ok_or_else(|| {
+                        PanelsError::ScrapeFailed(format!("{} answered 304 to a first fetch", url))
+                    });
+            }
+            StatusCode::NOT_FOUND | StatusCode::GONE => return Ok(None),
+            status if !status.is_success() => {
+                return Err(PanelsError::ScrapeFailed(format!(
+                    "{} returned {}",
+                    url, status
+                )));
+            }
+            _ => {}
+        }
+
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v: &reqwest::header::HeaderValue| v.to_str().ok())
+                .map(str::to_string)
+        };
+        let (etag, last_modified) = (header(ETAG), header(LAST_MODIFIED));
+        let body = response
+            .text()
+            .await
+            .map_err(|e| PanelsError::ScrapeFailed(format!("failed to read {}: {}", url, e)))?;
+        let feed = Arc::new(
+            parse(&body)
+                .map_err(|e| PanelsError::ScrapeFailed(format!("bad feed at {}: {}", url, e)))?,
+        );
+
+        let mut cached = self.cached.lock().unwrap();
+        if etag.is_some() || last_modified.is_some() {
+            cached.insert(
+                url.to_string(),
+                Cached {
+                    etag,
+                    last_modified,
+                    feed: Arc::clone(&feed),
+                },
+            );
+        } else {
+            cached.remove(url);
+        }
+        Ok(Some(feed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::Router;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::get;
+
+    use super::*;
+
+    const ETAG_VALUE: &str = "\"v1\"";
+
+    #[tokio::test]
+    async fn unchanged_feeds_are_revalidated_not_refetched() {
+        let full_responses = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&full_responses);
+        let app = Router::new().route(
+            "/feed.xml",
+            get(move |headers: HeaderMap| async move {
+                if headers.get(IF_NONE_MATCH).is_some_and(|v| v == ETAG_VALUE) {
+                    return (
+                        StatusCode::NOT_MODIFIED,
+                        [(ETAG, ETAG_VALUE)],
+                        String::new(),
+                    );
+                }
+                counter.fetch_add(1, Ordering::SeqCst);
+                let xml = std::fs::read_to_string("tests/fixtures/feeds/rss2.xml").unwrap();
+                (StatusCode::OK, [(ETAG, ETAG_VALUE)], xml)
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let fetcher = FeedFetcher::new(reqwest::Client::new());
+        let url = format!("http://{}/feed.xml", addr);
+        let first = fetcher.fetch(&url).await.unwrap().unwrap();
+        let second = fetcher.fetch(&url).await.unwrap().unwrap();
+        assert_eq!(first.entries.len(), 2);
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(full_responses.load(Ordering::SeqCst), 1);
+
+        let missing = format!("http://{}/other.xml", addr);
+        assert!(fetcher.fetch(&missing).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,396 @@
+mod fetch;
+
+pub use fetch::FeedFetcher;
+
+use std::sync::LazyLock;
+
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use quick_xml::Reader;
+use quick_xml::events::{BytesStart, Event};
+use scraper::{Html, Selector};
+
+/// A parsed RSS or Atom feed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Feed {
+    pub title: Option<String>,
+    pub entries: Vec<FeedEntry>,
+}
+
+/// An RSS `<item>` or Atom `<entry>`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FeedEntry {
+    pub title: String,
+    pub link: Option<String>,
+    /// RSS `guid` or Atom `id`
+    pub id: Option<String>,
+    pub published: Option<DateTime<FixedOffset>>,
+    pub updated: Option<DateTime<FixedOffset>>,
+    /// RSS `description` or Atom `summary`, as HTML
+    pub summary: Option<String>,
+    /// `content:encoded` or Atom `content`, as HTML
+    pub content: Option<String>,
+    /// Image URLs, best first: `media:content` and image enclosures, then images in the
+    /// content and summary, then `media:thumbnail`s
+    pub images: Vec<String>,
+}
+
+impl FeedEntry {
+    pub fn image(&self) -> Option<&str> {
+        self.images.first().map(String::as_str)
+    }
+
+    /// When the entry was published, or last updated if the feed doesn't say.
+    pub fn date(&self) -> Option<DateTime<FixedOffset>> {
+        self.published.or(self.updated)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Title,
+    Link,
+    Id,
+    Published,
+    Updated,
+    Summary,
+    Content,
+}
+
+/// Entry being read, with the images found in its elements kept apart until the HTML
+/// ones are known.
+#[derive(Default)]
+struct Partial {
+    entry: FeedEntry,
+    media: Vec<String>,
+    thumbnails: Vec<String>,
+}
+
+fn attr(element: &BytesStart, name: &str) -> Option<String> {
+    element
+        .try_get_attribute(name)
+        .ok()
+        .flatten()
+        .and_then(|a| a.unescape_value().ok())
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+fn is_image(element: &BytesStart) -> bool {
+    match (attr(element, "medium"), attr(element, "type")) {
+        (Some(medium), _) => medium == "image",
+        (None, Some(mime)) => mime.starts_with("image/"),
+        (None, None) => true,
+    }
+}
+
+static IMAGES: LazyLock<Selector> = LazyLock::new(|| Selector::parse("img[src]").unwrap());
+
+/// Image sources in an HTML fragment. Descriptions escaped once more than they should be
+/// are unescaped and searched again.
+pub fn html_images(html: &str) -> Vec<String> {
+    let fragment = Html::parse_fragment(html);
+    let images: Vec<String> = fragment
+        .select(&IMAGES)
+        .filter_map(|img| img.value().attr("src"))
+        .map(|src| src.trim().to_string())
+        .filter(|src| !src.is_empty())
+        .collect();
+    if images.is_empty() && html.contains("&lt;") {
+        let unescaped: String = fragment.root_element().text().collect();
+        if unescaped.contains('<') {
+            return html_images(&unescaped);
+        }
+    }
+    images
+}
+
+/// Reads an RSS 0.9x/2.0 or Atom 1.0 feed. Extensions are matched by their usual `media:`,
+/// `content:` and `dc:` prefixes rather than by namespace.
+pub fn parse(xml: &str) -> Result<Feed, quick_xml::Error> {
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+    reader.config_mut().check_end_names = false;
+
+    let mut feed = Feed::default();
+    let mut partial: Option<Partial> = None;
+    // The field being read, the element it is read from and the text so far
+    let mut field: Option<(Field, Vec<u8>, String)> = None;
+    let mut in_feed_title = false;
+
+    loop {
+        let event = reader.read_event()?;
+        let empty = matches!(event, Event::Empty(_));
+        match event {
+            Event::Start(ref e) | Event::Empty(ref e) => {
+                let name = e.name().as_ref().to_vec();
+                let Some(current) = partial.as_mut() else {
+                    match name.as_slice() {
+                        b"item" | b"entry" => partial = Some(Partial::default()),
+                        b"title" if feed.title.is_none() && !empty => in_feed_title = true,
+                        _ => {}
+                    }
+                    continue;
+                };
+                if let Some((Field::Summary | Field::Content, _, _)) = field {
+                    // Atom xhtml content arrives as elements rather than escaped text
+                    if e.local_name().as_ref() == b"img"
+                        && let Some(src) = attr(e, "src")
+                    {
+                        current.entry.images.push(src);
+                    }
+                    continue;
+                }
+                let starts = match name.as_slice() {
+                    b"title" => Some(Field::Title),
+                    b"link" => {
+                        if let Some(href) = attr(e, "href") {
+                            match attr(e, "rel").as_deref().unwrap_or("alternate") {
+                                "alternate" if current.entry.link.is_none() => {
+                                    current.entry.link = Some(href)
+                                }
+                                "enclosure" if is_image(e) => current.media.push(href),
+                                _ => {}
+                            }
+                            None
+                        } else {
+                            Some(Field::Link)
+                        }
+                    }
+                    b"guid" | b"id" => Some(Field::Id),
+                    b"pubDate" | b"published" | b"dc:date" => Some(Field::Published),
+                    b"updated" => Some(Field::Updated),
+                    b"description" | b"summary" => Some(Field::Summary),
+                    b"content:encoded" | b"content" => Some(Field::Content),
+                    b"media:content" | b"enclosure" => {
+                        if let Some(url) = attr(e, "url").filter(|_| is_image(e)) {
+                            current.media.push(url);
+                        }
+                        None
+                    }
+                    b"media:thumbnail" => {
+                        if let Some(url) = attr(e, "url") {
+                            current.thumbnails.push(url);
+                        }
+                        None
+                    }
+                    _ => None,
+                };
+                if let Some(starts) = starts
+                    && !empty
+                {
+                    field = Some((starts, name, String::new()));
+                }
+            }
+            Event::Text(ref t) => {
+                let text = match t.unescape() {
+                    Ok(text) => text.into_owned(),
+                    // HTML entities such as &nbsp; aren't XML; keep them for the HTML parser
+                    Err(_) => String::from_utf8_lossy(t.as_ref()).into_owned(),
+                };
+                if let Some((_, _, ref mut value)) = field {
+                    value.push_str(&text);
+                } else if in_feed_title {
+                    feed.title = Some(text.trim().to_string());
+                }
+            }
+            Event::CData(ref c) => {
+                if let Some((_, _, ref mut value)) = field {
+                    value.push_str(&String::from_utf8_lossy(c.as_ref()));
+                }
+            }
+            Event::End(ref e) => {
+                let name = e.name();
+                in_feed_title = false;
+                let closes_field =
+                    matches!(&field, Some((_, element, _)) if element.as_slice() == name.as_ref());
+                if closes_field {
+                    let (kind, _, value) = field.take().unwrap();
+                    if let Some(current) = partial.as_mut() {
+                        set_field(&mut current.entry, kind, value.trim().to_string());
+                    }
+                } else if matches!(name.as_ref(), b"item" | b"entry")
+                    && let Some(done) = partial.take()
+                {
+                    field = None;
+                    feed.entries.push(finish(done));
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(feed)
+}
+
+fn set_field(entry: &mut FeedEntry, field: Field, value: String) {
+    if value.is_empty() {
+        return;
+    }
+    match field {
+        Field::Title => entry.title = value,
+        Field::Link => entry.link = Some(value),
+        Field::Id => entry.id = Some(value),
+        Field::Published => entry.published = parse_date(&value),
+        Field::Updated => entry.updated = parse_date(&value),
+        Field::Summary => entry.summary = Some(value),
+        Field::Content => entry.content = Some(value),
+    }
+}
+
+fn finish(partial: Partial) -> FeedEntry {
+    let Partial {
+        mut entry,
+        media,
+        thumbnails,
+    } = partial;
+    let mut images = media;
+    images.append(&mut entry.images);
+    for html in [&entry.content, &entry.summary].into_iter().flatten() {
+        images.extend(html_images(html));
+    }
+    images.extend(thumbnails);
+    let mut seen = std::collections::HashSet::new();
+    images.retain(|url| seen.insert(url.clone()));
+    entry.images = images;
+    entry
+}
+
+/// Reads RFC 2822 (RSS), RFC 3339 (Atom, Dublin Core) and the looser `13 Feb 2026` dates
+/// some feeds use, the last taken as midnight UTC.
+pub fn parse_date(value: &str) -> Option<DateTime<FixedOffset>> {
+    let value = value.trim();
+    if let Ok(date) = DateTime::parse_from_rfc2822(value) {
+        return Some(date);
+    }
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Some(date);
+    }
+    let without_weekday = value.split_once(", ").map_or(value, |(_, rest)| rest);
+    let day: Vec<&str> = without_weekday.split_whitespace().take(3).collect();
+    let date = NaiveDate::parse_from_str(&day.join(" "), "%d %b %Y")
+        .ok()
+        .or_else(|| NaiveDate::parse_from_str(value.get(..10)?, "%Y-%m-%d").ok())?;
+    Some(date.and_hms_opt(0, 0, 0)?.and_utc().fixed_offset())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> Feed {
+        let xml = std::fs::read_to_string(format!("tests/fixtures/feeds/{}", name)).unwrap();
+        parse(&xml).unwrap()
+    }
+
+    #[test]
+    fn rss2_with_cdata_descriptions() {
+        let feed = fixture("rss2.xml");
+        assert_eq!(feed.title.as_deref(), Some("Blondie"));
+        assert_eq!(feed.entries.len(), 2);
+        let entry = &feed.entries[0];
+        assert_eq!(entry.title, "Blondie by Dean Young for Thu, 13 Feb 2026");
+        assert_eq!(entry.id.as_deref(), Some("blondie2026-02-13"));
+        assert_eq!(
+            entry.link.as_deref(),
+            Some("https://www.arcamax.com/thefunnies/blondie/s-3146123")
+        );
+        assert_eq!(
+            entry.image(),
+            Some("https://resources.arcamax.com/newspics/blondie.gif")
+        );
+        assert_eq!(
+            entry.date().unwrap().date_naive(),
+            NaiveDate::from_ymd_opt(2026, 2, 13).unwrap()
+        );
+    }
+
+    #[test]
+    fn rss_with_escaped_html_and_single_quotes() {
+        let feed = fixture("rss_escaped.xml");
+        let images: Vec<Option<&str>> = feed.entries.iter().map(|e| e.image()).collect();
+        assert_eq!(
+            images,
+            vec![
+                Some("https://example.com/strips/2024-03-01.png?w=900&h=300"),
+                Some("https://example.com/strips/2024-02-29.png"),
+                None,
+            ]
+        );
+        assert_eq!(feed.entries[0].title, "Tom & Jerry's day");
+        assert_eq!(
+            feed.entries[1].date().unwrap().date_naive(),
+            NaiveDate::from_ymd_opt(2024, 2, 29).unwrap()
+        );
+    }
+
+    #[test]
+    fn rss_with_media_and_enclosures() {
+        let feed = fixture("rss_media.xml");
+        assert_eq!(
+            feed.entries[0].images,
+            vec![
+                "https://cdn.example.com/strip-0412.jpg",
+                "https://cdn.example.com/thumb-0412.jpg"
+            ]
+        );
+        // An audio enclosure isn't a strip image
+        assert_eq!(
+            feed.entries[1].image(),
+            Some("https://cdn.example.com/strip-0411.png")
+        );
+        assert_eq!(
+            feed.entries[2].image(),
+            Some("https://cdn.example.com/thumb-0410.jpg")
+        );
+    }
+
+    #[test]
+    fn atom_entries() {
+        let feed = fixture("atom.xml");
+        assert_eq!(feed.title.as_deref(), Some("Old Webcomic"));
+        assert_eq!(feed.entries.len(), 3);
+
+        let entry = &feed.entries[0];
+        assert_eq!(entry.title, "Page 120");
+        assert_eq!(entry.link.as_deref(), Some("https://comic.example.org/120"));
+        assert_eq!(entry.id.as_deref(), Some("tag:comic.example.org,2024:120"));
+        assert_eq!(
+            entry.image(),
+            Some("https://comic.example.org/pages/120.png")
+        );
+        assert_eq!(
+            entry.published.unwrap().to_rfc3339(),
+            "2024-05-06T09:30:00+02:00"
+        );
+
+        // xhtml content, and an entry with only `updated`
+        assert_eq!(
+            feed.entries[1].image(),
+            Some("https://comic.example.org/pages/119.png")
+        );
+        assert!(feed.entries[2].published.is_none());
+        assert_eq!(
+            feed.entries[2].date().unwrap().date_naive(),
+            NaiveDate::from_ymd_opt(2024, 4, 29).unwrap()
+        );
+        assert_eq!(
+            feed.entries[2].image(),
+            Some("https://comic.example.org/pages/118.png")
+        );
+    }
+
+    #[test]
+    fn loose_dates() {
+        let midnight = |y, m, d| {
+            NaiveDate::from_ymd_opt(y, m, d)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                .and_utc()
+                .fixed_offset()
+        };
+        assert_eq!(parse_date("Thu, 13 Feb 2026"), Some(midnight(2026, 2, 13)));
+        assert_eq!(parse_date("2026-02-13"), Some(midnight(2026, 2, 13)));
+        assert_eq!(parse_date("someday"), None);
+    }
+}
@@ -66,6 +66,79 @@ pub async fn fetch_page_with_options(
     .await
 }
 
+/// Like [`fetch_page_with_options`], but only a 404 comes back as `None`. Timeouts,
+/// connection errors and other failing statuses that outlast the retries are errors, for
+/// callers that must not mistake "couldn't fetch" for "isn't there".
+pub async fn fetch_page_or_missing(
+    client: &Client,
+    url: &str,
+    retries: u32,
+    timeout_ms: u64,
+) -> crate::error::Result<Option<PageResponse>> {
+    match fetch(
+        client,
+        url,
+        random_user_agent(),
+        retries,
+        timeout_ms,
+        true,
+        &[404],
+    )
+    .await?
+    {
+        Fetched::Page(page) => Ok(Some(page)),
+        Fetched::Missing => Ok(None),
+        Fetched::Failed(reason) => Err(crate::error::PanelsError::ScrapeFailed(format!(
+            "failed to fetch {}: {}",
+            url, reason
+        ))),
+    }
+}
+
+/// Fetches a strip image for proxying, with its content type.
+pub async fn fetch_image(
+    client: &Client,
+    image_url: &str,
+) -> crate::error::Result<(Vec<u8>, String)> {
+    fetch_image_with_referer(client, image_url, None).await
+}
+
+/// Like [`fetch_image`], for sites that refuse images requested without their own `Referer`.
+pub async fn fetch_image_with_referer(
+    client: &Client,
+    image_url: &str,
+    referer: Option<&str>,
+) -> crate::error::Result<(Vec<u8>, String)> {
+    let mut request = client
+        .get(image_url)
+        .header("User-Agent", random_user_agent());
+    if let Some(referer) = referer {
+        request = request.header("Referer", referer);
+    }
+    let response = request.send().await.map_err(|e| {
+        crate::error::PanelsError::ScrapeFailed(format!("failed to fetch image: {}", e))
+    })?;
+
+    if !response.status().is_success() {
+        return Err(crate::error::PanelsError::NotFound(
+            "image not found".into(),
+        ));
+    }
+
+    let content_type = response
+        .headers()
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("image/gif")
+        .to_string();
+
+    let bytes = response.bytes().await.map_err(|e| {
+        crate::error::PanelsError::ScrapeFailed(format!("failed to read image bytes: {}", e))
+    })?;
+
+    Ok((bytes.to_vec(), content_type))
+}
+
 async fn fetch_page_inner(
     client: &Client,
     url: &str,
@@ -75,6 +148,40 @@ async fn fetch_page_inner(
     suppress_errors: bool,
     silent_statuses: &[u16],
 ) -> crate::error::Result<Option<PageResponse>> {
+    match fetch(
+        client,
+        url,
+        user_agent,
+        retries,
+        timeout_ms,
+        suppress_errors,
+        silent_statuses,
+    )
+    .await?
+    {
+        Fetched::Page(page) => Ok(Some(page)),
+        Fetched::Missing | Fetched::Failed(_) => Ok(None),
+    }
+}
+
+/// How a page fetch ended, once the retries are used up.
+enum Fetched {
+    Page(PageResponse),
+    /// The server answered 404
+    Missing,
+    /// A timeout, connection error or other failing status
+    Failed(String),
+}
+
+async fn fetch(
+    client: &Client,
+    url: &str,
+    user_agent: &str,
+    retries: u32,
+    timeout_ms: u64,
+    suppress_errors: bool,
+    silent_statuses: &[u16],
+) -> crate::error::Result<Fetched> {
     for attempt in 0..=retries {
         let result = client
             .get(url)
@@ -98,13 +205,13 @@ async fn fetch_page_inner(
                         warn!("Failed to fetch {}: {}", url, status);
                     }
                     if status == 404 {
-                        return Ok(None);
+                        return Ok(Fetched::Missing);
                     }
                     if attempt < retries {
                         tokio::time::sleep(Duration::from_secs(1)).await;
                         continue;
                     }
-                    return Ok(None);
+                    return Ok(Fetched::Failed(format!("status {}", status)));
                 }
 
                 let html = response.text().await.map_err(|e| {
@@ -114,7 +221,7 @@ async fn fetch_page_inner(
                     ))
                 })?;
 
-                return Ok(Some(PageResponse { html, final_url }));
+                return Ok(Fetched::Page(PageResponse { html, final_url }));
             }
             Err(e) => {
                 if !suppress_errors {
@@ -135,11 +242,11 @@ async fn fetch_page_inner(
                     tokio::time::sleep(Duration::from_secs(1)).await;
                     continue;
                 }
-                return Ok(None);
+                return Ok(Fetched::Failed(e.to_string()));
             }
         }
     }
-    Ok(None)
+    Ok(Fetched::Failed("no attempts made".into()))
 }
 
 #[cfg(test)]
@@ -0,0 +1,250 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::imaging::hash::{ImageHashes, Placeholder, find_placeholder};
+
+const HASHES_FILE: &str = "image_hashes.json";
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+/// Bits strips may differ by and still be reported as duplicates. Tighter than placeholder
+/// matching: recurring panels in otherwise different strips land within a few bits.
+pub const DUPLICATE_DISTANCE: u32 = 2;
+
+/// Hashes of the image a strip was served with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StripHash {
+    pub image_url: String,
+    #[serde(flatten)]
+    pub hashes: ImageHashes,
+}
+
+/// Strips of one comic that show the same picture on different dates.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateGroup {
+    pub endpoint: String,
+    pub dates: Vec<String>,
+    pub image_urls: Vec<String>,
+}
+
+type Hashes = BTreeMap<String, BTreeMap<String, StripHash>>;
+
+/// Perceptual hashes of every proxied strip image, by endpoint and date.
+pub struct ImageHashStore {
+    path: Option<PathBuf>,
+    data: RwLock<Hashes>,
+    dirty: AtomicBool,
+}
+
+impl ImageHashStore {
+    pub fn open(data_dir: &str) -> anyhow::Result<Self> {
+        let path = Path::new(data_dir).join(HASHES_FILE);
+        let data: Hashes = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| anyhow::anyhow!("failed to parse {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Hashes::new(),
+            Err(e) => anyhow::bail!("failed to read {}: {}", path.display(), e),
+        };
+        info!(
+            comics = data.len(),
+            strips = data.values().map(BTreeMap::len).sum::<usize>(),
+            "loaded image hashes"
+        );
+        Ok(Self {
+            path: Some(path),
+            data: RwLock::new(data),
+            dirty: AtomicBool::new(false),
+        })
+    }
+
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            data: RwLock::new(Hashes::new()),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    pub fn get(&self, endpoint: &str, date: &str) -> Option<StripHash> {
+        self.data
+            .read()
+            .unwrap()
+            .get(endpoint)
+            .and_then(|dates| dates.get(date))
+            .cloned()
+    }
+
+    pub fn record(&self, endpoint: &str, date: &str, hash: StripHash) {
+        let mut data = self.data.write().unwrap();
+        let dates = data.entry(endpoint.to_string()).or_default();
+        if dates.get(date) == Some(&hash) {
+            return;
+        }
+        dates.insert(date.to_string(), hash);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Groups each comic's strips that are within `max_distance` bits of some other strip in
+    /// the group, on both hashes. Strips showing a placeholder are left out; they are reported as
+    /// missing instead.
+    pub fn duplicates(
+        &self,
+        endpoint: Option<&str>,
+        max_distance: u32,
+        placeholders: &[Placeholder],
+    ) -> Vec<DuplicateGroup> {
+        let data = self.data.read().unwrap();
+        let mut groups = Vec::new();
+        for (comic, dates) in data.iter() {
+            if endpoint.is_some_and(|e| e != comic) {
+                continue;
+            }
+            let strips: Vec<(&String, &StripHash)> = dates
+                .iter()
+                .filter(|(_, s)| find_placeholder(placeholders, &s.hashes).is_none())
+                .collect();
+
+            // Union-find over the strips, joining every pair close enough
+            let mut parent: Vec<usize> = (0..strips.len()).collect();
+            fn root(parent: &mut [usize], mut i: usize) -> usize {
+                while parent[i] != i {
+                    parent[i] = parent[parent[i]];
+                    i = parent[i];
+                }
+                i
+            }
+            for i in 0..strips.len() {
+                for j in i + 1..strips.len() {
+                    if strips[i].1.hashes.within(&strips[j].1.hashes, max_distance) {
+                        let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                        parent[b] = a;
+                    }
+                }
+            }
+
+            let mut members: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+            for i in 0..strips.len() {
+                members.entry(root(&mut parent, i)).or_default().push(i);
+            }
+            for indices in members.into_values().filter(|m| m.len() > 1) {
+                let urls: BTreeSet<&String> =
+                    indices.iter().map(|&i| &strips[i].1.image_url).collect();
+                groups.push(DuplicateGroup {
+                    endpoint: comic.clone(),
+                    dates: indices.iter().map(|&i| strips[i].0.clone()).collect(),
+                    image_urls: urls.into_iter().cloned().collect(),
+                });
+            }
+        }
+        groups
+    }
+
+    pub fn flush(&self) -> anyhow::Result<()> {
+        let Some(ref path) = self.path else {
+            return Ok(());
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let json = serde_json::to_string_pretty(&*self.data.read().unwrap())?;
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, json)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn spawn_flusher(self: &Arc<Self>) {
+        let store = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+            loop {
+                interval.tick().await;
+                let store = Arc::clone(&store);
+                let result = tokio::task::spawn_blocking(move || store.flush()).await;
+                if let Ok(Err(e)) = result {
+                    warn!("failed to write image hashes: {}", e);
+                }
+            }
+        });
+    }
+}
+
+/// The repeated strips among the hashes recorded in `data_dir`, as `duplicates` groups them.
+pub fn find_duplicates(
+    data_dir: &str,
+    endpoint: Option<&str>,
+    max_distance: u32,
+) -> anyhow::Result<Vec<DuplicateGroup>> {
+    let store = ImageHashStore::open(data_dir)?;
+    let placeholders = crate::data::load_placeholders(data_dir)?;
+    Ok(store.duplicates(endpoint, max_distance, &placeholders))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip_hash(url: &str, dhash: u64, phash: u64) -> StripHash {
+        StripHash {
+            image_url: url.to_string(),
+            hashes: ImageHashes { dhash, phash },
+        }
+    }
+
+    #[test]
+    fn groups_repeats_and_skips_placeholders() {
+        let store = ImageHashStore::in_memory();
+        store.record("garfield", "2024-01-01", strip_hash("a", 0xff00, 0xf0f0));
+        store.record("garfield", "2024-01-02", strip_hash("b", 0x1234, 0x5678));
+        store.record("garfield", "2024-03-05", strip_hash("c", 0xff01, 0xf0f0));
+        store.record("garfield", "2024-04-01", strip_hash("d", !0, !0));
+        store.record("garfield", "2024-04-02", strip_hash("e", !0, !0));
+        store.record("peanuts", "2024-01-01", strip_hash("a", 0xff00, 0xf0f0));
+
+        let placeholders = vec![Placeholder {
+            name: "unavailable".into(),
+            hashes: ImageHashes {
+                dhash: !0,
+                phash: !0,
+            },
+            note: None,
+        }];
+        let groups = store.duplicates(None, 2, &placeholders);
+        assert_eq!(
+            groups,
+            vec![DuplicateGroup {
+                endpoint: "garfield".into(),
+                dates: vec!["2024-01-01".into(), "2024-03-05".into()],
+                image_urls: vec!["a".into(), "c".into()],
+            }]
+        );
+        assert!(
+            store
+                .duplicates(Some("peanuts"), 2, &placeholders)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn hashes_survive_flush_and_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().to_str().unwrap();
+
+        let store = ImageHashStore::open(data_dir).unwrap();
+        store.record("garfield", "2024-01-01", strip_hash("a", 1, 2));
+        store.flush().unwrap();
+
+        let reloaded = ImageHashStore::open(data_dir).unwrap();
+        assert_eq!(
+            reloaded.get("garfield", "2024-01-01"),
+            Some(strip_hash("a", 1, 2))
+        );
+        assert_eq!(reloaded.get("garfield", "2024-01-02"), None);
+    }
+}
@@ -0,0 +1,196 @@
+use std::path::{Path, PathBuf};
+
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageFormat};
+
+use crate::catalog::CatalogData;
+use crate::sources::SourceRegistry;
+use crate::validation::badge_endpoints;
+
+pub const BADGE_SIZE: u32 = 512;
+/// Height the strip is scaled to while scoring candidate squares
+const ANALYSIS_HEIGHT: u32 = 128;
+/// Candidate squares are this many steps apart per side length
+const STEPS_PER_SIDE: u32 = 8;
+
+/// Sum of luma differences between neighbouring pixels inside the square at `offset` along
+/// the long axis. Busy squares score high; gutters and empty sky score low.
+fn detail(luma: &image::GrayImage, offset: u32, side: u32, horizontal: bool) -> u64 {
+    let (x0, y0) = if horizontal { (offset, 0) } else { (0, offset) };
+    let mut total = 0u64;
+    for y in y0..y0 + side - 1 {
+        for x in x0..x0 + side - 1 {
+            let p = luma.get_pixel(x, y)[0] as i32;
+            let right = luma.get_pixel(x + 1, y)[0] as i32;
+            let below = luma.get_pixel(x, y + 1)[0] as i32;
+            total += ((p - right).abs() + (p - below).abs()) as u64;
+        }
+    }
+    total
+}
+
+/// Picks the square spanning the strip's short side with the most detail, returned as
+/// `(x, y, side)` in source pixels.
+pub fn representative_square(image: &DynamicImage) -> (u32, u32, u32) {
+    let (width, height) = image.dimensions();
+    let side = width.min(height);
+    let horizontal = width >= height;
+    let long = width.max(height);
+    if long == side {
+        return (0, 0, side);
+    }
+
+    let scale = ANALYSIS_HEIGHT as f64 / side as f64;
+    let small = image
+        .resize_exact(
+            ((width as f64 * scale).round() as u32).max(1),
+            ((height as f64 * scale).round() as u32).max(1),
+            FilterType::Triangle,
+        )
+        .to_luma8();
+    let small_side = small.width().min(small.height());
+    let small_long = small.width().max(small.height());
+    let step = (small_side / STEPS_PER_SIDE).max(1);
+
+    let mut best = (0, 0);
+    let mut offset = 0;
+    loop {
+        let score = detail(&small, offset, small_side, horizontal);
+        if score > best.1 {
+            best = (offset, score);
+        }
+        if offset + small_side >= small_long {
+            break;
+        }
+        offset = (offset + step).min(small_long - small_side);
+    }
+
+    let start = ((best.0 as f64 / scale).round() as u32).min(long - side);
+    if horizontal {
+        (start, 0, side)
+    } else {
+        (0, start, side)
+    }
+}
+
+/// Crops the most detailed square out of a strip and scales it to `size`, as PNG.
+pub fn badge_from_strip(bytes: &[u8], size: u32) -> anyhow::Result<Vec<u8>> {
+    let strip = super::decode(bytes)?;
+    let (x, y, side) = representative_square(&strip);
+    let badge = strip
+        .crop_imm(x, y, side, side)
+        .resize_exact(size, size, FilterType::Lanczos3);
+    super::encode(&badge, ImageFormat::Png)
+}
+
+/// The comics a badge run draws for, and the badges in `data/badges` no comic uses.
+#[derive(Debug, Default)]
+pub struct BadgePlan {
+    pub missing: Vec<String>,
+    pub orphans: Vec<String>,
+}
+
+/// Plans badges for `only`, or for every available comic without one when it's empty.
+pub fn plan_badges(
+    data_dir: &str,
+    catalog: &CatalogData,
+    only: Vec<String>,
+) -> anyhow::Result<BadgePlan> {
+    let existing = badge_endpoints(data_dir)?;
+    let orphans = existing
+        .iter()
+        .filter(|e| catalog.comic(e.as_str()).is_none())
+        .cloned()
+        .collect();
+    let missing = if only.is_empty() {
+        catalog
+            .comics
+            .iter()
+            .filter(|c| c.available && !existing.contains(&c.endpoint))
+            .map(|c| c.endpoint.clone())
+            .collect()
+    } else {
+        for endpoint in &only {
+            anyhow::ensure!(
+                catalog.comic(endpoint).is_some(),
+                "{} is not in comics.json",
+                endpoint
+            );
+        }
+        only
+    };
+    Ok(BadgePlan { missing, orphans })
+}
+
+/// Draws the badge of `endpoint` from its latest strip into `path`, returning the strip's date.
+pub async fn generate_badge(
+    sources: &SourceRegistry,
+    endpoint: &str,
+    size: u32,
+    path: &Path,
+) -> anyhow::Result<String> {
+    let source = sources
+        .find(endpoint)
+        .ok_or_else(|| anyhow::anyhow!("no source handles it"))?;
+    let strip = source
+        .fetch_latest(endpoint)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("no latest strip"))?;
+    let (bytes, _) = source.proxy_image(&strip.image_url).await?;
+    let png = tokio::task::spawn_blocking(move || badge_from_strip(&bytes, size)).await??;
+    std::fs::write(path, png)?;
+    Ok(strip.date)
+}
+
+/// Draws a badge for each of `endpoints` into `data/badges`, one after another, with where
+/// it went and the date of its strip or why it failed.
+pub async fn generate_badges(
+    sources: &SourceRegistry,
+    data_dir: &str,
+    endpoints: &[String],
+    size: u32,
+) -> anyhow::Result<Vec<(String, anyhow::Result<(PathBuf, String)>)>> {
+    anyhow::ensure!(size > 0, "--size must be at least 1");
+    let dir = Path::new(data_dir).join("badges");
+    std::fs::create_dir_all(&dir)?;
+    let mut results = Vec::new();
+    for endpoint in endpoints {
+        let path = dir.join(format!("{}.png", endpoint));
+        let result = generate_badge(sources, endpoint, size, &path).await;
+        results.push((endpoint.clone(), result.map(|date| (path, date))));
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imaging::testing::strip;
+
+    #[test]
+    fn picks_the_busiest_panel() {
+        // Three panels side by side; only the last one has anything drawn in it
+        let img = strip(
+            900,
+            300,
+            &[
+                (610, 20, 20, 260),
+                (660, 40, 40, 40),
+                (720, 120, 30, 100),
+                (800, 60, 50, 20),
+            ],
+        );
+        let (x, y, side) = representative_square(&img);
+        assert_eq!((y, side), (0, 300));
+        assert!(x >= 450, "picked x = {}", x);
+    }
+
+    #[test]
+    fn writes_square_png() {
+        let img = strip(400, 100, &[(10, 10, 30, 30)]);
+        let png = crate::imaging::encode(&img, ImageFormat::Png).unwrap();
+        let badge = badge_from_strip(&png, 64).unwrap();
+        let decoded = crate::imaging::decode(&badge).unwrap();
+        assert_eq!(decoded.dimensions(), (64, 64));
+    }
+}
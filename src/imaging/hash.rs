@@ -0,0 +1,185 @@
+use std::f64::consts::PI;
+use std::path::Path;
+
+use image::DynamicImage;
+use image::imageops::FilterType;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Bits two hashes may differ by and still count as the same picture
+pub const DHASH_TOLERANCE: u32 = 5;
+pub const PHASH_TOLERANCE: u32 = 8;
+
+const PHASH_SIZE: usize = 32;
+const PHASH_LOW: usize = 8;
+
+/// Perceptual hashes of an image. Both survive re-encoding and resizing; dHash follows the
+/// gradients, pHash the low frequencies, so requiring both to match keeps false positives
+/// down. Serialized as 16-digit hex so JSON readers don't lose precision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ImageHashes {
+    #[serde(serialize_with = "to_hex", deserialize_with = "from_hex")]
+    pub dhash: u64,
+    #[serde(serialize_with = "to_hex", deserialize_with = "from_hex")]
+    pub phash: u64,
+}
+
+fn to_hex<S: Serializer>(value: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{:016x}", value))
+}
+
+fn from_hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    let hex = String::deserialize(deserializer)?;
+    u64::from_str_radix(&hex, 16).map_err(serde::de::Error::custom)
+}
+
+impl ImageHashes {
+    pub fn of(image: &DynamicImage) -> Self {
+        Self {
+            dhash: dhash(image),
+            phash: phash(image),
+        }
+    }
+
+    pub fn distance(&self, other: &ImageHashes) -> (u32, u32) {
+        (
+            (self.dhash ^ other.dhash).count_ones(),
+            (self.phash ^ other.phash).count_ones(),
+        )
+    }
+
+    /// Whether both hashes are within `max_distance` bits of `other`'s.
+    pub fn within(&self, other: &ImageHashes, max_distance: u32) -> bool {
+        let (d, p) = self.distance(other);
+        d <= max_distance && p <= max_distance
+    }
+
+    /// Whether this is perceptually the same picture as `other`.
+    pub fn matches(&self, other: &ImageHashes) -> bool {
+        let (d, p) = self.distance(other);
+        d <= DHASH_TOLERANCE && p <= PHASH_TOLERANCE
+    }
+}
+
+/// A known placeholder image, such as a "strip not available" notice. Strips whose image
+/// matches one are treated as missing.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Placeholder {
+    pub name: String,
+    #[serde(flatten)]
+    pub hashes: ImageHashes,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+impl Placeholder {
+    /// The entry for a saved placeholder image, named after the file.
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let bytes = std::fs::read(path)
+            .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+        let image =
+            super::decode(&bytes).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+        let name = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.display().to_string());
+        Ok(Self {
+            name,
+            hashes: ImageHashes::of(&image),
+            note: None,
+        })
+    }
+}
+
+/// The first placeholder `hashes` matches, if any.
+pub fn find_placeholder<'a>(
+    placeholders: &'a [Placeholder],
+    hashes: &ImageHashes,
+) -> Option<&'a Placeholder> {
+    placeholders.iter().find(|p| p.hashes.matches(hashes))
+}
+
+/// Difference hash: one bit per neighbouring pixel pair of a 9x8 greyscale thumbnail.
+pub fn dhash(image: &DynamicImage) -> u64 {
+    let small = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let left = small.get_pixel(x, y)[0];
+            let right = small.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | (left > right) as u64;
+        }
+    }
+    hash
+}
+
+/// DCT hash: one bit per low-frequency coefficient of a 32x32 greyscale thumbnail, set when
+/// the coefficient is above the median.
+pub fn phash(image: &DynamicImage) -> u64 {
+    let n = PHASH_SIZE;
+    let small = image
+        .resize_exact(n as u32, n as u32, FilterType::Triangle)
+        .to_luma8();
+    let pixels: Vec<f64> = small.pixels().map(|p| p[0] as f64).collect();
+
+    // Only the low frequencies are needed, so the separable DCT is cut short on both passes
+    let cos: Vec<f64> = (0..PHASH_LOW * n)
+        .map(|i| {
+            let (u, x) = (i / n, i % n);
+            ((2 * x + 1) as f64 * u as f64 * PI / (2 * n) as f64).cos()
+        })
+        .collect();
+    let mut rows = vec![0.0; n * PHASH_LOW];
+    for y in 0..n {
+        for u in 0..PHASH_LOW {
+            rows[y * PHASH_LOW + u] = (0..n).map(|x| pixels[y * n + x] * cos[u * n + x]).sum();
+        }
+    }
+    let mut low = Vec::with_capacity(PHASH_LOW * PHASH_LOW);
+    for v in 0..PHASH_LOW {
+        for u in 0..PHASH_LOW {
+            low.push(
+                (0..n)
+                    .map(|y| rows[y * PHASH_LOW + u] * cos[v * n + y])
+                    .sum::<f64>(),
+            );
+        }
+    }
+
+    // The DC term only tracks overall brightness, so it stays out of the median
+    let mut sorted = low[1..].to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let median = sorted[sorted.len() / 2];
+    low.iter()
+        .fold(0u64, |hash, &c| (hash << 1) | (c > median) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imaging::testing::strip;
+
+    #[test]
+    fn survives_resizing_but_tells_strips_apart() {
+        let original = strip(600, 200, &[(20, 20, 100, 160), (300, 50, 200, 40)]);
+        let smaller = original.resize_exact(300, 100, FilterType::Lanczos3);
+        let other = strip(600, 200, &[(400, 20, 150, 150), (30, 120, 100, 60)]);
+
+        let a = ImageHashes::of(&original);
+        assert!(a.matches(&ImageHashes::of(&smaller)));
+        assert!(!a.matches(&ImageHashes::of(&other)));
+    }
+
+    #[test]
+    fn serializes_as_hex() {
+        let hashes = ImageHashes {
+            dhash: 0xdead_beef,
+            phash: u64::MAX,
+        };
+        let json = serde_json::to_string(&hashes).unwrap();
+        assert_eq!(
+            json,
+            r#"{"dhash":"00000000deadbeef","phash":"ffffffffffffffff"}"#
+        );
+        assert_eq!(serde_json::from_str::<ImageHashes>(&json).unwrap(), hashes);
+    }
+}
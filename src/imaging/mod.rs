@@ -0,0 +1,44 @@
+pub mod badge;
+pub mod hash;
+pub mod panels;
+pub mod resize;
+
+use std::io::Cursor;
+
+use image::{DynamicImage, ImageFormat};
+
+/// Decodes an image whatever its format, going by the bytes rather than the content type
+/// upstream claimed.
+pub fn decode(bytes: &[u8]) -> anyhow::Result<DynamicImage> {
+    let reader = image::ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+    let format = reader
+        .format()
+        .ok_or_else(|| anyhow::anyhow!("unrecognized image format"))?;
+    reader
+        .decode()
+        .map_err(|e| anyhow::anyhow!("failed to decode {:?} image: {}", format, e))
+}
+
+pub fn encode(image: &DynamicImage, format: ImageFormat) -> anyhow::Result<Vec<u8>> {
+    let mut out = Cursor::new(Vec::new());
+    image.write_to(&mut out, format)?;
+    Ok(out.into_inner())
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use image::{DynamicImage, Rgb, RgbImage};
+
+    /// A white strip with solid black rectangles drawn at `(x, y, width, height)`.
+    pub fn strip(width: u32, height: u32, boxes: &[(u32, u32, u32, u32)]) -> DynamicImage {
+        let mut img = RgbImage::from_pixel(width, height, Rgb([255, 255, 255]));
+        for &(x, y, w, h) in boxes {
+            for py in y..(y + h).min(height) {
+                for px in x..(x + w).min(width) {
+                    img.put_pixel(px, py, Rgb([0, 0, 0]));
+                }
+            }
+        }
+        DynamicImage::ImageRgb8(img)
+    }
+}
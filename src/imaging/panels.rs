@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+
+use image::{DynamicImage, GenericImageView, RgbImage};
+use serde::Serialize;
+
+/// Largest per-channel difference from the background that still counts as background
+const TOLERANCE: i16 = 40;
+/// Share of a line that has to be background for the line to be a gutter
+const GUTTER_FILL: f64 = 0.98;
+/// Nested row/column splits to try; enough for rows of panels inside Sunday tiers
+const MAX_DEPTH: usize = 4;
+/// Leaves smaller than this share of the image are specks, not panels
+const MIN_PANEL_AREA: f64 = 0.01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub struct PanelBox {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PanelLayout {
+    pub width: u32,
+    pub height: u32,
+    /// In reading order: tiers top to bottom, panels left to right
+    pub panels: Vec<PanelBox>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Axis {
+    /// Split on horizontal gutters into tiers
+    Rows,
+    /// Split on vertical gutters into panels side by side
+    Columns,
+}
+
+struct Mask {
+    width: u32,
+    height: u32,
+    background: Vec<bool>,
+}
+
+/// The most common colour around the border, where strips almost always show their
+/// background.
+fn background_colour(image: &RgbImage) -> [u8; 3] {
+    let (width, height) = image.dimensions();
+    let mut buckets: HashMap<[u8; 3], (u32, [u64; 3])> = HashMap::new();
+    let mut add = |x: u32, y: u32| {
+        let p = image.get_pixel(x, y).0;
+        let entry = buckets
+            .entry([p[0] >> 4, p[1] >> 4, p[2] >> 4])
+            .or_insert((0, [0; 3]));
+        entry.0 += 1;
+        for (sum, channel) in entry.1.iter_mut().zip(p) {
+            *sum += channel as u64;
+        }
+    };
+    for x in 0..width {
+        add(x, 0);
+        add(x, height - 1);
+    }
+    for y in 0..height {
+        add(0, y);
+        add(width - 1, y);
+    }
+    let (count, sums) = buckets
+        .into_values()
+        .max_by_key(|(count, _)| *count)
+        .unwrap_or((1, [255; 3]));
+    [
+        (sums[0] / count as u64) as u8,
+        (sums[1] / count as u64) as u8,
+        (sums[2] / count as u64) as u8,
+    ]
+}
+
+impl Mask {
+    fn new(image: &RgbImage) -> Self {
+        let background = background_colour(image);
+        let near = |p: &image::Rgb<u8>| {
+            (0..3).all(|c| (p[c] as i16 - background[c] as i16).abs() <= TOLERANCE)
+        };
+        Self {
+            width: image.width(),
+            height: image.height(),
+            background: image.pixels().map(near).collect(),
+        }
+    }
+
+    fn is_background(&self, x: u32, y: u32) -> bool {
+        self.background[(y * self.width + x) as usize]
+    }
+
+    /// Whether line `at` (a row for `Rows`, a column for `Columns`) inside `rect` is gutter.
+    fn is_gutter(&self, rect: PanelBox, axis: Axis, at: u32) -> bool {
+        let (len, background) = match axis {
+            Axis::Rows => (
+                rect.width,
+                (rect.x..rect.x + rect.width)
+                    .filter(|&x| self.is_background(x, at))
+                    .count(),
+            ),
+            Axis::Columns => (
+                rect.height,
+                (rect.y..rect.y + rect.height)
+                    .filter(|&y| self.is_background(at, y))
+                    .count(),
+            ),
+        };
+        background as f64 >= len as f64 * GUTTER_FILL
+    }
+
+    /// Shrinks `rect` to the lines that are not gutter. `None` if nothing is left.
+    fn trim(&self, rect: PanelBox) -> Option<PanelBox> {
+        let rows: Vec<u32> = (rect.y..rect.y + rect.height)
+            .filter(|&y| !self.is_gutter(rect, Axis::Rows, y))
+            .collect();
+        let columns: Vec<u32> = (rect.x..rect.x + rect.width)
+            .filter(|&x| !self.is_gutter(rect, Axis::Columns, x))
+            .collect();
+        let (top, bottom) = (*rows.first()?, *rows.last()?);
+        let (left, right) = (*columns.first()?, *columns.last()?);
+        Some(PanelBox {
+            x: left,
+            y: top,
+            width: right - left + 1,
+            height: bottom - top + 1,
+        })
+    }
+
+    /// Splits `rect` at gutters along `axis`. Gutters thinner than a fraction of the image
+    /// are ignored so lettering and hatching don't cut panels apart.
+    fn split(&self, rect: PanelBox, axis: Axis) -> Vec<PanelBox> {
+        let (start, len, full) = match axis {
+            Axis::Rows => (rect.y, rect.height, self.height),
+            Axis::Columns => (rect.x, rect.width, self.width),
+        };
+        let min_gutter = (full / 200).max(2);
+
+        let mut parts = Vec::new();
+        let mut part_start = start;
+        let mut gutter_run = 0;
+        for at in start..start + len {
+            if self.is_gutter(rect, axis, at) {
+                gutter_run += 1;
+                continue;
+            }
+            if gutter_run >= min_gutter && at - gutter_run > part_start {
+                parts.push((part_start, at - gutter_run));
+                part_start = at;
+            } else if gutter_run >= min_gutter {
+                part_start = at;
+            }
+            gutter_run = 0;
+        }
+        parts.push((part_start, start + len - gutter_run));
+
+        parts
+            .into_iter()
+            .filter(|(from, to)| to > from)
+            .map(|(from, to)| match axis {
+                Axis::Rows => PanelBox {
+                    y: from,
+                    height: to - from,
+                    ..rect
+                },
+                Axis::Columns => PanelBox {
+                    x: from,
+                    width: to - from,
+                    ..rect
+                },
+            })
+            .collect()
+    }
+
+    fn cut(&self, rect: PanelBox, depth: usize) -> Vec<PanelBox> {
+        let Some(rect) = self.trim(rect) else {
+            return Vec::new();
+        };
+        if depth < MAX_DEPTH {
+            for axis in [Axis::Rows, Axis::Columns] {
+                let parts = self.split(rect, axis);
+                if parts.len() > 1 {
+                    return parts
+                        .into_iter()
+                        .flat_map(|part| self.cut(part, depth + 1))
+                        .collect();
+                }
+            }
+        }
+        vec![rect]
+    }
+}
+
+/// Finds panels by recursively cutting the strip along background-coloured gutters. A strip
+/// without gutters comes back as one panel covering its content.
+pub fn detect_panels(image: &DynamicImage) -> PanelLayout {
+    let (width, height) = image.dimensions();
+    let whole = PanelBox {
+        x: 0,
+        y: 0,
+        width,
+        height,
+    };
+    if width == 0 || height == 0 {
+        return PanelLayout {
+            width,
+            height,
+            panels: Vec::new(),
+        };
+    }
+
+    let mask = Mask::new(&image.to_rgb8());
+    let min_area = (width as f64 * height as f64 * MIN_PANEL_AREA) as u64;
+    let mut panels: Vec<PanelBox> = mask
+        .cut(whole, 0)
+        .into_iter()
+        .filter(|p| p.width as u64 * p.height as u64 >= min_area)
+        .collect();
+    if panels.is_empty() {
+        panels.push(whole);
+    }
+    PanelLayout {
+        width,
+        height,
+        panels,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imaging::testing::strip;
+
+    #[test]
+    fn finds_panels_in_a_daily() {
+        let img = strip(
+            620,
+            200,
+            &[(10, 10, 190, 180), (215, 10, 190, 180), (420, 10, 190, 180)],
+        );
+        let layout = detect_panels(&img);
+        assert_eq!(
+            layout.panels,
+            vec![
+                PanelBox {
+                    x: 10,
+                    y: 10,
+                    width: 190,
+                    height: 180
+                },
+                PanelBox {
+                    x: 215,
+                    y: 10,
+                    width: 190,
+                    height: 180
+                },
+                PanelBox {
+                    x: 420,
+                    y: 10,
+                    width: 190,
+                    height: 180
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_tiers_top_to_bottom() {
+        // A title band over a tier of two panels, over one wide panel
+        let img = strip(
+            400,
+            420,
+            &[
+                (10, 10, 380, 60),
+                (10, 90, 180, 150),
+                (210, 90, 180, 150),
+                (10, 260, 380, 150),
+            ],
+        );
+        let panels = detect_panels(&img).panels;
+        assert_eq!(panels.len(), 4);
+        assert_eq!((panels[1].x, panels[1].y), (10, 90));
+        assert_eq!((panels[2].x, panels[2].y), (210, 90));
+        assert_eq!((panels[3].y, panels[3].width), (260, 380));
+    }
+
+    #[test]
+    fn strip_without_gutters_is_one_panel() {
+        let img = strip(300, 100, &[(0, 0, 300, 100)]);
+        let panels = detect_panels(&img).panels;
+        assert_eq!(panels.len(), 1);
+        assert_eq!((panels[0].width, panels[0].height), (300, 100));
+    }
+}
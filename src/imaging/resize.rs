@@ -0,0 +1,246 @@
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageFormat};
+
+use super::panels::PanelBox;
+
+/// Widths and heights a variant may be resized to. Requests snap up to the next preset so
+/// the variant cache holds a bounded number of entries per image.
+pub const SIZE_PRESETS: &[u32] = &[160, 320, 480, 640, 800, 1024, 1280, 1600, 2048];
+
+const JPEG_QUALITY: u8 = 82;
+const AVIF_SPEED: u8 = 8;
+const AVIF_QUALITY: u8 = 70;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Fit {
+    /// Scale to fit inside the box, keeping the aspect ratio
+    #[default]
+    Contain,
+    /// Scale to fill the box, cropping the overflow from the center
+    Cover,
+    /// Stretch to exactly the box
+    Fill,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    Webp,
+    Avif,
+}
+
+impl OutputFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "image/png",
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::Webp => "image/webp",
+            OutputFormat::Avif => "image/avif",
+        }
+    }
+
+    /// AVIF if the client advertises it in `Accept`. WebP is never picked this way: the
+    /// encoder is lossless only, so a WebP strip can come out larger than its JPEG or GIF.
+    pub fn from_accept(accept: &str) -> Option<Self> {
+        let accepts = |mime: &str| {
+            accept.split(',').any(|part| {
+                let mut params = part.split(';');
+                let matches = params.next().is_some_and(|m| m.trim() == mime);
+                let refused = params.any(|p| {
+                    p.trim()
+                        .strip_prefix("q=")
+                        .and_then(|q| q.parse::<f32>().ok())
+                        .is_some_and(|q| q == 0.0)
+                });
+                matches && !refused
+            })
+        };
+        accepts("image/avif").then_some(OutputFormat::Avif)
+    }
+
+    /// Same family as the source; GIF strips become PNG since they are rarely animated and
+    /// resizing breaks their palette anyway.
+    fn matching(source: Option<ImageFormat>) -> Self {
+        match source {
+            Some(ImageFormat::Jpeg) => OutputFormat::Jpeg,
+            Some(ImageFormat::WebP) => OutputFormat::Webp,
+            Some(ImageFormat::Avif) => OutputFormat::Avif,
+            _ => OutputFormat::Png,
+        }
+    }
+}
+
+/// Smallest preset at least `requested`, or the largest preset.
+pub fn snap_to_preset(requested: u32) -> u32 {
+    SIZE_PRESETS
+        .iter()
+        .copied()
+        .find(|&p| p >= requested)
+        .unwrap_or(SIZE_PRESETS[SIZE_PRESETS.len() - 1])
+}
+
+/// A variant to produce. Sizes are already snapped to presets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Variant {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fit: Fit,
+    /// `None` keeps the source's format family
+    pub format: Option<OutputFormat>,
+    /// Region to cut out before resizing, such as a single panel
+    pub crop: Option<PanelBox>,
+}
+
+impl Variant {
+    pub fn new(
+        width: Option<u32>,
+        height: Option<u32>,
+        fit: Fit,
+        format: Option<OutputFormat>,
+    ) -> Self {
+        Self {
+            width: width.map(snap_to_preset),
+            height: height.map(snap_to_preset),
+            fit,
+            format,
+            crop: None,
+        }
+    }
+
+    pub fn cache_key(&self, image_url: &str) -> String {
+        let size = |s: Option<u32>| s.map(|v| v.to_string()).unwrap_or_default();
+        let crop = self
+            .crop
+            .map(|c| format!("|{},{},{}x{}", c.x, c.y, c.width, c.height))
+            .unwrap_or_default();
+        format!(
+            "{}|{}x{}|{:?}|{:?}{}",
+            image_url,
+            size(self.width),
+            size(self.height),
+            self.fit,
+            self.format,
+            crop
+        )
+    }
+}
+
+fn resize(image: DynamicImage, variant: &Variant) -> DynamicImage {
+    let (width, height) = image.dimensions();
+    let (box_w, box_h) = match (variant.width, variant.height) {
+        (None, None) => return image,
+        (Some(w), None) => (w, u32::MAX),
+        (None, Some(h)) => (u32::MAX, h),
+        (Some(w), Some(h)) => (w, h),
+    };
+    // Never upscale: the box shrinks to the source where the source is smaller
+    let box_w = box_w.min(width);
+    let box_h = box_h.min(height);
+
+    let only_one_side = variant.width.is_none() || variant.height.is_none();
+    match variant.fit {
+        _ if only_one_side => image.resize(box_w, box_h, FilterType::Lanczos3),
+        Fit::Contain => image.resize(box_w, box_h, FilterType::Lanczos3),
+        Fit::Cover => image.resize_to_fill(box_w, box_h, FilterType::Lanczos3),
+        Fit::Fill => image.resize_exact(box_w, box_h, FilterType::Lanczos3),
+    }
+}
+
+fn encode(image: &DynamicImage, format: OutputFormat) -> anyhow::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    match format {
+        OutputFormat::Png => return super::encode(image, ImageFormat::Png),
+        // The WebP encoder is lossless only
+        OutputFormat::Webp => return super::encode(image, ImageFormat::WebP),
+        OutputFormat::Jpeg => {
+            image
+                .to_rgb8()
+                .write_with_encoder(JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY))?;
+        }
+        OutputFormat::Avif => {
+            image
+                .to_rgba8()
+                .write_with_encoder(AvifEncoder::new_with_speed_quality(
+                    &mut out,
+                    AVIF_SPEED,
+                    AVIF_QUALITY,
+                ))?;
+        }
+    }
+    Ok(out)
+}
+
+/// Crops, resizes and re-encodes `bytes`. Returns the encoded variant and its content type.
+pub fn transform(bytes: &[u8], variant: &Variant) -> anyhow::Result<(Vec<u8>, &'static str)> {
+    let source_format = image::guess_format(bytes).ok();
+    let mut image = super::decode(bytes)?;
+    if let Some(crop) = variant.crop {
+        anyhow::ensure!(
+            crop.x + crop.width <= image.width() && crop.y + crop.height <= image.height(),
+            "crop falls outside the {}x{} image",
+            image.width(),
+            image.height()
+        );
+        image = image.crop_imm(crop.x, crop.y, crop.width, crop.height);
+    }
+    let format = variant
+        .format
+        .unwrap_or_else(|| OutputFormat::matching(source_format));
+    let resized = resize(image, variant);
+    Ok((encode(&resized, format)?, format.content_type()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imaging::testing::strip;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        crate::imaging::encode(&strip(width, height, &[(0, 0, 10, 10)]), ImageFormat::Png).unwrap()
+    }
+
+    fn dimensions(bytes: &[u8]) -> (u32, u32) {
+        crate::imaging::decode(bytes).unwrap().dimensions()
+    }
+
+    #[test]
+    fn sizes_snap_to_presets() {
+        assert_eq!(snap_to_preset(1), 160);
+        assert_eq!(snap_to_preset(320), 320);
+        assert_eq!(snap_to_preset(321), 480);
+        assert_eq!(snap_to_preset(100_000), 2048);
+    }
+
+    #[test]
+    fn negotiates_from_accept() {
+        let chrome = "image/avif,image/webp,image/apng,image/*,*/*;q=0.8";
+        assert_eq!(OutputFormat::from_accept(chrome), Some(OutputFormat::Avif));
+        assert_eq!(OutputFormat::from_accept("image/webp,*/*"), None);
+        assert_eq!(
+            OutputFormat::from_accept("image/avif;q=0, image/webp"),
+            None
+        );
+        assert_eq!(OutputFormat::from_accept("image/*,*/*;q=0.8"), None);
+    }
+
+    #[test]
+    fn resizes_within_the_box_without_upscaling() {
+        let source = png(1200, 400);
+        let contain = Variant::new(Some(600), Some(600), Fit::Contain, None);
+        let (bytes, content_type) = transform(&source, &contain).unwrap();
+        assert_eq!(content_type, "image/png");
+        assert_eq!(dimensions(&bytes), (640, 213));
+
+        let cover = Variant::new(Some(320), Some(320), Fit::Cover, Some(OutputFormat::Jpeg));
+        let (bytes, content_type) = transform(&source, &cover).unwrap();
+        assert_eq!(content_type, "image/jpeg");
+        assert_eq!(dimensions(&bytes), (320, 320));
+
+        let wide = Variant::new(Some(2048), None, Fit::Contain, Some(OutputFormat::Webp));
+        let (bytes, _) = transform(&source, &wide).unwrap();
+        assert_eq!(dimensions(&bytes), (1200, 400));
+    }
+}
@@ -1,20 +1,69 @@
+pub mod bookmarks;
 pub mod cache;
+pub mod catalog;
+pub mod catalog_sync;
 pub mod config;
 pub mod data;
+pub mod directory;
 pub mod error;
+pub mod export;
+pub mod feed;
+pub mod html;
 pub mod http_client;
+pub mod image_hashes;
+pub mod imaging;
+pub mod mirror;
 pub mod models;
+pub mod recommend;
 pub mod routes;
+pub mod search;
 pub mod sources;
+pub mod taxonomy;
+pub mod usage;
+pub mod validation;
 
-use std::collections::HashMap;
+use std::sync::Arc;
 
-use models::Comic;
+use bookmarks::BookmarkStore;
+use cache::Caches;
+use catalog::Catalog;
+use image_hashes::ImageHashStore;
+use search::SearchIndex;
 use sources::SourceRegistry;
+use usage::UsageStats;
 
 pub struct AppState {
     pub config: config::PanelsConfig,
-    pub comics: Vec<Comic>,
-    pub tags: HashMap<String, Vec<String>>,
+    pub catalog: Arc<Catalog>,
     pub sources: SourceRegistry,
+    pub caches: Caches,
+    pub bookmarks: BookmarkStore,
+    pub search: Arc<SearchIndex>,
+    pub usage: Arc<UsageStats>,
+    pub image_hashes: Arc<ImageHashStore>,
+}
+
+impl AppState {
+    pub fn source_name(&self, endpoint: &str) -> String {
+        self.catalog
+            .get()
+            .comic(endpoint)
+            .map(|c| c.source.clone())
+            .unwrap_or_else(|| endpoint.to_string())
+    }
+
+    /// Whether a request runs in safe mode: server-wide, or asked for with `safe=1`.
+    pub fn safe_mode(&self, requested: Option<&str>) -> bool {
+        self.config.safe_mode || matches!(requested, Some("1" | "true" | "yes"))
+    }
+
+    /// Endpoints missing from the catalog count as safe.
+    pub fn is_safe(&self, endpoint: &str) -> bool {
+        let catalog = self.catalog.get();
+        let tags = catalog.tags.get(endpoint).map(Vec::as_slice).unwrap_or(&[]);
+        match catalog.comic(endpoint) {
+            Some(comic) => catalog.taxonomy.is_safe(comic, tags),
+            None => true,
+        }
+    }
 }
@@ -1,19 +1,23 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use clap::Parser;
 use panels::AppState;
+use panels::bookmarks::BookmarkStore;
 use panels::cache::Caches;
+use panels::catalog::Catalog;
 use panels::config::PanelsConfig;
-use panels::data;
 use panels::http_client;
+use panels::image_hashes::ImageHashStore;
+use panels::mirror::MirrorStore;
+use panels::mirror::sync::{SyncOptions, spawn_syncer};
 use panels::routes;
-use panels::sources::SourceRegistry;
-use panels::sources::comicsrss::ComicsRssSource;
-use panels::sources::dilbert::DilbertSource;
-use panels::sources::gocomics::GoComicsSource;
-use panels::sources::phd::PhdSource;
-use panels::sources::xkcd::XkcdSource;
-use tracing::info;
+use panels::search::SearchIndex;
+use panels::sources::mirror::MirrorSource;
+use panels::sources::{self, SourceRegistry};
+use panels::usage::UsageStats;
+use panels::validation::validate_data_dir;
+use tracing::{info, warn};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -27,33 +31,94 @@ async fn main() -> anyhow::Result<()> {
     let config = PanelsConfig::parse();
     info!(port = config.port, data_dir = %config.data_dir, "starting panels");
 
-    let comics = data::load_comics(&config.data_dir)?;
-    info!(count = comics.len(), "loaded comics directory");
+    if config.strict_catalog {
+        let report = validate_data_dir(&config.data_dir);
+        for issue in &report.issues {
+            warn!("catalog: {}", issue);
+        }
+        anyhow::ensure!(
+            report.passed(true),
+            "catalog validation failed with {} errors and {} warnings",
+            report.errors(),
+            report.warnings()
+        );
+    }
 
-    let tags = data::load_tags(&config.data_dir)?;
-    info!(count = tags.len(), "loaded tags");
+    let catalog = Arc::new(Catalog::load(&config.data_dir)?);
+    if config.catalog_poll_secs > 0 {
+        catalog.spawn_watcher(Duration::from_secs(config.catalog_poll_secs));
+    }
+
+    let bookmarks = BookmarkStore::open(&config.data_dir)?;
+    let search = Arc::new(SearchIndex::open(&config.data_dir)?);
+    search.spawn_committer();
+    let usage = Arc::new(UsageStats::open(&config.data_dir)?);
+    usage.spawn_flusher();
+    let image_hashes = Arc::new(ImageHashStore::open(&config.data_dir)?);
+    image_hashes.spawn_flusher();
 
     let client = http_client::build_client();
-    let caches = Caches::new(config.strip_cache_max, config.strip_cache_ttl_secs);
-
-    let gocomics = GoComicsSource::new(client.clone(), comics.clone(), caches.clone());
-    let dilbert = DilbertSource::new(client.clone(), &config.data_dir);
-    let xkcd = XkcdSource::new(client.clone(), caches.clone());
-    let phd = PhdSource::new(client.clone(), caches.clone());
-    let comicsrss = ComicsRssSource::new(client.clone(), comics.clone(), caches);
-    let sources = SourceRegistry::new(vec![
-        Box::new(gocomics),
-        Box::new(dilbert),
-        Box::new(xkcd),
-        Box::new(phd),
-        Box::new(comicsrss),
-    ]);
+    let caches = Caches::new(
+        config.strip_cache_max,
+        config.strip_cache_ttl_secs,
+        config.image_cache_mb * 1024 * 1024,
+    );
+    let mut sources =
+        SourceRegistry::standard(client.clone(), Arc::clone(&catalog), caches.clone());
+    if !config.mirror.is_empty() {
+        let store = Arc::new(MirrorStore::open(&config.data_dir, &config.mirror)?);
+        if config.mirror_sync_secs > 0 {
+            let options = SyncOptions {
+                since: config.mirror_since,
+                ..SyncOptions::default()
+            };
+            // A registry of its own so syncing never reads back from the mirror
+            let live = SourceRegistry::standard(client, Arc::clone(&catalog), caches.clone());
+            spawn_syncer(
+                Arc::clone(&store),
+                live,
+                Arc::clone(&catalog),
+                options,
+                Duration::from_secs(config.mirror_sync_secs),
+            );
+        }
+        let mirror = MirrorSource::new(store);
+        sources = if config.mirror_offline {
+            sources.prepend(Box::new(mirror))
+        } else {
+            sources.chain_ahead(Arc::new(mirror))
+        };
+    }
 
+    let flush_usage = Arc::clone(&usage);
+    let flush_hashes = Arc::clone(&image_hashes);
     let state = Arc::new(AppState {
         config: config.clone(),
-        comics,
-        tags,
+        catalog,
         sources,
+        caches,
+        bookmarks,
+        search,
+        usage,
+        image_hashes,
+    });
+
+    if state.config.history_poll_secs > 0 {
+        sources::spawn_poller(
+            Arc::clone(&state),
+            Duration::from_secs(state.config.history_poll_secs),
+        );
+    }
+
+    let seed_state = Arc::clone(&state);
+    tokio::task::spawn_blocking(move || {
+        let strips = seed_state.sources.known_strips();
+        if let Err(e) = seed_state
+            .search
+            .seed(&strips, |endpoint| seed_state.source_name(endpoint))
+        {
+            warn!("failed to seed search index: {}", e);
+        }
     });
 
     let app = routes::build_router(state);
@@ -66,6 +131,13 @@ async fn main() -> anyhow::Result<()> {
         .with_graceful_shutdown(shutdown_signal())
         .await?;
 
+    if let Err(e) = flush_usage.flush() {
+        warn!("failed to write usage stats: {}", e);
+    }
+    if let Err(e) = flush_hashes.flush() {
+        warn!("failed to write image hashes: {}", e);
+    }
+
     Ok(())
 }
 
@@ -0,0 +1,291 @@
+pub mod sync;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+use rand::seq::IteratorRandom;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::models::ComicStrip;
+
+const MIRROR_DIR: &str = "mirror";
+const OBJECTS_DIR: &str = "objects";
+
+/// An image in the content-addressed store.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ObjectRef {
+    pub sha256: String,
+    pub content_type: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MirroredStrip {
+    /// As upstream described it, including the original image URL
+    pub strip: ComicStrip,
+    pub image: ObjectRef,
+}
+
+/// What has been mirrored for one comic. Every date from `oldest` to `newest` has been
+/// checked upstream, so a sync resumes from either end.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MirrorIndex {
+    pub newest: Option<String>,
+    pub oldest: Option<String>,
+    /// Set once the walk back has reached the comic's first strip and no date is `missing`
+    #[serde(default)]
+    pub complete: bool,
+    /// Dates to fetch again, such as ones whose image failed its checksum
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub pending: BTreeSet<String>,
+    /// Dates upstream had no strip for, with how many times it was asked. A source that
+    /// times out or is rate limited can also answer nothing, so they are asked again on
+    /// later passes before being taken as days without a strip.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub missing: BTreeMap<String, u32>,
+    #[serde(default)]
+    pub strips: BTreeMap<String, MirroredStrip>,
+}
+
+impl MirrorIndex {
+    /// The strip for `date` linked to its neighbours in the index, which includes dates
+    /// waiting to be fetched again, rather than to the ones upstream had when it was
+    /// mirrored. Past either end of the index upstream's links are kept.
+    fn linked(&self, date: &str) -> Option<ComicStrip> {
+        let mut strip = self.strips.get(date)?.strip.clone();
+        let before = (Bound::Unbounded, Bound::Excluded(date));
+        let after = (Bound::Excluded(date), Bound::Unbounded);
+        let prev = [
+            self.strips
+                .range::<str, _>(before)
+                .next_back()
+                .map(|(d, _)| d),
+            self.pending.range::<str, _>(before).next_back(),
+        ]
+        .into_iter()
+        .flatten()
+        .max();
+        let next = [
+            self.strips.range::<str, _>(after).next().map(|(d, _)| d),
+            self.pending.range::<str, _>(after).next(),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        if let Some(prev) = prev {
+            strip.prev_date = Some(prev.clone());
+        }
+        if let Some(next) = next {
+            strip.next_date = Some(next.clone());
+        }
+        Some(strip)
+    }
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Strip metadata and images for mirrored comics, under `<data dir>/mirror`. Each comic has
+/// an index, `<endpoint>.json`; images live once each under `objects/`, named by checksum.
+pub struct MirrorStore {
+    root: PathBuf,
+    endpoints: Vec<String>,
+    indexes: RwLock<HashMap<String, MirrorIndex>>,
+    /// Upstream image URL to stored object, across all comics
+    images: RwLock<HashMap<String, ObjectRef>>,
+}
+
+impl MirrorStore {
+    pub fn open(data_dir: &str, endpoints: &[String]) -> anyhow::Result<Self> {
+        let root = Path::new(data_dir).join(MIRROR_DIR);
+        let mut indexes = HashMap::new();
+        let mut images = HashMap::new();
+        for endpoint in endpoints {
+            anyhow::ensure!(
+                !endpoint.is_empty()
+                    && endpoint
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+                "invalid mirror endpoint: {:?}",
+                endpoint
+            );
+            let path = root.join(format!("{}.json", endpoint));
+            let index: MirrorIndex = match std::fs::read_to_string(&path) {
+                Ok(contents) => serde_json::from_str(&contents)
+                    .map_err(|e| anyhow::anyhow!("failed to parse {}: {}", path.display(), e))?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => MirrorIndex::default(),
+                Err(e) => anyhow::bail!("failed to read {}: {}", path.display(), e),
+            };
+            for mirrored in index.strips.values() {
+                images.insert(mirrored.strip.image_url.clone(), mirrored.image.clone());
+            }
+            info!(endpoint, strips = index.strips.len(), "loaded mirror index");
+            indexes.insert(endpoint.clone(), index);
+        }
+        let mut endpoints = endpoints.to_vec();
+        endpoints.sort();
+        endpoints.dedup();
+        Ok(Self {
+            root,
+            endpoints,
+            indexes: RwLock::new(indexes),
+            images: RwLock::new(images),
+        })
+    }
+
+    pub fn mirrors(&self, endpoint: &str) -> bool {
+        self.endpoints.iter().any(|e| e == endpoint)
+    }
+
+    pub fn endpoints(&self) -> &[String] {
+        &self.endpoints
+    }
+
+    pub async fn index(&self, endpoint: &str) -> Option<MirrorIndex> {
+        self.indexes.read().await.get(endpoint).cloned()
+    }
+
+    pub async fn strip(&self, endpoint: &str, date: &str) -> Option<ComicStrip> {
+        let indexes = self.indexes.read().await;
+        indexes.get(endpoint)?.linked(date)
+    }
+
+    pub async fn latest(&self, endpoint: &str) -> Option<ComicStrip> {
+        let indexes = self.indexes.read().await;
+        let index = indexes.get(endpoint)?;
+        index.linked(index.strips.last_key_value()?.0)
+    }
+
+    pub async fn random(&self, endpoint: &str) -> Option<ComicStrip> {
+        let indexes = self.indexes.read().await;
+        let index = indexes.get(endpoint)?;
+        let date = index.strips.keys().choose(&mut rand::thread_rng())?;
+        index.linked(date)
+    }
+
+    pub async fn image(&self, image_url: &str) -> Option<ObjectRef> {
+        self.images.read().await.get(image_url).cloned()
+    }
+
+    fn object_path(&self, sha256: &str) -> PathBuf {
+        self.root
+            .join(OBJECTS_DIR)
+            .join(&sha256[..2])
+            .join(&sha256[2..])
+    }
+
+    /// Reads an object back, failing if its contents no longer match the checksum.
+    pub async fn read_object(&self, object: &ObjectRef) -> anyhow::Result<Vec<u8>> {
+        let path = self.object_path(&object.sha256);
+        let bytes = tokio::fs::read(&path)
+            .await
+            .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+        let actual = sha256_hex(&bytes);
+        anyhow::ensure!(
+            actual == object.sha256,
+            "{} is corrupt: checksum {}",
+            path.display(),
+            actual
+        );
+        Ok(bytes)
+    }
+
+    /// Stores `bytes` unless an object with the same checksum is already there.
+    pub async fn write_object(
+        &self,
+        bytes: &[u8],
+        content_type: &str,
+    ) -> anyhow::Result<ObjectRef> {
+        let object = ObjectRef {
+            sha256: sha256_hex(bytes),
+            content_type: content_type.to_string(),
+            size: bytes.len() as u64,
+        };
+        let path = self.object_path(&object.sha256);
+        if self.read_object(&object).await.is_ok() {
+            return Ok(object);
+        }
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, bytes).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(object)
+    }
+
+    pub async fn record(&self, endpoint: &str, strip: ComicStrip, image: ObjectRef) {
+        self.images
+            .write()
+            .await
+            .insert(strip.image_url.clone(), image.clone());
+        let mut indexes = self.indexes.write().await;
+        let index = indexes.entry(endpoint.to_string()).or_default();
+        index
+            .strips
+            .insert(strip.date.clone(), MirroredStrip { strip, image });
+    }
+
+    /// Drops a strip so the next sync fetches it again.
+    pub async fn forget(&self, endpoint: &str, date: &str) {
+        let mut indexes = self.indexes.write().await;
+        if let Some(index) = indexes.get_mut(endpoint) {
+            index.strips.remove(date);
+            index.pending.insert(date.to_string());
+        }
+    }
+
+    pub async fn update(&self, endpoint: &str, f: impl FnOnce(&mut MirrorIndex)) {
+        let mut indexes = self.indexes.write().await;
+        f(indexes.entry(endpoint.to_string()).or_default());
+    }
+
+    /// Writes the comic's index to disk.
+    pub async fn save(&self, endpoint: &str) -> anyhow::Result<()> {
+        let json = {
+            let indexes = self.indexes.read().await;
+            let Some(index) = indexes.get(endpoint) else {
+                return Ok(());
+            };
+            serde_json::to_string_pretty(index)?
+        };
+        tokio::fs::create_dir_all(&self.root).await?;
+        let path = self.root.join(format!("{}.json", endpoint));
+        let tmp = path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, json).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    /// Re-reads every image of the comic and returns the dates whose image is missing or
+    /// doesn't match its checksum.
+    pub async fn verify(&self, endpoint: &str) -> Vec<String> {
+        let strips: Vec<(String, ObjectRef)> = match self.indexes.read().await.get(endpoint) {
+            Some(index) => index
+                .strips
+                .iter()
+                .map(|(date, m)| (date.clone(), m.image.clone()))
+                .collect(),
+            None => return Vec::new(),
+        };
+        let mut bad = Vec::new();
+        for (date, object) in strips {
+            if let Err(e) = self.read_object(&object).await {
+                warn!(endpoint, date, "mirror check failed: {}", e);
+                bad.push(date);
+            }
+        }
+        bad
+    }
+}
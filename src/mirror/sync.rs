@@ -0,0 +1,518 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::NaiveDate;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use super::MirrorStore;
+use crate::catalog::Catalog;
+use crate::models::Comic;
+use crate::sources::{ComicSource, SourceRegistry};
+
+/// Dates checked upstream per comic in one pass
+pub const SYNC_BATCH: usize = 100;
+/// Pause between upstream requests
+pub const FETCH_INTERVAL: Duration = Duration::from_millis(500);
+/// How far back to mirror a comic with no `startDate` and no `--mirror-since`
+const DEFAULT_BACKFILL_DAYS: i64 = 365;
+/// Times a date is asked for before it counts as having no strip
+const MISSING_CHECKS: u32 = 3;
+/// Strips fetched between index writes; a crash loses at most this much progress
+const SAVE_EVERY: usize = 20;
+
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    pub batch: usize,
+    /// Don't mirror strips before this date
+    pub since: Option<NaiveDate>,
+    pub interval: Duration,
+    /// Re-check every stored image's checksum first and fetch the bad ones again
+    pub verify: bool,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        Self {
+            batch: SYNC_BATCH,
+            since: None,
+            interval: FETCH_INTERVAL,
+            verify: false,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncReport {
+    pub endpoint: String,
+    /// Dates looked up upstream
+    pub checked: usize,
+    /// Strips downloaded
+    pub added: usize,
+    /// Stored images that failed their checksum
+    pub corrupt: usize,
+    /// Whether everything back to the first strip is mirrored
+    pub complete: bool,
+}
+
+fn parse_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
+}
+
+fn format_date(date: NaiveDate) -> String {
+    date.format("%Y-%m-%d").to_string()
+}
+
+struct Syncer<'a> {
+    store: &'a MirrorStore,
+    source: &'a dyn ComicSource,
+    endpoint: &'a str,
+    options: &'a SyncOptions,
+    report: SyncReport,
+    unsaved: usize,
+}
+
+impl Syncer<'_> {
+    fn budget_left(&self) -> bool {
+        self.report.checked < self.options.batch
+    }
+
+    /// Mirrors the strip for `date`, if there is one. Errors leave the date unchecked; a date
+    /// without a strip is kept to ask about again until it has been `MISSING_CHECKS` times.
+    async fn fetch(&mut self, date: &str) -> anyhow::Result<()> {
+        if self.report.checked > 0 {
+            tokio::time::sleep(self.options.interval).await;
+        }
+        self.report.checked += 1;
+        let found = self.source.fetch_strip(self.endpoint, date).await?;
+        self.store
+            .update(self.endpoint, |index| {
+                if found.is_some() {
+                    index.missing.remove(date);
+                    return;
+                }
+                let asked = index.missing.entry(date.to_string()).or_default();
+                *asked += 1;
+                if *asked >= MISSING_CHECKS {
+                    index.missing.remove(date);
+                }
+            })
+            .await;
+        let Some(strip) = found else {
+            return Ok(());
+        };
+        if self.store.strip(self.endpoint, &strip.date).await.is_none() {
+            let (bytes, content_type) = self.source.proxy_image(&strip.image_url).await?;
+            let image = self.store.write_object(&bytes, &content_type).await?;
+            self.store.record(self.endpoint, strip, image).await;
+            self.report.added += 1;
+            self.unsaved += 1;
+        }
+        Ok(())
+    }
+
+    async fn checkpoint(&mut self) -> anyhow::Result<()> {
+        if self.unsaved >= SAVE_EVERY {
+            self.store.save(self.endpoint).await?;
+            self.unsaved = 0;
+        }
+        Ok(())
+    }
+
+    async fn run(&mut self, comic: &Comic) -> anyhow::Result<()> {
+        let endpoint = self.endpoint;
+        if self.options.verify {
+            for date in self.store.verify(endpoint).await {
+                self.store.forget(endpoint, &date).await;
+                self.report.corrupt += 1;
+            }
+            self.store.save(endpoint).await?;
+        }
+
+        let index = self.store.index(endpoint).await.unwrap_or_default();
+        for date in index.pending {
+            if !self.budget_left() {
+                return Ok(());
+            }
+            self.fetch(&date).await?;
+            self.store
+                .update(endpoint, |index| {
+                    index.pending.remove(&date);
+                })
+                .await;
+            self.checkpoint().await?;
+        }
+
+        // Dates that had no strip on an earlier pass
+        for date in index.missing.into_keys() {
+            if !self.budget_left() {
+                return Ok(());
+            }
+            self.fetch(&date).await?;
+            self.checkpoint().await?;
+        }
+
+        let latest = self
+            .source
+            .fetch_latest(endpoint)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("upstream has no latest strip"))?;
+        let latest_date = parse_date(&latest.date).ok_or_else(|| {
+            anyhow::anyhow!(
+                "mirroring walks dates, but this source names strips like {:?}",
+                latest.date
+            )
+        })?;
+        let floor = [
+            comic.start_date.as_deref().and_then(parse_date),
+            self.options.since,
+        ]
+        .into_iter()
+        .flatten()
+        .max()
+        .unwrap_or(latest_date - chrono::Duration::days(DEFAULT_BACKFILL_DAYS));
+
+        // Catch up on strips published since the last sync
+        let index = self.store.index(endpoint).await.unwrap_or_default();
+        if let Some(newest) = index.newest.as_deref().and_then(parse_date) {
+            let mut date = newest;
+            while date < latest_date && self.budget_left() {
+                date = date.succ_opt().unwrap_or(latest_date);
+                self.fetch(&format_date(date)).await?;
+                self.store
+                    .update(endpoint, |index| index.newest = Some(format_date(date)))
+                    .await;
+                self.checkpoint().await?;
+            }
+        }
+
+        // Then keep walking back from the oldest date checked
+        let mut next = match index.oldest.as_deref().and_then(parse_date) {
+            Some(oldest) => oldest.pred_opt(),
+            None => Some(latest_date),
+        };
+        while let Some(date) = next.filter(|d| *d >= floor) {
+            if !self.budget_left() {
+                return Ok(());
+            }
+            self.fetch(&format_date(date)).await?;
+            self.store
+                .update(endpoint, |index| {
+                    index.oldest = Some(format_date(date));
+                    index.newest.get_or_insert_with(|| format_date(date));
+                    index.complete = false;
+                })
+                .await;
+            self.checkpoint().await?;
+            next = date.pred_opt();
+        }
+        let mut complete = false;
+        self.store
+            .update(endpoint, |index| {
+                index.complete = index.missing.is_empty();
+                complete = index.complete;
+            })
+            .await;
+        self.report.complete = complete;
+        Ok(())
+    }
+}
+
+/// Runs one pass for a comic: strips that failed verification and dates that had no strip,
+/// then anything newer than the mirror, then further back, up to `options.batch` dates. Progress is saved as it goes, so a
+/// pass that fails or runs out of budget picks up where it stopped next time.
+pub async fn sync_comic(
+    store: &MirrorStore,
+    source: &dyn ComicSource,
+    comic: &Comic,
+    options: &SyncOptions,
+) -> anyhow::Result<SyncReport> {
+    let mut syncer = Syncer {
+        store,
+        source,
+        endpoint: &comic.endpoint,
+        options,
+        report: SyncReport {
+            endpoint: comic.endpoint.clone(),
+            ..SyncReport::default()
+        },
+        unsaved: 0,
+    };
+    let result = syncer.run(comic).await;
+    store.save(&comic.endpoint).await?;
+    result.map(|_| syncer.report)
+}
+
+/// Syncs every mirrored comic through `live`, one after another.
+pub async fn sync_all(
+    store: &MirrorStore,
+    live: &SourceRegistry,
+    catalog: &Catalog,
+    options: &SyncOptions,
+) -> Vec<anyhow::Result<SyncReport>> {
+    let mut reports = Vec::new();
+    for endpoint in store.endpoints() {
+        let report = match (catalog.get().comic(endpoint), live.find(endpoint)) {
+            (Some(comic), Some(source)) => sync_comic(store, source, comic, options).await,
+            (None, _) => Err(anyhow::anyhow!("{} is not in the catalog", endpoint)),
+            (_, None) => Err(anyhow::anyhow!("no source handles {}", endpoint)),
+        };
+        reports.push(report.map_err(|e| anyhow::anyhow!("{}: {}", endpoint, e)));
+    }
+    reports
+}
+
+impl fmt::Display for SyncReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} dates checked, {} strips added, {} corrupt",
+            self.endpoint, self.checked, self.added, self.corrupt
+        )
+    }
+}
+
+/// One line per mirrored comic on how far back it goes.
+pub async fn status(store: &MirrorStore) -> Vec<String> {
+    let mut lines = Vec::new();
+    for endpoint in store.endpoints() {
+        let index = store.index(endpoint).await.unwrap_or_default();
+        lines.push(format!(
+            "{:<8} {}: {} strips, {} to {}",
+            if index.complete {
+                "complete"
+            } else {
+                "partial"
+            },
+            endpoint,
+            index.strips.len(),
+            index.oldest.as_deref().unwrap_or("-"),
+            index.newest.as_deref().unwrap_or("-")
+        ));
+    }
+    lines
+}
+
+/// Syncs the mirror now and then every `interval`.
+pub fn spawn_syncer(
+    store: Arc<MirrorStore>,
+    live: SourceRegistry,
+    catalog: Arc<Catalog>,
+    options: SyncOptions,
+    interval: Duration,
+) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            for report in sync_all(&store, &live, &catalog, &options).await {
+                match report {
+                    Ok(report) => info!(
+                        endpoint = %report.endpoint,
+                        added = report.added,
+                        checked = report.checked,
+                        complete = report.complete,
+                        "mirror synced"
+                    ),
+                    Err(e) => warn!("mirror sync stopped: {}", e),
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::error::Result;
+    use crate::models::{ComicStrip, StripMetadata};
+
+    /// A daily comic whose latest strip is on January `latest`, 2024.
+    struct Daily {
+        latest: AtomicU32,
+    }
+
+    fn strip(date: &str) -> ComicStrip {
+        ComicStrip {
+            endpoint: "daily".into(),
+            title: "Daily".into(),
+            date: date.into(),
+            image_url: format!("https://example.com/{}.png", date),
+            source_url: String::new(),
+            prev_date: None,
+            next_date: None,
+            metadata: StripMetadata::default(),
+        }
+    }
+
+    #[async_trait]
+    impl ComicSource for Daily {
+        fn handles(&self, endpoint: &str) -> bool {
+            endpoint == "daily"
+        }
+        async fn fetch_strip(&self, _: &str, date: &str) -> Result<Option<ComicStrip>> {
+            Ok(Some(strip(date)))
+        }
+        async fn fetch_latest(&self, _: &str) -> Result<Option<ComicStrip>> {
+            let day = self.latest.load(Ordering::Relaxed);
+            Ok(Some(strip(&format!("2024-01-{:02}", day))))
+        }
+        async fn fetch_random(&self, _: &str) -> Result<Option<ComicStrip>> {
+            Ok(None)
+        }
+        async fn proxy_image(&self, image_url: &str) -> Result<(Vec<u8>, String)> {
+            Ok((image_url.as_bytes().to_vec(), "image/png".into()))
+        }
+    }
+
+    fn comic() -> Comic {
+        Comic {
+            endpoint: "daily".into(),
+            title: "Daily".into(),
+            author: None,
+            available: true,
+            start_date: Some("2024-01-05".into()),
+            source: "gocomics".into(),
+            keywords: Vec::new(),
+            rating: Default::default(),
+            feed: None,
+        }
+    }
+
+    fn options() -> SyncOptions {
+        SyncOptions {
+            batch: 3,
+            interval: Duration::ZERO,
+            ..SyncOptions::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn resumes_and_catches_up_across_passes() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().to_str().unwrap();
+        let source = Daily {
+            latest: AtomicU32::new(10),
+        };
+        let store = MirrorStore::open(data_dir, &["daily".into()]).unwrap();
+        let report = sync_comic(&store, &source, &comic(), &options())
+            .await
+            .unwrap();
+        assert_eq!((report.added, report.complete), (3, false));
+
+        // A new strip comes out; a fresh process picks up from the saved index
+        source.latest.store(11, Ordering::Relaxed);
+        let store = MirrorStore::open(data_dir, &["daily".into()]).unwrap();
+        sync_comic(&store, &source, &comic(), &options())
+            .await
+            .unwrap();
+        let index = store.index("daily").await.unwrap();
+        assert_eq!(index.newest.as_deref(), Some("2024-01-11"));
+        assert_eq!(index.oldest.as_deref(), Some("2024-01-06"));
+
+        let report = sync_comic(&store, &source, &comic(), &options())
+            .await
+            .unwrap();
+        assert_eq!((report.added, report.complete), (1, true));
+        assert_eq!(store.index("daily").await.unwrap().strips.len(), 7);
+    }
+
+    /// Answers nothing for some dates the first time they are asked for, like a rate limited
+    /// site would.
+    struct Flaky {
+        daily: Daily,
+        unanswered: std::sync::Mutex<std::collections::BTreeSet<String>>,
+    }
+
+    #[async_trait]
+    impl ComicSource for Flaky {
+        fn handles(&self, endpoint: &str) -> bool {
+            self.daily.handles(endpoint)
+        }
+        async fn fetch_strip(&self, endpoint: &str, date: &str) -> Result<Option<ComicStrip>> {
+            if self.unanswered.lock().unwrap().remove(date) {
+                return Ok(None);
+            }
+            self.daily.fetch_strip(endpoint, date).await
+        }
+        async fn fetch_latest(&self, endpoint: &str) -> Result<Option<ComicStrip>> {
+            self.daily.fetch_latest(endpoint).await
+        }
+        async fn fetch_random(&self, _: &str) -> Result<Option<ComicStrip>> {
+            Ok(None)
+        }
+        async fn proxy_image(&self, image_url: &str) -> Result<(Vec<u8>, String)> {
+            self.daily.proxy_image(image_url).await
+        }
+    }
+
+    #[tokio::test]
+    async fn dates_without_a_strip_are_asked_for_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().to_str().unwrap();
+        let source = Flaky {
+            daily: Daily {
+                latest: AtomicU32::new(7),
+            },
+            unanswered: std::sync::Mutex::new(["2024-01-06".to_string()].into()),
+        };
+        let store = MirrorStore::open(data_dir, &["daily".into()]).unwrap();
+        let options = SyncOptions {
+            batch: 10,
+            ..options()
+        };
+        let report = sync_comic(&store, &source, &comic(), &options)
+            .await
+            .unwrap();
+        assert_eq!((report.added, report.complete), (2, false));
+        let index = store.index("daily").await.unwrap();
+        assert_eq!(index.missing.get("2024-01-06"), Some(&1));
+
+        let report = sync_comic(&store, &source, &comic(), &options)
+            .await
+            .unwrap();
+        assert_eq!((report.added, report.complete), (1, true));
+        let index = store.index("daily").await.unwrap();
+        assert!(index.missing.is_empty());
+        assert_eq!(index.strips.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn verify_fetches_corrupt_images_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().to_str().unwrap();
+        let source = Daily {
+            latest: AtomicU32::new(5),
+        };
+        let store = MirrorStore::open(data_dir, &["daily".into()]).unwrap();
+        sync_comic(&store, &source, &comic(), &options())
+            .await
+            .unwrap();
+        let image = store
+            .image("https://example.com/2024-01-05.png")
+            .await
+            .unwrap();
+        let object = dir
+            .path()
+            .join("mirror/objects")
+            .join(&image.sha256[..2])
+            .join(&image.sha256[2..]);
+        std::fs::write(&object, b"bit rot").unwrap();
+
+        let options = SyncOptions {
+            verify: true,
+            ..options()
+        };
+        let report = sync_comic(&store, &source, &comic(), &options)
+            .await
+            .unwrap();
+        assert_eq!((report.corrupt, report.added), (1, 1));
+        assert!(store.read_object(&image).await.is_ok());
+    }
+}
@@ -1,6 +1,10 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::imaging::hash::ImageHashes;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Comic {
     pub endpoint: String,
@@ -12,6 +16,35 @@ pub struct Comic {
     pub source: String,
     #[serde(default)]
     pub keywords: Vec<String>,
+    #[serde(default)]
+    pub rating: Rating,
+    /// Where comics with the `feed` source are read from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub feed: Option<FeedConfig>,
+}
+
+/// An RSS or Atom feed a comic publishes, with hints for finding the strip in each item.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeedConfig {
+    pub url: String,
+    /// CSS selector for the strip image in each item's HTML. Without one the feed's media
+    /// elements, enclosures and first `<img>` are used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_selector: Option<String>,
+    /// Text the image URL must contain, to skip avatars, ads and bonus panels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_filter: Option<String>,
+}
+
+/// Maturity rating from the catalog. Anything above `General` is hidden in safe mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rating {
+    #[default]
+    General,
+    Teen,
+    Mature,
 }
 
 fn default_source() -> String {
@@ -28,6 +61,55 @@ pub struct ComicStrip {
     pub source_url: String,
     pub prev_date: Option<String>,
     pub next_date: Option<String>,
+    #[serde(default, skip_serializing_if = "StripMetadata::is_empty")]
+    pub metadata: StripMetadata,
+}
+
+/// Optional details some sources publish alongside the image.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StripMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alt_text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transcript: Option<String>,
+    /// Title to show in safe mode when the regular one is not work-appropriate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub safe_title: Option<String>,
+    /// Publication date as `YYYY-MM-DD`, for sources whose strip id is not a date
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub published: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<StripLink>,
+    /// Perceptual hashes of the image, once it has been proxied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_hash: Option<ImageHashes>,
+    /// Source-specific values that have no dedicated field
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, String>,
+}
+
+impl ComicStrip {
+    /// The strip as shown in safe mode.
+    pub fn into_safe(mut self) -> Self {
+        if let Some(safe_title) = self.metadata.safe_title.take() {
+            self.title = safe_title;
+        }
+        self
+    }
+}
+
+impl StripMetadata {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StripLink {
+    pub label: String,
+    pub url: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -0,0 +1,652 @@
+use std::collections::HashMap;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+
+use crate::config::PanelsConfig;
+use crate::models::{Comic, ComicWithTags};
+use crate::taxonomy::{TagKind, Taxonomy};
+
+/// Pairs seen together in fewer feeds than this are ignored, so a single reader's
+/// selection can never surface on its own.
+const MIN_SUPPORT: u64 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Weights {
+    pub content: f64,
+    pub collaborative: f64,
+    pub popularity: f64,
+}
+
+impl Weights {
+    pub fn from_config(config: &PanelsConfig) -> Self {
+        Self {
+            content: config.rec_content_weight,
+            collaborative: config.rec_collaborative_weight,
+            popularity: config.rec_popularity_weight,
+        }
+    }
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Self {
+            content: 1.0,
+            collaborative: 1.0,
+            popularity: 0.1,
+        }
+    }
+}
+
+/// Item-item co-occurrence over feeds (the set of comics a reader follows). Feeds that
+/// several readers share count once per reader.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CoOccurrence {
+    items: HashMap<String, u64>,
+    pairs: HashMap<String, HashMap<String, u64>>,
+}
+
+impl CoOccurrence {
+    /// From feeds and how many readers chose each.
+    pub fn build<'a, I>(baskets: I) -> Self
+    where
+        I: IntoIterator<Item = (&'a [String], u64)>,
+    {
+        let mut model = Self::default();
+        for (basket, readers) in baskets {
+            model.add_basket(basket, readers);
+        }
+        model
+    }
+
+    /// Adds a feed chosen by `readers` readers.
+    pub fn add_basket(&mut self, basket: &[String], readers: u64) {
+        for (i, a) in basket.iter().enumerate() {
+            *self.items.entry(a.clone()).or_insert(0) += readers;
+            for b in &basket[i + 1..] {
+                *self
+                    .pairs
+                    .entry(a.clone())
+                    .or_default()
+                    .entry(b.clone())
+                    .or_insert(0) += readers;
+                *self
+                    .pairs
+                    .entry(b.clone())
+                    .or_default()
+                    .entry(a.clone())
+                    .or_insert(0) += readers;
+            }
+        }
+    }
+
+    /// Takes back one reader of a basket added before.
+    pub fn remove_basket(&mut self, basket: &[String]) {
+        fn decrement(counts: &mut HashMap<String, u64>, key: &str) {
+            if let Some(count) = counts.get_mut(key) {
+                *count -= 1;
+                if *count == 0 {
+                    counts.remove(key);
+                }
+            }
+        }
+        for (i, a) in basket.iter().enumerate() {
+            decrement(&mut self.items, a);
+            for b in &basket[i + 1..] {
+                for (x, y) in [(a, b), (b, a)] {
+                    if let Some(pairs) = self.pairs.get_mut(x) {
+                        decrement(pairs, y);
+                        if pairs.is_empty() {
+                            self.pairs.remove(x);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Cosine similarity between two comics' feed memberships.
+    pub fn similarity(&self, a: &str, b: &str) -> f64 {
+        let together = self
+            .pairs
+            .get(a)
+            .and_then(|p| p.get(b))
+            .copied()
+            .unwrap_or(0);
+        if together < MIN_SUPPORT {
+            return 0.0;
+        }
+        let a_count = self.items.get(a).copied().unwrap_or(0);
+        let b_count = self.items.get(b).copied().unwrap_or(0);
+        together as f64 / ((a_count * b_count) as f64).sqrt()
+    }
+}
+
+pub struct Recommender<'a> {
+    pub comics: &'a [Comic],
+    pub tags: &'a HashMap<String, Vec<String>>,
+    pub taxonomy: &'a Taxonomy,
+    pub model: &'a CoOccurrence,
+    pub views: &'a HashMap<String, u64>,
+    pub weights: Weights,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecommendOptions {
+    pub exclude_tags: Vec<String>,
+    pub exclude_sources: Vec<String>,
+    pub require_available: bool,
+    /// Leave out comics that safe mode hides
+    pub safe: bool,
+    /// 0 ranks purely by score; towards 1, comics whose tags overlap ones already
+    /// picked are pushed down (maximal marginal relevance)
+    pub diversity: f64,
+    /// Orders comics with equal scores
+    pub seed: u64,
+}
+
+impl Default for RecommendOptions {
+    fn default() -> Self {
+        Self {
+            exclude_tags: Vec::new(),
+            exclude_sources: Vec::new(),
+            require_available: true,
+            safe: false,
+            diversity: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Recommendation {
+    #[serde(flatten)]
+    pub comic: ComicWithTags,
+    pub score: f64,
+    pub reasons: Vec<String>,
+}
+
+struct Candidate<'a> {
+    comic: &'a Comic,
+    tags: Vec<String>,
+    content: f64,
+    collaborative: f64,
+    popularity: f64,
+    reasons: Vec<String>,
+    tie_break: u64,
+}
+
+fn tag_similarity(a: &[String], b: &[String]) -> f64 {
+    let shared = a.iter().filter(|t| b.contains(t)).count();
+    let union = a.len() + b.len() - shared;
+    if union == 0 {
+        0.0
+    } else {
+        shared as f64 / union as f64
+    }
+}
+
+fn list(items: &[&str]) -> String {
+    match items {
+        [] => String::new(),
+        [one] => one.to_string(),
+        [init @ .., last] => format!("{} and {}", init.join(", "), last),
+    }
+}
+
+impl Recommender<'_> {
+    fn title<'b>(&'b self, endpoint: &'b str) -> &'b str {
+        self.comics
+            .iter()
+            .find(|c| c.endpoint == endpoint)
+            .map(|c| c.title.as_str())
+            .unwrap_or(endpoint)
+    }
+
+    pub fn recommend(
+        &self,
+        selected: &[&str],
+        options: &RecommendOptions,
+        limit: usize,
+    ) -> Vec<Recommendation> {
+        if selected.is_empty() || limit == 0 {
+            return Vec::new();
+        }
+
+        let mut selected_tags: HashMap<&str, usize> = HashMap::new();
+        let mut selected_comics: Vec<&Comic> = Vec::new();
+
+        for endpoint in selected {
+            if let Some(tags) = self.tags.get(*endpoint) {
+                for tag in tags {
+                    *selected_tags.entry(tag.as_str()).or_insert(0) += 1;
+                }
+            }
+            if let Some(comic) = self.comics.iter().find(|c| c.endpoint == *endpoint) {
+                selected_comics.push(comic);
+            }
+        }
+
+        let excluded_tags: Vec<String> = options
+            .exclude_tags
+            .iter()
+            .flat_map(|t| self.taxonomy.expand(t))
+            .collect();
+        let mut rng = StdRng::seed_from_u64(options.seed);
+
+        let candidates: Vec<Candidate> = self
+            .comics
+            .iter()
+            .filter(|c| {
+                (c.available || !options.require_available)
+                    && !selected.contains(&c.endpoint.as_str())
+                    && !options
+                        .exclude_sources
+                        .iter()
+                        .any(|s| s.eq_ignore_ascii_case(&c.source))
+            })
+            .filter_map(|comic| {
+                let tags = self.tags.get(&comic.endpoint).cloned().unwrap_or_default();
+
+                // Skip comics in a language none of the selected comics are in
+                if tags.iter().any(|t| {
+                    self.taxonomy.is_kind(t, TagKind::Language)
+                        && !selected_tags.contains_key(t.as_str())
+                }) {
+                    return None;
+                }
+                if tags
+                    .iter()
+                    .any(|t| excluded_tags.iter().any(|x| x.eq_ignore_ascii_case(t)))
+                {
+                    return None;
+                }
+                if options.safe && !self.taxonomy.is_safe(comic, &tags) {
+                    return None;
+                }
+
+                let mut content: f64 = 0.0;
+                let mut reasons = Vec::new();
+
+                let shared: Vec<&str> = tags
+                    .iter()
+                    .map(String::as_str)
+                    .filter(|t| selected_tags.contains_key(t))
+                    .collect();
+                for tag in &shared {
+                    content += selected_tags[tag] as f64;
+                }
+                if !shared.is_empty() {
+                    let overlapping = selected
+                        .iter()
+                        .filter(|e| {
+                            self.tags
+                                .get(**e)
+                                .is_some_and(|ts| ts.iter().any(|t| shared.contains(&t.as_str())))
+                        })
+                        .count();
+                    reasons.push(format!(
+                        "shares {} {} with {} of your comics",
+                        if shared.len() == 1 { "tag" } else { "tags" },
+                        shared.join(", "),
+                        overlapping
+                    ));
+                }
+
+                if let Some(ref author) = comic.author
+                    && let Some(same) = selected_comics
+                        .iter()
+                        .find(|s| s.author.as_ref() == Some(author))
+                {
+                    content += 2.0;
+                    reasons.push(format!("same author as {}", same.title));
+                }
+
+                if selected_comics.iter().any(|s| s.source == comic.source) {
+                    content += 0.5;
+                    if reasons.is_empty() {
+                        reasons.push(format!("also from {}", comic.source));
+                    }
+                }
+
+                let mut neighbours: Vec<(&str, f64)> = selected
+                    .iter()
+                    .map(|s| (*s, self.model.similarity(s, &comic.endpoint)))
+                    .filter(|(_, sim)| *sim > 0.0)
+                    .collect();
+                let collaborative: f64 = neighbours.iter().map(|(_, sim)| sim).sum();
+                if !neighbours.is_empty() {
+                    neighbours.sort_by(|a, b| b.1.total_cmp(&a.1));
+                    let titles: Vec<&str> = neighbours
+                        .iter()
+                        .take(2)
+                        .map(|(e, _)| self.title(e))
+                        .collect();
+                    reasons.push(format!("readers often follow it with {}", list(&titles)));
+                }
+
+                let views = self.views.get(&comic.endpoint).copied().unwrap_or(0);
+
+                Some(Candidate {
+                    comic,
+                    tags,
+                    content,
+                    collaborative,
+                    popularity: (views as f64).ln_1p(),
+                    reasons,
+                    tie_break: rng.r#gen(),
+                })
+            })
+            .collect();
+
+        // Each signal is scaled to 0..1 so the weights compare like with like
+        let max = |f: fn(&Candidate) -> f64| candidates.iter().map(f).fold(0.0, f64::max);
+        let max_content = max(|c| c.content);
+        let max_collaborative = max(|c| c.collaborative);
+        let max_popularity = max(|c| c.popularity);
+        let scaled = |value: f64, max: f64| if max > 0.0 { value / max } else { 0.0 };
+
+        let mut scored: Vec<(f64, Candidate)> = candidates
+            .into_iter()
+            .filter_map(|mut c| {
+                let content = scaled(c.content, max_content) * self.weights.content;
+                let collaborative =
+                    scaled(c.collaborative, max_collaborative) * self.weights.collaborative;
+                // Popularity alone never makes a comic a recommendation
+                if content <= 0.0 && collaborative <= 0.0 {
+                    return None;
+                }
+                let popularity = scaled(c.popularity, max_popularity);
+                if popularity >= 0.75 && self.weights.popularity > 0.0 {
+                    c.reasons.push("popular with readers".to_string());
+                }
+                Some((
+                    content + collaborative + popularity * self.weights.popularity,
+                    c,
+                ))
+            })
+            .collect();
+
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.tie_break.cmp(&b.1.tie_break)));
+
+        let picked = if options.diversity > 0.0 {
+            diversify(scored, options.diversity.min(1.0), limit)
+        } else {
+            scored.truncate(limit);
+            scored
+        };
+
+        picked
+            .into_iter()
+            .map(|(score, c)| Recommendation {
+                comic: ComicWithTags {
+                    comic: c.comic.clone(),
+                    tags: c.tags,
+                },
+                score,
+                reasons: c.reasons,
+            })
+            .collect()
+    }
+}
+
+/// Greedy maximal marginal relevance: each pick trades its own score against its tag
+/// overlap with the comics already picked. Expects `scored` sorted best first.
+fn diversify(scored: Vec<(f64, Candidate)>, diversity: f64, limit: usize) -> Vec<(f64, Candidate)> {
+    let top = scored.first().map(|(s, _)| *s).unwrap_or(0.0);
+    let mut remaining: Vec<Option<(f64, Candidate)>> = scored.into_iter().map(Some).collect();
+    let mut picked: Vec<(f64, Candidate)> = Vec::new();
+
+    while picked.len() < limit {
+        let best = remaining
+            .iter()
+            .enumerate()
+            .filter_map(|(i, c)| c.as_ref().map(|c| (i, c)))
+            .map(|(i, (score, c))| {
+                let redundancy = picked
+                    .iter()
+                    .map(|(_, p)| tag_similarity(&c.tags, &p.tags))
+                    .fold(0.0, f64::max);
+                let relevance = if top > 0.0 { score / top } else { 0.0 };
+                (i, (1.0 - diversity) * relevance - diversity * redundancy)
+            })
+            // Earlier entries win exact ties, keeping the seeded order
+            .fold(None, |best: Option<(usize, f64)>, (i, mmr)| match best {
+                Some((_, b)) if b >= mmr => best,
+                _ => Some((i, mmr)),
+            });
+        let Some((i, _)) = best else {
+            break;
+        };
+        picked.extend(remaining[i].take());
+    }
+
+    picked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comic(endpoint: &str, author: &str) -> Comic {
+        Comic {
+            endpoint: endpoint.into(),
+            title: endpoint.into(),
+            author: Some(author.into()),
+            available: true,
+            start_date: None,
+            source: "gocomics".into(),
+            keywords: Vec::new(),
+            rating: Default::default(),
+            feed: None,
+        }
+    }
+
+    fn basket(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn similarity_requires_minimum_support() {
+        let baskets = [
+            basket(&["garfield", "peanuts"]),
+            basket(&["garfield", "peanuts", "dilbert"]),
+            basket(&["garfield", "dilbert-classics"]),
+        ];
+        let model = CoOccurrence::build(baskets.iter().map(|b| (b.as_slice(), 1)));
+
+        let sim = model.similarity("garfield", "peanuts");
+        assert!((sim - 2.0 / 6f64.sqrt()).abs() < 1e-9);
+        assert_eq!(model.similarity("peanuts", "garfield"), sim);
+        assert_eq!(model.similarity("garfield", "dilbert-classics"), 0.0);
+    }
+
+    #[test]
+    fn collaborative_signal_surfaces_unrelated_comics() {
+        let mut xkcd = comic("xkcd", "Randall Munroe");
+        xkcd.source = "xkcd".into();
+        let comics = vec![
+            comic("garfield", "Jim Davis"),
+            comic("heathcliff", "Peter Gallagher"),
+            xkcd,
+        ];
+        let tags = HashMap::from([
+            ("garfield".to_string(), vec!["humor".to_string()]),
+            ("heathcliff".to_string(), vec!["humor".to_string()]),
+            ("xkcd".to_string(), vec!["webcomic".to_string()]),
+        ]);
+        let baskets = [basket(&["garfield", "xkcd"]), basket(&["garfield", "xkcd"])];
+        let model = CoOccurrence::build(baskets.iter().map(|b| (b.as_slice(), 1)));
+        let views = HashMap::new();
+
+        let content_only = Recommender {
+            comics: &comics,
+            tags: &tags,
+            taxonomy: &Taxonomy::default(),
+            model: &model,
+            views: &views,
+            weights: Weights {
+                content: 1.0,
+                collaborative: 0.0,
+                popularity: 0.0,
+            },
+        };
+        let endpoints: Vec<_> = content_only
+            .recommend(&["garfield"], &RecommendOptions::default(), 10)
+            .into_iter()
+            .map(|r| r.comic.comic.endpoint)
+            .collect();
+        assert_eq!(endpoints, ["heathcliff"]);
+
+        let blended = Recommender {
+            weights: Weights {
+                content: 1.0,
+                collaborative: 2.0,
+                popularity: 0.0,
+            },
+            ..content_only
+        };
+        let endpoints: Vec<_> = blended
+            .recommend(&["garfield"], &RecommendOptions::default(), 10)
+            .into_iter()
+            .map(|r| r.comic.comic.endpoint)
+            .collect();
+        assert_eq!(endpoints, ["xkcd", "heathcliff"]);
+    }
+
+    #[test]
+    fn explains_and_diversifies() {
+        let comics = vec![
+            comic("peanuts", "Charles Schulz"),
+            comic("peanuts-begins", "Charles Schulz"),
+            comic("garfield", "Jim Davis"),
+            comic("heathcliff", "Peter Gallagher"),
+            comic("pearls", "Stephan Pastis"),
+        ];
+        let tags = HashMap::from([
+            ("peanuts".to_string(), vec!["humor".into(), "kids".into()]),
+            (
+                "peanuts-begins".to_string(),
+                vec!["humor".into(), "kids".into()],
+            ),
+            (
+                "garfield".to_string(),
+                vec!["humor".into(), "animals".into()],
+            ),
+            (
+                "heathcliff".to_string(),
+                vec!["humor".into(), "animals".into()],
+            ),
+            ("pearls".to_string(), vec!["humor".into(), "kids".into()]),
+        ]);
+        let model = CoOccurrence::default();
+        let views = HashMap::new();
+        let recommender = Recommender {
+            comics: &comics,
+            tags: &tags,
+            taxonomy: &Taxonomy::default(),
+            model: &model,
+            views: &views,
+            weights: Weights::default(),
+        };
+
+        let recs = recommender.recommend(&["peanuts"], &RecommendOptions::default(), 10);
+        assert_eq!(recs[0].comic.comic.endpoint, "peanuts-begins");
+        assert_eq!(
+            recs[0].reasons,
+            [
+                "shares tags humor, kids with 1 of your comics",
+                "same author as peanuts"
+            ]
+        );
+        assert!(recs[0].score > recs[1].score);
+
+        let options = RecommendOptions {
+            exclude_tags: vec!["Animals".into()],
+            ..RecommendOptions::default()
+        };
+        let recs = recommender.recommend(&["peanuts"], &options, 10);
+        assert!(
+            recs.iter()
+                .all(|r| !r.comic.tags.contains(&"animals".into()))
+        );
+
+        // With diversity the second pick moves away from another humor/kids comic
+        let options = RecommendOptions {
+            diversity: 0.7,
+            ..RecommendOptions::default()
+        };
+        let recs = recommender.recommend(&["peanuts"], &options, 2);
+        assert_eq!(recs[0].comic.comic.endpoint, "peanuts-begins");
+        assert_ne!(recs[1].comic.comic.endpoint, "pearls");
+    }
+
+    #[test]
+    fn seed_decides_ties() {
+        let comics: Vec<Comic> = (0..8).map(|i| comic(&format!("c{}", i), "x")).collect();
+        let tags = HashMap::new();
+        let model = CoOccurrence::default();
+        let views = HashMap::new();
+        let recommender = Recommender {
+            comics: &comics,
+            tags: &tags,
+            taxonomy: &Taxonomy::default(),
+            model: &model,
+            views: &views,
+            weights: Weights::default(),
+        };
+        let order = |seed| -> Vec<String> {
+            let options = RecommendOptions {
+                seed,
+                ..RecommendOptions::default()
+            };
+            recommender
+                .recommend(&["c0"], &options, 10)
+                .into_iter()
+                .map(|r| r.comic.comic.endpoint)
+                .collect()
+        };
+
+        assert_eq!(order(1), order(1));
+        assert_ne!(order(1), order(2));
+    }
+
+    #[test]
+    fn language_tags_need_a_selected_comic_in_that_language() {
+        let comics = vec![
+            comic("garfield", "Jim Davis"),
+            comic("garfield-en-espanol", "Jim Davis"),
+            comic("heathcliff", "Peter Gallagher"),
+        ];
+        let tags = HashMap::from([
+            ("garfield".to_string(), vec!["humor".into()]),
+            (
+                "garfield-en-espanol".to_string(),
+                vec!["humor".into(), "en-espanol".into()],
+            ),
+            ("heathcliff".to_string(), vec!["humor".into()]),
+        ]);
+        let taxonomy = crate::data::load_taxonomy("data", &tags).unwrap();
+        let model = CoOccurrence::default();
+        let views = HashMap::new();
+        let recommender = Recommender {
+            comics: &comics,
+            tags: &tags,
+            taxonomy: &taxonomy,
+            model: &model,
+            views: &views,
+            weights: Weights::default(),
+        };
+        let endpoints = |selected: &[&str]| -> Vec<String> {
+            recommender
+                .recommend(selected, &RecommendOptions::default(), 10)
+                .into_iter()
+                .map(|r| r.comic.comic.endpoint)
+                .collect()
+        };
+
+        assert_eq!(endpoints(&["garfield"]), ["heathcliff"]);
+        assert!(endpoints(&["garfield-en-espanol"]).contains(&"garfield".to_string()));
+    }
+}
@@ -0,0 +1,104 @@
+use axum::Json;
+use axum::extract::{Query, State};
+use axum::http::{HeaderMap, header};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::AppState;
+use crate::catalog::CatalogDiff;
+use crate::error::{PanelsError, Result};
+use crate::image_hashes::{DUPLICATE_DISTANCE, DuplicateGroup};
+use crate::validation::validate_data_dir;
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// Compares without short-circuiting so response timing does not leak the token.
+fn token_matches(provided: &str, expected: &str) -> bool {
+    provided.len() == expected.len()
+        && provided
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+fn authorize(state: &AppState, headers: &HeaderMap) -> Result<()> {
+    let Some(ref expected) = state.config.admin_token else {
+        return Err(PanelsError::NotFound("admin API is disabled".into()));
+    };
+    match bearer_token(headers) {
+        Some(token) if token_matches(token, expected) => Ok(()),
+        _ => Err(PanelsError::Unauthorized(
+            "missing or invalid admin token".into(),
+        )),
+    }
+}
+
+pub async fn reload_catalog(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<CatalogDiff>> {
+    authorize(&state, &headers)?;
+
+    let catalog = Arc::clone(&state.catalog);
+    let reloaded = tokio::task::spawn_blocking(move || {
+        catalog.reload().map_err(|e| {
+            // The full report, so the caller sees everything to fix and not just the first
+            let report = validate_data_dir(catalog.data_dir());
+            PanelsError::CatalogRejected(format!("keeping the previous catalog: {:#}", e), report)
+        })
+    })
+    .await
+    .map_err(anyhow::Error::from)?;
+    Ok(Json(reloaded?))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicatesQuery {
+    pub endpoint: Option<String>,
+    pub max_distance: Option<u32>,
+}
+
+pub async fn list_duplicates(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<DuplicatesQuery>,
+) -> Result<Json<Vec<DuplicateGroup>>> {
+    authorize(&state, &headers)?;
+
+    // Compares every pair of a comic's strips, so it stays off the async workers
+    let groups = tokio::task::spawn_blocking(move || {
+        state.image_hashes.duplicates(
+            query.endpoint.as_deref(),
+            query.max_distance.unwrap_or(DUPLICATE_DISTANCE),
+            &state.catalog.get().placeholders,
+        )
+    })
+    .await
+    .map_err(anyhow::Error::from)?;
+    Ok(Json(groups))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_and_checks_bearer_token() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(bearer_token(&headers), None);
+
+        headers.insert(header::AUTHORIZATION, "Bearer s3cret".parse().unwrap());
+        let token = bearer_token(&headers).unwrap();
+        assert!(token_matches(token, "s3cret"));
+        assert!(!token_matches(token, "s3cre"));
+        assert!(!token_matches(token, "s3creT"));
+    }
+}
@@ -0,0 +1,366 @@
+use axum::Json;
+use axum::extract::{Path, Query, State};
+use axum::http::{StatusCode, header};
+use axum::response::{Html, IntoResponse, Response};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::AppState;
+use crate::bookmarks::{
+    Bookmark, BookmarkPatch, Collection, CollectionExport, CollectionPatch, SharedCollection,
+    render_collection_html,
+};
+use crate::error::{PanelsError, Result};
+use crate::routes::strips::{StripQuery, resolve_strip};
+
+#[derive(Deserialize)]
+pub struct BookmarksQuery {
+    pub collection: Option<String>,
+    pub tag: Option<String>,
+    pub safe: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct NewBookmark {
+    pub endpoint: String,
+    pub date: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub note: Option<String>,
+    #[serde(default)]
+    pub collections: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct NewCollection {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    pub format: Option<String>,
+    pub safe: Option<String>,
+}
+
+/// A bookmark as safe mode shows it: `None` for comics it hides, a safe title otherwise.
+fn safe_bookmark(state: &AppState, mut bookmark: Bookmark, safe: bool) -> Option<Bookmark> {
+    if !safe {
+        return Some(bookmark);
+    }
+    if !state.is_safe(&bookmark.strip.endpoint) {
+        return None;
+    }
+    bookmark.strip = bookmark.strip.into_safe();
+    Some(bookmark)
+}
+
+fn safe_export(state: &AppState, mut export: CollectionExport, safe: bool) -> CollectionExport {
+    export.bookmarks = export
+        .bookmarks
+        .into_iter()
+        .filter_map(|b| safe_bookmark(state, b, safe))
+        .collect();
+    export
+}
+
+fn safe_shared(state: &AppState, mut shared: SharedCollection, safe: bool) -> SharedCollection {
+    if safe {
+        shared.bookmarks = shared
+            .bookmarks
+            .into_iter()
+            .filter(|b| state.is_safe(&b.strip.endpoint))
+            .map(|mut b| {
+                b.strip = b.strip.into_safe();
+                b
+            })
+            .collect();
+    }
+    shared
+}
+
+pub async fn list_bookmarks(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<BookmarksQuery>,
+) -> Json<Vec<Bookmark>> {
+    let safe = state.safe_mode(query.safe.as_deref());
+    let bookmarks = state
+        .bookmarks
+        .list(query.collection.as_deref(), query.tag.as_deref())
+        .await;
+    Json(
+        bookmarks
+            .into_iter()
+            .filter_map(|b| safe_bookmark(&state, b, safe))
+            .collect(),
+    )
+}
+
+pub async fn create_bookmark(
+    State(state): State<Arc<AppState>>,
+    Json(body): Json<NewBookmark>,
+) -> Result<(StatusCode, Json<Bookmark>)> {
+    let source = state
+        .sources
+        .find(&body.endpoint)
+        .ok_or_else(|| PanelsError::NotFound(format!("unknown comic: {}", body.endpoint)))?;
+
+    let strip = resolve_strip(
+        &state,
+        source,
+        &body.endpoint,
+        &body.date,
+        state.safe_mode(None),
+    )
+    .await?;
+
+    let image = match source.proxy_image(&strip.image_url).await {
+        Ok(image) => Some(image),
+        Err(e) => {
+            warn!(endpoint = %strip.endpoint, date = %strip.date, "failed to store bookmark image: {}", e);
+            None
+        }
+    };
+
+    let note = body.note.filter(|n| !n.is_empty());
+    let bookmark = state
+        .bookmarks
+        .add(strip, body.tags, note, body.collections, image)
+        .await?;
+    Ok((StatusCode::CREATED, Json(bookmark)))
+}
+
+pub async fn get_bookmark(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<StripQuery>,
+) -> Result<Json<Bookmark>> {
+    let safe = state.safe_mode(query.safe.as_deref());
+    state
+        .bookmarks
+        .get(&id)
+        .await
+        .and_then(|b| safe_bookmark(&state, b, safe))
+        .map(Json)
+        .ok_or_else(|| PanelsError::NotFound(format!("unknown bookmark: {}", id)))
+}
+
+pub async fn update_bookmark(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(patch): Json<BookmarkPatch>,
+) -> Result<Json<Bookmark>> {
+    state
+        .bookmarks
+        .update(&id, patch)
+        .await?
+        .map(Json)
+        .ok_or_else(|| PanelsError::NotFound(format!("unknown bookmark: {}", id)))
+}
+
+pub async fn delete_bookmark(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode> {
+    if state.bookmarks.remove(&id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(PanelsError::NotFound(format!("unknown bookmark: {}", id)))
+    }
+}
+
+pub async fn bookmark_image(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<StripQuery>,
+) -> Result<impl IntoResponse> {
+    let safe = state.safe_mode(query.safe.as_deref());
+    let bookmark = state
+        .bookmarks
+        .get(&id)
+        .await
+        .and_then(|b| safe_bookmark(&state, b, safe))
+        .ok_or_else(|| PanelsError::NotFound(format!("unknown bookmark: {}", id)))?;
+    stored_image(&state, &bookmark).await
+}
+
+/// The bookmark's stored image, or the live one if it was bookmarked without.
+async fn stored_image(state: &AppState, bookmark: &Bookmark) -> Result<impl IntoResponse + use<>> {
+    let (bytes, content_type) = match state.bookmarks.image(&bookmark.id).await {
+        Some(image) => image,
+        None => {
+            // Bookmarks created while the upstream image was unreachable fall back to the live source
+            let source = state
+                .sources
+                .find(&bookmark.strip.endpoint)
+                .ok_or_else(|| {
+                    PanelsError::NotFound(format!("unknown comic: {}", bookmark.strip.endpoint))
+                })?;
+            source.proxy_image(&bookmark.strip.image_url).await?
+        }
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type),
+            (
+                header::CACHE_CONTROL,
+                "public, max-age=86400, s-maxage=604800".to_string(),
+            ),
+        ],
+        bytes,
+    ))
+}
+
+pub async fn list_collections(State(state): State<Arc<AppState>>) -> Json<Vec<Collection>> {
+    Json(state.bookmarks.list_collections().await)
+}
+
+pub async fn create_collection(
+    State(state): State<Arc<AppState>>,
+    Json(body): Json<NewCollection>,
+) -> Result<(StatusCode, Json<Collection>)> {
+    let name = body.name.trim().to_string();
+    if name.is_empty() {
+        return Err(PanelsError::InvalidParam(
+            "collection name must not be empty".into(),
+        ));
+    }
+    let description = body.description.filter(|d| !d.is_empty());
+    let collection = state.bookmarks.create_collection(name, description).await?;
+    Ok((StatusCode::CREATED, Json(collection)))
+}
+
+pub async fn get_collection(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<StripQuery>,
+) -> Result<Json<CollectionExport>> {
+    let safe = state.safe_mode(query.safe.as_deref());
+    state
+        .bookmarks
+        .export_collection(&id)
+        .await
+        .map(|export| Json(safe_export(&state, export, safe)))
+        .ok_or_else(|| PanelsError::NotFound(format!("unknown collection: {}", id)))
+}
+
+pub async fn update_collection(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(patch): Json<CollectionPatch>,
+) -> Result<Json<Collection>> {
+    state
+        .bookmarks
+        .update_collection(&id, patch)
+        .await?
+        .map(Json)
+        .ok_or_else(|| PanelsError::NotFound(format!("unknown collection: {}", id)))
+}
+
+pub async fn delete_collection(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode> {
+    if state.bookmarks.remove_collection(&id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(PanelsError::NotFound(format!("unknown collection: {}", id)))
+    }
+}
+
+pub async fn export_collection(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response> {
+    let safe = state.safe_mode(query.safe.as_deref());
+    let export = state
+        .bookmarks
+        .export_collection(&id)
+        .await
+        .map(|export| safe_export(&state, export, safe))
+        .ok_or_else(|| PanelsError::NotFound(format!("unknown collection: {}", id)))?;
+
+    let (body, content_type, extension) = match query.format.as_deref().unwrap_or("json") {
+        "json" => (
+            serde_json::to_string_pretty(&export).map_err(anyhow::Error::from)?,
+            "application/json",
+            "json",
+        ),
+        "html" => (
+            render_collection_html(&shared_view(&state, &id, safe).await?),
+            "text/html; charset=utf-8",
+            "html",
+        ),
+        other => {
+            return Err(PanelsError::InvalidParam(format!(
+                "unsupported export format: {} (expected json or html)",
+                other
+            )));
+        }
+    };
+
+    let disposition = format!("attachment; filename=\"collection-{}.{}\"", id, extension);
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+async fn shared_view(state: &AppState, id: &str, safe: bool) -> Result<SharedCollection> {
+    state
+        .bookmarks
+        .shared_view(id)
+        .await
+        .map(|shared| safe_shared(state, shared, safe))
+        .ok_or_else(|| PanelsError::NotFound(format!("unknown collection: {}", id)))
+}
+
+pub async fn shared_collection(
+    State(state): State<Arc<AppState>>,
+    Path(token): Path<String>,
+    Query(query): Query<StripQuery>,
+) -> Result<Json<SharedCollection>> {
+    let safe = state.safe_mode(query.safe.as_deref());
+    state
+        .bookmarks
+        .export_shared(&token)
+        .await
+        .map(|shared| Json(safe_shared(&state, shared, safe)))
+        .ok_or_else(|| PanelsError::NotFound("unknown shared collection".into()))
+}
+
+pub async fn shared_collection_page(
+    State(state): State<Arc<AppState>>,
+    Path(token): Path<String>,
+    Query(query): Query<StripQuery>,
+) -> Result<Html<String>> {
+    let safe = state.safe_mode(query.safe.as_deref());
+    state
+        .bookmarks
+        .export_shared(&token)
+        .await
+        .map(|shared| Html(render_collection_html(&safe_shared(&state, shared, safe))))
+        .ok_or_else(|| PanelsError::NotFound("unknown shared collection".into()))
+}
+
+pub async fn shared_image(
+    State(state): State<Arc<AppState>>,
+    Path((token, index)): Path<(String, usize)>,
+    Query(query): Query<StripQuery>,
+) -> Result<impl IntoResponse> {
+    let safe = state.safe_mode(query.safe.as_deref());
+    let bookmark = state
+        .bookmarks
+        .shared_bookmark(&token, index)
+        .await
+        .and_then(|b| safe_bookmark(&state, b, safe))
+        .ok_or_else(|| PanelsError::NotFound("unknown shared image".into()))?;
+    stored_image(&state, &bookmark).await
+}
@@ -1,46 +1,256 @@
 use axum::Json;
 use axum::extract::{Query, State};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
 use std::sync::Arc;
 
 use crate::AppState;
-use crate::error::Result;
+use crate::directory::{Facets, facets, has_any_tag, match_score};
+use crate::error::{PanelsError, Result};
 use crate::models::ComicWithTags;
 
 #[derive(Deserialize)]
 pub struct ComicsQuery {
     pub search: Option<String>,
     pub tag: Option<String>,
+    pub source: Option<String>,
+    pub sort: Option<String>,
+    pub order: Option<String>,
+    pub page: Option<usize>,
+    pub per_page: Option<usize>,
+    pub safe: Option<String>,
+    /// Wraps the results in a page with totals and facet counts
+    pub facets: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ComicsPage {
+    pub total: usize,
+    pub page: usize,
+    pub per_page: usize,
+    pub results: Vec<ComicWithTags>,
+    pub facets: Facets,
+}
+
+/// A bare array by default, as `/api/comics` has always returned; the page with `facets=1`.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum ComicsResponse {
+    List(Vec<ComicWithTags>),
+    Page(ComicsPage),
+}
+
+enum SortKey {
+    /// The order of `comics.json`
+    Catalog,
+    Relevance,
+    Title,
+    StartDate,
+    Popularity,
+}
+
+fn parse_sort(sort: Option<&str>, has_search: bool) -> Result<SortKey> {
+    match sort {
+        None if has_search => Ok(SortKey::Relevance),
+        None => Ok(SortKey::Catalog),
+        Some("relevance") => Ok(SortKey::Relevance),
+        Some("title") => Ok(SortKey::Title),
+        Some("start_date") | Some("startDate") => Ok(SortKey::StartDate),
+        Some("popularity") => Ok(SortKey::Popularity),
+        Some(other) => Err(PanelsError::InvalidParam(format!(
+            "unknown sort: {} (expected relevance, title, start_date or popularity)",
+            other
+        ))),
+    }
+}
+
+fn by_title(a: &ComicWithTags, b: &ComicWithTags) -> Ordering {
+    a.comic
+        .title
+        .to_lowercase()
+        .cmp(&b.comic.title.to_lowercase())
+}
+
+/// Sorts scored matches in place; ties go by title, ascending.
+fn sort_comics(
+    matched: &mut [(f64, ComicWithTags)],
+    sort: SortKey,
+    descending: bool,
+    views: impl Fn(&str) -> u64,
+) {
+    if matches!(sort, SortKey::Catalog) {
+        if descending {
+            matched.reverse();
+        }
+        return;
+    }
+    matched.sort_by(|(score_a, a), (score_b, b)| {
+        let primary = match sort {
+            SortKey::Catalog => Ordering::Equal,
+            SortKey::Relevance => score_a.partial_cmp(score_b).unwrap_or(Ordering::Equal),
+            SortKey::Title => by_title(a, b),
+            // Comics without a start date sort last either way
+            SortKey::StartDate => match (&a.comic.start_date, &b.comic.start_date) {
+                (Some(x), Some(y)) => x.cmp(y),
+                (Some(_), None) if descending => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) if descending => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            },
+            SortKey::Popularity => views(&a.comic.endpoint).cmp(&views(&b.comic.endpoint)),
+        };
+        let primary = if descending {
+            primary.reverse()
+        } else {
+            primary
+        };
+        primary.then_with(|| by_title(a, b))
+    });
 }
 
 pub async fn list_comics(
     State(state): State<Arc<AppState>>,
     Query(query): Query<ComicsQuery>,
-) -> Result<Json<Vec<ComicWithTags>>> {
-    let mut results: Vec<ComicWithTags> = state
+) -> Result<Json<ComicsResponse>> {
+    let search = query
+        .search
+        .as_deref()
+        .map(str::trim)
+        .filter(|s| !s.is_empty());
+    let sort = parse_sort(query.sort.as_deref(), search.is_some())?;
+    let descending = match query.order.as_deref() {
+        None => matches!(sort, SortKey::Relevance | SortKey::Popularity),
+        Some("asc") => false,
+        Some("desc") => true,
+        Some(other) => {
+            return Err(PanelsError::InvalidParam(format!(
+                "unknown order: {} (expected asc or desc)",
+                other
+            )));
+        }
+    };
+
+    let catalog = state.catalog.get();
+    let safe = state.safe_mode(query.safe.as_deref());
+    let mut matched: Vec<(f64, ComicWithTags)> = catalog
         .comics
         .iter()
-        .map(|comic| {
-            let tags = state.tags.get(&comic.endpoint).cloned().unwrap_or_default();
-            ComicWithTags {
-                comic: comic.clone(),
-                tags,
+        .filter_map(|comic| {
+            let tags = catalog
+                .tags
+                .get(&comic.endpoint)
+                .cloned()
+                .unwrap_or_default();
+            if safe && !catalog.taxonomy.is_safe(comic, &tags) {
+                return None;
             }
+            let score = match search {
+                Some(text) => match_score(text, comic)?,
+                None => 0.0,
+            };
+            Some((
+                score,
+                ComicWithTags {
+                    comic: comic.clone(),
+                    tags,
+                },
+            ))
         })
         .collect();
 
-    if let Some(ref search) = query.search {
-        let search_lower = search.to_lowercase();
-        results.retain(|c| {
-            c.comic.title.to_lowercase().contains(&search_lower)
-                || c.comic.endpoint.to_lowercase().contains(&search_lower)
-        });
+    // A tag filter also matches synonyms and every tag below it in the taxonomy
+    let tag_filter = query.tag.as_deref().map(|t| catalog.taxonomy.expand(t));
+
+    let facets = facets(
+        matched.iter().map(|(_, c)| c),
+        tag_filter.as_deref(),
+        query.source.as_deref(),
+    );
+
+    if let Some(ref tags) = tag_filter {
+        matched.retain(|(_, c)| has_any_tag(c, tags));
+    }
+
+    if let Some(ref source) = query.source {
+        matched.retain(|(_, c)| c.comic.source.eq_ignore_ascii_case(source));
+    }
+
+    sort_comics(&mut matched, sort, descending, |endpoint| {
+        state.usage.views(endpoint)
+    });
+
+    let total = matched.len();
+    let per_page = query.per_page.filter(|&n| n > 0).unwrap_or(total.max(1));
+    let page = query.page.unwrap_or(1).max(1);
+    let results = matched
+        .into_iter()
+        .skip((page - 1).saturating_mul(per_page))
+        .take(per_page)
+        .map(|(_, comic)| comic)
+        .collect();
+
+    if !matches!(query.facets.as_deref(), Some("1" | "true" | "yes")) {
+        return Ok(Json(ComicsResponse::List(results)));
+    }
+    Ok(Json(ComicsResponse::Page(ComicsPage {
+        total,
+        page,
+        per_page,
+        results,
+        facets,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comics(titles: &[&str]) -> Vec<(f64, ComicWithTags)> {
+        titles
+            .iter()
+            .map(|title| {
+                let comic = serde_json::from_value(serde_json::json!({
+                    "endpoint": title.to_lowercase(),
+                    "title": title,
+                    "author": null,
+                    "available": true,
+                    "startDate": null,
+                }))
+                .unwrap();
+                (
+                    0.0,
+                    ComicWithTags {
+                        comic,
+                        tags: vec![],
+                    },
+                )
+            })
+            .collect()
     }
 
-    if let Some(ref tag) = query.tag {
-        let tag_lower = tag.to_lowercase();
-        results.retain(|c| c.tags.iter().any(|t| t.to_lowercase() == tag_lower));
+    fn titles(matched: &[(f64, ComicWithTags)]) -> Vec<&str> {
+        matched
+            .iter()
+            .map(|(_, c)| c.comic.title.as_str())
+            .collect()
     }
 
-    Ok(Json(results))
+    #[test]
+    fn sorts_by_title_either_way_and_keeps_catalog_order_by_default() {
+        let mut matched = comics(&["Nancy", "garfield", "Zits"]);
+        sort_comics(&mut matched, SortKey::Catalog, false, |_| 0);
+        assert_eq!(titles(&matched), ["Nancy", "garfield", "Zits"]);
+
+        sort_comics(&mut matched, SortKey::Title, true, |_| 0);
+        assert_eq!(titles(&matched), ["Zits", "Nancy", "garfield"]);
+        sort_comics(&mut matched, SortKey::Title, false, |_| 0);
+        assert_eq!(titles(&matched), ["garfield", "Nancy", "Zits"]);
+
+        sort_comics(&mut matched, SortKey::Popularity, true, |e| {
+            (e == "nancy") as u64
+        });
+        assert_eq!(titles(&matched), ["Nancy", "garfield", "Zits"]);
+    }
 }
@@ -0,0 +1,101 @@
+use axum::body::{Body, Bytes};
+use axum::extract::{Path, Query, State};
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use chrono::NaiveDate;
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio::sync::{Semaphore, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{info, warn};
+
+use crate::AppState;
+use crate::error::{PanelsError, Result};
+use crate::export::{ExportFormat, ExportRequest, export};
+
+/// Exports running at once; the rest wait their turn so upstream sees a steady trickle
+static EXPORTS: Semaphore = Semaphore::const_new(2);
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub format: Option<String>,
+    pub safe: Option<String>,
+}
+
+fn parse_date(name: &str, value: Option<&str>) -> Result<NaiveDate> {
+    let value = value.ok_or_else(|| PanelsError::InvalidParam(format!("{} is required", name)))?;
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| PanelsError::InvalidDate(format!("{}={} is not YYYY-MM-DD", name, value)))
+}
+
+pub async fn export_comic(
+    State(state): State<Arc<AppState>>,
+    Path(endpoint): Path<String>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response> {
+    let comic = state
+        .catalog
+        .get()
+        .comic(&endpoint)
+        .cloned()
+        .filter(|_| state.sources.find(&endpoint).is_some())
+        .ok_or_else(|| PanelsError::NotFound(format!("unknown comic: {}", endpoint)))?;
+    if state.safe_mode(query.safe.as_deref()) && !state.is_safe(&endpoint) {
+        return Err(PanelsError::NotFound(format!(
+            "{} is not available in safe mode",
+            endpoint
+        )));
+    }
+
+    let from = parse_date("from", query.from.as_deref())?;
+    let to = parse_date("to", query.to.as_deref())?;
+    let format = ExportFormat::parse(query.format.as_deref())?;
+    let mut request = ExportRequest::new(&comic, from, to, format)?;
+    request.placeholders = state.catalog.get().placeholders.clone();
+    let disposition = format!("attachment; filename=\"{}\"", request.file_name());
+
+    let permit = EXPORTS
+        .acquire()
+        .await
+        .map_err(|e| PanelsError::Internal(e.into()))?;
+    let (tx, rx) = mpsc::channel::<std::io::Result<Bytes>>(2);
+    tokio::spawn(async move {
+        let _permit = permit;
+        let Some(source) = state.sources.find(&request.endpoint) else {
+            return;
+        };
+        let result = export(source, &request, |chunk| {
+            let tx = tx.clone();
+            async move {
+                tx.send(Ok(Bytes::from(chunk)))
+                    .await
+                    .map_err(|_| anyhow::anyhow!("client went away"))
+            }
+        })
+        .await;
+        match result {
+            Ok(summary) => info!(
+                endpoint = %request.endpoint,
+                pages = summary.pages,
+                missing = summary.missing.len(),
+                "exported {}",
+                request.file_name()
+            ),
+            Err(e) => {
+                warn!(endpoint = %request.endpoint, "export failed: {}", e);
+                let _ = tx.send(Err(std::io::Error::other(e.to_string()))).await;
+            }
+        }
+    });
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        Body::from_stream(ReceiverStream::new(rx)),
+    )
+        .into_response())
+}
@@ -1,10 +1,17 @@
+pub mod admin;
+pub mod bookmarks;
 pub mod comics;
+pub mod export;
+pub mod panels;
+pub mod permalink;
 pub mod proxy;
 pub mod recommendations;
+pub mod search;
 pub mod strips;
+pub mod tags;
 
 use axum::Router;
-use axum::routing::get;
+use axum::routing::{get, post};
 use std::sync::Arc;
 use tower_http::cors::CorsLayer;
 use tower_http::services::{ServeDir, ServeFile};
@@ -18,15 +25,56 @@ pub fn build_router(state: Arc<AppState>) -> Router {
     Router::new()
         .route("/api/health", get(health))
         .route("/api/comics", get(comics::list_comics))
+        .route("/api/tags", get(tags::list_tags))
         .route(
             "/api/recommendations",
             get(recommendations::get_recommendations),
         )
+        .route("/api/search", get(search::search_strips))
         .route("/api/comics/{endpoint}/{date}", get(strips::get_strip))
+        .route("/api/comics/{endpoint}/export", get(export::export_comic))
         .route(
             "/api/comics/{endpoint}/{date}/image",
             get(proxy::proxy_image),
         )
+        .route(
+            "/api/comics/{endpoint}/{date}/panels",
+            get(panels::get_panels),
+        )
+        .route(
+            "/api/bookmarks",
+            get(bookmarks::list_bookmarks).post(bookmarks::create_bookmark),
+        )
+        .route(
+            "/api/bookmarks/{id}",
+            get(bookmarks::get_bookmark)
+                .patch(bookmarks::update_bookmark)
+                .delete(bookmarks::delete_bookmark),
+        )
+        .route("/api/bookmarks/{id}/image", get(bookmarks::bookmark_image))
+        .route(
+            "/api/collections",
+            get(bookmarks::list_collections).post(bookmarks::create_collection),
+        )
+        .route(
+            "/api/collections/{id}",
+            get(bookmarks::get_collection)
+                .patch(bookmarks::update_collection)
+                .delete(bookmarks::delete_collection),
+        )
+        .route(
+            "/api/collections/{id}/export",
+            get(bookmarks::export_collection),
+        )
+        .route("/api/shared/{token}", get(bookmarks::shared_collection))
+        .route(
+            "/api/shared/{token}/images/{index}",
+            get(bookmarks::shared_image),
+        )
+        .route("/shared/{token}", get(bookmarks::shared_collection_page))
+        .route("/c/{endpoint}/{date}", get(permalink::permalink_page))
+        .route("/api/admin/reload", post(admin::reload_catalog))
+        .route("/api/admin/duplicates", get(admin::list_duplicates))
         .nest_service("/api/badges", ServeDir::new(badges_dir))
         .route_service("/feed", ServeFile::new("web/index.html"))
         .fallback_service(ServeDir::new("web").fallback(ServeFile::new("web/index.html")))
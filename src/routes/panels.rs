@@ -0,0 +1,96 @@
+use axum::Json;
+use axum::body::Bytes;
+use axum::extract::{Path, Query, State};
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::AppState;
+use crate::error::{PanelsError, Result};
+use crate::imaging;
+use crate::imaging::panels::{PanelBox, PanelLayout, detect_panels};
+use crate::routes::strips::{StripQuery, record_image_hash, resolve_strip};
+use crate::sources::ComicSource;
+
+/// Upstream image bytes, fetched at most once per request.
+pub(crate) struct Original {
+    pub bytes: Bytes,
+    pub content_type: String,
+}
+
+pub(crate) async fn fetch_original(source: &dyn ComicSource, image_url: &str) -> Result<Original> {
+    let (bytes, content_type) = source.proxy_image(image_url).await?;
+    Ok(Original {
+        bytes: Bytes::from(bytes),
+        content_type,
+    })
+}
+
+/// Panels of the image at `image_url`, detected once per image and cached. Fills `original`
+/// if the image had to be fetched.
+pub(crate) async fn panel_layout(
+    state: &AppState,
+    source: &dyn ComicSource,
+    image_url: &str,
+    original: &mut Option<Original>,
+) -> Result<Arc<PanelLayout>> {
+    if let Some(layout) = state.caches.panel_layouts.get(image_url).await {
+        return Ok(layout);
+    }
+    if original.is_none() {
+        *original = Some(fetch_original(source, image_url).await?);
+    }
+    let bytes = original
+        .as_ref()
+        .map(|o| o.bytes.clone())
+        .unwrap_or_default();
+    let layout = tokio::task::spawn_blocking(move || {
+        imaging::decode(&bytes).map(|image| detect_panels(&image))
+    })
+    .await
+    .map_err(|e| PanelsError::Internal(e.into()))?
+    .map_err(|e| PanelsError::ScrapeFailed(format!("could not read strip image: {}", e)))?;
+
+    let layout = Arc::new(layout);
+    state
+        .caches
+        .panel_layouts
+        .insert(image_url.to_string(), Arc::clone(&layout))
+        .await;
+    Ok(layout)
+}
+
+#[derive(Serialize)]
+pub struct StripPanels {
+    pub endpoint: String,
+    pub date: String,
+    pub width: u32,
+    pub height: u32,
+    pub panels: Vec<PanelBox>,
+}
+
+pub async fn get_panels(
+    State(state): State<Arc<AppState>>,
+    Path((endpoint, date)): Path<(String, String)>,
+    Query(query): Query<StripQuery>,
+) -> Result<Json<StripPanels>> {
+    let source = state
+        .sources
+        .find(&endpoint)
+        .ok_or_else(|| PanelsError::NotFound(format!("unknown comic: {}", endpoint)))?;
+
+    let safe = state.safe_mode(query.safe.as_deref());
+    let strip = resolve_strip(&state, source, &endpoint, &date, safe).await?;
+    let mut original = None;
+    let layout = panel_layout(&state, source, &strip.image_url, &mut original).await?;
+    if let Some(original) = original {
+        record_image_hash(&state, &strip, &original.bytes).await?;
+    }
+
+    Ok(Json(StripPanels {
+        endpoint: strip.endpoint,
+        date: strip.date,
+        width: layout.width,
+        height: layout.height,
+        panels: layout.panels.clone(),
+    }))
+}
@@ -0,0 +1,223 @@
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{Html, IntoResponse, Response};
+use regex::Regex;
+use std::sync::{Arc, LazyLock};
+use tracing::debug;
+
+use crate::AppState;
+use crate::html;
+use crate::models::ComicStrip;
+use crate::routes::strips::resolve_strip;
+
+const INDEX_PATH: &str = "web/index.html";
+
+pub struct PermalinkMeta {
+    pub title: String,
+    pub description: String,
+    pub url: String,
+    pub image_url: String,
+}
+
+fn encode_path_segment(segment: &str) -> String {
+    let mut out = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+pub fn strip_path(endpoint: &str, date: &str) -> String {
+    format!(
+        "/c/{}/{}",
+        encode_path_segment(endpoint),
+        encode_path_segment(date)
+    )
+}
+
+fn base_url(state: &AppState, headers: &HeaderMap) -> String {
+    if let Some(ref url) = state.config.public_url {
+        return url.trim_end_matches('/').to_string();
+    }
+    let host = headers
+        .get("x-forwarded-host")
+        .or_else(|| headers.get(header::HOST))
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("localhost");
+    let scheme = headers
+        .get("x-forwarded-proto")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("http");
+    format!("{}://{}", scheme, host)
+}
+
+fn build_meta(state: &AppState, strip: &ComicStrip, base: &str) -> PermalinkMeta {
+    let catalog = state.catalog.get();
+    let comic = catalog.comic(&strip.endpoint);
+    let comic_title = comic.map(|c| c.title.as_str()).unwrap_or(&strip.title);
+    let byline = comic
+        .and_then(|c| c.author.as_deref())
+        .map(|a| format!(" by {}", a))
+        .unwrap_or_default();
+
+    let title = if strip.title == comic_title {
+        format!("{} — {}", comic_title, strip.date)
+    } else {
+        format!("{}: {} — {}", comic_title, strip.title, strip.date)
+    };
+
+    PermalinkMeta {
+        title,
+        description: strip.metadata.alt_text.clone().unwrap_or_else(|| {
+            format!(
+                "{}{}, {}. Read it on panels.",
+                comic_title, byline, strip.date
+            )
+        }),
+        url: format!("{}{}", base, strip_path(&strip.endpoint, &strip.date)),
+        image_url: format!(
+            "{}/api/comics/{}/{}/image",
+            base,
+            encode_path_segment(&strip.endpoint),
+            encode_path_segment(&strip.date)
+        ),
+    }
+}
+
+/// The site-wide preview tags of `index.html`, one per line
+static SITE_TAGS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r#"(?m)^[ \t]*(<title>.*</title>|<meta (property="og:[^"]*"|name="twitter:[^"]*"|name="description")[^>]*>|<link rel="canonical"[^>]*>)[ \t]*\r?\n"#,
+    )
+    .unwrap()
+});
+
+pub fn render_permalink_page(template: &str, meta: &PermalinkMeta) -> String {
+    // Drop the site-wide preview tags so crawlers only see the strip's own title, description
+    // and image
+    let stripped = SITE_TAGS.replace_all(template, "");
+
+    let title = html::escape(&meta.title);
+    let description = html::escape(&meta.description);
+    let url = html::escape(&meta.url);
+    let image = html::escape(&meta.image_url);
+    let head = format!(
+        r#"<head>
+  <title>{title}</title>
+  <meta name="description" content="{description}">
+  <link rel="canonical" href="{url}">
+  <meta property="og:type" content="article">
+  <meta property="og:site_name" content="panels">
+  <meta property="og:title" content="{title}">
+  <meta property="og:description" content="{description}">
+  <meta property="og:url" content="{url}">
+  <meta property="og:image" content="{image}">
+  <meta property="og:image:alt" content="{title}">
+  <meta name="twitter:card" content="summary_large_image">
+  <meta name="twitter:title" content="{title}">
+  <meta name="twitter:description" content="{description}">
+  <meta name="twitter:image" content="{image}">
+  <meta name="twitter:image:alt" content="{title}">"#
+    );
+
+    stripped.replacen("<head>", &head, 1)
+}
+
+pub async fn permalink_page(
+    State(state): State<Arc<AppState>>,
+    Path((endpoint, date)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    let template = match tokio::fs::read_to_string(INDEX_PATH).await {
+        Ok(t) => t,
+        Err(_) => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    let strip = match state.sources.find(&endpoint) {
+        Some(source) => resolve_strip(&state, source, &endpoint, &date, state.safe_mode(None))
+            .await
+            .ok(),
+        None => None,
+    };
+
+    let Some(strip) = strip else {
+        debug!(endpoint, date, "permalink strip not found");
+        return (StatusCode::NOT_FOUND, Html(template)).into_response();
+    };
+
+    let base = base_url(&state, &headers);
+    let meta = build_meta(&state, &strip, &base);
+    Html(render_permalink_page(&template, &meta)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="UTF-8">
+  <title>panels by shehab</title>
+  <meta name="description" content="site">
+  <link rel="canonical" href="https://panels.ashmod.dev/">
+  <meta property="og:title" content="panels by shehab">
+  <meta name="twitter:card" content="summary_large_image">
+  <link rel="stylesheet" href="/style.css">
+</head>
+<body><script src="/app.js"></script></body>
+</html>"#;
+
+    #[test]
+    fn strip_path_encodes_number_dates() {
+        assert_eq!(strip_path("xkcd", "#927"), "/c/xkcd/%23927");
+        assert_eq!(
+            strip_path("garfield", "1993-05-12"),
+            "/c/garfield/1993-05-12"
+        );
+    }
+
+    #[test]
+    fn renders_strip_preview_tags() {
+        let meta = PermalinkMeta {
+            title: "Garfield — 1993-05-12".into(),
+            description: "Garfield by Jim Davis".into(),
+            url: "https://panels.example/c/garfield/1993-05-12".into(),
+            image_url: "https://panels.example/api/comics/garfield/1993-05-12/image".into(),
+        };
+        let page = render_permalink_page(TEMPLATE, &meta);
+
+        assert!(page.contains(r#"<meta property="og:title" content="Garfield — 1993-05-12">"#));
+        assert!(page.contains(
+            r#"<meta property="og:image" content="https://panels.example/api/comics/garfield/1993-05-12/image">"#
+        ));
+        assert!(page.contains(
+            r#"<link rel="canonical" href="https://panels.example/c/garfield/1993-05-12">"#
+        ));
+        assert!(!page.contains("panels by shehab"));
+        assert_eq!(page.matches("twitter:card").count(), 1);
+        assert!(page.contains(r#"<script src="/app.js"></script>"#));
+    }
+
+    #[test]
+    fn renders_against_shipped_index() {
+        let template = std::fs::read_to_string(INDEX_PATH).expect("should read web/index.html");
+        let meta = PermalinkMeta {
+            title: "xkcd: Standards — #927".into(),
+            description: "xkcd".into(),
+            url: "http://localhost:3000/c/xkcd/%23927".into(),
+            image_url: "http://localhost:3000/api/comics/xkcd/%23927/image".into(),
+        };
+        let page = render_permalink_page(&template, &meta);
+
+        assert_eq!(page.matches("og:image\"").count(), 1);
+        assert_eq!(page.matches("<title>").count(), 1);
+        assert_eq!(page.matches("rel=\"canonical\"").count(), 1);
+        assert!(page.contains("/app.js"));
+    }
+}
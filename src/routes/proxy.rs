@@ -1,43 +1,199 @@
-use axum::extract::{Path, State};
-use axum::http::header;
+use axum::body::Bytes;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, HeaderValue, header};
 use axum::response::IntoResponse;
-use std::sync::Arc;
+use serde::Deserialize;
+use std::sync::{Arc, LazyLock};
+use tokio::sync::Semaphore;
+use tracing::warn;
 
 use crate::AppState;
+use crate::cache::ImageVariant;
 use crate::error::{PanelsError, Result};
+use crate::imaging::resize::{Fit, OutputFormat, Variant, transform};
+use crate::routes::panels::{fetch_original, panel_layout};
+use crate::routes::strips::{record_image_hash, resolve_strip};
+
+/// Variants encoded at once. An AVIF encode of a large strip takes a while, so a burst of
+/// uncached requests waits here instead of filling the blocking pool.
+static TRANSFORMS: LazyLock<Semaphore> =
+    LazyLock::new(|| Semaphore::new(std::thread::available_parallelism().map_or(2, |n| n.get())));
+
+#[derive(Deserialize)]
+pub struct ImageQuery {
+    pub safe: Option<String>,
+    pub w: Option<u32>,
+    pub h: Option<u32>,
+    pub fit: Option<String>,
+    pub format: Option<String>,
+    /// Index into the strip's detected panels, from 0
+    pub panel: Option<usize>,
+}
+
+fn parse_fit(fit: Option<&str>) -> Result<Fit> {
+    match fit {
+        None | Some("contain") => Ok(Fit::Contain),
+        Some("cover") => Ok(Fit::Cover),
+        Some("fill") => Ok(Fit::Fill),
+        Some(other) => Err(PanelsError::InvalidParam(format!(
+            "unknown fit: {} (expected contain, cover or fill)",
+            other
+        ))),
+    }
+}
+
+enum FormatChoice {
+    Explicit(OutputFormat),
+    /// Pick from the `Accept` header
+    Negotiate,
+    /// Keep the source's format family
+    Source,
+}
+
+fn parse_format(format: Option<&str>, resizing: bool) -> Result<FormatChoice> {
+    match format {
+        None if resizing => Ok(FormatChoice::Negotiate),
+        None => Ok(FormatChoice::Source),
+        Some("auto") => Ok(FormatChoice::Negotiate),
+        Some("png") => Ok(FormatChoice::Explicit(OutputFormat::Png)),
+        Some("jpeg") | Some("jpg") => Ok(FormatChoice::Explicit(OutputFormat::Jpeg)),
+        Some("webp") => Ok(FormatChoice::Explicit(OutputFormat::Webp)),
+        Some("avif") => Ok(FormatChoice::Explicit(OutputFormat::Avif)),
+        Some(other) => Err(PanelsError::InvalidParam(format!(
+            "unknown format: {} (expected png, jpeg, webp, avif or auto)",
+            other
+        ))),
+    }
+}
+
+/// The variant asked for, if any, and whether it depends on the `Accept` header.
+fn requested_variant(query: &ImageQuery, headers: &HeaderMap) -> Result<Option<(Variant, bool)>> {
+    if query.w == Some(0) || query.h == Some(0) {
+        return Err(PanelsError::InvalidParam(
+            "w and h must be at least 1".into(),
+        ));
+    }
+    let resizing = query.w.is_some() || query.h.is_some();
+    let fit = parse_fit(query.fit.as_deref())?;
+    let (format, negotiated) = match parse_format(query.format.as_deref(), resizing)? {
+        FormatChoice::Explicit(format) => (Some(format), false),
+        FormatChoice::Negotiate => {
+            let accept = headers
+                .get(header::ACCEPT)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("");
+            (OutputFormat::from_accept(accept), true)
+        }
+        FormatChoice::Source if !resizing && query.panel.is_none() => return Ok(None),
+        FormatChoice::Source => (None, false),
+    };
+    Ok(Some((
+        Variant::new(query.w, query.h, fit, format),
+        negotiated,
+    )))
+}
 
 pub async fn proxy_image(
     State(state): State<Arc<AppState>>,
     Path((endpoint, date)): Path<(String, String)>,
+    Query(query): Query<ImageQuery>,
+    headers: HeaderMap,
 ) -> Result<impl IntoResponse> {
     let source = state
         .sources
         .find(&endpoint)
         .ok_or_else(|| PanelsError::NotFound(format!("unknown comic: {}", endpoint)))?;
 
-    let strip = match date.as_str() {
-        "latest" => source.fetch_latest(&endpoint).await?,
-        "random" => source.fetch_random(&endpoint).await?,
-        date_str => source.fetch_strip(&endpoint, date_str).await?,
+    let requested = requested_variant(&query, &headers)?;
+    let safe = state.safe_mode(query.safe.as_deref());
+    let strip = resolve_strip(&state, source, &endpoint, &date, safe).await?;
+
+    let cache_control = if date == "random" {
+        "no-store"
+    } else {
+        "public, max-age=86400, s-maxage=604800"
     };
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static(cache_control),
+    );
 
-    let strip = strip.ok_or_else(|| {
-        PanelsError::NotFound(format!("no strip found for {}/{}", endpoint, date))
-    })?;
+    let mut original = None;
+    let Some((mut variant, negotiated)) = requested else {
+        let original = fetch_original(source, &strip.image_url).await?;
+        record_image_hash(&state, &strip, &original.bytes).await?;
+        if let Ok(value) = HeaderValue::from_str(&original.content_type) {
+            response_headers.insert(header::CONTENT_TYPE, value);
+        }
+        return Ok((response_headers, original.bytes));
+    };
+    if negotiated {
+        response_headers.insert(header::VARY, HeaderValue::from_static("Accept"));
+    }
+    if let Some(index) = query.panel {
+        let layout = panel_layout(&state, source, &strip.image_url, &mut original).await?;
+        let panel = layout.panels.get(index).ok_or_else(|| {
+            PanelsError::NotFound(format!(
+                "{}/{} has {} panels",
+                endpoint,
+                strip.date,
+                layout.panels.len()
+            ))
+        })?;
+        variant.crop = Some(*panel);
+    }
 
-    let (bytes, content_type) = source.proxy_image(&strip.image_url).await?;
+    let key = variant.cache_key(&strip.image_url);
+    if let Some(cached) = state.caches.image_variants.get(&key).await {
+        response_headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(cached.content_type),
+        );
+        return Ok((response_headers, cached.bytes));
+    }
 
-    let cache_control = if date == "random" {
-        "no-store".to_string()
-    } else {
-        "public, max-age=86400, s-maxage=604800".to_string()
+    let original = match original {
+        Some(original) => original,
+        None => fetch_original(source, &strip.image_url).await?,
     };
+    record_image_hash(&state, &strip, &original.bytes).await?;
+    // Requests for the same variant that arrive while it is encoded wait for that encode
+    let input = original.bytes.clone();
+    let transformed = state
+        .caches
+        .image_variants
+        .try_get_with(key, async move {
+            let _permit = TRANSFORMS.acquire().await?;
+            let (bytes, content_type) =
+                tokio::task::spawn_blocking(move || transform(&input, &variant)).await??;
+            anyhow::Ok(ImageVariant {
+                bytes: Bytes::from(bytes),
+                content_type,
+            })
+        })
+        .await;
 
-    Ok((
-        [
-            (header::CONTENT_TYPE, content_type),
-            (header::CACHE_CONTROL, cache_control),
-        ],
-        bytes,
-    ))
+    match transformed {
+        Ok(made) => {
+            response_headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(made.content_type),
+            );
+            Ok((response_headers, made.bytes))
+        }
+        Err(e) if variant.crop.is_some() => Err(PanelsError::Internal(anyhow::anyhow!("{:#}", e))),
+        // Serve what upstream sent rather than failing the strip over a format we can't decode
+        Err(e) => {
+            warn!(
+                endpoint,
+                date, "could not resize {}: {}", strip.image_url, e
+            );
+            response_headers.remove(header::VARY);
+            if let Ok(value) = HeaderValue::from_str(&original.content_type) {
+                response_headers.insert(header::CONTENT_TYPE, value);
+            }
+            Ok((response_headers, original.bytes))
+        }
+    }
 }
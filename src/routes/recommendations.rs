@@ -1,107 +1,104 @@
 use axum::Json;
 use axum::extract::{Query, State};
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::sync::Arc;
 
 use crate::AppState;
-use crate::error::Result;
-use crate::models::ComicWithTags;
+use crate::error::{PanelsError, Result};
+use crate::recommend::{RecommendOptions, Recommendation, Recommender, Weights};
 
 #[derive(Deserialize)]
 pub struct RecommendationsQuery {
     pub selected: Option<String>,
     pub limit: Option<usize>,
+    pub exclude_tags: Option<String>,
+    pub exclude_sources: Option<String>,
+    pub available: Option<bool>,
+    pub diversity: Option<f64>,
+    pub seed: Option<u64>,
+    pub safe: Option<String>,
+    /// Opaque per-page token, so a feed built up a comic at a time is recorded once
+    pub session: Option<String>,
+}
+
+fn split_list(value: Option<&str>) -> Vec<&str> {
+    value
+        .unwrap_or("")
+        .split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .collect()
 }
 
 pub async fn get_recommendations(
     State(state): State<Arc<AppState>>,
     Query(query): Query<RecommendationsQuery>,
-) -> Result<Json<Vec<ComicWithTags>>> {
+) -> Result<Json<Vec<Recommendation>>> {
     let limit = query.limit.unwrap_or(10);
 
-    let selected_endpoints: Vec<&str> = query
-        .selected
-        .as_deref()
-        .unwrap_or("")
-        .split(',')
-        .map(|s| s.trim())
-        .filter(|s| !s.is_empty())
-        .collect();
+    let selected_endpoints = split_list(query.selected.as_deref());
 
     if selected_endpoints.is_empty() {
         return Ok(Json(vec![]));
     }
 
-    let mut selected_tags: HashMap<&str, usize> = HashMap::new();
-    let mut selected_authors: Vec<&str> = Vec::new();
-    let mut selected_sources: Vec<&str> = Vec::new();
-
-    for endpoint in &selected_endpoints {
-        if let Some(tags) = state.tags.get(*endpoint) {
-            for tag in tags {
-                *selected_tags.entry(tag.as_str()).or_insert(0) += 1;
-            }
-        }
-        if let Some(comic) = state.comics.iter().find(|c| c.endpoint == *endpoint) {
-            if let Some(ref author) = comic.author {
-                selected_authors.push(author.as_str());
-            }
-            selected_sources.push(comic.source.as_str());
-        }
+    let diversity = query.diversity.unwrap_or(0.0);
+    if !(0.0..=1.0).contains(&diversity) {
+        return Err(PanelsError::InvalidParam(format!(
+            "diversity must be between 0 and 1, got {}",
+            diversity
+        )));
     }
 
-    let has_espanol = selected_tags.contains_key("en-espanol");
-
-    // Score all non-selected comics
-    let mut scored: Vec<(f64, ComicWithTags)> = state
-        .comics
+    // Without an explicit seed, ties stay stable for the same selection
+    let seed = query.seed.unwrap_or_else(|| {
+        let mut sorted = selected_endpoints.clone();
+        sorted.sort_unstable();
+        let mut hasher = DefaultHasher::new();
+        sorted.hash(&mut hasher);
+        hasher.finish()
+    });
+
+    let options = RecommendOptions {
+        exclude_tags: split_list(query.exclude_tags.as_deref())
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+        exclude_sources: split_list(query.exclude_sources.as_deref())
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+        require_available: query.available.unwrap_or(true),
+        safe: state.safe_mode(query.safe.as_deref()),
+        diversity,
+        seed,
+    };
+
+    let catalog = state.catalog.get();
+    let known: Vec<&str> = selected_endpoints
         .iter()
-        .filter(|c| c.available && !selected_endpoints.contains(&c.endpoint.as_str()))
-        .filter_map(|comic| {
-            let tags = state.tags.get(&comic.endpoint).cloned().unwrap_or_default();
-
-            // Skip espanol comics unless user has one selected
-            if !has_espanol && tags.iter().any(|t| t == "en-espanol") {
-                return None;
-            }
-
-            let mut score: f64 = 0.0;
-
-            for tag in &tags {
-                if let Some(&count) = selected_tags.get(tag.as_str()) {
-                    score += count as f64;
-                }
-            }
-
-            if let Some(ref author) = comic.author
-                && selected_authors.contains(&author.as_str())
-            {
-                score += 2.0;
-            }
-
-            if selected_sources.contains(&comic.source.as_str()) {
-                score += 0.5;
-            }
-
-            if score > 0.0 {
-                Some((
-                    score,
-                    ComicWithTags {
-                        comic: comic.clone(),
-                        tags,
-                    },
-                ))
-            } else {
-                None
-            }
-        })
+        .copied()
+        .filter(|e| catalog.comic(e).is_some())
         .collect();
-
-    scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
-
-    scored.truncate(limit);
-
-    let results: Vec<ComicWithTags> = scored.into_iter().map(|(_, comic)| comic).collect();
-    Ok(Json(results))
+    state
+        .usage
+        .record_selection(query.session.as_deref(), &known);
+
+    let model = state.usage.co_occurrence();
+    let views = state.usage.all_views();
+    let recommender = Recommender {
+        comics: &catalog.comics,
+        tags: &catalog.tags,
+        taxonomy: &catalog.taxonomy,
+        model: &model,
+        views: &views,
+        weights: Weights::from_config(&state.config),
+    };
+
+    Ok(Json(recommender.recommend(
+        &selected_endpoints,
+        &options,
+        limit,
+    )))
 }
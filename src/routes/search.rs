@@ -0,0 +1,71 @@
+use axum::Json;
+use axum::extract::{Query, State};
+use chrono::NaiveDate;
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::AppState;
+use crate::error::{PanelsError, Result};
+use crate::search::{SearchFilter, SearchResults};
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    pub q: Option<String>,
+    pub comic: Option<String>,
+    pub source: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub limit: Option<usize>,
+    pub safe: Option<String>,
+}
+
+fn check_date(date: &Option<String>) -> Result<()> {
+    if let Some(date) = date
+        && NaiveDate::parse_from_str(date, "%Y-%m-%d").is_err()
+    {
+        return Err(PanelsError::InvalidDate(format!(
+            "invalid date format: {}",
+            date
+        )));
+    }
+    Ok(())
+}
+
+pub async fn search_strips(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<SearchResults>> {
+    let text = query.q.as_deref().unwrap_or("").trim().to_string();
+    if text.is_empty() {
+        return Err(PanelsError::InvalidParam("missing search query `q`".into()));
+    }
+    check_date(&query.from)?;
+    check_date(&query.to)?;
+
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    let exclude_endpoints = if state.safe_mode(query.safe.as_deref()) {
+        state
+            .catalog
+            .get()
+            .comics
+            .iter()
+            .filter(|c| !state.is_safe(&c.endpoint))
+            .map(|c| c.endpoint.clone())
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let filter = SearchFilter {
+        endpoint: query.comic,
+        source: query.source,
+        from: query.from,
+        to: query.to,
+        exclude_endpoints,
+    };
+
+    let search = Arc::clone(&state.search);
+    let results = tokio::task::spawn_blocking(move || search.search(&text, &filter, limit))
+        .await
+        .map_err(anyhow::Error::from)??;
+    Ok(Json(results))
+}
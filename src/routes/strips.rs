@@ -1,31 +1,146 @@
 use axum::Json;
-use axum::extract::{Path, State};
-use std::sync::Arc;
+use axum::extract::{Path, Query, State};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::sync::{Arc, LazyLock, Mutex};
+use tracing::warn;
 
 use crate::AppState;
 use crate::error::{PanelsError, Result};
+use crate::image_hashes::StripHash;
+use crate::imaging;
+use crate::imaging::hash::{ImageHashes, find_placeholder};
 use crate::models::ComicStrip;
+use crate::routes::panels::fetch_original;
+use crate::sources::ComicSource;
+
+pub(crate) async fn resolve_strip(
+    state: &AppState,
+    source: &dyn ComicSource,
+    endpoint: &str,
+    date: &str,
+    safe: bool,
+) -> Result<ComicStrip> {
+    if safe && !state.is_safe(endpoint) {
+        return Err(PanelsError::NotFound(format!(
+            "{} is not available in safe mode",
+            endpoint
+        )));
+    }
+
+    let strip = match date {
+        "latest" => source.fetch_latest(endpoint).await?,
+        "random" => source.fetch_random(endpoint).await?,
+        date_str => source.fetch_strip(endpoint, date_str).await?,
+    };
+
+    let mut strip = strip.ok_or_else(|| {
+        PanelsError::NotFound(format!("no strip found for {}/{}", endpoint, date))
+    })?;
+
+    if let Some(recorded) = state.image_hashes.get(endpoint, &strip.date)
+        && recorded.image_url == strip.image_url
+    {
+        reject_placeholder(state, &strip, &recorded.hashes)?;
+        strip.metadata.image_hash = Some(recorded.hashes);
+    }
+
+    state.search.record(&strip, &state.source_name(endpoint));
+    Ok(if safe { strip.into_safe() } else { strip })
+}
+
+/// Strips whose image is a known placeholder are reported as missing.
+fn reject_placeholder(state: &AppState, strip: &ComicStrip, hashes: &ImageHashes) -> Result<()> {
+    match find_placeholder(&state.catalog.get().placeholders, hashes) {
+        Some(placeholder) => Err(PanelsError::NotFound(format!(
+            "no strip found for {}/{}: upstream serves the {} placeholder",
+            strip.endpoint, strip.date, placeholder.name
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Hashes a freshly fetched strip image and records it. Fails with `NotFound` if the image
+/// is a known placeholder. Images that can't be decoded are passed over.
+pub(crate) async fn record_image_hash(
+    state: &AppState,
+    strip: &ComicStrip,
+    bytes: &axum::body::Bytes,
+) -> Result<()> {
+    let hashes = match state.image_hashes.get(&strip.endpoint, &strip.date) {
+        Some(recorded) if recorded.image_url == strip.image_url => recorded.hashes,
+        _ => {
+            let bytes = bytes.clone();
+            let hashed = tokio::task::spawn_blocking(move || {
+                imaging::decode(&bytes).map(|image| ImageHashes::of(&image))
+            })
+            .await
+            .map_err(|e| PanelsError::Internal(e.into()))?;
+            let Ok(hashes) = hashed else {
+                return Ok(());
+            };
+            state.image_hashes.record(
+                &strip.endpoint,
+                &strip.date,
+                StripHash {
+                    image_url: strip.image_url.clone(),
+                    hashes,
+                },
+            );
+            hashes
+        }
+    };
+    reject_placeholder(state, strip, &hashes)
+}
+
+/// Strips whose image is being hashed in the background, by `endpoint:date`
+static CHECKING: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(Default::default);
+
+/// Hashes the image of a strip that was never proxied, in the background so the strip is
+/// answered without waiting on a full image download. Once a placeholder is found this way
+/// the strip is reported missing; the image route hashes for itself if it is asked first.
+fn check_unseen_image(state: &Arc<AppState>, strip: &ComicStrip) {
+    if strip.metadata.image_hash.is_some() || state.catalog.get().placeholders.is_empty() {
+        return;
+    }
+    let key = format!("{}:{}", strip.endpoint, strip.date);
+    if !CHECKING.lock().unwrap().insert(key.clone()) {
+        return;
+    }
+    let state = Arc::clone(state);
+    let strip = strip.clone();
+    tokio::spawn(async move {
+        if let Some(source) = state.sources.find(&strip.endpoint) {
+            let checked = match fetch_original(source, &strip.image_url).await {
+                Ok(original) => record_image_hash(&state, &strip, &original.bytes).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = checked {
+                warn!(endpoint = %strip.endpoint, date = %strip.date, "image check: {}", e);
+            }
+        }
+        CHECKING.lock().unwrap().remove(&key);
+    });
+}
+
+#[derive(Deserialize)]
+pub struct StripQuery {
+    pub safe: Option<String>,
+}
 
 pub async fn get_strip(
     State(state): State<Arc<AppState>>,
     Path((endpoint, date)): Path<(String, String)>,
+    Query(query): Query<StripQuery>,
 ) -> Result<Json<ComicStrip>> {
     let source = state
         .sources
         .find(&endpoint)
         .ok_or_else(|| PanelsError::NotFound(format!("unknown comic: {}", endpoint)))?;
 
-    let strip = match date.as_str() {
-        "latest" => source.fetch_latest(&endpoint).await?,
-        "random" => source.fetch_random(&endpoint).await?,
-        date_str => source.fetch_strip(&endpoint, date_str).await?,
-    };
-
-    match strip {
-        Some(s) => Ok(Json(s)),
-        None => Err(PanelsError::NotFound(format!(
-            "no strip found for {}/{}",
-            endpoint, date
-        ))),
-    }
+    let safe = state.safe_mode(query.safe.as_deref());
+    let strip = resolve_strip(&state, source, &endpoint, &date, safe).await?;
+    check_unseen_image(&state, &strip);
+    state.usage.record_view(&endpoint);
+    Ok(Json(strip))
 }
@@ -0,0 +1,11 @@
+use axum::Json;
+use axum::extract::State;
+use std::sync::Arc;
+
+use crate::AppState;
+use crate::taxonomy::TagNode;
+
+pub async fn list_tags(State(state): State<Arc<AppState>>) -> Json<Vec<TagNode>> {
+    let catalog = state.catalog.get();
+    Json(catalog.taxonomy.tree(&catalog.tags))
+}
@@ -0,0 +1,528 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::NaiveDate;
+use serde::Serialize;
+use tantivy::collector::{Count, DocSetCollector, TopDocs};
+use tantivy::directory::MmapDirectory;
+use tantivy::query::{BooleanQuery, Occur, Query, QueryParser, RangeQuery, TermQuery};
+use tantivy::schema::{Field, IndexRecordOption, STORED, STRING, Schema, TEXT, Value};
+use tantivy::snippet::SnippetGenerator;
+use tantivy::{Index, IndexReader, IndexWriter, ReloadPolicy, TantivyDocument, Term, doc};
+use tracing::{debug, info, warn};
+
+use crate::error::{PanelsError, Result};
+use crate::models::ComicStrip;
+
+const WRITER_HEAP_BYTES: usize = 15_000_000;
+const COMMIT_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy)]
+struct Fields {
+    id: Field,
+    endpoint: Field,
+    source: Field,
+    date: Field,
+    day: Field,
+    title: Field,
+    alt: Field,
+    transcript: Field,
+    image_url: Field,
+}
+
+fn build_schema() -> (Schema, Fields) {
+    let mut builder = Schema::builder();
+    let fields = Fields {
+        id: builder.add_text_field("id", STRING | STORED),
+        endpoint: builder.add_text_field("endpoint", STRING | STORED),
+        source: builder.add_text_field("source", STRING | STORED),
+        date: builder.add_text_field("date", STRING | STORED),
+        // Calendar day of the strip; numbered strips use their publication date when known
+        day: builder.add_text_field("day", STRING),
+        title: builder.add_text_field("title", TEXT | STORED),
+        alt: builder.add_text_field("alt", TEXT | STORED),
+        transcript: builder.add_text_field("transcript", TEXT | STORED),
+        image_url: builder.add_text_field("image_url", STORED),
+    };
+    (builder.build(), fields)
+}
+
+/// Text of a resolved strip that gets indexed. Sources that expose alt text
+/// or transcripts fill in the optional fields.
+#[derive(Debug, Clone)]
+pub struct SearchDocument {
+    pub endpoint: String,
+    pub source: String,
+    pub date: String,
+    pub day: Option<String>,
+    pub title: String,
+    pub alt: Option<String>,
+    pub transcript: Option<String>,
+    pub image_url: String,
+}
+
+impl SearchDocument {
+    pub fn from_strip(strip: &ComicStrip, source: &str) -> Self {
+        let day = NaiveDate::parse_from_str(&strip.date, "%Y-%m-%d")
+            .ok()
+            .map(|_| strip.date.clone())
+            .or_else(|| strip.metadata.published.clone());
+        Self {
+            endpoint: strip.endpoint.clone(),
+            source: source.to_string(),
+            date: strip.date.clone(),
+            day,
+            title: strip.title.clone(),
+            alt: strip.metadata.alt_text.clone(),
+            transcript: strip.metadata.transcript.clone(),
+            image_url: strip.image_url.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SearchFilter {
+    pub endpoint: Option<String>,
+    pub source: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub exclude_endpoints: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchHit {
+    pub endpoint: String,
+    pub source: String,
+    pub date: String,
+    pub title: String,
+    pub image_url: String,
+    pub score: f32,
+    /// HTML snippets with matches wrapped in `<b>`, keyed by field name
+    pub highlights: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchResults {
+    pub total: usize,
+    pub hits: Vec<SearchHit>,
+}
+
+pub struct SearchIndex {
+    index: Index,
+    reader: IndexReader,
+    /// Only taken by commits; documents reach it through `queue`
+    writer: Mutex<IndexWriter>,
+    fields: Fields,
+    queue: Sender<(String, TantivyDocument)>,
+    queued: Mutex<Receiver<(String, TantivyDocument)>>,
+}
+
+impl SearchIndex {
+    pub fn open(data_dir: &str) -> anyhow::Result<Self> {
+        let path = Path::new(data_dir).join("index");
+        std::fs::create_dir_all(&path)
+            .map_err(|e| anyhow::anyhow!("failed to create {}: {}", path.display(), e))?;
+        let (schema, fields) = build_schema();
+        let directory = MmapDirectory::open(&path)?;
+        let index = Index::open_or_create(directory, schema)?;
+        Self::from_index(index, fields)
+    }
+
+    pub fn in_memory() -> anyhow::Result<Self> {
+        let (schema, fields) = build_schema();
+        Self::from_index(Index::create_in_ram(schema), fields)
+    }
+
+    fn from_index(index: Index, fields: Fields) -> anyhow::Result<Self> {
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::OnCommitWithDelay)
+            .try_into()?;
+        let writer = index.writer_with_num_threads(1, WRITER_HEAP_BYTES)?;
+        let (queue, queued) = channel();
+        Ok(Self {
+            index,
+            reader,
+            writer: Mutex::new(writer),
+            fields,
+            queue,
+            queued: Mutex::new(queued),
+        })
+    }
+
+    fn strip_id(endpoint: &str, date: &str) -> String {
+        format!("{}:{}", endpoint, date)
+    }
+
+    /// Queues a strip to be added or replaced, without waiting on the writer. Changes become
+    /// searchable after the next commit.
+    pub fn add(&self, document: &SearchDocument) -> anyhow::Result<()> {
+        let f = self.fields;
+        let id = Self::strip_id(&document.endpoint, &document.date);
+
+        let mut doc = doc!(
+            f.id => id.clone(),
+            f.endpoint => document.endpoint.clone(),
+            f.source => document.source.clone(),
+            f.date => document.date.clone(),
+            f.title => document.title.clone(),
+            f.image_url => document.image_url.clone(),
+        );
+        if let Some(ref day) = document.day {
+            doc.add_text(f.day, day);
+        }
+        if let Some(ref alt) = document.alt {
+            doc.add_text(f.alt, alt);
+        }
+        if let Some(ref transcript) = document.transcript {
+            doc.add_text(f.transcript, transcript);
+        }
+
+        self.queue
+            .send((id, doc))
+            .map_err(|_| anyhow::anyhow!("search index queue is closed"))
+    }
+
+    pub fn record(&self, strip: &ComicStrip, source: &str) {
+        if let Err(e) = self.add(&SearchDocument::from_strip(strip, source)) {
+            warn!(endpoint = %strip.endpoint, date = %strip.date, "failed to index strip: {}", e);
+        }
+    }
+
+    /// Writes the queued documents and commits them. Blocks on disk, so request handlers
+    /// leave it to the committer.
+    pub fn commit(&self) -> anyhow::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        let queued: Vec<_> = self.queued.lock().unwrap().try_iter().collect();
+        if queued.is_empty() {
+            return Ok(());
+        }
+        let count = queued.len();
+        for (id, doc) in queued {
+            writer.delete_term(Term::from_field_text(self.fields.id, &id));
+            writer.add_document(doc)?;
+        }
+        writer.commit()?;
+        drop(writer);
+        self.reader.reload()?;
+        debug!(count, "committed search index");
+        Ok(())
+    }
+
+    /// Commits pending documents in the background so request handlers never wait on disk.
+    pub fn spawn_committer(self: &Arc<Self>) {
+        let index = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(COMMIT_INTERVAL);
+            loop {
+                interval.tick().await;
+                let index = Arc::clone(&index);
+                let result = tokio::task::spawn_blocking(move || index.commit()).await;
+                if let Ok(Err(e)) = result {
+                    warn!("failed to commit search index: {}", e);
+                }
+            }
+        });
+    }
+
+    pub fn count_endpoint(&self, endpoint: &str) -> usize {
+        let query = TermQuery::new(
+            Term::from_field_text(self.fields.endpoint, endpoint),
+            IndexRecordOption::Basic,
+        );
+        self.reader.searcher().search(&query, &Count).unwrap_or(0)
+    }
+
+    /// The dates of the strips of `endpoint` that are in the committed index.
+    fn indexed_dates(&self, endpoint: &str) -> HashSet<String> {
+        let searcher = self.reader.searcher();
+        let query = TermQuery::new(
+            Term::from_field_text(self.fields.endpoint, endpoint),
+            IndexRecordOption::Basic,
+        );
+        let Ok(addresses) = searcher.search(&query, &DocSetCollector) else {
+            return HashSet::new();
+        };
+        addresses
+            .into_iter()
+            .filter_map(|address| searcher.doc::<TantivyDocument>(address).ok())
+            .filter_map(|doc| {
+                doc.get_first(self.fields.date)
+                    .and_then(|v| v.as_str())
+                    .map(str::to_string)
+            })
+            .collect()
+    }
+
+    /// Indexes the strips of sources' local archives that the index doesn't have yet.
+    pub fn seed<F>(&self, strips: &[ComicStrip], source_of: F) -> anyhow::Result<()>
+    where
+        F: Fn(&str) -> String,
+    {
+        let mut by_endpoint: BTreeMap<&str, Vec<&ComicStrip>> = BTreeMap::new();
+        for strip in strips {
+            by_endpoint.entry(&strip.endpoint).or_default().push(strip);
+        }
+
+        for (endpoint, group) in by_endpoint {
+            let indexed = self.indexed_dates(endpoint);
+            let missing: Vec<_> = group
+                .into_iter()
+                .filter(|strip| !indexed.contains(&strip.date))
+                .collect();
+            if missing.is_empty() {
+                continue;
+            }
+            let source = source_of(endpoint);
+            for strip in &missing {
+                self.add(&SearchDocument::from_strip(strip, &source))?;
+            }
+            self.commit()?;
+            info!(endpoint, count = missing.len(), "seeded search index");
+        }
+        Ok(())
+    }
+
+    pub fn search(&self, text: &str, filter: &SearchFilter, limit: usize) -> Result<SearchResults> {
+        let f = self.fields;
+        let parser = QueryParser::for_index(&self.index, vec![f.title, f.alt, f.transcript]);
+        let text_query = parser
+            .parse_query(text)
+            .map_err(|e| PanelsError::InvalidParam(format!("invalid search query: {}", e)))?;
+
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = vec![(Occur::Must, text_query.box_clone())];
+        if let Some(ref endpoint) = filter.endpoint {
+            clauses.push((
+                Occur::Must,
+                Box::new(TermQuery::new(
+                    Term::from_field_text(f.endpoint, endpoint),
+                    IndexRecordOption::Basic,
+                )),
+            ));
+        }
+        if let Some(ref source) = filter.source {
+            clauses.push((
+                Occur::Must,
+                Box::new(TermQuery::new(
+                    Term::from_field_text(f.source, source),
+                    IndexRecordOption::Basic,
+                )),
+            ));
+        }
+        for endpoint in &filter.exclude_endpoints {
+            clauses.push((
+                Occur::MustNot,
+                Box::new(TermQuery::new(
+                    Term::from_field_text(f.endpoint, endpoint),
+                    IndexRecordOption::Basic,
+                )),
+            ));
+        }
+        if filter.from.is_some() || filter.to.is_some() {
+            let lower = filter
+                .from
+                .as_deref()
+                .map_or(Bound::Unbounded, Bound::Included);
+            let upper = filter
+                .to
+                .as_deref()
+                .map_or(Bound::Unbounded, Bound::Included);
+            clauses.push((
+                Occur::Must,
+                Box::new(RangeQuery::new_str_bounds("day".to_string(), lower, upper)),
+            ));
+        }
+        let query = BooleanQuery::new(clauses);
+
+        let searcher = self.reader.searcher();
+        let (top, total) = searcher
+            .search(&query, &(TopDocs::with_limit(limit), Count))
+            .map_err(anyhow::Error::from)?;
+
+        let mut generators = Vec::new();
+        for (name, field) in [
+            ("title", f.title),
+            ("alt", f.alt),
+            ("transcript", f.transcript),
+        ] {
+            let generator = SnippetGenerator::create(&searcher, &*text_query, field)
+                .map_err(anyhow::Error::from)?;
+            generators.push((name, generator));
+        }
+
+        let mut hits = Vec::with_capacity(top.len());
+        for (score, address) in top {
+            let doc: TantivyDocument = searcher.doc(address).map_err(anyhow::Error::from)?;
+            let text = |field: Field| {
+                doc.get_first(field)
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default()
+                    .to_string()
+            };
+
+            let mut highlights = HashMap::new();
+            for (name, generator) in &generators {
+                let snippet = generator.snippet_from_doc(&doc);
+                if !snippet.is_empty() {
+                    highlights.insert(name.to_string(), snippet.to_html());
+                }
+            }
+
+            hits.push(SearchHit {
+                endpoint: text(f.endpoint),
+                source: text(f.source),
+                date: text(f.date),
+                title: text(f.title),
+                image_url: text(f.image_url),
+                score,
+                highlights,
+            });
+        }
+
+        Ok(SearchResults { total, hits })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::StripMetadata;
+
+    fn strip(endpoint: &str, date: &str, title: &str) -> ComicStrip {
+        ComicStrip {
+            endpoint: endpoint.into(),
+            title: title.into(),
+            date: date.into(),
+            image_url: format!("https://example.com/{}.gif", date),
+            source_url: "https://example.com".into(),
+            prev_date: None,
+            next_date: None,
+            metadata: StripMetadata::default(),
+        }
+    }
+
+    fn index_with(strips: &[(&str, &str, &str, &str)]) -> SearchIndex {
+        let index = SearchIndex::in_memory().unwrap();
+        for (endpoint, source, date, title) in strips {
+            index.record(&strip(endpoint, date, title), source);
+        }
+        index.commit().unwrap();
+        index
+    }
+
+    #[test]
+    fn finds_strips_by_title_with_highlights() {
+        let index = index_with(&[
+            (
+                "dilbert",
+                "dilbert",
+                "2017-05-27",
+                "Cyborg Makes Wally Unnecessary",
+            ),
+            ("dilbert", "dilbert", "2016-02-28", "Dilbert"),
+        ]);
+
+        let results = index.search("wally", &SearchFilter::default(), 10).unwrap();
+        assert_eq!(results.total, 1);
+        assert_eq!(results.hits[0].date, "2017-05-27");
+        assert_eq!(
+            results.hits[0].highlights.get("title").unwrap(),
+            "Cyborg Makes <b>Wally</b> Unnecessary"
+        );
+    }
+
+    #[test]
+    fn matches_alt_text_and_dates_numbered_strips() {
+        let index = SearchIndex::in_memory().unwrap();
+        let mut standards = strip("xkcd", "#927", "Standards");
+        standards.metadata.alt_text = Some("Fortunately, the charging one has been solved".into());
+        standards.metadata.published = Some("2011-08-17".into());
+        index.record(&standards, "xkcd");
+        index.commit().unwrap();
+
+        let filter = SearchFilter {
+            from: Some("2011-01-01".into()),
+            to: Some("2011-12-31".into()),
+            ..Default::default()
+        };
+        let results = index.search("charging", &filter, 10).unwrap();
+        assert_eq!(results.total, 1);
+        assert!(results.hits[0].highlights["alt"].contains("<b>charging</b>"));
+    }
+
+    #[test]
+    fn recording_never_waits_on_the_writer() {
+        let index = SearchIndex::in_memory().unwrap();
+        let writer = index.writer.lock().unwrap();
+        index.record(&strip("xkcd", "#927", "Standards"), "xkcd");
+        drop(writer);
+        index.commit().unwrap();
+        let results = index
+            .search("standards", &SearchFilter::default(), 10)
+            .unwrap();
+        assert_eq!(results.total, 1);
+    }
+
+    #[test]
+    fn recording_same_strip_twice_replaces_it() {
+        let index = index_with(&[
+            ("xkcd", "xkcd", "#927", "Standards"),
+            ("xkcd", "xkcd", "#927", "Standards"),
+        ]);
+        let results = index
+            .search("standards", &SearchFilter::default(), 10)
+            .unwrap();
+        assert_eq!(results.total, 1);
+    }
+
+    #[test]
+    fn filters_by_comic_source_and_date_range() {
+        let index = index_with(&[
+            ("dilbert", "dilbert", "2010-01-01", "Meeting"),
+            ("dilbert", "dilbert", "2020-01-01", "Meeting"),
+            ("phd", "phd", "#100", "Meeting"),
+        ]);
+
+        let by_source = SearchFilter {
+            source: Some("phd".into()),
+            ..Default::default()
+        };
+        assert_eq!(index.search("meeting", &by_source, 10).unwrap().total, 1);
+
+        let by_range = SearchFilter {
+            endpoint: Some("dilbert".into()),
+            from: Some("2015-01-01".into()),
+            to: Some("2021-12-31".into()),
+            ..Default::default()
+        };
+        let results = index.search("meeting", &by_range, 10).unwrap();
+        assert_eq!(results.total, 1);
+        assert_eq!(results.hits[0].date, "2020-01-01");
+
+        let excluding = SearchFilter {
+            exclude_endpoints: vec!["dilbert".into()],
+            ..Default::default()
+        };
+        assert_eq!(index.search("meeting", &excluding, 10).unwrap().total, 1);
+    }
+
+    #[test]
+    fn seed_adds_only_the_strips_not_indexed() {
+        let index = SearchIndex::in_memory().unwrap();
+        let mut strips = vec![strip("dilbert", "2017-05-27", "Wally")];
+        index.seed(&strips, |e| e.to_string()).unwrap();
+        index.seed(&strips, |e| e.to_string()).unwrap();
+        assert_eq!(index.count_endpoint("dilbert"), 1);
+
+        // The archive grew since the index was last seeded
+        strips.push(strip("dilbert", "2017-05-28", "Dogbert"));
+        index.seed(&strips, |e| e.to_string()).unwrap();
+        assert_eq!(index.count_endpoint("dilbert"), 2);
+    }
+}
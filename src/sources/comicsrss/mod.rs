@@ -1,59 +1,45 @@
+use std::sync::Arc;
+use std::time::Duration;
+
 use async_trait::async_trait;
-use rand::Rng;
-use regex::Regex;
+use chrono::NaiveDate;
 use tracing::{debug, info, warn};
 
-use crate::cache::Caches;
-use crate::error::{PanelsError, Result};
-use crate::http_client::{fetch_page, random_user_agent};
-use crate::models::{Comic, ComicStrip};
+use crate::catalog::Catalog;
+use crate::error::Result;
+use crate::feed::{Feed, FeedEntry, FeedFetcher};
+use crate::http_client::fetch_image;
+use crate::models::{ComicStrip, StripMetadata};
 use crate::sources::ComicSource;
+use crate::sources::history::{HistoryItem, StripHistory};
+
+const HISTORY_DIR: &str = "comicsrss";
+/// How long a feed is trusted before it is asked for again
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30 * 60);
 
 fn feed_url(slug: &str) -> String {
     format!("https://www.comicsrss.com/rss/{}.rss", slug)
 }
 
-fn parse_rss_items(xml: &str, endpoint: &str) -> Vec<ComicStrip> {
+/// The strips in a comicsrss.com feed, oldest first. They are linked to each other by the
+/// history they are merged into.
+fn strips_from_feed(feed: &Feed, endpoint: &str) -> Vec<ComicStrip> {
     let mut items = Vec::new();
 
-    let item_re = Regex::new(r"(?s)<item>(.*?)</item>").unwrap();
-    let title_re = Regex::new(r"(?s)<title><!\[CDATA\[(.*?)\]\]></title>").unwrap();
-    let title_plain_re = Regex::new(r"(?s)<title>(.*?)</title>").unwrap();
-    let link_re = Regex::new(r"<link>(.*?)</link>").unwrap();
-    let guid_re = Regex::new(r"<guid[^>]*>(.*?)</guid>").unwrap();
-    let img_re = Regex::new(r#"<img[^>]+src="([^"]+)""#).unwrap();
-    let pub_date_re = Regex::new(r"<pubDate>(.*?)</pubDate>").unwrap();
-
-    for item_match in item_re.captures_iter(xml) {
-        let item_xml = &item_match[1];
-
-        let image_url = img_re.captures(item_xml).map(|c| c[1].to_string());
-
-        let Some(image_url) = image_url else {
+    for entry in &feed.entries {
+        let Some(image_url) = entry.image() else {
             continue;
         };
 
-        let title = title_re
-            .captures(item_xml)
-            .map(|c| c[1].to_string())
-            .or_else(|| title_plain_re.captures(item_xml).map(|c| c[1].to_string()))
-            .unwrap_or_default();
-
-        let clean_title = title
+        let clean_title = entry
+            .title
             .split(" by ")
             .next()
-            .unwrap_or(&title)
+            .unwrap_or(&entry.title)
             .trim()
             .to_string();
 
-        let source_url = link_re
-            .captures(item_xml)
-            .map(|c| c[1].to_string())
-            .unwrap_or_default();
-
-        let date = extract_date_from_rss(item_xml, endpoint, &pub_date_re, &guid_re);
-
-        let Some(date) = date else {
+        let Some(date) = entry_date(entry) else {
             continue;
         };
 
@@ -61,161 +47,148 @@ fn parse_rss_items(xml: &str, endpoint: &str) -> Vec<ComicStrip> {
             endpoint: endpoint.to_string(),
             title: clean_title,
             date,
-            image_url,
-            source_url,
+            image_url: image_url.to_string(),
+            source_url: entry.link.clone().unwrap_or_default(),
             prev_date: None,
             next_date: None,
+            metadata: StripMetadata::default(),
         });
     }
 
     items.sort_by(|a, b| a.date.cmp(&b.date));
-    for i in 0..items.len() {
-        if i > 0 {
-            items[i].prev_date = Some(items[i - 1].date.clone());
-        }
-        if i + 1 < items.len() {
-            items[i].next_date = Some(items[i + 1].date.clone());
-        }
-    }
 
     items
 }
 
-fn extract_date_from_rss(
-    item_xml: &str,
-    _endpoint: &str,
-    pub_date_re: &Regex,
-    guid_re: &Regex,
-) -> Option<String> {
-    if let Some(caps) = guid_re.captures(item_xml) {
-        let guid = &caps[1];
-        let date_re = Regex::new(r"(\d{4}-\d{2}-\d{2})$").ok()?;
-        if let Some(date_caps) = date_re.captures(guid) {
-            return Some(date_caps[1].to_string());
-        }
+/// The strip date from the guid, which ends in it (`blondie2026-02-13`), or else the
+/// publication date.
+fn entry_date(entry: &FeedEntry) -> Option<String> {
+    if let Some(ref guid) = entry.id
+        && let Some(suffix) = guid.get(guid.len().saturating_sub(10)..)
+        && NaiveDate::parse_from_str(suffix, "%Y-%m-%d").is_ok()
+    {
+        return Some(suffix.to_string());
     }
-
-    if let Some(caps) = pub_date_re.captures(item_xml) {
-        let pub_date = &caps[1];
-        if let Ok(parsed) = chrono::DateTime::parse_from_rfc2822(pub_date) {
-            return Some(parsed.format("%Y-%m-%d").to_string());
-        }
-        let months = [
-            "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
-        ];
-        let date_parts_re = Regex::new(r"(\d{1,2})\s+(\w{3})\s+(\d{4})").ok()?;
-        if let Some(date_caps) = date_parts_re.captures(pub_date) {
-            let day: u32 = date_caps[1].parse().ok()?;
-            let month_str = &date_caps[2];
-            let year: i32 = date_caps[3].parse().ok()?;
-            let month = months.iter().position(|&m| m == month_str)? as u32 + 1;
-            return Some(format!("{:04}-{:02}-{:02}", year, month, day));
-        }
-    }
-
-    None
+    entry.date().map(|d| d.format("%Y-%m-%d").to_string())
 }
 
+/// Serves comics from their comicsrss.com feed, keeping every strip the feed has listed so
+/// dates that scrolled out of it stay reachable.
 pub struct ComicsRssSource {
     client: reqwest::Client,
-    comics: Vec<Comic>,
-    caches: Caches,
+    feeds: FeedFetcher,
+    catalog: Arc<Catalog>,
+    history: StripHistory,
 }
 
 impl ComicsRssSource {
-    pub fn new(client: reqwest::Client, comics: Vec<Comic>, caches: Caches) -> Self {
+    pub fn new(client: reqwest::Client, catalog: Arc<Catalog>) -> Self {
         Self {
+            feeds: FeedFetcher::new(client.clone()),
+            history: StripHistory::open(catalog.data_dir(), HISTORY_DIR),
             client,
-            comics,
-            caches,
+            catalog,
         }
     }
 
-    async fn fetch_feed(&self, endpoint: &str) -> Result<Vec<ComicStrip>> {
+    /// Reads the comic's feed into its history, returning how many strips were new.
+    async fn fetch_feed(&self, endpoint: &str) -> Result<usize> {
         let url = feed_url(endpoint);
-        let page = fetch_page(&self.client, &url, 1, 15000).await?;
-
-        let Some(page) = page else {
-            return Ok(vec![]);
+        let Some(feed) = self.feeds.fetch(&url).await? else {
+            return Ok(0);
         };
 
-        let items = parse_rss_items(&page.html, endpoint);
-
-        for item in &items {
-            let cache_key = format!("{}:{}", endpoint, item.date);
-            self.caches.strips.insert(cache_key, item.clone()).await;
-        }
-
+        let items = strips_from_feed(&feed, endpoint);
         if items.is_empty() {
             warn!(endpoint, "no items found in RSS feed");
         } else {
             debug!(endpoint, count = items.len(), "parsed RSS feed items");
         }
 
-        Ok(items)
+        let added = self
+            .history
+            .merge(
+                endpoint,
+                items
+                    .iter()
+                    .map(|strip| (strip.date.clone(), HistoryItem::from(strip))),
+            )
+            .await?;
+        if added > 0 {
+            info!(endpoint, added, "archived new comicsrss strips");
+        }
+        Ok(added)
+    }
+
+    /// Reads the feed if it wasn't lately, but serves the history if that fails.
+    async fn refresh_if_stale(&self, endpoint: &str) -> Result<()> {
+        if !self.history.is_stale(endpoint, REFRESH_INTERVAL) {
+            return Ok(());
+        }
+        self.history.mark_tried(endpoint);
+        match self.fetch_feed(endpoint).await {
+            Err(e) if !self.history.is_empty(endpoint) => {
+                warn!(
+                    endpoint,
+                    "serving strip history, feed refresh failed: {}", e
+                );
+                Ok(())
+            }
+            result => result.map(|_| ()),
+        }
     }
 }
 
 #[async_trait]
 impl ComicSource for ComicsRssSource {
     fn handles(&self, endpoint: &str) -> bool {
-        self.comics
-            .iter()
-            .any(|c| c.endpoint == endpoint && c.source == "comicsrss")
+        self.catalog
+            .get()
+            .comic(endpoint)
+            .is_some_and(|c| c.source == "comicsrss")
     }
 
     async fn fetch_strip(&self, endpoint: &str, date: &str) -> Result<Option<ComicStrip>> {
-        let cache_key = format!("{}:{}", endpoint, date);
-        if let Some(cached) = self.caches.strips.get(&cache_key).await {
-            debug!(endpoint, date, "comicsrss strip cache hit");
-            return Ok(Some(cached));
+        if let Some(strip) = self.history.strip(endpoint, date) {
+            debug!(endpoint, date, "comicsrss strip from history");
+            return Ok(Some(strip));
         }
-
-        let items = self.fetch_feed(endpoint).await?;
-        Ok(items.into_iter().find(|s| s.date == date))
+        self.refresh_if_stale(endpoint).await?;
+        Ok(self.history.strip(endpoint, date))
     }
 
     async fn fetch_latest(&self, endpoint: &str) -> Result<Option<ComicStrip>> {
-        let items = self.fetch_feed(endpoint).await?;
-        Ok(items.into_iter().last())
+        self.refresh_if_stale(endpoint).await?;
+        Ok(self.history.latest(endpoint))
     }
 
     async fn fetch_random(&self, endpoint: &str) -> Result<Option<ComicStrip>> {
-        let items = self.fetch_feed(endpoint).await?;
-        if items.is_empty() {
-            return Ok(None);
+        if self.history.is_empty(endpoint) {
+            self.refresh_if_stale(endpoint).await?;
         }
-        let idx = rand::thread_rng().gen_range(0..items.len());
-        info!(endpoint, date = %items[idx].date, "fetching random comicsrss strip");
-        Ok(Some(items[idx].clone()))
+        let strip = self.history.random(endpoint);
+        if let Some(ref strip) = strip {
+            info!(endpoint, date = %strip.date, "fetching random comicsrss strip");
+        }
+        Ok(strip)
     }
 
     async fn proxy_image(&self, image_url: &str) -> Result<(Vec<u8>, String)> {
-        let response = self
-            .client
-            .get(image_url)
-            .header("User-Agent", random_user_agent())
-            .send()
-            .await
-            .map_err(|e| PanelsError::ScrapeFailed(format!("failed to fetch image: {}", e)))?;
-
-        if !response.status().is_success() {
-            return Err(PanelsError::NotFound("image not found".into()));
-        }
-
-        let content_type = response
-            .headers()
-            .get("content-type")
-            .and_then(|v| v.to_str().ok())
-            .unwrap_or("image/gif")
-            .to_string();
+        fetch_image(&self.client, image_url).await
+    }
 
-        let bytes = response
-            .bytes()
-            .await
-            .map_err(|e| PanelsError::ScrapeFailed(format!("failed to read image bytes: {}", e)))?;
+    async fn poll(&self, endpoint: &str) -> Result<usize> {
+        self.fetch_feed(endpoint).await
+    }
 
-        Ok((bytes.to_vec(), content_type))
+    fn known_strips(&self) -> Vec<ComicStrip> {
+        self.catalog
+            .get()
+            .comics
+            .iter()
+            .filter(|c| c.source == "comicsrss")
+            .flat_map(|comic| self.history.strips(&comic.endpoint))
+            .collect()
     }
 }
 
@@ -246,17 +219,13 @@ mod tests {
 </channel>
 </rss>"#;
 
-        let items = parse_rss_items(xml, "blondie");
+        let feed = crate::feed::parse(xml).unwrap();
+        let items = strips_from_feed(&feed, "blondie");
         assert_eq!(items.len(), 2);
 
         assert_eq!(items[0].date, "2026-02-12");
         assert_eq!(items[1].date, "2026-02-13");
 
-        assert_eq!(items[0].prev_date, None);
-        assert_eq!(items[0].next_date, Some("2026-02-13".to_string()));
-        assert_eq!(items[1].prev_date, Some("2026-02-12".to_string()));
-        assert_eq!(items[1].next_date, None);
-
         assert!(items[0].image_url.contains("arcamax.com"));
         assert_eq!(items[0].title, "Blondie");
     }
@@ -1,21 +1,24 @@
 use std::collections::HashMap;
 use std::path::Path;
+use std::sync::Arc;
 
 use async_trait::async_trait;
 use chrono::NaiveDate;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
-use tracing::{debug, info, warn};
+use tracing::{debug, info};
 
-use crate::error::{PanelsError, Result};
-use crate::http_client::random_user_agent;
-use crate::models::ComicStrip;
+use crate::catalog::Catalog;
+use crate::error::Result;
+use crate::http_client::fetch_image;
+use crate::models::{ComicStrip, StripMetadata};
 use crate::sources::ComicSource;
+use crate::sources::wayback::{WaybackComic, load_archive};
 
 pub const FIRST_COMIC: &str = "1989-04-16";
 pub const LAST_COMIC: &str = "2023-03-12";
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DilbertCacheEntry {
     pub image_url: String,
     pub title: String,
@@ -25,25 +28,26 @@ pub fn dilbert_strip_url(date: &str) -> String {
     format!("https://dilbert.com/strip/{}", date)
 }
 
-fn load_dilbert_cache(data_dir: &str) -> HashMap<String, DilbertCacheEntry> {
+/// How `scrape_dilbert` rebuilds `dilbert_cache.json` from the Wayback Machine.
+pub fn archive_config() -> WaybackComic {
+    WaybackComic {
+        endpoint: "dilbert".to_string(),
+        url_pattern: "dilbert.com/strip/{date}".to_string(),
+        date_format: "%Y-%m-%d".to_string(),
+        image_selector: ".img-comic, img".to_string(),
+        image_filter: Some("assets.amuniversal.com".to_string()),
+        title_selector: Some(".comic-title-name".to_string()),
+        archived_until: NaiveDate::parse_from_str(LAST_COMIC, "%Y-%m-%d").ok(),
+    }
+}
+
+/// Reads `dilbert_cache.json`. A missing file is an empty cache; a malformed one is an error.
+pub fn load_dilbert_cache(data_dir: &str) -> anyhow::Result<HashMap<String, DilbertCacheEntry>> {
     let path = Path::new(data_dir).join("dilbert_cache.json");
-    match std::fs::read_to_string(&path) {
-        Ok(contents) => match serde_json::from_str(&contents) {
-            Ok(cache) => {
-                let cache: HashMap<String, DilbertCacheEntry> = cache;
-                info!(count = cache.len(), "loaded dilbert pre-built cache");
-                cache
-            }
-            Err(e) => {
-                warn!("failed to parse dilbert cache: {}", e);
-                HashMap::new()
-            }
-        },
-        Err(_) => {
-            info!("no dilbert cache file found");
-            HashMap::new()
-        }
+    if !path.exists() {
+        info!("no dilbert cache file found");
     }
+    Ok(load_archive(&path)?.into_iter().collect())
 }
 
 fn build_strip_from_cache(date_str: &str, entry: &DilbertCacheEntry) -> ComicStrip {
@@ -68,22 +72,22 @@ fn build_strip_from_cache(date_str: &str, entry: &DilbertCacheEntry) -> ComicStr
         source_url: dilbert_strip_url(date_str),
         prev_date,
         next_date,
+        metadata: StripMetadata::default(),
     }
 }
 
 pub struct DilbertSource {
     client: reqwest::Client,
-    prebuilt: HashMap<String, DilbertCacheEntry>,
+    catalog: Arc<Catalog>,
 }
 
 impl DilbertSource {
-    pub fn new(client: reqwest::Client, data_dir: &str) -> Self {
-        let prebuilt = load_dilbert_cache(data_dir);
-        Self { client, prebuilt }
+    pub fn new(client: reqwest::Client, catalog: Arc<Catalog>) -> Self {
+        Self { client, catalog }
     }
 
     fn fetch_strip_for_date(&self, date_str: &str) -> Result<Option<ComicStrip>> {
-        match self.prebuilt.get(date_str) {
+        match self.catalog.get().dilbert.get(date_str) {
             Some(entry) => {
                 debug!(date = date_str, "dilbert strip from cache");
                 Ok(Some(build_strip_from_cache(date_str, entry)))
@@ -108,11 +112,12 @@ impl ComicSource for DilbertSource {
     }
 
     async fn fetch_random(&self, _endpoint: &str) -> Result<Option<ComicStrip>> {
-        if self.prebuilt.is_empty() {
+        let catalog = self.catalog.get();
+        if catalog.dilbert.is_empty() {
             return Ok(None);
         }
 
-        let keys: Vec<&String> = self.prebuilt.keys().collect();
+        let keys: Vec<&String> = catalog.dilbert.keys().collect();
         let idx = rand::thread_rng().gen_range(0..keys.len());
         let date_str = keys[idx];
         info!(date = %date_str, "fetching random dilbert strip");
@@ -120,30 +125,16 @@ impl ComicSource for DilbertSource {
     }
 
     async fn proxy_image(&self, image_url: &str) -> Result<(Vec<u8>, String)> {
-        let response = self
-            .client
-            .get(image_url)
-            .header("User-Agent", random_user_agent())
-            .send()
-            .await
-            .map_err(|e| PanelsError::ScrapeFailed(format!("failed to fetch image: {}", e)))?;
-
-        if !response.status().is_success() {
-            return Err(PanelsError::NotFound("image not found".into()));
-        }
-
-        let content_type = response
-            .headers()
-            .get("content-type")
-            .and_then(|v| v.to_str().ok())
-            .unwrap_or("image/gif")
-            .to_string();
-
-        let bytes = response
-            .bytes()
-            .await
-            .map_err(|e| PanelsError::ScrapeFailed(format!("failed to read image bytes: {}", e)))?;
+        fetch_image(&self.client, image_url).await
+    }
 
-        Ok((bytes.to_vec(), content_type))
+    fn known_strips(&self) -> Vec<ComicStrip> {
+        let catalog = self.catalog.get();
+        let mut dates: Vec<&String> = catalog.dilbert.keys().collect();
+        dates.sort();
+        dates
+            .into_iter()
+            .map(|date| build_strip_from_cache(date, &catalog.dilbert[date]))
+            .collect()
     }
 }
@@ -0,0 +1,285 @@
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use scraper::{Html, Selector};
+use tracing::{debug, info, warn};
+
+use crate::catalog::Catalog;
+use crate::error::{PanelsError, Result};
+use crate::feed::{Feed, FeedEntry, FeedFetcher};
+use crate::http_client::fetch_image;
+use crate::models::{ComicStrip, FeedConfig};
+use crate::sources::ComicSource;
+use crate::sources::history::{HistoryItem, StripHistory};
+
+const HISTORY_DIR: &str = "feeds";
+/// How long a feed is trusted before it is asked for again
+const REFRESH_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+static IMAGES: LazyLock<Selector> = LazyLock::new(|| Selector::parse("img[src]").unwrap());
+
+/// The comic's `imageSelector`, if it has one.
+fn image_selector(config: &FeedConfig) -> anyhow::Result<Option<Selector>> {
+    config
+        .image_selector
+        .as_deref()
+        .map(|css| {
+            Selector::parse(css)
+                .map_err(|e| anyhow::anyhow!("bad feed.imageSelector {:?}: {}", css, e))
+        })
+        .transpose()
+}
+
+/// The strip image of a feed entry and its hover text. With an `imageSelector` only the
+/// elements it matches in the entry's HTML count; otherwise the images the feed parser found,
+/// media elements first.
+fn pick_image(
+    entry: &FeedEntry,
+    selector: Option<&Selector>,
+    config: &FeedConfig,
+) -> Option<(String, Option<String>)> {
+    let wanted = |src: &str| {
+        config
+            .image_filter
+            .as_ref()
+            .is_none_or(|f| src.contains(f.as_str()))
+    };
+
+    let mut tagged: Vec<(String, Option<String>)> = Vec::new();
+    for html in [&entry.content, &entry.summary].into_iter().flatten() {
+        let fragment = Html::parse_fragment(html);
+        for element in fragment.select(selector.unwrap_or(&IMAGES)) {
+            if let Some(src) = element.value().attr("src") {
+                let title = element.value().attr("title").map(str::to_string);
+                tagged.push((src.trim().to_string(), title));
+            }
+        }
+    }
+
+    let image = if selector.is_some() {
+        tagged.iter().map(|(src, _)| src).find(|src| wanted(src))
+    } else {
+        entry.images.iter().find(|src| wanted(src))
+    }?
+    .clone();
+    let alt_text = tagged
+        .iter()
+        .find(|(src, _)| *src == image)
+        .and_then(|(_, title)| title.clone())
+        .filter(|t| !t.trim().is_empty());
+    Some((image, alt_text))
+}
+
+/// The dated strips in a feed. Relative URLs are resolved against the feed's.
+fn items_from_feed(feed: &Feed, config: &FeedConfig) -> anyhow::Result<Vec<(String, HistoryItem)>> {
+    let selector = image_selector(config)?;
+    let base = reqwest::Url::parse(&config.url).ok();
+    let absolute = |url: &str| {
+        base.as_ref()
+            .and_then(|b| b.join(url).ok())
+            .map_or_else(|| url.to_string(), |u| u.to_string())
+    };
+    Ok(feed
+        .entries
+        .iter()
+        .filter_map(|entry| {
+            let date = entry.date()?.format("%Y-%m-%d").to_string();
+            let (image, alt_text) = pick_image(entry, selector.as_ref(), config)?;
+            let title = if entry.title.is_empty() {
+                date.clone()
+            } else {
+                entry.title.clone()
+            };
+            let item = HistoryItem {
+                title,
+                image_url: absolute(&image),
+                source_url: entry.link.as_deref().map(absolute).unwrap_or_default(),
+                alt_text,
+            };
+            Some((date, item))
+        })
+        .collect())
+}
+
+/// Serves comics with `"source": "feed"` from the RSS or Atom feed in their catalog entry,
+/// keeping every item it has seen.
+pub struct FeedSource {
+    client: reqwest::Client,
+    feeds: FeedFetcher,
+    catalog: Arc<Catalog>,
+    history: StripHistory,
+}
+
+impl FeedSource {
+    pub fn new(client: reqwest::Client, catalog: Arc<Catalog>) -> Self {
+        Self {
+            feeds: FeedFetcher::new(client.clone()),
+            history: StripHistory::open(catalog.data_dir(), HISTORY_DIR),
+            client,
+            catalog,
+        }
+    }
+
+    fn config(&self, endpoint: &str) -> Result<FeedConfig> {
+        self.catalog
+            .get()
+            .comic(endpoint)
+            .and_then(|c| c.feed.clone())
+            .ok_or_else(|| PanelsError::NotFound(format!("{} has no feed configured", endpoint)))
+    }
+
+    /// Reads the comic's feed into its history, returning how many strips were new.
+    async fn refresh(&self, endpoint: &str) -> Result<usize> {
+        let config = self.config(endpoint)?;
+        let Some(feed) = self.feeds.fetch(&config.url).await? else {
+            return Err(PanelsError::NotFound(format!(
+                "no feed at {} for {}",
+                config.url, endpoint
+            )));
+        };
+        let found =
+            items_from_feed(&feed, &config).map_err(|e| anyhow::anyhow!("{}: {}", endpoint, e))?;
+        if found.is_empty() {
+            warn!(endpoint, "no strips found in feed");
+        }
+        let added = self.history.merge(endpoint, found).await?;
+        if added > 0 {
+            info!(endpoint, added, "archived new feed items");
+        }
+        Ok(added)
+    }
+
+    /// Refreshes the feed if it wasn't lately, but serves the history if that fails.
+    async fn refresh_if_stale(&self, endpoint: &str) -> Result<()> {
+        if !self.history.is_stale(endpoint, REFRESH_INTERVAL) {
+            return Ok(());
+        }
+        // Stamped before trying, so a failing feed isn't hit again by every request
+        self.history.mark_tried(endpoint);
+        match self.refresh(endpoint).await {
+            Err(e) if !self.history.is_empty(endpoint) => {
+                warn!(
+                    endpoint,
+                    "serving archived items, feed refresh failed: {}", e
+                );
+                Ok(())
+            }
+            result => result.map(|_| ()),
+        }
+    }
+}
+
+#[async_trait]
+impl ComicSource for FeedSource {
+    fn handles(&self, endpoint: &str) -> bool {
+        self.catalog
+            .get()
+            .comic(endpoint)
+            .is_some_and(|c| c.source == "feed")
+    }
+
+    async fn fetch_strip(&self, endpoint: &str, date: &str) -> Result<Option<ComicStrip>> {
+        if NaiveDate::parse_from_str(date, "%Y-%m-%d").is_err() {
+            return Err(PanelsError::InvalidDate(date.to_string()));
+        }
+        if let Some(strip) = self.history.strip(endpoint, date) {
+            debug!(endpoint, date, "feed strip from history");
+            return Ok(Some(strip));
+        }
+        self.refresh_if_stale(endpoint).await?;
+        Ok(self.history.strip(endpoint, date))
+    }
+
+    async fn fetch_latest(&self, endpoint: &str) -> Result<Option<ComicStrip>> {
+        self.refresh_if_stale(endpoint).await?;
+        Ok(self.history.latest(endpoint))
+    }
+
+    async fn fetch_random(&self, endpoint: &str) -> Result<Option<ComicStrip>> {
+        if self.history.is_empty(endpoint) {
+            self.refresh_if_stale(endpoint).await?;
+        }
+        Ok(self.history.random(endpoint))
+    }
+
+    async fn proxy_image(&self, image_url: &str) -> Result<(Vec<u8>, String)> {
+        fetch_image(&self.client, image_url).await
+    }
+
+    async fn poll(&self, endpoint: &str) -> Result<usize> {
+        self.refresh(endpoint).await
+    }
+
+    fn known_strips(&self) -> Vec<ComicStrip> {
+        self.catalog
+            .get()
+            .comics
+            .iter()
+            .filter(|c| c.source == "feed")
+            .flat_map(|comic| self.history.strips(&comic.endpoint))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(image_selector: Option<&str>, image_filter: Option<&str>) -> FeedConfig {
+        FeedConfig {
+            url: "https://comic.example.com/feed/".into(),
+            image_selector: image_selector.map(str::to_string),
+            image_filter: image_filter.map(str::to_string),
+        }
+    }
+
+    fn smbc_like() -> Feed {
+        crate::feed::parse(
+            r#"<rss version="2.0"><channel><title>Comic</title>
+            <item>
+              <title>Saturday Morning Breakfast Cereal - Hypothesis</title>
+              <link>/comic/hypothesis</link>
+              <pubDate>Mon, 06 May 2024 11:00:00 -0400</pubDate>
+              <description>&lt;img src="/avatars/author.png"&gt;&lt;img src="https://comic.example.com/comics/hypothesis.png" title="Hover text here"&gt;&lt;p&gt;Bonus&lt;/p&gt;&lt;img src="https://comic.example.com/comics/hypothesis-after.png"&gt;</description>
+            </item>
+            <item>
+              <title>Undated</title>
+              <description>&lt;img src="https://comic.example.com/comics/x.png"&gt;</description>
+            </item>
+            </channel></rss>"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn hints_pick_the_strip_and_its_hover_text() {
+        let feed = smbc_like();
+        let items = items_from_feed(&feed, &config(None, None)).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].0, "2024-05-06");
+        assert_eq!(
+            items[0].1.image_url,
+            "https://comic.example.com/avatars/author.png"
+        );
+        assert_eq!(
+            items[0].1.source_url,
+            "https://comic.example.com/comic/hypothesis"
+        );
+
+        let items = items_from_feed(&feed, &config(None, Some("/comics/"))).unwrap();
+        assert_eq!(
+            items[0].1.image_url,
+            "https://comic.example.com/comics/hypothesis.png"
+        );
+        assert_eq!(items[0].1.alt_text.as_deref(), Some("Hover text here"));
+
+        let items = items_from_feed(&feed, &config(Some("img[title]"), None)).unwrap();
+        assert_eq!(
+            items[0].1.image_url,
+            "https://comic.example.com/comics/hypothesis.png"
+        );
+        assert!(items_from_feed(&feed, &config(Some("img["), None)).is_err());
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+use std::sync::LazyLock;
+
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+
+use crate::error::Result;
+use crate::http_client::fetch_page_or_missing;
+
+use super::scraper::{extract_date_links, parse_comic_page};
+use super::{BASE_URL, fetch_page_handling_challenge, past_challenge};
+
+pub const A_TO_Z_URL: &str = "https://www.gocomics.com/comics/a-to-z";
+
+/// Top-level GoComics paths that look like feature slugs but are site pages.
+const SITE_PATHS: &[&str] = &[
+    "comics",
+    "political-cartoons",
+    "news",
+    "search",
+    "account",
+    "profiles",
+    "login",
+    "signup",
+    "subscribe",
+    "about",
+    "privacy",
+    "terms",
+    "help",
+    "shop",
+    "games",
+    "newsletters",
+];
+
+/// A feature as listed on the GoComics A-to-Z page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListedFeature {
+    pub endpoint: String,
+    pub title: String,
+    pub author: Option<String>,
+}
+
+fn class_contains(el: &ElementRef, needles: &[&str]) -> bool {
+    el.value()
+        .attr("class")
+        .map(|c| c.to_ascii_lowercase())
+        .is_some_and(|c| needles.iter().any(|n| c.contains(n)))
+}
+
+fn element_text(el: ElementRef) -> String {
+    el.text()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+static FEATURE_LINK: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?:https?://(?:www\.)?gocomics\.com)?/([a-z0-9][a-z0-9_-]*)/?$").unwrap()
+});
+
+fn feature_slug(href: &str) -> Option<String> {
+    let slug = FEATURE_LINK.captures(href)?[1].to_string();
+    if SITE_PATHS.contains(&slug.as_str()) {
+        None
+    } else {
+        Some(slug)
+    }
+}
+
+/// Reads the features off an A-to-Z listing page. Only links carrying a title element are
+/// taken, which leaves out the site navigation. Results are keyed and sorted by endpoint.
+pub fn parse_listing(html: &str) -> Vec<ListedFeature> {
+    let document = Html::parse_document(html);
+    let Ok(links) = Selector::parse("a[href]") else {
+        return Vec::new();
+    };
+    let Ok(descendants) = Selector::parse("*") else {
+        return Vec::new();
+    };
+
+    let mut features = BTreeMap::new();
+    for link in document.select(&links) {
+        let Some(endpoint) = link.value().attr("href").and_then(feature_slug) else {
+            continue;
+        };
+        let mut title = None;
+        let mut author = None;
+        for child in link.select(&descendants) {
+            if title.is_none() && class_contains(&child, &["title"]) {
+                title = Some(element_text(child));
+            } else if author.is_none() && class_contains(&child, &["author", "creator", "byline"]) {
+                let text = element_text(child);
+                let text = text.strip_prefix("By ").unwrap_or(&text).trim().to_string();
+                author = Some(text);
+            }
+        }
+        let Some(title) = title.filter(|t| !t.is_empty()) else {
+            continue;
+        };
+        features.entry(endpoint.clone()).or_insert(ListedFeature {
+            endpoint,
+            title,
+            author: author.filter(|a| !a.is_empty()),
+        });
+    }
+    features.into_values().collect()
+}
+
+pub async fn crawl_listing(client: &reqwest::Client, url: &str) -> Result<Vec<ListedFeature>> {
+    let page = fetch_page_handling_challenge(client, url, 2, 20000, false, &[]).await?;
+    Ok(page.map(|p| parse_listing(&p.html)).unwrap_or_default())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Probe {
+    /// The feature page loads and shows a strip; carries the earliest strip date it links to
+    Reachable {
+        earliest_date: Option<String>,
+    },
+    Unreachable,
+}
+
+/// Loads a feature's landing page. A feature counts as reachable only when the page has a
+/// strip image, since retired features can still redirect somewhere that answers 200. Only a
+/// 404 or such a page makes it unreachable; timeouts, rate limiting and server errors are
+/// errors, since they say nothing about the feature.
+pub async fn probe_feature(client: &reqwest::Client, endpoint: &str) -> Result<Probe> {
+    probe_url(client, &format!("{}/{}", BASE_URL, endpoint), endpoint).await
+}
+
+async fn probe_url(client: &reqwest::Client, url: &str, endpoint: &str) -> Result<Probe> {
+    let Some(page) = fetch_page_or_missing(client, url, 1, 15000).await? else {
+        return Ok(Probe::Unreachable);
+    };
+    let page = past_challenge(url, page).await?;
+    Ok(probe_page(&page.html, endpoint))
+}
+
+fn probe_page(html: &str, endpoint: &str) -> Probe {
+    if parse_comic_page(html, endpoint, "", endpoint).is_none() {
+        return Probe::Unreachable;
+    }
+    let mut dates = extract_date_links(html, endpoint);
+    dates.sort();
+    Probe::Reachable {
+        earliest_date: dates.into_iter().next(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LISTING: &str = r#"
+    <html><body>
+      <nav><a href="/comics">Comics</a><a href="/political-cartoons"><span class="title">Political</span></a></nav>
+      <div class="ComicsList">
+        <a href="/garfield" class="card">
+          <h4 class="ComicCard_title__x1">Garfield</h4>
+          <span class="ComicCard_author__y2">By Jim Davis</span>
+        </a>
+        <a href="https://www.gocomics.com/calvinandhobbes/">
+          <div class="feature-title">Calvin and
+            Hobbes</div>
+        </a>
+        <a href="/garfield"><span class="title">Garfield again</span></a>
+        <a href="/garfield/2024/01/15"><span class="title">A strip</span></a>
+        <a href="/peanuts">Peanuts</a>
+      </div>
+    </body></html>
+    "#;
+
+    #[test]
+    fn parses_listing_cards() {
+        let features = parse_listing(LISTING);
+        assert_eq!(
+            features,
+            vec![
+                ListedFeature {
+                    endpoint: "calvinandhobbes".into(),
+                    title: "Calvin and Hobbes".into(),
+                    author: None,
+                },
+                ListedFeature {
+                    endpoint: "garfield".into(),
+                    title: "Garfield".into(),
+                    author: Some("Jim Davis".into()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn probe_needs_a_strip_image() {
+        let page = r#"<html><body>
+            <img src="https://featureassets.gocomics.com/strip.gif" />
+            <a href="/garfield/2024/01/15">prev</a>
+            <a href="/garfield/1978/06/19">first</a>
+        </body></html>"#;
+        assert_eq!(
+            probe_page(page, "garfield"),
+            Probe::Reachable {
+                earliest_date: Some("1978-06-19".into())
+            }
+        );
+        assert_eq!(
+            probe_page("<html><body>Not found</body></html>", "garfield"),
+            Probe::Unreachable
+        );
+    }
+
+    #[tokio::test]
+    async fn only_a_missing_page_is_unreachable() {
+        use axum::http::StatusCode;
+        use axum::routing::get;
+
+        let app = axum::Router::new()
+            .route("/gone", get(|| async { StatusCode::NOT_FOUND }))
+            .route("/busy", get(|| async { StatusCode::SERVICE_UNAVAILABLE }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = reqwest::Client::new();
+        let gone = probe_url(&client, &format!("{}/gone", base), "gone").await;
+        assert_eq!(gone.unwrap(), Probe::Unreachable);
+        let busy = probe_url(&client, &format!("{}/busy", base), "busy").await;
+        assert!(busy.is_err());
+    }
+}
@@ -1,43 +1,94 @@
-mod bunny;
 mod browser;
+mod bunny;
+pub mod discovery;
 pub mod scraper;
 
+use std::sync::Arc;
+
 use async_trait::async_trait;
 use chrono::{Local, NaiveDate};
 use rand::Rng;
 use tracing::{debug, info};
 
 use crate::cache::Caches;
+use crate::catalog::Catalog;
 use crate::error::{PanelsError, Result};
 use crate::http_client::{fetch_page_with_options, random_user_agent};
-use crate::models::{Comic, ComicStrip};
+use crate::models::ComicStrip;
 use crate::sources::ComicSource;
 
 use self::scraper::{
     extract_date_links, extract_nav_date, extract_page_date_from_html, parse_comic_page,
 };
 
-const BASE_URL: &str = "https://www.gocomics.com";
+pub const BASE_URL: &str = "https://www.gocomics.com";
 
-fn find_title<'a>(comics: &'a [Comic], endpoint: &'a str) -> &'a str {
-    comics
-        .iter()
-        .find(|c| c.endpoint == endpoint)
-        .map(|c| c.title.as_str())
-        .unwrap_or(endpoint)
+fn find_title(catalog: &Catalog, endpoint: &str) -> String {
+    catalog
+        .get()
+        .comic(endpoint)
+        .map(|c| c.title.clone())
+        .unwrap_or_else(|| endpoint.to_string())
+}
+
+/// Fetches a GoComics page, retrying through the browser helper when the plain request lands
+/// on the Bunny Shield challenge.
+pub async fn fetch_page_handling_challenge(
+    client: &reqwest::Client,
+    url: &str,
+    retries: u32,
+    timeout_ms: u64,
+    suppress_errors: bool,
+    silent_statuses: &[u16],
+) -> Result<Option<crate::http_client::PageResponse>> {
+    let page = fetch_page_with_options(
+        client,
+        url,
+        retries,
+        timeout_ms,
+        suppress_errors,
+        silent_statuses,
+    )
+    .await?;
+
+    match page {
+        Some(page) => past_challenge(url, page).await.map(Some),
+        None => Ok(None),
+    }
+}
+
+/// The page itself, fetched again through the browser helper if `page` is the Bunny Shield
+/// challenge.
+async fn past_challenge(
+    url: &str,
+    page: crate::http_client::PageResponse,
+) -> Result<crate::http_client::PageResponse> {
+    if !bunny::is_bunny_challenge(&page.html) {
+        return Ok(page);
+    }
+
+    let browser_page = browser::fetch_page(url).await?;
+
+    if bunny::is_bunny_challenge(&browser_page.html) {
+        Err(PanelsError::ScrapeFailed(
+            "GoComics browser fetch still landed on Bunny Shield challenge".into(),
+        ))
+    } else {
+        Ok(browser_page)
+    }
 }
 
 pub struct GoComicsSource {
     client: reqwest::Client,
-    comics: Vec<Comic>,
+    catalog: Arc<Catalog>,
     caches: Caches,
 }
 
 impl GoComicsSource {
-    pub fn new(client: reqwest::Client, comics: Vec<Comic>, caches: Caches) -> Self {
+    pub fn new(client: reqwest::Client, catalog: Arc<Catalog>, caches: Caches) -> Self {
         Self {
             client,
-            comics,
+            catalog,
             caches,
         }
     }
@@ -50,7 +101,7 @@ impl GoComicsSource {
         suppress_errors: bool,
         silent_statuses: &[u16],
     ) -> Result<Option<crate::http_client::PageResponse>> {
-        let page = fetch_page_with_options(
+        fetch_page_handling_challenge(
             &self.client,
             url,
             retries,
@@ -58,25 +109,7 @@ impl GoComicsSource {
             suppress_errors,
             silent_statuses,
         )
-        .await?;
-
-        let Some(page) = page else {
-            return Ok(None);
-        };
-
-        if !bunny::is_bunny_challenge(&page.html) {
-            return Ok(Some(page));
-        }
-
-        let browser_page = browser::fetch_page(url).await?;
-
-        if bunny::is_bunny_challenge(&browser_page.html) {
-            Err(PanelsError::ScrapeFailed(
-                "GoComics browser fetch still landed on Bunny Shield challenge".into(),
-            ))
-        } else {
-            Ok(Some(browser_page))
-        }
+        .await
     }
 
     async fn fetch_strip_inner(
@@ -115,8 +148,8 @@ impl GoComicsSource {
             .or_else(|| extract_page_date_from_html(&page.html, endpoint))
             .unwrap_or_else(|| date_str.to_string());
 
-        let title = find_title(&self.comics, endpoint);
-        let strip = parse_comic_page(&page.html, endpoint, &resolved_date, title);
+        let title = find_title(&self.catalog, endpoint);
+        let strip = parse_comic_page(&page.html, endpoint, &resolved_date, &title);
 
         if let Some(ref s) = strip {
             let strip_cache_key = format!("{}:{}", endpoint, s.date);
@@ -130,9 +163,10 @@ impl GoComicsSource {
 #[async_trait]
 impl ComicSource for GoComicsSource {
     fn handles(&self, endpoint: &str) -> bool {
-        self.comics
-            .iter()
-            .any(|c| c.endpoint == endpoint && c.source == "gocomics")
+        self.catalog
+            .get()
+            .comic(endpoint)
+            .is_some_and(|c| c.source == "gocomics")
     }
 
     async fn fetch_strip(&self, endpoint: &str, date: &str) -> Result<Option<ComicStrip>> {
@@ -161,8 +195,8 @@ impl ComicSource for GoComicsSource {
             .or_else(|| extract_page_date_from_html(&page.html, endpoint))
             .unwrap_or(today);
 
-        let title = find_title(&self.comics, endpoint);
-        let strip = parse_comic_page(&page.html, endpoint, &resolved_date, title);
+        let title = find_title(&self.catalog, endpoint);
+        let strip = parse_comic_page(&page.html, endpoint, &resolved_date, &title);
 
         if let Some(ref s) = strip {
             let cache_key = format!("{}:{}", endpoint, s.date);
@@ -219,8 +253,8 @@ impl ComicSource for GoComicsSource {
             let resolved = extract_nav_date(&page.final_url, endpoint)
                 .or_else(|| extract_page_date_from_html(&page.html, endpoint))
                 .unwrap_or(today);
-            let title = find_title(&self.comics, endpoint);
-            return Ok(parse_comic_page(&page.html, endpoint, &resolved, title));
+            let title = find_title(&self.catalog, endpoint);
+            return Ok(parse_comic_page(&page.html, endpoint, &resolved, &title));
         }
 
         let idx = rand::thread_rng().gen_range(0..dates.len());
@@ -1,7 +1,7 @@
 use regex::Regex;
 use scraper::{Html, Selector};
 
-use crate::models::ComicStrip;
+use crate::models::{ComicStrip, StripMetadata};
 
 const BASE_URL: &str = "https://www.gocomics.com";
 const ASSETS: &str = "featureassets.gocomics.com";
@@ -116,6 +116,7 @@ pub fn parse_comic_page(
         source_url: format!("{}/{}", BASE_URL, endpoint),
         prev_date: None,
         next_date: None,
+        metadata: StripMetadata::default(),
     })
 }
 
@@ -0,0 +1,336 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use chrono::Utc;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::models::{ComicStrip, StripMetadata};
+
+/// A strip kept after it has dropped out of its feed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryItem {
+    pub title: String,
+    pub image_url: String,
+    pub source_url: String,
+    /// Hover text, for feeds that carry it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alt_text: Option<String>,
+}
+
+impl From<&ComicStrip> for HistoryItem {
+    fn from(strip: &ComicStrip) -> Self {
+        Self {
+            title: strip.title.clone(),
+            image_url: strip.image_url.clone(),
+            source_url: strip.source_url.clone(),
+            alt_text: strip.metadata.alt_text.clone(),
+        }
+    }
+}
+
+type Items = BTreeMap<String, HistoryItem>;
+
+/// Held while a history file is read back and rewritten, so two histories of the same
+/// directory, such as the serving and the syncing registry's, never drop each other's strips.
+static SAVING: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Reads a saved history. One that can't be parsed is renamed to `.corrupt-<time>` next to
+/// it, to be looked at, and the history starts over.
+fn read(path: &Path) -> anyhow::Result<Items> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Items::new()),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+    };
+    match serde_json::from_str(&contents) {
+        Ok(items) => Ok(items),
+        Err(e) => {
+            let aside = path.with_extension(format!(
+                "json.corrupt-{}",
+                Utc::now().format("%Y%m%dT%H%M%S")
+            ));
+            std::fs::rename(path, &aside)
+                .with_context(|| format!("failed to move aside {}", path.display()))?;
+            warn!(
+                path = %path.display(),
+                aside = %aside.display(),
+                "moved aside an unreadable strip history: {}",
+                e
+            );
+            Ok(Items::new())
+        }
+    }
+}
+
+fn write_atomic(path: &Path, contents: &str) -> anyhow::Result<()> {
+    let tmp = path.with_extension(format!(
+        "json.{}-{}.tmp",
+        std::process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut file = std::fs::File::create(&tmp)
+        .with_context(|| format!("failed to create {}", tmp.display()))?;
+    file.write_all(contents.as_bytes())
+        .and_then(|()| file.sync_all())
+        .with_context(|| format!("failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("failed to replace {}", path.display()))
+}
+
+/// Every strip a feed-backed source has seen, by `YYYY-MM-DD` date, saved per comic to
+/// `data/<name>/<endpoint>.json` so a comic's archive grows past the feed's window.
+pub struct StripHistory {
+    dir: PathBuf,
+    items: RwLock<HashMap<String, Items>>,
+    refreshed: Mutex<HashMap<String, Instant>>,
+}
+
+impl StripHistory {
+    pub fn open(data_dir: &str, name: &str) -> Self {
+        Self {
+            dir: Path::new(data_dir).join(name),
+            items: RwLock::new(HashMap::new()),
+            refreshed: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn path(&self, endpoint: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", endpoint))
+    }
+
+    /// Reads a comic's history from disk the first time it is needed.
+    fn ensure_loaded(&self, endpoint: &str) {
+        if self.items.read().unwrap().contains_key(endpoint) {
+            return;
+        }
+        let items = read(&self.path(endpoint)).unwrap_or_else(|e| {
+            warn!(endpoint, "serving an empty strip history: {:#}", e);
+            Items::new()
+        });
+        self.items
+            .write()
+            .unwrap()
+            .entry(endpoint.to_string())
+            .or_insert(items);
+    }
+
+    fn with<T>(&self, endpoint: &str, f: impl FnOnce(&Items) -> T) -> T {
+        self.ensure_loaded(endpoint);
+        f(&self.items.read().unwrap()[endpoint])
+    }
+
+    pub fn is_empty(&self, endpoint: &str) -> bool {
+        self.with(endpoint, Items::is_empty)
+    }
+
+    /// Whether the comic's feed was last read or tried more than `max_age` ago, or never.
+    pub fn is_stale(&self, endpoint: &str, max_age: Duration) -> bool {
+        self.refreshed
+            .lock()
+            .unwrap()
+            .get(endpoint)
+            .is_none_or(|at| at.elapsed() >= max_age)
+    }
+
+    /// Records an attempt to read the comic's feed, so one that is down is tried again once
+    /// it is stale rather than on every request.
+    pub fn mark_tried(&self, endpoint: &str) {
+        self.refreshed
+            .lock()
+            .unwrap()
+            .insert(endpoint.to_string(), Instant::now());
+    }
+
+    /// Adds the strips for dates not in the history yet, keeping the first one seen for a
+    /// date, and saves the history if any were new. Returns how many were added.
+    ///
+    /// The file is read back before it is written, so histories kept of the same directory
+    /// by other registries never lose each other's strips. Both happen on a blocking thread.
+    pub async fn merge(
+        &self,
+        endpoint: &str,
+        found: impl IntoIterator<Item = (String, HistoryItem)>,
+    ) -> anyhow::Result<usize> {
+        self.ensure_loaded(endpoint);
+        self.mark_tried(endpoint);
+
+        let current = self.items.read().unwrap()[endpoint].clone();
+        let new: Vec<_> = found
+            .into_iter()
+            .filter(|(date, _)| !current.contains_key(date))
+            .collect();
+        if new.is_empty() {
+            return Ok(0);
+        }
+
+        let _saving = SAVING.lock().await;
+        let (dir, path) = (self.dir.clone(), self.path(endpoint));
+        let saved = tokio::task::spawn_blocking(move || -> anyhow::Result<Items> {
+            let mut items = current;
+            for (date, item) in read(&path)?.into_iter().chain(new) {
+                items.entry(date).or_insert(item);
+            }
+            std::fs::create_dir_all(&dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+            write_atomic(&path, &serde_json::to_string_pretty(&items)?)?;
+            Ok(items)
+        })
+        .await??;
+
+        let mut all = self.items.write().unwrap();
+        let items = all.entry(endpoint.to_string()).or_default();
+        let before = items.len();
+        for (date, item) in saved {
+            items.entry(date).or_insert(item);
+        }
+        Ok(items.len() - before)
+    }
+
+    pub fn strip(&self, endpoint: &str, date: &str) -> Option<ComicStrip> {
+        self.with(endpoint, |items| build_strip(endpoint, items, date))
+    }
+
+    pub fn latest(&self, endpoint: &str) -> Option<ComicStrip> {
+        self.with(endpoint, |items| {
+            let (date, _) = items.last_key_value()?;
+            build_strip(endpoint, items, date)
+        })
+    }
+
+    pub fn random(&self, endpoint: &str) -> Option<ComicStrip> {
+        self.with(endpoint, |items| {
+            if items.is_empty() {
+                return None;
+            }
+            let idx = rand::thread_rng().gen_range(0..items.len());
+            let date = items.keys().nth(idx)?;
+            build_strip(endpoint, items, date)
+        })
+    }
+
+    pub fn strips(&self, endpoint: &str) -> Vec<ComicStrip> {
+        self.with(endpoint, |items| {
+            items
+                .keys()
+                .filter_map(|date| build_strip(endpoint, items, date))
+                .collect()
+        })
+    }
+}
+
+fn build_strip(endpoint: &str, items: &Items, date: &str) -> Option<ComicStrip> {
+    let item = items.get(date)?;
+    let prev_date = items.range(..date.to_string()).next_back();
+    let next_date = items
+        .range(date.to_string()..)
+        .find(|(d, _)| d.as_str() != date);
+    Some(ComicStrip {
+        endpoint: endpoint.to_string(),
+        title: item.title.clone(),
+        date: date.to_string(),
+        image_url: item.image_url.clone(),
+        source_url: item.source_url.clone(),
+        prev_date: prev_date.map(|(d, _)| d.clone()),
+        next_date: next_date.map(|(d, _)| d.clone()),
+        metadata: StripMetadata {
+            alt_text: item.alt_text.clone(),
+            ..StripMetadata::default()
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(title: &str) -> HistoryItem {
+        HistoryItem {
+            title: title.into(),
+            image_url: format!("https://example.com/{}.png", title),
+            source_url: String::new(),
+            alt_text: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn history_keeps_strips_that_left_the_feed() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().to_str().unwrap();
+
+        let history = StripHistory::open(data_dir, "feeds");
+        let first = vec![
+            ("2024-05-01".to_string(), item("a")),
+            ("2024-05-02".to_string(), item("b")),
+        ];
+        assert_eq!(history.merge("comic", first).await.unwrap(), 2);
+        // The feed has moved on by a day, and re-titled a strip it still lists
+        let second = vec![
+            ("2024-05-02".to_string(), item("b2")),
+            ("2024-05-03".to_string(), item("c")),
+        ];
+        assert_eq!(history.merge("comic", second).await.unwrap(), 1);
+
+        let reopened = StripHistory::open(data_dir, "feeds");
+        let strip = reopened.strip("comic", "2024-05-02").unwrap();
+        assert_eq!(strip.title, "b");
+        assert_eq!(strip.prev_date.as_deref(), Some("2024-05-01"));
+        assert_eq!(strip.next_date.as_deref(), Some("2024-05-03"));
+        assert_eq!(reopened.latest("comic").unwrap().date, "2024-05-03");
+    }
+
+    #[tokio::test]
+    async fn histories_of_the_same_directory_keep_each_others_strips() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().to_str().unwrap();
+        let serving = StripHistory::open(data_dir, "comicsrss");
+        let syncing = StripHistory::open(data_dir, "comicsrss");
+
+        serving
+            .merge("comic", [("2024-05-01".to_string(), item("a"))])
+            .await
+            .unwrap();
+        syncing
+            .merge("comic", [("2024-05-02".to_string(), item("b"))])
+            .await
+            .unwrap();
+
+        let reopened = StripHistory::open(data_dir, "comicsrss");
+        assert_eq!(reopened.strips("comic").len(), 2);
+        assert!(reopened.is_stale("comic", Duration::from_secs(60)));
+        assert!(!syncing.is_stale("comic", Duration::from_secs(60)));
+        reopened.mark_tried("comic");
+        assert!(!reopened.is_stale("comic", Duration::from_secs(60)));
+    }
+
+    #[tokio::test]
+    async fn corrupt_histories_are_moved_aside() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().to_str().unwrap();
+        std::fs::create_dir(dir.path().join("feeds")).unwrap();
+        std::fs::write(dir.path().join("feeds/comic.json"), "{\"2024-05-01\": ").unwrap();
+
+        let history = StripHistory::open(data_dir, "feeds");
+        assert!(history.is_empty("comic"));
+        history
+            .merge("comic", [("2024-05-02".to_string(), item("b"))])
+            .await
+            .unwrap();
+
+        let names: Vec<String> = std::fs::read_dir(dir.path().join("feeds"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names.len(), 2, "{:?}", names);
+        assert!(names.iter().any(|n| n.starts_with("comic.json.corrupt-")));
+        let reopened = StripHistory::open(data_dir, "feeds");
+        assert_eq!(reopened.strips("comic").len(), 1);
+    }
+}
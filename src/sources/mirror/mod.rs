@@ -0,0 +1,134 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::error::{PanelsError, Result};
+use crate::mirror::MirrorStore;
+use crate::models::ComicStrip;
+use crate::sources::ComicSource;
+
+/// Serves mirrored comics from the local store only; it never contacts upstream. Chain it
+/// ahead of the live sources with [`SourceRegistry::chain_ahead`](super::SourceRegistry::chain_ahead)
+/// to fall back to them for strips the mirror doesn't have yet.
+pub struct MirrorSource {
+    store: Arc<MirrorStore>,
+}
+
+impl MirrorSource {
+    pub fn new(store: Arc<MirrorStore>) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl ComicSource for MirrorSource {
+    fn handles(&self, endpoint: &str) -> bool {
+        self.store.mirrors(endpoint)
+    }
+
+    async fn fetch_strip(&self, endpoint: &str, date: &str) -> Result<Option<ComicStrip>> {
+        Ok(self.store.strip(endpoint, date).await)
+    }
+
+    async fn fetch_latest(&self, endpoint: &str) -> Result<Option<ComicStrip>> {
+        Ok(self.store.latest(endpoint).await)
+    }
+
+    async fn fetch_random(&self, endpoint: &str) -> Result<Option<ComicStrip>> {
+        Ok(self.store.random(endpoint).await)
+    }
+
+    async fn proxy_image(&self, image_url: &str) -> Result<(Vec<u8>, String)> {
+        let object = self
+            .store
+            .image(image_url)
+            .await
+            .ok_or_else(|| PanelsError::NotFound(format!("{} is not mirrored", image_url)))?;
+        let bytes = self.store.read_object(&object).await?;
+        Ok((bytes, object.content_type))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::StripMetadata;
+
+    fn strip(date: &str) -> ComicStrip {
+        ComicStrip {
+            endpoint: "garfield".into(),
+            title: "Garfield".into(),
+            date: date.into(),
+            image_url: format!("https://example.com/{}.gif", date),
+            source_url: String::new(),
+            prev_date: None,
+            next_date: None,
+            metadata: StripMetadata::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_what_was_stored_and_catches_corruption() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().to_str().unwrap();
+        let store = Arc::new(MirrorStore::open(data_dir, &["garfield".into()]).unwrap());
+        let image = store
+            .write_object(b"GIF89a-one", "image/gif")
+            .await
+            .unwrap();
+        store
+            .record("garfield", strip("2024-01-01"), image.clone())
+            .await;
+        store.save("garfield").await.unwrap();
+
+        let reopened = Arc::new(MirrorStore::open(data_dir, &["garfield".into()]).unwrap());
+        let source = MirrorSource::new(Arc::clone(&reopened));
+        assert!(source.handles("garfield") && !source.handles("peanuts"));
+        let latest = source.fetch_latest("garfield").await.unwrap().unwrap();
+        assert_eq!(latest.date, "2024-01-01");
+        assert!(
+            source
+                .fetch_strip("garfield", "2024-01-02")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        let (bytes, content_type) = source.proxy_image(&latest.image_url).await.unwrap();
+        assert_eq!(
+            (bytes.as_slice(), content_type.as_str()),
+            (&b"GIF89a-one"[..], "image/gif")
+        );
+
+        let object = dir
+            .path()
+            .join("mirror/objects")
+            .join(&image.sha256[..2])
+            .join(&image.sha256[2..]);
+        std::fs::write(object, b"GIF89a-two").unwrap();
+        assert!(source.proxy_image(&latest.image_url).await.is_err());
+        assert_eq!(reopened.verify("garfield").await, vec!["2024-01-01"]);
+    }
+
+    #[tokio::test]
+    async fn links_follow_what_was_mirrored_since() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MirrorStore::open(dir.path().to_str().unwrap(), &["garfield".into()]).unwrap();
+        let image = store.write_object(b"GIF89a", "image/gif").await.unwrap();
+        // Upstream had nothing newer when the first strip was mirrored
+        let mut first = strip("2024-01-01");
+        first.prev_date = Some("2023-12-31".into());
+        store.record("garfield", first, image.clone()).await;
+        store
+            .record("garfield", strip("2024-01-02"), image.clone())
+            .await;
+        store.record("garfield", strip("2024-01-03"), image).await;
+        store.forget("garfield", "2024-01-02").await;
+
+        let first = store.strip("garfield", "2024-01-01").await.unwrap();
+        assert_eq!(first.prev_date.as_deref(), Some("2023-12-31"));
+        assert_eq!(first.next_date.as_deref(), Some("2024-01-02"));
+        let latest = store.latest("garfield").await.unwrap();
+        assert_eq!(latest.prev_date.as_deref(), Some("2024-01-02"));
+        assert_eq!(latest.next_date, None);
+    }
+}
@@ -1,14 +1,50 @@
 pub mod comicsrss;
 pub mod dilbert;
+pub mod feed;
 pub mod gocomics;
+pub mod history;
+pub mod mirror;
 pub mod phd;
+pub mod scrape;
+pub mod script;
+pub mod wayback;
 pub mod xkcd;
 
+use std::sync::Arc;
+use std::time::Duration;
+
 use async_trait::async_trait;
+use tracing::{info, warn};
 
+use crate::AppState;
+use crate::cache::Caches;
+use crate::catalog::Catalog;
 use crate::error::Result;
 use crate::models::ComicStrip;
 
+use self::comicsrss::ComicsRssSource;
+use self::dilbert::DilbertSource;
+use self::feed::FeedSource;
+use self::gocomics::GoComicsSource;
+use self::phd::PhdSource;
+use self::scrape::ScrapeSource;
+use self::script::ScriptSource;
+use self::wayback::WaybackSource;
+use self::xkcd::XkcdSource;
+
+/// Values `Comic.source` may take in `comics.json`.
+pub const KNOWN_SOURCES: &[&str] = &[
+    "gocomics",
+    "comicsrss",
+    "dilbert",
+    "xkcd",
+    "phd",
+    "wayback",
+    "feed",
+    "scrape",
+    "script",
+];
+
 #[async_trait]
 pub trait ComicSource: Send + Sync {
     fn handles(&self, endpoint: &str) -> bool;
@@ -20,6 +56,94 @@ pub trait ComicSource: Send + Sync {
     async fn fetch_random(&self, endpoint: &str) -> Result<Option<ComicStrip>>;
 
     async fn proxy_image(&self, image_url: &str) -> Result<(Vec<u8>, String)>;
+
+    /// Records what upstream publishes now in the source's strip history, returning how many
+    /// strips were new. Sources that keep no history have nothing to do.
+    async fn poll(&self, _endpoint: &str) -> Result<usize> {
+        Ok(0)
+    }
+
+    /// Strips the source already holds locally, used to seed the search index.
+    fn known_strips(&self) -> Vec<ComicStrip> {
+        Vec::new()
+    }
+}
+
+/// Asks `first` before `then` for the endpoints `first` handles, and `then` alone for the
+/// rest, except for the latest strip which `then` is asked for first. Images `first` can't
+/// serve are fetched through `then`.
+struct ChainedSource {
+    first: Arc<dyn ComicSource>,
+    then: Box<dyn ComicSource>,
+}
+
+impl ChainedSource {
+    /// The answer from `first` if it has one; its errors are logged and passed over.
+    fn prefer(&self, endpoint: &str, result: Result<Option<ComicStrip>>) -> Option<ComicStrip> {
+        match result {
+            Ok(strip) => strip,
+            Err(e) => {
+                warn!(endpoint, "falling back to the live source: {}", e);
+                None
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl ComicSource for ChainedSource {
+    fn handles(&self, endpoint: &str) -> bool {
+        self.then.handles(endpoint)
+    }
+
+    async fn fetch_strip(&self, endpoint: &str, date: &str) -> Result<Option<ComicStrip>> {
+        if self.first.handles(endpoint)
+            && let Some(strip) = self.prefer(endpoint, self.first.fetch_strip(endpoint, date).await)
+        {
+            return Ok(Some(strip));
+        }
+        self.then.fetch_strip(endpoint, date).await
+    }
+
+    async fn fetch_latest(&self, endpoint: &str) -> Result<Option<ComicStrip>> {
+        let live = self.then.fetch_latest(endpoint).await;
+        if !self.first.handles(endpoint) || matches!(live, Ok(Some(_))) {
+            return live;
+        }
+        match self.first.fetch_latest(endpoint).await {
+            Ok(Some(strip)) => {
+                if let Err(e) = &live {
+                    warn!(endpoint, "serving the latest mirrored strip: {}", e);
+                }
+                Ok(Some(strip))
+            }
+            _ => live,
+        }
+    }
+
+    async fn fetch_random(&self, endpoint: &str) -> Result<Option<ComicStrip>> {
+        if self.first.handles(endpoint)
+            && let Some(strip) = self.prefer(endpoint, self.first.fetch_random(endpoint).await)
+        {
+            return Ok(Some(strip));
+        }
+        self.then.fetch_random(endpoint).await
+    }
+
+    async fn proxy_image(&self, image_url: &str) -> Result<(Vec<u8>, String)> {
+        match self.first.proxy_image(image_url).await {
+            Ok(image) => Ok(image),
+            Err(_) => self.then.proxy_image(image_url).await,
+        }
+    }
+
+    async fn poll(&self, endpoint: &str) -> Result<usize> {
+        self.then.poll(endpoint).await
+    }
+
+    fn known_strips(&self) -> Vec<ComicStrip> {
+        self.then.known_strips()
+    }
 }
 
 pub struct SourceRegistry {
@@ -31,12 +155,88 @@ impl SourceRegistry {
         Self { sources }
     }
 
+    /// Every built-in source, in the order endpoints are matched against them.
+    pub fn standard(client: reqwest::Client, catalog: Arc<Catalog>, caches: Caches) -> Self {
+        Self::new(vec![
+            Box::new(GoComicsSource::new(
+                client.clone(),
+                Arc::clone(&catalog),
+                caches.clone(),
+            )),
+            Box::new(DilbertSource::new(client.clone(), Arc::clone(&catalog))),
+            Box::new(WaybackSource::new(client.clone(), Arc::clone(&catalog))),
+            Box::new(FeedSource::new(client.clone(), Arc::clone(&catalog))),
+            Box::new(ScrapeSource::new(
+                client.clone(),
+                Arc::clone(&catalog),
+                caches.clone(),
+            )),
+            Box::new(ScriptSource::new(
+                client.clone(),
+                Arc::clone(&catalog),
+                caches.clone(),
+            )),
+            Box::new(XkcdSource::new(client.clone(), caches.clone())),
+            Box::new(PhdSource::new(client.clone(), caches.clone())),
+            Box::new(ComicsRssSource::new(client, catalog)),
+        ])
+    }
+
+    /// Puts `first` ahead of every source: endpoints it handles are asked of it first and
+    /// fall back to the source that would otherwise serve them.
+    pub fn chain_ahead(self, first: Arc<dyn ComicSource>) -> Self {
+        Self::new(
+            self.sources
+                .into_iter()
+                .map(|then| {
+                    Box::new(ChainedSource {
+                        first: Arc::clone(&first),
+                        then,
+                    }) as Box<dyn ComicSource>
+                })
+                .collect(),
+        )
+    }
+
+    /// Puts `only` ahead of every source, so endpoints it handles are never asked of the
+    /// others.
+    pub fn prepend(mut self, only: Box<dyn ComicSource>) -> Self {
+        self.sources.insert(0, only);
+        self
+    }
+
     pub fn find(&self, endpoint: &str) -> Option<&dyn ComicSource> {
         self.sources
             .iter()
             .find(|s| s.handles(endpoint))
             .map(|s| s.as_ref())
     }
+
+    pub fn known_strips(&self) -> Vec<ComicStrip> {
+        self.sources.iter().flat_map(|s| s.known_strips()).collect()
+    }
+}
+
+/// Polls every comic in the catalog every `interval`, so sources that keep a strip history
+/// keep growing it while nobody is reading.
+pub fn spawn_poller(state: Arc<AppState>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let catalog = state.catalog.get();
+            for comic in &catalog.comics {
+                let Some(source) = state.sources.find(&comic.endpoint) else {
+                    continue;
+                };
+                match source.poll(&comic.endpoint).await {
+                    Ok(0) => {}
+                    Ok(added) => info!(endpoint = %comic.endpoint, added, "strip history grew"),
+                    Err(e) => warn!(endpoint = %comic.endpoint, "poll failed: {}", e),
+                }
+            }
+        }
+    });
 }
 
 #[cfg(test)]
@@ -66,6 +266,71 @@ mod tests {
         }
     }
 
+    /// Has a strip titled `title` on 2024-01-01 only.
+    struct OneStrip {
+        title: &'static str,
+    }
+
+    #[async_trait]
+    impl ComicSource for OneStrip {
+        fn handles(&self, endpoint: &str) -> bool {
+            endpoint == "garfield"
+        }
+        async fn fetch_strip(&self, endpoint: &str, date: &str) -> Result<Option<ComicStrip>> {
+            Ok((date == "2024-01-01").then(|| ComicStrip {
+                endpoint: endpoint.into(),
+                title: self.title.into(),
+                date: date.into(),
+                image_url: String::new(),
+                source_url: String::new(),
+                prev_date: None,
+                next_date: None,
+                metadata: Default::default(),
+            }))
+        }
+        async fn fetch_latest(&self, endpoint: &str) -> Result<Option<ComicStrip>> {
+            self.fetch_strip(endpoint, "2024-01-01").await
+        }
+        async fn fetch_random(&self, _: &str) -> Result<Option<ComicStrip>> {
+            Ok(None)
+        }
+        async fn proxy_image(&self, _: &str) -> Result<(Vec<u8>, String)> {
+            Ok((self.title.as_bytes().to_vec(), "image/png".into()))
+        }
+    }
+
+    #[tokio::test]
+    async fn chained_source_is_asked_first_and_falls_back() {
+        let live = MockSource {
+            endpoints: vec!["garfield".into(), "peanuts".into()],
+        };
+        let registry = SourceRegistry::new(vec![Box::new(live)])
+            .chain_ahead(Arc::new(OneStrip { title: "mirrored" }));
+        let source = registry.find("peanuts").unwrap();
+        assert!(source.fetch_latest("peanuts").await.unwrap().is_none());
+
+        let source = registry.find("garfield").unwrap();
+        let strip = source.fetch_strip("garfield", "2024-01-01").await.unwrap();
+        assert_eq!(strip.unwrap().title, "mirrored");
+        assert!(
+            source
+                .fetch_strip("garfield", "2024-01-02")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(source.proxy_image("x").await.unwrap().0, b"mirrored");
+
+        // The live source is asked for the latest strip, the mirror only when it has none
+        let latest = source.fetch_latest("garfield").await.unwrap();
+        assert_eq!(latest.unwrap().title, "mirrored");
+        let registry = SourceRegistry::new(vec![Box::new(OneStrip { title: "live" })])
+            .chain_ahead(Arc::new(OneStrip { title: "mirrored" }));
+        let source = registry.find("garfield").unwrap();
+        let latest = source.fetch_latest("garfield").await.unwrap();
+        assert_eq!(latest.unwrap().title, "live");
+    }
+
     #[test]
     fn registry_finds_correct_source() {
         let source = MockSource {
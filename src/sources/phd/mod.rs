@@ -6,7 +6,7 @@ use tracing::{debug, info, warn};
 use crate::cache::Caches;
 use crate::error::{PanelsError, Result};
 use crate::http_client::{fetch_page, random_user_agent};
-use crate::models::ComicStrip;
+use crate::models::{ComicStrip, StripMetadata};
 use crate::sources::ComicSource;
 
 const BASE: &str = "https://phdcomics.com/comics/archive.php";
@@ -54,6 +54,7 @@ fn parse_strip(html: &str, num: u32) -> Option<ComicStrip> {
             .filter(|&id| id > num)
             .min()
             .map(|id| format!("#{}", id)),
+        metadata: StripMetadata::default(),
     })
 }
 
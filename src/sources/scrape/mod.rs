@@ -0,0 +1,588 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock};
+
+use async_trait::async_trait;
+use chrono::{Duration, NaiveDate};
+use rand::Rng;
+use regex::Regex;
+use reqwest::Url;
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::cache::Caches;
+use crate::catalog::Catalog;
+use crate::error::{PanelsError, Result};
+use crate::http_client::{fetch_image_with_referer, fetch_page};
+use crate::models::{ComicStrip, StripMetadata};
+use crate::sources::ComicSource;
+
+const DEFINITION_DIR: &str = "sources";
+/// Random ids tried before falling back to the latest strip
+const RANDOM_ATTEMPTS: usize = 5;
+
+/// How a site numbers its strips.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StripIds {
+    /// By publication date, `{date}` in `urlTemplate`
+    #[default]
+    Date,
+    /// By sequence number, `{number}` in `urlTemplate`; strips are served as `#<number>`
+    Number,
+}
+
+impl StripIds {
+    fn placeholder(self) -> &'static str {
+        match self {
+            StripIds::Date => "{date}",
+            StripIds::Number => "{number}",
+        }
+    }
+}
+
+/// A strip's place in its comic: a date or a number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum StripId {
+    Date(NaiveDate),
+    Number(u32),
+}
+
+impl StripId {
+    /// The id as the API names it: `YYYY-MM-DD`, or `#<number>`.
+    fn key(self) -> String {
+        match self {
+            StripId::Date(date) => date.format("%Y-%m-%d").to_string(),
+            StripId::Number(number) => format!("#{}", number),
+        }
+    }
+}
+
+/// How to scrape a site's strip pages, from a `.toml` or `.json` file in `data/sources/`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScrapeDefinition {
+    pub endpoint: String,
+    /// Strip page URL with `{date}` or `{number}` where the strip's id goes
+    pub url_template: String,
+    #[serde(default)]
+    pub ids: StripIds,
+    /// chrono format of `{date}`
+    #[serde(default = "default_date_format")]
+    pub date_format: String,
+    /// Page showing the newest strip
+    pub latest_url: String,
+    /// Elements holding the strip image
+    pub image_selector: String,
+    /// Attribute of those elements with the image URL, `content` for `<meta>` tags
+    #[serde(default = "default_image_attr")]
+    pub image_attr: String,
+    /// Text the image URL must contain, to skip logos and ads the selector also matches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_filter: Option<String>,
+    /// Attribute of the image element holding hover text, usually `title`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alt_attr: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title_selector: Option<String>,
+    /// Regex applied to the title text; its first group is the title
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title_pattern: Option<String>,
+    /// Link to the previous strip. Without one the nearest earlier strip linked from the
+    /// page is used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prev_selector: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_selector: Option<String>,
+    /// Element whose `href` or `content` is the URL of the strip on the latest page, such as
+    /// `link[rel=canonical]`. Without one the latest strip is the newest one linked from it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current_selector: Option<String>,
+    /// Sent with image requests, for sites that refuse hotlinked images
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub referer: Option<String>,
+    /// The first strip's id (`YYYY-MM-DD` or a number), the lower bound of random strips.
+    /// Numbered comics start at 1 without one; dated comics have no random strips
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first: Option<String>,
+}
+
+fn default_date_format() -> String {
+    "%Y-%m-%d".to_string()
+}
+
+fn default_image_attr() -> String {
+    "src".to_string()
+}
+
+fn selector(definition: &str, field: &str, css: &str) -> anyhow::Result<Selector> {
+    Selector::parse(css).map_err(|e| anyhow::anyhow!("{}: bad {}: {}", definition, field, e))
+}
+
+/// The host without a leading `www.`, so `www.example.com` and `example.com` are one site.
+fn site(url: &Url) -> Option<&str> {
+    url.host_str().map(|h| h.trim_start_matches("www."))
+}
+
+/// A definition with its selectors and title pattern compiled, as the catalog holds it.
+#[derive(Debug)]
+pub struct CompiledDefinition {
+    pub definition: ScrapeDefinition,
+    image: Selector,
+    title: Option<Selector>,
+    title_pattern: Option<Regex>,
+    prev: Option<Selector>,
+    next: Option<Selector>,
+    current: Option<Selector>,
+}
+
+impl ScrapeDefinition {
+    /// Checks the definition and compiles its selectors and title pattern.
+    pub fn compile(self) -> anyhow::Result<CompiledDefinition> {
+        anyhow::ensure!(
+            !self.endpoint.trim().is_empty(),
+            "a definition in {} has no endpoint",
+            DEFINITION_DIR
+        );
+        let placeholder = self.ids.placeholder();
+        anyhow::ensure!(
+            self.url_template.matches(placeholder).count() == 1,
+            "{}: urlTemplate must contain {} exactly once",
+            self.endpoint,
+            placeholder
+        );
+        for (field, url) in [
+            ("urlTemplate", self.url_template.as_str()),
+            ("latestUrl", self.latest_url.as_str()),
+        ] {
+            Url::parse(url)
+                .map_err(|e| anyhow::anyhow!("{}: bad {}: {}", self.endpoint, field, e))?;
+        }
+        if let Some(ref first) = self.first {
+            anyhow::ensure!(
+                self.parse_id(first).is_ok(),
+                "{}: first {:?} is not a strip id",
+                self.endpoint,
+                first
+            );
+        }
+
+        let optional = |field: &str, css: &Option<String>| {
+            css.as_deref()
+                .map(|css| selector(&self.endpoint, field, css))
+                .transpose()
+        };
+        Ok(CompiledDefinition {
+            image: selector(&self.endpoint, "imageSelector", &self.image_selector)?,
+            title: optional("titleSelector", &self.title_selector)?,
+            title_pattern: self
+                .title_pattern
+                .as_deref()
+                .map(Regex::new)
+                .transpose()
+                .map_err(|e| anyhow::anyhow!("{}: bad titlePattern: {}", self.endpoint, e))?,
+            prev: optional("prevSelector", &self.prev_selector)?,
+            next: optional("nextSelector", &self.next_selector)?,
+            current: optional("currentSelector", &self.current_selector)?,
+            definition: self,
+        })
+    }
+
+    /// The strip named by an API date parameter.
+    fn parse_id(&self, key: &str) -> Result<StripId> {
+        match self.ids {
+            StripIds::Date => NaiveDate::parse_from_str(key, "%Y-%m-%d")
+                .map(StripId::Date)
+                .map_err(|_| PanelsError::InvalidDate(key.to_string())),
+            StripIds::Number => key
+                .strip_prefix('#')
+                .unwrap_or(key)
+                .parse()
+                .map(StripId::Number)
+                .map_err(|_| {
+                    PanelsError::InvalidParam(format!(
+                        "{} uses strip numbers (e.g. #100), not dates. Got: {}",
+                        self.endpoint, key
+                    ))
+                }),
+        }
+    }
+
+    fn page_url(&self, id: StripId) -> String {
+        let value = match id {
+            StripId::Date(date) => date.format(&self.date_format).to_string(),
+            StripId::Number(number) => number.to_string(),
+        };
+        self.url_template.replace(self.ids.placeholder(), &value)
+    }
+
+    /// The strip a URL matching `urlTemplate` points at. Only the path and query are
+    /// compared, since sites link to themselves with and without `www.` or `https`.
+    fn id_in_url(&self, url: &Url) -> Option<StripId> {
+        let (before, after) = self.url_template.split_once(self.ids.placeholder())?;
+        let rest = before.split_once("://").map_or(before, |(_, r)| r);
+        let before = rest.find('/').map_or("/", |i| &rest[i..]);
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        let value = path.strip_prefix(before)?.strip_suffix(after)?;
+        match self.ids {
+            StripIds::Date => NaiveDate::parse_from_str(value, &self.date_format)
+                .ok()
+                .map(StripId::Date),
+            StripIds::Number => value.parse().ok().map(StripId::Number),
+        }
+    }
+}
+
+static LINKS: LazyLock<Selector> = LazyLock::new(|| Selector::parse("a[href]").unwrap());
+
+impl CompiledDefinition {
+    /// The strip on a page, `id` being the strip the page was fetched for or `None` for the
+    /// latest page.
+    fn parse(&self, html: &str, page_url: &str, id: Option<StripId>) -> Option<ComicStrip> {
+        let definition = &self.definition;
+        let document = Html::parse_document(html);
+        let base = Url::parse(page_url).ok()?;
+        let resolve = |url: &str| base.join(url.trim()).ok();
+        let link_id = |element: ElementRef| {
+            let value = element.value();
+            let url = value.attr("href").or_else(|| value.attr("content"))?;
+            definition.id_in_url(&resolve(url)?)
+        };
+
+        let (image, image_url) = document.select(&self.image).find_map(|element| {
+            let url = resolve(element.value().attr(&definition.image_attr)?)?;
+            let wanted = definition
+                .image_filter
+                .as_ref()
+                .is_none_or(|f| url.as_str().contains(f.as_str()));
+            wanted.then_some((element, url))
+        })?;
+
+        let linked: BTreeSet<StripId> = document.select(&LINKS).filter_map(link_id).collect();
+        let id = id
+            .or_else(|| document.select(self.current.as_ref()?).find_map(link_id))
+            .or_else(|| linked.last().copied())?;
+        let neighbour = |selector: &Option<Selector>, nearest: Option<&StripId>| match selector {
+            Some(selector) => document.select(selector).find_map(link_id),
+            None => nearest.copied(),
+        };
+        let prev = neighbour(&self.prev, linked.range(..id).next_back());
+        let next = neighbour(&self.next, linked.range(id..).find(|&&l| l != id));
+
+        let title = self
+            .title
+            .as_ref()
+            .and_then(|selector| document.select(selector).next())
+            .map(|element| element.text().collect::<String>())
+            .map(|text| {
+                match self
+                    .title_pattern
+                    .as_ref()
+                    .and_then(|p| p.captures(&text)?.get(1).map(|m| m.as_str().to_string()))
+                {
+                    Some(title) => title,
+                    None => text,
+                }
+            })
+            .map(|title| title.trim().to_string())
+            .unwrap_or_default();
+        let alt_text = definition
+            .alt_attr
+            .as_deref()
+            .and_then(|attr| image.value().attr(attr))
+            .map(|alt| alt.trim().to_string())
+            .filter(|alt| !alt.is_empty());
+
+        Some(ComicStrip {
+            endpoint: definition.endpoint.clone(),
+            title,
+            date: id.key(),
+            image_url: image_url.to_string(),
+            source_url: definition.page_url(id),
+            prev_date: prev.map(StripId::key),
+            next_date: next.map(StripId::key),
+            metadata: StripMetadata {
+                alt_text,
+                ..StripMetadata::default()
+            },
+        })
+    }
+}
+
+/// The definition files in `data/sources/`, in name order.
+pub fn definition_paths(data_dir: &str) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(Path::new(data_dir).join(DEFINITION_DIR)) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            matches!(
+                path.extension().and_then(|e| e.to_str()),
+                Some("toml" | "json")
+            )
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+pub fn load_definition(path: &Path) -> anyhow::Result<CompiledDefinition> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+    let definition: ScrapeDefinition = if path.extension().is_some_and(|e| e == "toml") {
+        toml::from_str(&contents)
+            .map_err(|e| anyhow::anyhow!("failed to parse {}: {}", path.display(), e))?
+    } else {
+        serde_json::from_str(&contents)
+            .map_err(|e| anyhow::anyhow!("failed to parse {}: {}", path.display(), e))?
+    };
+    definition.compile()
+}
+
+/// Reads every definition in `data/sources/`. The directory is optional.
+pub fn load_definitions(data_dir: &str) -> anyhow::Result<Vec<Arc<CompiledDefinition>>> {
+    let definitions = definition_paths(data_dir)
+        .iter()
+        .map(|path| load_definition(path).map(Arc::new))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut seen = BTreeSet::new();
+    for compiled in &definitions {
+        anyhow::ensure!(
+            seen.insert(compiled.definition.endpoint.as_str()),
+            "comic {} has more than one definition in {}",
+            compiled.definition.endpoint,
+            DEFINITION_DIR
+        );
+    }
+    Ok(definitions)
+}
+
+/// Serves comics with `"source": "scrape"` by scraping their pages as their definition in
+/// `data/sources/` describes.
+pub struct ScrapeSource {
+    client: reqwest::Client,
+    catalog: Arc<Catalog>,
+    caches: Caches,
+}
+
+impl ScrapeSource {
+    pub fn new(client: reqwest::Client, catalog: Arc<Catalog>, caches: Caches) -> Self {
+        Self {
+            client,
+            catalog,
+            caches,
+        }
+    }
+
+    fn definition(&self, endpoint: &str) -> Result<Arc<CompiledDefinition>> {
+        self.catalog
+            .get()
+            .scrapers
+            .iter()
+            .find(|d| d.definition.endpoint == endpoint)
+            .cloned()
+            .ok_or_else(|| {
+                PanelsError::NotFound(format!("{} has no definition in data/sources", endpoint))
+            })
+    }
+
+    async fn fetch_id(
+        &self,
+        compiled: &CompiledDefinition,
+        id: StripId,
+    ) -> Result<Option<ComicStrip>> {
+        let definition = &compiled.definition;
+        let cache_key = format!("{}:{}", definition.endpoint, id.key());
+        if let Some(cached) = self.caches.strips.get(&cache_key).await {
+            debug!(endpoint = %definition.endpoint, id = %id.key(), "scraped strip cache hit");
+            return Ok(Some(cached));
+        }
+
+        let url = definition.page_url(id);
+        let Some(page) = fetch_page(&self.client, &url, 2, 10_000).await? else {
+            return Ok(None);
+        };
+        let Some(strip) = compiled.parse(&page.html, &page.final_url, Some(id)) else {
+            warn!(endpoint = %definition.endpoint, url, "no strip found on page");
+            return Ok(None);
+        };
+
+        self.caches.strips.insert(cache_key, strip.clone()).await;
+        Ok(Some(strip))
+    }
+}
+
+#[async_trait]
+impl ComicSource for ScrapeSource {
+    fn handles(&self, endpoint: &str) -> bool {
+        self.catalog
+            .get()
+            .comic(endpoint)
+            .is_some_and(|c| c.source == "scrape")
+    }
+
+    async fn fetch_strip(&self, endpoint: &str, date: &str) -> Result<Option<ComicStrip>> {
+        let compiled = self.definition(endpoint)?;
+        let id = compiled.definition.parse_id(date)?;
+        self.fetch_id(&compiled, id).await
+    }
+
+    async fn fetch_latest(&self, endpoint: &str) -> Result<Option<ComicStrip>> {
+        let cache_key = format!("{}:latest", endpoint);
+        if let Some(cached) = self.caches.strips.get(&cache_key).await {
+            debug!(endpoint, "scraped latest cache hit");
+            return Ok(Some(cached));
+        }
+
+        let compiled = self.definition(endpoint)?;
+        let url = &compiled.definition.latest_url;
+        let Some(page) = fetch_page(&self.client, url, 2, 10_000).await? else {
+            return Ok(None);
+        };
+        let strip = compiled
+            .parse(&page.html, &page.final_url, None)
+            .ok_or_else(|| {
+                PanelsError::ScrapeFailed(format!("failed to parse latest {} strip", endpoint))
+            })?;
+
+        self.caches.strips.insert(cache_key, strip.clone()).await;
+        self.caches
+            .strips
+            .insert(format!("{}:{}", endpoint, strip.date), strip.clone())
+            .await;
+        Ok(Some(strip))
+    }
+
+    /// A random id between the first strip and the latest. Sites skip days and numbers, so
+    /// a few are tried before settling for the latest strip.
+    async fn fetch_random(&self, endpoint: &str) -> Result<Option<ComicStrip>> {
+        let compiled = self.definition(endpoint)?;
+        let definition = &compiled.definition;
+        let Some(latest) = self.fetch_latest(endpoint).await? else {
+            return Ok(None);
+        };
+        let latest_id = definition.parse_id(&latest.date)?;
+        let first = match definition.first.as_deref() {
+            Some(first) => Some(definition.parse_id(first)?),
+            None if definition.ids == StripIds::Number => Some(StripId::Number(1)),
+            None => None,
+        };
+
+        for _ in 0..RANDOM_ATTEMPTS {
+            let id = match (first, latest_id) {
+                (Some(StripId::Number(first)), StripId::Number(latest)) if first < latest => {
+                    StripId::Number(rand::thread_rng().gen_range(first..=latest))
+                }
+                (Some(StripId::Date(first)), StripId::Date(latest)) if first < latest => {
+                    let days = (latest - first).num_days();
+                    StripId::Date(first + Duration::days(rand::thread_rng().gen_range(0..=days)))
+                }
+                _ => break,
+            };
+            info!(endpoint, id = %id.key(), "fetching random scraped strip");
+            if let Some(strip) = self.fetch_id(&compiled, id).await? {
+                return Ok(Some(strip));
+            }
+        }
+        Ok(Some(latest))
+    }
+
+    async fn proxy_image(&self, image_url: &str) -> Result<(Vec<u8>, String)> {
+        // Images carry no endpoint, so the referer is that of the site serving them
+        let image = Url::parse(image_url).ok();
+        let referer = self.catalog.get().scrapers.iter().find_map(|d| {
+            let referer = d.definition.referer.as_ref()?;
+            let same_site = site(&Url::parse(referer).ok()?) == site(image.as_ref()?);
+            same_site.then(|| referer.clone())
+        });
+        fetch_image_with_referer(&self.client, image_url, referer.as_deref()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn phd() -> CompiledDefinition {
+        load_definition(Path::new("tests/fixtures/sources/phd.toml")).unwrap()
+    }
+
+    fn fixture(name: &str) -> String {
+        std::fs::read_to_string(Path::new("tests/fixtures/sources").join(name)).unwrap()
+    }
+
+    #[test]
+    fn phd_comics_as_a_definition() {
+        let phd = phd();
+        let url = phd.definition.page_url(StripId::Number(100));
+        assert_eq!(url, "https://phdcomics.com/comics/archive.php?comicid=100");
+
+        let strip = phd
+            .parse(&fixture("phd.html"), &url, Some(StripId::Number(100)))
+            .unwrap();
+        assert_eq!(
+            strip.image_url,
+            "http://phdcomics.com/comics/archive/phd012345s.gif"
+        );
+        assert_eq!(strip.title, "The Science Gap");
+        assert_eq!(strip.date, "#100");
+        assert_eq!(strip.prev_date.as_deref(), Some("#99"));
+        assert_eq!(strip.next_date.as_deref(), Some("#101"));
+        assert_eq!(strip.source_url, url);
+
+        // The latest page has no id of its own; the newest strip it links to is the latest
+        let latest = phd
+            .parse(&fixture("phd.html"), &phd.definition.latest_url, None)
+            .unwrap();
+        assert_eq!(latest.date, "#101");
+        assert!(matches!(
+            phd.definition.parse_id("2024-01-01"),
+            Err(PanelsError::InvalidParam(_))
+        ));
+    }
+
+    #[test]
+    fn dated_comic_with_explicit_links() {
+        let definition = load_definition(Path::new("tests/fixtures/sources/dated.json")).unwrap();
+        let html = fixture("dated.html");
+
+        let latest = definition
+            .parse(&html, "https://www.example.com/latest", None)
+            .unwrap();
+        assert_eq!(latest.date, "2024-05-06");
+        assert_eq!(latest.title, "Monday");
+        assert_eq!(
+            latest.image_url,
+            "https://cdn.example.com/strips/2024-05-06.png"
+        );
+        assert_eq!(latest.metadata.alt_text.as_deref(), Some("Hover text"));
+        // The archive link to a far older strip is not the previous one
+        assert_eq!(latest.prev_date.as_deref(), Some("2024-05-03"));
+        assert_eq!(latest.next_date, None);
+        assert_eq!(
+            definition
+                .definition
+                .page_url(StripId::Date(NaiveDate::from_ymd_opt(2024, 5, 6).unwrap())),
+            "https://example.com/comic/2024/05/06/"
+        );
+    }
+
+    #[test]
+    fn bad_definitions_are_rejected() {
+        let mut definition = phd().definition;
+        definition.url_template = "https://phdcomics.com/comics/archive.php".into();
+        assert!(definition.compile().is_err());
+        let mut definition = phd().definition;
+        definition.image_selector = "img[".into();
+        assert!(definition.compile().is_err());
+        let mut definition = phd().definition;
+        definition.title_pattern = Some("(".into());
+        assert!(definition.compile().is_err());
+    }
+}
@@ -0,0 +1,605 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use regex::Regex;
+use reqwest::Url;
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{AST, Array, Dynamic, Engine, EvalAltResult, Map, Scope};
+use scraper::{Html, Selector};
+use serde::Deserialize;
+use tracing::{debug, info};
+
+use crate::cache::Caches;
+use crate::catalog::Catalog;
+use crate::error::{PanelsError, Result};
+use crate::http_client::{fetch_image, fetch_page};
+use crate::models::{ComicStrip, StripMetadata};
+use crate::sources::ComicSource;
+
+const SCRIPT_DIR: &str = "scripts";
+/// Functions every script must define
+const REQUIRED_FNS: [&str; 2] = ["latest", "strip"];
+/// Least time between two script requests to the same host
+const HOST_INTERVAL: Duration = Duration::from_millis(500);
+
+/// What a single call into a script may use.
+#[derive(Debug, Clone, Copy)]
+pub struct ScriptLimits {
+    /// Rhai operations, a measure of CPU time; 0 is unlimited
+    pub max_operations: u64,
+    /// Wall-clock time, including the pages the script fetches
+    pub timeout: Duration,
+    pub max_fetches: usize,
+}
+
+impl Default for ScriptLimits {
+    fn default() -> Self {
+        Self {
+            max_operations: 1_000_000,
+            timeout: Duration::from_secs(20),
+            max_fetches: 10,
+        }
+    }
+}
+
+/// A source adapter compiled from `data/scripts/<endpoint>.rhai`.
+#[derive(Debug, Clone)]
+pub struct Script {
+    pub endpoint: String,
+    ast: Arc<AST>,
+}
+
+impl Script {
+    pub fn compile(endpoint: &str, source: &str) -> anyhow::Result<Self> {
+        let ast = Engine::new()
+            .compile(source)
+            .map_err(|e| anyhow::anyhow!("{}: {}", endpoint, e))?;
+        let script = Self {
+            endpoint: endpoint.to_string(),
+            ast: Arc::new(ast),
+        };
+        for name in REQUIRED_FNS {
+            anyhow::ensure!(
+                script.defines(name, 0) || script.defines(name, 1),
+                "{}: script does not define {}()",
+                endpoint,
+                name
+            );
+        }
+        Ok(script)
+    }
+
+    fn defines(&self, name: &str, params: usize) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|f| f.name == name && f.params.len() == params)
+    }
+}
+
+/// The `.rhai` files in `data/scripts/`, in name order.
+pub fn script_paths(data_dir: &str) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(Path::new(data_dir).join(SCRIPT_DIR)) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|e| e == "rhai"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Compiles every script in `data/scripts/`, each serving the endpoint it is named after.
+/// The directory is optional.
+pub fn load_scripts(data_dir: &str) -> anyhow::Result<Vec<Script>> {
+    let mut seen = BTreeSet::new();
+    script_paths(data_dir)
+        .iter()
+        .map(|path| {
+            let endpoint = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .ok_or_else(|| anyhow::anyhow!("bad script name: {}", path.display()))?;
+            anyhow::ensure!(
+                seen.insert(endpoint.to_string()),
+                "comic {} has more than one script",
+                endpoint
+            );
+            let source = std::fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+            Script::compile(endpoint, &source)
+        })
+        .collect()
+}
+
+/// The strip a script returns, as a map with these keys.
+#[derive(Debug, Deserialize)]
+struct ScriptStrip {
+    #[serde(default)]
+    title: String,
+    date: String,
+    image_url: String,
+    #[serde(default)]
+    source_url: String,
+    #[serde(default)]
+    prev_date: Option<String>,
+    #[serde(default)]
+    next_date: Option<String>,
+    #[serde(default)]
+    alt_text: Option<String>,
+}
+
+/// Spaces out script requests to each host, so a script looping over pages can't hammer
+/// the site.
+#[derive(Default)]
+struct HostThrottle {
+    next: Mutex<HashMap<String, Instant>>,
+}
+
+impl HostThrottle {
+    /// Books the next slot for `host`, returning how long to wait for it.
+    fn reserve(&self, host: &str) -> Duration {
+        let now = Instant::now();
+        let mut next = self.next.lock().unwrap();
+        let at = next
+            .get(host)
+            .copied()
+            .filter(|at| *at > now)
+            .unwrap_or(now);
+        next.insert(host.to_string(), at + HOST_INTERVAL);
+        at - now
+    }
+}
+
+fn script_error(message: impl Into<String>) -> Box<EvalAltResult> {
+    message.into().into()
+}
+
+/// The elements matching `css` in `html`, each a map of its `text`, inner `html` and `attrs`.
+fn select(html: &str, css: &str) -> std::result::Result<Array, Box<EvalAltResult>> {
+    let selector =
+        Selector::parse(css).map_err(|e| script_error(format!("bad selector {:?}: {}", css, e)))?;
+    let document = Html::parse_document(html);
+    Ok(document
+        .select(&selector)
+        .map(|element| {
+            let attrs: Map = element
+                .value()
+                .attrs()
+                .map(|(name, value)| (name.into(), value.to_string().into()))
+                .collect();
+            let mut map = Map::new();
+            map.insert("text".into(), element.text().collect::<String>().into());
+            map.insert("html".into(), element.inner_html().into());
+            map.insert("attrs".into(), attrs.into());
+            Dynamic::from_map(map)
+        })
+        .collect())
+}
+
+/// A sandboxed engine for one call: no file or network access beyond the host functions,
+/// with `limits` enforced from `started`.
+fn engine(
+    client: reqwest::Client,
+    limits: ScriptLimits,
+    throttle: Arc<HostThrottle>,
+    endpoint: &str,
+    started: Instant,
+) -> Engine {
+    let deadline = started + limits.timeout;
+    let mut engine = Engine::new();
+    // The default resolver would let `import` load any file the server can read
+    engine.set_module_resolver(DummyModuleResolver::new());
+    engine
+        .set_max_operations(limits.max_operations)
+        .set_max_call_levels(32)
+        .set_max_expr_depths(64, 32)
+        .set_max_string_size(8 * 1024 * 1024)
+        .set_max_array_size(10_000)
+        .set_max_map_size(10_000)
+        .on_progress(move |_| (Instant::now() >= deadline).then(|| "time limit exceeded".into()));
+
+    let log_endpoint = endpoint.to_string();
+    engine.on_print(move |text| debug!(endpoint = %log_endpoint, "script: {}", text));
+    let log_endpoint = endpoint.to_string();
+    engine.on_debug(
+        move |text, _, position| debug!(endpoint = %log_endpoint, %position, "script: {}", text),
+    );
+
+    // Runs on a blocking thread, so fetches can wait on the runtime
+    let handle = tokio::runtime::Handle::current();
+    let fetches = AtomicUsize::new(0);
+    engine.register_fn(
+        "fetch",
+        move |url: &str| -> std::result::Result<Dynamic, Box<EvalAltResult>> {
+            if fetches.fetch_add(1, Ordering::SeqCst) >= limits.max_fetches {
+                return Err(script_error(format!(
+                    "more than {} fetches in one call",
+                    limits.max_fetches
+                )));
+            }
+            let parsed =
+                Url::parse(url).map_err(|e| script_error(format!("bad URL {}: {}", url, e)))?;
+            if !matches!(parsed.scheme(), "http" | "https") {
+                return Err(script_error(format!(
+                    "only http(s) URLs can be fetched: {}",
+                    url
+                )));
+            }
+            let wait = throttle.reserve(parsed.host_str().unwrap_or_default());
+            let remaining = deadline.saturating_duration_since(Instant::now() + wait);
+            if remaining.is_zero() {
+                return Err(script_error("time limit exceeded"));
+            }
+            let page = handle.block_on(async {
+                tokio::time::sleep(wait).await;
+                fetch_page(&client, url, 0, remaining.as_millis() as u64).await
+            });
+            match page {
+                Ok(Some(page)) => Ok(page.html.into()),
+                Ok(None) => Ok(Dynamic::UNIT),
+                Err(e) => Err(script_error(e.to_string())),
+            }
+        },
+    );
+    engine.register_fn("select", select);
+    engine.register_fn("resolve", |base: &str, url: &str| -> Dynamic {
+        Url::parse(base)
+            .and_then(|b| b.join(url))
+            .map_or(Dynamic::UNIT, |u| u.to_string().into())
+    });
+    engine.register_fn(
+        "capture",
+        |text: &str, pattern: &str| -> std::result::Result<Dynamic, Box<EvalAltResult>> {
+            let re = Regex::new(pattern)
+                .map_err(|e| script_error(format!("bad pattern {:?}: {}", pattern, e)))?;
+            Ok(re
+                .captures(text)
+                .and_then(|c| c.get(1).or_else(|| c.get(0)))
+                .map_or(Dynamic::UNIT, |m| m.as_str().to_string().into()))
+        },
+    );
+    engine.register_fn("parse_date", |text: &str, format: &str| -> Dynamic {
+        NaiveDate::parse_from_str(text.trim(), format)
+            .map_or(Dynamic::UNIT, |d| d.format("%Y-%m-%d").to_string().into())
+    });
+    engine.register_fn("format_date", |date: &str, format: &str| -> Dynamic {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_or(Dynamic::UNIT, |d| d.format(format).to_string().into())
+    });
+    engine
+}
+
+/// Calls `function` of `script` with `args` on a blocking thread. A script returning `()`
+/// has no strip.
+async fn run(
+    script: Script,
+    client: reqwest::Client,
+    limits: ScriptLimits,
+    throttle: Arc<HostThrottle>,
+    function: &'static str,
+    args: Vec<Dynamic>,
+) -> Result<Option<ComicStrip>> {
+    let endpoint = script.endpoint.clone();
+    let failed = |e: String| {
+        PanelsError::ScrapeFailed(format!(
+            "script {} failed in {}(): {}",
+            endpoint, function, e
+        ))
+    };
+    let started = Instant::now();
+    let call = tokio::task::spawn_blocking(move || {
+        let engine = engine(client, limits, throttle, &script.endpoint, started);
+        engine.call_fn::<Dynamic>(&mut Scope::new(), &script.ast, function, args)
+    });
+    // Fetches honour the deadline too, so this only catches a runtime that stopped polling
+    let value = tokio::time::timeout(limits.timeout + Duration::from_secs(1), call)
+        .await
+        .map_err(|_| failed("time limit exceeded".into()))?
+        .map_err(|e| failed(e.to_string()))?
+        .map_err(|e| match *e {
+            // The reason a limit was hit, rather than a bare "Script terminated"
+            EvalAltResult::ErrorTerminated(reason, _) => failed(reason.to_string()),
+            e => failed(e.to_string()),
+        })?;
+    if value.is_unit() {
+        return Ok(None);
+    }
+
+    let strip: ScriptStrip =
+        rhai::serde::from_dynamic(&value).map_err(|e| failed(format!("bad strip: {}", e)))?;
+    for date in [
+        Some(&strip.date),
+        strip.prev_date.as_ref(),
+        strip.next_date.as_ref(),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        if NaiveDate::parse_from_str(date, "%Y-%m-%d").is_err() {
+            return Err(failed(format!(
+                "bad strip: {:?} is not a YYYY-MM-DD date",
+                date
+            )));
+        }
+    }
+    debug!(endpoint, function, elapsed = ?started.elapsed(), "script returned a strip");
+    Ok(Some(ComicStrip {
+        endpoint,
+        title: strip.title,
+        date: strip.date,
+        image_url: strip.image_url,
+        source_url: strip.source_url,
+        prev_date: strip.prev_date,
+        next_date: strip.next_date,
+        metadata: StripMetadata {
+            alt_text: strip.alt_text,
+            ..StripMetadata::default()
+        },
+    }))
+}
+
+/// Serves comics with `"source": "script"` through their script in `data/scripts/`.
+pub struct ScriptSource {
+    client: reqwest::Client,
+    catalog: Arc<Catalog>,
+    caches: Caches,
+    limits: ScriptLimits,
+    throttle: Arc<HostThrottle>,
+}
+
+impl ScriptSource {
+    pub fn new(client: reqwest::Client, catalog: Arc<Catalog>, caches: Caches) -> Self {
+        Self {
+            client,
+            catalog,
+            caches,
+            limits: ScriptLimits::default(),
+            throttle: Arc::new(HostThrottle::default()),
+        }
+    }
+
+    fn script(&self, endpoint: &str) -> Result<Script> {
+        self.catalog
+            .get()
+            .scripts
+            .iter()
+            .find(|s| s.endpoint == endpoint)
+            .cloned()
+            .ok_or_else(|| {
+                PanelsError::NotFound(format!("{} has no script in data/scripts", endpoint))
+            })
+    }
+
+    async fn call(
+        &self,
+        endpoint: &str,
+        function: &'static str,
+        args: Vec<Dynamic>,
+    ) -> Result<Option<ComicStrip>> {
+        let script = self.script(endpoint)?;
+        run(
+            script,
+            self.client.clone(),
+            self.limits,
+            Arc::clone(&self.throttle),
+            function,
+            args,
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl ComicSource for ScriptSource {
+    fn handles(&self, endpoint: &str) -> bool {
+        self.catalog
+            .get()
+            .comic(endpoint)
+            .is_some_and(|c| c.source == "script")
+    }
+
+    async fn fetch_strip(&self, endpoint: &str, date: &str) -> Result<Option<ComicStrip>> {
+        let cache_key = format!("{}:{}", endpoint, date);
+        if let Some(cached) = self.caches.strips.get(&cache_key).await {
+            debug!(endpoint, date, "script strip cache hit");
+            return Ok(Some(cached));
+        }
+        let strip = self.call(endpoint, "strip", vec![date.into()]).await?;
+        if let Some(ref strip) = strip {
+            self.caches.strips.insert(cache_key, strip.clone()).await;
+        }
+        Ok(strip)
+    }
+
+    async fn fetch_latest(&self, endpoint: &str) -> Result<Option<ComicStrip>> {
+        let cache_key = format!("{}:latest", endpoint);
+        if let Some(cached) = self.caches.strips.get(&cache_key).await {
+            debug!(endpoint, "script latest cache hit");
+            return Ok(Some(cached));
+        }
+        let strip = self.call(endpoint, "latest", Vec::new()).await?;
+        if let Some(ref strip) = strip {
+            self.caches.strips.insert(cache_key, strip.clone()).await;
+            self.caches
+                .strips
+                .insert(format!("{}:{}", endpoint, strip.date), strip.clone())
+                .await;
+        }
+        Ok(strip)
+    }
+
+    async fn fetch_random(&self, endpoint: &str) -> Result<Option<ComicStrip>> {
+        if !self.script(endpoint)?.defines("random", 0) {
+            info!(endpoint, "script has no random(), serving the latest strip");
+            return self.fetch_latest(endpoint).await;
+        }
+        self.call(endpoint, "random", Vec::new()).await
+    }
+
+    async fn proxy_image(&self, image_url: &str) -> Result<(Vec<u8>, String)> {
+        fetch_image(&self.client, image_url).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::Router;
+    use axum::routing::get;
+
+    use super::*;
+
+    const COMIC: &str = r##"
+        fn latest() { strip(capture(fetch(global::BASE + "/"), "/strips/([0-9-]+)")) }
+
+        fn strip(date) {
+            let url = global::BASE + "/strips/" + date;
+            let page = fetch(url);
+            if page == () { return (); }
+            let images = select(page, "#comic img");
+            #{
+                title: select(page, "h1")[0].text,
+                date: date,
+                image_url: resolve(url, images[0].attrs.src),
+                source_url: url,
+                prev_date: parse_date(select(page, "a.prev")[0].text, "%B %d, %Y"),
+                alt_text: images[0].attrs.title,
+            }
+        }
+    "##;
+
+    async fn serve() -> String {
+        let app = Router::new()
+            .route(
+                "/",
+                get(|| async { r#"<a href="/strips/2024-05-06">Latest</a>"# }),
+            )
+            .route(
+                "/strips/2024-05-06",
+                get(|| async {
+                    r#"<h1>Monday</h1>
+                    <div id="comic"><img src="/images/0506.png" title="Hover"></div>
+                    <a class="prev" href="/strips/2024-05-03">May 3, 2024</a>"#
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}", addr)
+    }
+
+    async fn call(
+        source: &str,
+        function: &'static str,
+        limits: ScriptLimits,
+    ) -> Result<Option<ComicStrip>> {
+        let script = Script::compile("comic", source).unwrap();
+        run(
+            script,
+            reqwest::Client::new(),
+            limits,
+            Arc::new(HostThrottle::default()),
+            function,
+            Vec::new(),
+        )
+        .await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn script_scrapes_through_host_functions() {
+        let base = serve().await;
+        let source = format!("const BASE = {:?};\n{}", base, COMIC);
+        let strip = call(&source, "latest", ScriptLimits::default())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(strip.endpoint, "comic");
+        assert_eq!(strip.title, "Monday");
+        assert_eq!(strip.date, "2024-05-06");
+        assert_eq!(strip.image_url, format!("{}/images/0506.png", base));
+        assert_eq!(strip.prev_date.as_deref(), Some("2024-05-03"));
+        assert_eq!(strip.next_date, None);
+        assert_eq!(strip.metadata.alt_text.as_deref(), Some("Hover"));
+
+        // A page that isn't there is no strip, not an error
+        let source = format!(
+            "const BASE = {:?};\n{}\nfn missing() {{ strip(\"1999-01-01\") }}",
+            base, COMIC
+        );
+        assert!(
+            call(&source, "missing", ScriptLimits::default())
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn runaway_scripts_are_stopped() {
+        let spin = "fn latest() { loop {} } fn strip(date) { () }";
+        let cpu = ScriptLimits {
+            max_operations: 10_000,
+            ..ScriptLimits::default()
+        };
+        let err = call(spin, "latest", cpu).await.unwrap_err().to_string();
+        assert!(err.contains("operations"), "{}", err);
+
+        let time = ScriptLimits {
+            max_operations: 0,
+            timeout: Duration::from_millis(200),
+            ..ScriptLimits::default()
+        };
+        let started = Instant::now();
+        let err = call(spin, "latest", time).await.unwrap_err().to_string();
+        assert!(err.contains("time limit"), "{}", err);
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let fetches = ScriptLimits {
+            max_fetches: 0,
+            ..ScriptLimits::default()
+        };
+        let greedy = r#"fn latest() { fetch("http://127.0.0.1:9/") } fn strip(date) { () }"#;
+        let err = call(greedy, "latest", fetches)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("fetches"), "{}", err);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn scripts_cannot_import_files_or_return_bad_dates() {
+        let dir = tempfile::tempdir().unwrap();
+        let module = dir.path().join("secret.rhai");
+        std::fs::write(&module, "export const KEY = 42;").unwrap();
+        let importer = format!(
+            "fn latest() {{ import {:?} as s; s::KEY }} fn strip(date) {{ () }}",
+            module.with_extension("").display()
+        );
+        let err = call(&importer, "latest", ScriptLimits::default())
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("secret"), "{}", err);
+
+        let undated = r#"fn latest() { #{ date: "May 1st", image_url: "https://example.com/a.png" } }
+            fn strip(date) { () }"#;
+        let err = call(undated, "latest", ScriptLimits::default())
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("YYYY-MM-DD"), "{}", err);
+    }
+
+    #[test]
+    fn scripts_must_define_latest_and_strip() {
+        assert!(Script::compile("comic", "fn latest() { () }").is_err());
+        assert!(Script::compile("comic", "fn latest( {").is_err());
+        assert!(Script::compile("comic", "fn latest() { () } fn strip(date) { () }").is_ok());
+    }
+}
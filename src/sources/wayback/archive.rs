@@ -0,0 +1,438 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{NaiveDate, Utc};
+use scraper::{Html, Selector};
+use tokio::task::JoinSet;
+use tracing::{debug, info, warn};
+
+use super::WaybackComic;
+use super::journal::{ArchiveState, Journal, Outcome};
+use crate::http_client::fetch_page;
+use crate::sources::dilbert::DilbertCacheEntry;
+
+const CDX_URL: &str = "https://web.archive.org/cdx/search/cdx";
+const CDX_LIMIT: &str = "100000";
+
+/// A capture of a strip page in the Wayback Machine.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capture {
+    pub timestamp: String,
+    pub original: String,
+}
+
+impl Capture {
+    pub fn archive_url(&self) -> String {
+        format!(
+            "https://web.archive.org/web/{}/{}",
+            self.timestamp, self.original
+        )
+    }
+}
+
+/// Pause between rounds of fetches
+const PAUSE: Duration = Duration::from_millis(200);
+/// How often progress is logged during a build
+const PROGRESS_EVERY: Duration = Duration::from_secs(10);
+
+/// Which captured dates a build fetches, and how.
+#[derive(Debug, Clone, clap::Args)]
+pub struct BuildOptions {
+    /// Pages fetched at once
+    #[arg(long, default_value_t = 8)]
+    pub concurrency: usize,
+
+    /// Continue an interrupted build, skipping the dates it already tried
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Fetch dates that are already archived again
+    #[arg(long)]
+    pub refetch: bool,
+
+    /// Only retry the dates recorded as failed by earlier builds
+    #[arg(long)]
+    pub retry_failed: bool,
+
+    /// Skip dates before this one, YYYY-MM-DD
+    #[arg(long)]
+    pub since: Option<NaiveDate>,
+
+    #[arg(skip = PAUSE)]
+    pub pause: Duration,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 8,
+            resume: false,
+            refetch: false,
+            retry_failed: false,
+            since: None,
+            pause: PAUSE,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct BuildReport {
+    pub already_archived: usize,
+    pub fetched: usize,
+    pub failed: usize,
+    pub total: usize,
+}
+
+/// Time left at the rate of the work done so far.
+fn eta(done: usize, total: usize, elapsed: Duration) -> Option<Duration> {
+    if done == 0 {
+        return None;
+    }
+    let per_item = elapsed.as_secs_f64() / done as f64;
+    Some(Duration::from_secs_f64(
+        per_item * total.saturating_sub(done) as f64,
+    ))
+}
+
+struct Progress<'a> {
+    endpoint: &'a str,
+    total: usize,
+    done: usize,
+    failed: usize,
+    started: Instant,
+    logged: Instant,
+}
+
+impl<'a> Progress<'a> {
+    fn new(endpoint: &'a str, total: usize) -> Self {
+        let now = Instant::now();
+        Self {
+            endpoint,
+            total,
+            done: 0,
+            failed: 0,
+            started: now,
+            logged: now,
+        }
+    }
+
+    fn record(&mut self, outcome: &Outcome) {
+        self.done += 1;
+        if matches!(outcome, Outcome::Failed(_)) {
+            self.failed += 1;
+        }
+    }
+
+    /// Logs progress if it hasn't been for a while, or now with `force`.
+    fn log(&mut self, force: bool) {
+        if !force && self.logged.elapsed() < PROGRESS_EVERY {
+            return;
+        }
+        self.logged = Instant::now();
+        let elapsed = self.started.elapsed();
+        info!(
+            endpoint = self.endpoint,
+            done = self.done,
+            total = self.total,
+            failed = self.failed,
+            elapsed_secs = elapsed.as_secs(),
+            eta_secs = eta(self.done, self.total, elapsed).map(|d| d.as_secs()),
+            "archive progress"
+        );
+    }
+}
+
+fn normalize_url(src: &str) -> String {
+    if src.starts_with("//") {
+        format!("https:{}", src)
+    } else if src.starts_with('/') {
+        format!("https://web.archive.org{}", src)
+    } else {
+        src.to_string()
+    }
+}
+
+/// A comic's page selectors, parsed once for a whole build.
+#[derive(Debug)]
+pub struct PageSelectors {
+    image: Selector,
+    image_filter: Option<String>,
+    title: Option<Selector>,
+}
+
+impl PageSelectors {
+    pub fn new(comic: &WaybackComic) -> anyhow::Result<Self> {
+        let parse = |css: &str, field: &str| {
+            Selector::parse(css)
+                .map_err(|e| anyhow::anyhow!("{}: bad {}: {}", comic.endpoint, field, e))
+        };
+        Ok(Self {
+            image: parse(&comic.image_selector, "imageSelector")?,
+            image_filter: comic.image_filter.clone(),
+            title: comic
+                .title_selector
+                .as_deref()
+                .map(|css| parse(css, "titleSelector"))
+                .transpose()?,
+        })
+    }
+}
+
+/// Reads one archived strip page with the comic's selectors.
+pub fn parse_page(
+    selectors: &PageSelectors,
+    html: &str,
+    default_title: &str,
+) -> Option<DilbertCacheEntry> {
+    let document = Html::parse_document(html);
+
+    let image_url = document
+        .select(&selectors.image)
+        .filter_map(|el| el.value().attr("src"))
+        .find(|src| {
+            selectors
+                .image_filter
+                .as_ref()
+                .is_none_or(|f| src.contains(f.as_str()))
+        })
+        .map(normalize_url)?;
+
+    let title = selectors
+        .title
+        .as_ref()
+        .and_then(|sel| document.select(sel).next())
+        .map(|el| el.text().collect::<String>().trim().to_string())
+        .filter(|t| !t.is_empty())
+        .unwrap_or_else(|| default_title.to_string());
+
+    Some(DilbertCacheEntry { image_url, title })
+}
+
+/// Reads a CDX listing of `original timestamp` lines into one capture per strip date.
+pub fn parse_cdx(comic: &WaybackComic, listing: &str) -> BTreeMap<String, Capture> {
+    let mut captures = BTreeMap::new();
+    for line in listing.lines() {
+        let mut parts = line.split_whitespace();
+        let (Some(original), Some(timestamp), None) = (parts.next(), parts.next(), parts.next())
+        else {
+            continue;
+        };
+        if let Some(date) = comic.date_in_url(original) {
+            captures.insert(
+                date.format("%Y-%m-%d").to_string(),
+                Capture {
+                    timestamp: timestamp.to_string(),
+                    original: original.to_string(),
+                },
+            );
+        }
+    }
+    captures
+}
+
+/// Asks the CDX API for every successful capture of the comic's strip pages in one query.
+pub async fn fetch_captures(
+    client: &reqwest::Client,
+    comic: &WaybackComic,
+) -> anyhow::Result<BTreeMap<String, Capture>> {
+    let (prefix, _) = comic
+        .url_pattern
+        .split_once("{date}")
+        .ok_or_else(|| anyhow::anyhow!("{}: urlPattern has no {{date}}", comic.endpoint))?;
+    let mut params = vec![
+        ("url", format!("{}*", prefix)),
+        ("fl", "original,timestamp".to_string()),
+        ("filter", "statuscode:200".to_string()),
+        ("collapse", "urlkey".to_string()),
+        ("limit", CDX_LIMIT.to_string()),
+    ];
+    if let Some(until) = comic.archived_until {
+        params.push(("to", until.format("%Y%m%d").to_string()));
+    }
+    let url = reqwest::Url::parse_with_params(CDX_URL, &params)?;
+
+    let page = fetch_page(client, url.as_str(), 3, 60_000)
+        .await
+        .map_err(|e| anyhow::anyhow!("CDX query failed: {e}"))?
+        .ok_or_else(|| anyhow::anyhow!("CDX query returned nothing"))?;
+    Ok(parse_cdx(comic, &page.html))
+}
+
+async fn fetch_date(
+    client: &reqwest::Client,
+    selectors: &PageSelectors,
+    default_title: &str,
+    capture: &Capture,
+) -> Outcome {
+    match fetch_page(client, &capture.archive_url(), 2, 20_000).await {
+        Ok(Some(page)) => match parse_page(selectors, &page.html, default_title) {
+            Some(entry) => Outcome::Archived(entry),
+            None => Outcome::Failed("no image matched imageSelector".into()),
+        },
+        Ok(None) => Outcome::Failed(format!(
+            "capture {} could not be fetched",
+            capture.timestamp
+        )),
+        Err(e) => Outcome::Failed(e.to_string()),
+    }
+}
+
+/// Fetches the comic's captured strips into the archive at `path`. Each outcome is appended
+/// to a journal as it comes in, and the archive and its failures are only rewritten from it
+/// at the end, so a crash never leaves a half-written archive. A journal left by a crashed
+/// build is folded in first; with `resume` its dates are also skipped. Dates already
+/// archived are only fetched again with `refetch`.
+pub async fn build_archive(
+    client: &reqwest::Client,
+    comic: &WaybackComic,
+    default_title: &str,
+    path: &Path,
+    options: &BuildOptions,
+) -> anyhow::Result<BuildReport> {
+    let mut state = ArchiveState::load(path)?;
+    let leftover = Journal::replay(path)?;
+    let already_tried: BTreeSet<String> = leftover.iter().map(|(d, _)| d.clone()).collect();
+    if !leftover.is_empty() {
+        info!(
+            endpoint = %comic.endpoint,
+            dates = leftover.len(),
+            resume = options.resume,
+            "found the journal of an interrupted build"
+        );
+        for (date, outcome) in leftover {
+            state.apply(&date, outcome, Utc::now());
+        }
+        // Folded in now, so a build interrupted again doesn't replay these attempts twice
+        state.compact()?;
+    }
+    let already_archived = state.strips.len();
+
+    let captures = fetch_captures(client, comic).await?;
+    let since = options.since.map(|d| d.format("%Y-%m-%d").to_string());
+    let to_fetch: Vec<(String, Capture)> = captures
+        .into_iter()
+        .filter(|(date, _)| since.as_ref().is_none_or(|s| date >= s))
+        .filter(|(date, _)| !options.resume || !already_tried.contains(date))
+        .filter(|(date, _)| options.refetch || !state.strips.contains_key(date))
+        .filter(|(date, _)| !options.retry_failed || state.failures.contains_key(date))
+        .collect();
+    info!(
+        endpoint = %comic.endpoint,
+        already_archived,
+        to_fetch = to_fetch.len(),
+        "starting archive build"
+    );
+
+    let selectors = Arc::new(PageSelectors::new(comic)?);
+    let mut journal = Journal::open(path)?;
+    let mut progress = Progress::new(&comic.endpoint, to_fetch.len());
+    let mut fetched = 0;
+    for chunk in to_fetch.chunks(options.concurrency.max(1)) {
+        let mut tasks = JoinSet::new();
+        for (date, capture) in chunk {
+            let (client, selectors, default_title) = (
+                client.clone(),
+                Arc::clone(&selectors),
+                default_title.to_string(),
+            );
+            let (date, capture) = (date.clone(), capture.clone());
+            tasks.spawn(async move {
+                let outcome = fetch_date(&client, &selectors, &default_title, &capture).await;
+                (date, outcome)
+            });
+        }
+        while let Some(result) = tasks.join_next().await {
+            let (date, outcome) = result?;
+            match outcome {
+                Outcome::Archived(_) => {
+                    fetched += 1;
+                    debug!(%date, "archived");
+                }
+                Outcome::Failed(ref reason) => warn!(%date, "not archived: {}", reason),
+            }
+            journal.append(&date, &outcome)?;
+            progress.record(&outcome);
+            state.apply(&date, outcome, Utc::now());
+        }
+        journal.sync()?;
+        progress.log(false);
+        tokio::time::sleep(options.pause).await;
+    }
+    progress.log(true);
+
+    state.compact()?;
+    Ok(BuildReport {
+        already_archived,
+        fetched,
+        failed: progress.failed,
+        total: state.strips.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comic() -> WaybackComic {
+        WaybackComic {
+            endpoint: "old-comic".into(),
+            url_pattern: "old-comic.com/strip/{date}".into(),
+            date_format: "%Y%m%d".into(),
+            image_selector: "#comic img, img".into(),
+            image_filter: Some("/strips/".into()),
+            title_selector: Some("h1.title".into()),
+            archived_until: None,
+        }
+    }
+
+    #[test]
+    fn pages_are_read_with_the_configured_selectors() {
+        let html = r#"<html><body>
+            <img src="//web.archive.org/web/2010im_/http://old-comic.com/logo.png">
+            <h1 class="title"> Monday Again </h1>
+            <div id="comic"><img src="/web/2010im_/http://old-comic.com/strips/20100104.gif"></div>
+        </body></html>"#;
+        let selectors = PageSelectors::new(&comic()).unwrap();
+        let entry = parse_page(&selectors, html, "Old Comic").unwrap();
+        assert_eq!(
+            entry.image_url,
+            "https://web.archive.org/web/2010im_/http://old-comic.com/strips/20100104.gif"
+        );
+        assert_eq!(entry.title, "Monday Again");
+
+        let entry = parse_page(&selectors, r#"<img src="/strips/1.gif">"#, "Old Comic").unwrap();
+        assert_eq!(entry.title, "Old Comic");
+        assert!(parse_page(&selectors, "<p>gone</p>", "Old Comic").is_none());
+    }
+
+    #[test]
+    fn eta_follows_the_rate_so_far() {
+        assert_eq!(eta(0, 100, Duration::from_secs(5)), None);
+        assert_eq!(
+            eta(25, 100, Duration::from_secs(10)),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(eta(100, 100, Duration::from_secs(40)), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn cdx_lines_become_one_capture_per_date() {
+        let listing = "\
+http://old-comic.com:80/strip/20100104 20100110120000
+http://old-comic.com/strip/archive 20100110120000
+https://www.old-comic.com/strip/20100105 20110101000000
+malformed
+";
+        let captures = parse_cdx(&comic(), listing);
+        assert_eq!(
+            captures.keys().collect::<Vec<_>>(),
+            vec!["2010-01-04", "2010-01-05"]
+        );
+        assert_eq!(
+            captures["2010-01-04"].archive_url(),
+            "https://web.archive.org/web/20100110120000/http://old-comic.com:80/strip/20100104"
+        );
+    }
+}
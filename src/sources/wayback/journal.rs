@@ -0,0 +1,293 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use super::load_archive;
+use crate::sources::dilbert::DilbertCacheEntry;
+
+/// What fetching one date came to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Outcome {
+    Archived(DilbertCacheEntry),
+    Failed(String),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Line {
+    date: String,
+    #[serde(flatten)]
+    outcome: Outcome,
+}
+
+/// Why a date couldn't be archived, kept so later runs can retry it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Failure {
+    pub reason: String,
+    pub attempts: u32,
+    pub last_tried: DateTime<Utc>,
+}
+
+fn journal_path(archive: &Path) -> PathBuf {
+    archive.with_extension("journal")
+}
+
+fn failures_path(archive: &Path) -> PathBuf {
+    archive.with_extension("failures.json")
+}
+
+/// Append-only log of the outcomes of a build in progress, one JSON line per date, next to
+/// the archive. The archive itself is only rewritten when the journal is compacted, so a
+/// crash can at worst lose the line being written.
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+}
+
+impl Journal {
+    pub fn open(archive: &Path) -> anyhow::Result<Self> {
+        let path = journal_path(archive);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        Ok(Self { path, file })
+    }
+
+    /// The outcomes left by a build that didn't finish, in the order they were written. A
+    /// torn last line is dropped and cut from the file, so the next append starts a new line.
+    pub fn replay(archive: &Path) -> anyhow::Result<Vec<(String, Outcome)>> {
+        let path = journal_path(archive);
+        let contents = match std::fs::read(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        let complete = contents
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |end| end + 1);
+        if complete < contents.len() {
+            warn!(
+                "dropping {} bytes torn off the end of {}",
+                contents.len() - complete,
+                path.display()
+            );
+            OpenOptions::new()
+                .write(true)
+                .open(&path)
+                .and_then(|file| file.set_len(complete as u64))
+                .with_context(|| format!("failed to truncate {}", path.display()))?;
+        }
+        let mut outcomes = Vec::new();
+        for (number, line) in contents[..complete].lines().enumerate() {
+            let line = line.with_context(|| format!("failed to read {}", path.display()))?;
+            match serde_json::from_str::<Line>(&line) {
+                Ok(line) => outcomes.push((line.date, line.outcome)),
+                Err(e) => warn!("skipping line {} of {}: {}", number + 1, path.display(), e),
+            }
+        }
+        Ok(outcomes)
+    }
+
+    pub fn append(&mut self, date: &str, outcome: &Outcome) -> anyhow::Result<()> {
+        let mut line = serde_json::to_string(&Line {
+            date: date.to_string(),
+            outcome: outcome.clone(),
+        })?;
+        line.push('\n');
+        self.file
+            .write_all(line.as_bytes())
+            .with_context(|| format!("failed to append to {}", self.path.display()))
+    }
+
+    /// Makes everything appended so far durable.
+    pub fn sync(&self) -> anyhow::Result<()> {
+        self.file
+            .sync_data()
+            .with_context(|| format!("failed to sync {}", self.path.display()))
+    }
+
+    /// Removes the journal of `archive`, once its outcomes are in the archive.
+    pub fn discard(archive: &Path) -> anyhow::Result<()> {
+        let path = journal_path(archive);
+        match std::fs::remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e).with_context(|| format!("failed to remove {}", path.display())),
+        }
+    }
+}
+
+/// An archive with the failures recorded for it, as loaded before a build.
+#[derive(Debug)]
+pub struct ArchiveState {
+    path: PathBuf,
+    pub strips: BTreeMap<String, DilbertCacheEntry>,
+    pub failures: BTreeMap<String, Failure>,
+}
+
+impl ArchiveState {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let failures_path = failures_path(path);
+        let failures = match std::fs::read_to_string(&failures_path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("failed to parse {}", failures_path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("failed to read {}", failures_path.display()));
+            }
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            strips: load_archive(path)?,
+            failures,
+        })
+    }
+
+    pub fn apply(&mut self, date: &str, outcome: Outcome, at: DateTime<Utc>) {
+        match outcome {
+            Outcome::Archived(entry) => {
+                self.failures.remove(date);
+                self.strips.insert(date.to_string(), entry);
+            }
+            // A failed refetch leaves the strip archived before in place
+            Outcome::Failed(_) if self.strips.contains_key(date) => {}
+            Outcome::Failed(reason) => {
+                let attempts = self.failures.get(date).map_or(0, |f| f.attempts) + 1;
+                self.failures.insert(
+                    date.to_string(),
+                    Failure {
+                        reason,
+                        attempts,
+                        last_tried: at,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Writes the archive and its failures, each to a temporary file renamed into place, then
+    /// drops the journal they now include.
+    pub fn compact(&self) -> anyhow::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        write_atomic(&self.path, &serde_json::to_string_pretty(&self.strips)?)?;
+        let failures_path = failures_path(&self.path);
+        if self.failures.is_empty() {
+            if failures_path.exists() {
+                std::fs::remove_file(&failures_path)?;
+            }
+        } else {
+            write_atomic(
+                &failures_path,
+                &serde_json::to_string_pretty(&self.failures)?,
+            )?;
+        }
+        Journal::discard(&self.path)
+    }
+}
+
+fn write_atomic(path: &Path, contents: &str) -> anyhow::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file =
+        File::create(&tmp).with_context(|| format!("failed to create {}", tmp.display()))?;
+    file.write_all(contents.as_bytes())
+        .and_then(|()| file.sync_all())
+        .with_context(|| format!("failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(title: &str) -> DilbertCacheEntry {
+        DilbertCacheEntry {
+            image_url: format!("https://example.com/{}.gif", title),
+            title: title.into(),
+        }
+    }
+
+    #[test]
+    fn interrupted_build_is_replayed_and_compacted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("old-comic.json");
+        std::fs::write(&path, r#"{"2010-01-01": {"image_url": "a", "title": "a"}}"#).unwrap();
+
+        let mut journal = Journal::open(&path).unwrap();
+        journal
+            .append("2010-01-02", &Outcome::Archived(entry("b")))
+            .unwrap();
+        journal
+            .append("2010-01-03", &Outcome::Failed("no image matched".into()))
+            .unwrap();
+        drop(journal);
+        // A crash mid-write leaves half a line behind
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(journal_path(&path))
+            .unwrap();
+        file.write_all(br#"{"date": "2010-01-04", "arch"#).unwrap();
+
+        let outcomes = Journal::replay(&path).unwrap();
+        assert_eq!(outcomes.len(), 2);
+        // The torn line is cut, so appending again starts on a line of its own
+        let mut journal = Journal::open(&path).unwrap();
+        journal
+            .append("2010-01-04", &Outcome::Failed("timeout".into()))
+            .unwrap();
+        drop(journal);
+        let outcomes = Journal::replay(&path).unwrap();
+        assert_eq!(outcomes.len(), 3);
+        let mut state = ArchiveState::load(&path).unwrap();
+        assert_eq!(state.strips.len(), 1);
+        for (date, outcome) in outcomes {
+            state.apply(&date, outcome, Utc::now());
+        }
+        state.compact().unwrap();
+
+        assert!(!journal_path(&path).exists());
+        let state = ArchiveState::load(&path).unwrap();
+        assert_eq!(
+            state.strips.keys().collect::<Vec<_>>(),
+            vec!["2010-01-01", "2010-01-02"]
+        );
+        assert_eq!(state.failures["2010-01-03"].reason, "no image matched");
+        assert_eq!(state.failures["2010-01-03"].attempts, 1);
+    }
+
+    #[test]
+    fn archiving_a_date_clears_its_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("old-comic.json");
+        let mut state = ArchiveState::load(&path).unwrap();
+        state.apply("2010-01-03", Outcome::Failed("timeout".into()), Utc::now());
+        state.apply("2010-01-03", Outcome::Failed("timeout".into()), Utc::now());
+        assert_eq!(state.failures["2010-01-03"].attempts, 2);
+        state.compact().unwrap();
+        assert!(failures_path(&path).exists());
+
+        let mut state = ArchiveState::load(&path).unwrap();
+        state.apply("2010-01-03", Outcome::Archived(entry("c")), Utc::now());
+        state.compact().unwrap();
+        assert!(state.failures.is_empty());
+        assert!(!failures_path(&path).exists());
+
+        // Refetching an archived date and failing keeps the strip, and isn't a failure
+        state.apply("2010-01-03", Outcome::Failed("timeout".into()), Utc::now());
+        assert_eq!(state.strips["2010-01-03"], entry("c"));
+        assert!(state.failures.is_empty());
+    }
+}
@@ -0,0 +1,329 @@
+pub mod archive;
+pub mod journal;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::catalog::Catalog;
+use crate::error::{PanelsError, Result};
+use crate::http_client::fetch_image;
+use crate::models::{ComicStrip, StripMetadata};
+use crate::sources::ComicSource;
+use crate::sources::dilbert::DilbertCacheEntry;
+
+const CONFIG_FILE: &str = "wayback.json";
+const ARCHIVE_DIR: &str = "wayback";
+
+/// How to rebuild a discontinued comic from the Wayback Machine, from `wayback.json`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WaybackComic {
+    pub endpoint: String,
+    /// Strip page URL without the scheme, with `{date}` where the date goes,
+    /// e.g. `dilbert.com/strip/{date}`
+    pub url_pattern: String,
+    /// chrono format of the date in `url_pattern`
+    #[serde(default = "default_date_format")]
+    pub date_format: String,
+    /// Elements whose `src` is the strip image
+    pub image_selector: String,
+    /// Text the image `src` must contain, to skip logos and ads the selector also matches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_filter: Option<String>,
+    /// Element holding the strip's title; the comic's title is used without one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title_selector: Option<String>,
+    /// Ignore captures after this date, such as those of a site that has since been replaced
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archived_until: Option<NaiveDate>,
+}
+
+fn default_date_format() -> String {
+    "%Y-%m-%d".to_string()
+}
+
+impl WaybackComic {
+    fn check(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            !self.endpoint.trim().is_empty(),
+            "{} has a comic without an endpoint",
+            CONFIG_FILE
+        );
+        anyhow::ensure!(
+            self.url_pattern.matches("{date}").count() == 1,
+            "{}: urlPattern must contain {{date}} exactly once",
+            self.endpoint
+        );
+        archive::PageSelectors::new(self)?;
+        Ok(())
+    }
+
+    /// The live page of the strip for `date`.
+    pub fn strip_url(&self, date: NaiveDate) -> String {
+        let formatted = date.format(&self.date_format).to_string();
+        format!("https://{}", self.url_pattern.replace("{date}", &formatted))
+    }
+
+    /// The date of an archived URL matching `url_pattern`. The scheme, host and port are
+    /// ignored since captures differ in them.
+    pub fn date_in_url(&self, url: &str) -> Option<NaiveDate> {
+        let (before, after) = self.url_pattern.split_once("{date}")?;
+        let path_before = before.split_once('/').map_or("", |(_, p)| p);
+        let rest = url.split_once("://").map_or(url, |(_, r)| r);
+        let path = rest.split_once('/').map_or("", |(_, p)| p);
+        let mut date = path.strip_prefix(path_before)?;
+        if !after.ends_with('/') {
+            date = date.trim_end_matches('/');
+        }
+        let date = date.strip_suffix(after)?;
+        NaiveDate::parse_from_str(date, &self.date_format).ok()
+    }
+
+    /// Where the prebuilt archive of this comic is kept.
+    pub fn archive_path(&self, data_dir: &str) -> PathBuf {
+        Path::new(data_dir)
+            .join(ARCHIVE_DIR)
+            .join(format!("{}.json", self.endpoint))
+    }
+}
+
+/// A comic's configuration with the strips archived for it so far, by `YYYY-MM-DD` date.
+#[derive(Debug, Clone)]
+pub struct WaybackArchive {
+    pub comic: WaybackComic,
+    pub strips: BTreeMap<String, DilbertCacheEntry>,
+}
+
+/// Reads `wayback.json`. The file is optional.
+pub fn load_wayback_comics(data_dir: &str) -> anyhow::Result<Vec<WaybackComic>> {
+    let path = Path::new(data_dir).join(CONFIG_FILE);
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => anyhow::bail!("failed to read {}: {}", path.display(), e),
+    };
+    let comics: Vec<WaybackComic> = serde_json::from_str(&contents)
+        .map_err(|e| anyhow::anyhow!("failed to parse {}: {}", path.display(), e))?;
+    let mut seen = BTreeSet::new();
+    for comic in &comics {
+        comic.check()?;
+        anyhow::ensure!(
+            seen.insert(comic.endpoint.as_str()),
+            "comic {} appears more than once in {}",
+            comic.endpoint,
+            CONFIG_FILE
+        );
+    }
+    Ok(comics)
+}
+
+/// Reads an archive in the `dilbert_cache.json` format. A missing file is an empty archive;
+/// a malformed one is an error.
+pub fn load_archive(path: &Path) -> anyhow::Result<BTreeMap<String, DilbertCacheEntry>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => anyhow::bail!("failed to read {}: {}", path.display(), e),
+    };
+    let archive: BTreeMap<String, DilbertCacheEntry> = serde_json::from_str(&contents)
+        .map_err(|e| anyhow::anyhow!("failed to parse {}: {}", path.display(), e))?;
+    if let Some(bad) = archive
+        .keys()
+        .find(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").is_err())
+    {
+        anyhow::bail!("{} has an invalid date key: {}", path.display(), bad);
+    }
+    Ok(archive)
+}
+
+/// Loads every configured comic with its prebuilt archive.
+pub fn load_wayback(data_dir: &str) -> anyhow::Result<Vec<WaybackArchive>> {
+    load_wayback_comics(data_dir)?
+        .into_iter()
+        .map(|comic| {
+            let path = comic.archive_path(data_dir);
+            let strips = load_archive(&path)?;
+            if strips.is_empty() {
+                info!(endpoint = %comic.endpoint, "no wayback archive built yet");
+            }
+            Ok(WaybackArchive { comic, strips })
+        })
+        .collect()
+}
+
+fn build_strip(archive: &WaybackArchive, date: &str) -> Option<ComicStrip> {
+    let entry = archive.strips.get(date)?;
+    let day = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    // Archives have gaps, so neighbours are the nearest archived dates
+    let prev_date = archive.strips.range(..date.to_string()).next_back();
+    let next_date = archive
+        .strips
+        .range(date.to_string()..)
+        .find(|(d, _)| d.as_str() != date);
+    Some(ComicStrip {
+        endpoint: archive.comic.endpoint.clone(),
+        title: entry.title.clone(),
+        date: date.to_string(),
+        image_url: entry.image_url.clone(),
+        source_url: archive.comic.strip_url(day),
+        prev_date: prev_date.map(|(d, _)| d.clone()),
+        next_date: next_date.map(|(d, _)| d.clone()),
+        metadata: StripMetadata::default(),
+    })
+}
+
+/// Serves comics with `"source": "wayback"` from archives prebuilt by `scrape_wayback`.
+pub struct WaybackSource {
+    client: reqwest::Client,
+    catalog: Arc<Catalog>,
+}
+
+impl WaybackSource {
+    pub fn new(client: reqwest::Client, catalog: Arc<Catalog>) -> Self {
+        Self { client, catalog }
+    }
+
+    fn with_archive<T>(&self, endpoint: &str, f: impl FnOnce(&WaybackArchive) -> T) -> Result<T> {
+        let catalog = self.catalog.get();
+        let archive = catalog
+            .wayback
+            .iter()
+            .find(|a| a.comic.endpoint == endpoint)
+            .ok_or_else(|| {
+                PanelsError::NotFound(format!("{} has no entry in {}", endpoint, CONFIG_FILE))
+            })?;
+        Ok(f(archive))
+    }
+}
+
+#[async_trait]
+impl ComicSource for WaybackSource {
+    fn handles(&self, endpoint: &str) -> bool {
+        self.catalog
+            .get()
+            .comic(endpoint)
+            .is_some_and(|c| c.source == "wayback")
+    }
+
+    async fn fetch_strip(&self, endpoint: &str, date: &str) -> Result<Option<ComicStrip>> {
+        if NaiveDate::parse_from_str(date, "%Y-%m-%d").is_err() {
+            return Err(PanelsError::InvalidParam(format!(
+                "expected a YYYY-MM-DD date, got {}",
+                date
+            )));
+        }
+        debug!(endpoint, date, "wayback strip from archive");
+        self.with_archive(endpoint, |archive| build_strip(archive, date))
+    }
+
+    async fn fetch_latest(&self, endpoint: &str) -> Result<Option<ComicStrip>> {
+        self.with_archive(endpoint, |archive| {
+            let (date, _) = archive.strips.last_key_value()?;
+            build_strip(archive, date)
+        })
+    }
+
+    async fn fetch_random(&self, endpoint: &str) -> Result<Option<ComicStrip>> {
+        self.with_archive(endpoint, |archive| {
+            if archive.strips.is_empty() {
+                return None;
+            }
+            let idx = rand::thread_rng().gen_range(0..archive.strips.len());
+            let date = archive.strips.keys().nth(idx)?;
+            build_strip(archive, date)
+        })
+    }
+
+    async fn proxy_image(&self, image_url: &str) -> Result<(Vec<u8>, String)> {
+        fetch_image(&self.client, image_url).await
+    }
+
+    fn known_strips(&self) -> Vec<ComicStrip> {
+        let catalog = self.catalog.get();
+        catalog
+            .wayback
+            .iter()
+            .flat_map(|archive| {
+                archive
+                    .strips
+                    .keys()
+                    .filter_map(|date| build_strip(archive, date))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(url_pattern: &str, date_format: &str) -> WaybackComic {
+        WaybackComic {
+            endpoint: "old-comic".into(),
+            url_pattern: url_pattern.into(),
+            date_format: date_format.into(),
+            image_selector: "img.strip".into(),
+            image_filter: None,
+            title_selector: None,
+            archived_until: None,
+        }
+    }
+
+    #[test]
+    fn dates_are_read_from_captured_urls() {
+        let comic = config("old-comic.com/strips/{date}.html", "%Y/%m/%d");
+        let day = NaiveDate::from_ymd_opt(2004, 7, 14).unwrap();
+        assert_eq!(
+            comic.strip_url(day),
+            "https://old-comic.com/strips/2004/07/14.html"
+        );
+        assert_eq!(
+            comic.date_in_url("http://www.old-comic.com:80/strips/2004/07/14.html"),
+            Some(day)
+        );
+        assert_eq!(
+            comic.date_in_url("http://old-comic.com/strips/archive.html"),
+            None
+        );
+
+        let comic = config("dilbert.com/strip/{date}", "%Y-%m-%d");
+        assert_eq!(
+            comic.date_in_url("https://dilbert.com/strip/2004-07-14/"),
+            Some(day)
+        );
+    }
+
+    #[test]
+    fn neighbours_skip_gaps_in_the_archive() {
+        let entry = |title: &str| DilbertCacheEntry {
+            image_url: format!("https://example.com/{}.gif", title),
+            title: title.into(),
+        };
+        let archive = WaybackArchive {
+            comic: config("old-comic.com/{date}", "%Y-%m-%d"),
+            strips: BTreeMap::from([
+                ("2004-07-01".to_string(), entry("a")),
+                ("2004-07-14".to_string(), entry("b")),
+                ("2004-08-02".to_string(), entry("c")),
+            ]),
+        };
+        let strip = build_strip(&archive, "2004-07-14").unwrap();
+        assert_eq!(strip.prev_date.as_deref(), Some("2004-07-01"));
+        assert_eq!(strip.next_date.as_deref(), Some("2004-08-02"));
+        assert_eq!(strip.source_url, "https://old-comic.com/2004-07-14");
+        assert!(build_strip(&archive, "2004-07-15").is_none());
+        assert!(
+            build_strip(&archive, "2004-08-02")
+                .unwrap()
+                .next_date
+                .is_none()
+        );
+    }
+}
@@ -1,4 +1,7 @@
+use std::collections::BTreeMap;
+
 use async_trait::async_trait;
+use chrono::NaiveDate;
 use rand::Rng;
 use serde::Deserialize;
 use tracing::{debug, info};
@@ -6,18 +9,69 @@ use tracing::{debug, info};
 use crate::cache::Caches;
 use crate::error::{PanelsError, Result};
 use crate::http_client::random_user_agent;
-use crate::models::ComicStrip;
+use crate::models::{ComicStrip, StripLink, StripMetadata};
 use crate::sources::ComicSource;
 
 #[derive(Debug, Deserialize)]
-#[allow(dead_code)]
 struct XkcdComic {
     num: u32,
     title: String,
+    #[serde(default)]
+    safe_title: String,
     img: String,
+    #[serde(default)]
+    alt: String,
+    #[serde(default)]
+    transcript: String,
+    #[serde(default)]
+    year: String,
+    #[serde(default)]
+    month: String,
+    #[serde(default)]
+    day: String,
+    #[serde(default)]
+    link: String,
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
 }
 
 impl XkcdComic {
+    fn published(&self) -> Option<String> {
+        let year: i32 = self.year.parse().ok()?;
+        let month: u32 = self.month.parse().ok()?;
+        let day: u32 = self.day.parse().ok()?;
+        NaiveDate::from_ymd_opt(year, month, day).map(|d| d.format("%Y-%m-%d").to_string())
+    }
+
+    fn metadata(&self) -> StripMetadata {
+        let mut links = vec![StripLink {
+            label: "explain xkcd".to_string(),
+            url: format!("https://www.explainxkcd.com/wiki/index.php/{}", self.num),
+        }];
+        if let Some(link) = non_empty(&self.link) {
+            links.insert(
+                0,
+                StripLink {
+                    label: "link".to_string(),
+                    url: link,
+                },
+            );
+        }
+
+        StripMetadata {
+            alt_text: non_empty(&self.alt),
+            transcript: non_empty(&self.transcript),
+            safe_title: non_empty(&self.safe_title).filter(|t| *t != self.title),
+            published: self.published(),
+            links,
+            image_hash: None,
+            extra: BTreeMap::new(),
+        }
+    }
+
     fn to_strip(&self) -> ComicStrip {
         ComicStrip {
             endpoint: "xkcd".to_string(),
@@ -31,6 +85,7 @@ impl XkcdComic {
                 None
             },
             next_date: Some(format!("#{}", self.num + 1)),
+            metadata: self.metadata(),
         }
     }
 }
@@ -177,3 +232,42 @@ impl ComicSource for XkcdSource {
         Ok((bytes.to_vec(), content_type))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"{"month": "8", "num": 927, "link": "", "year": "2011", "news": "",
+        "safe_title": "Standards", "transcript": "", "alt": "Fortunately, the charging one has been solved now that we've all standardized on mini-USB.",
+        "img": "https://imgs.xkcd.com/comics/standards.png", "title": "Standards", "day": "17"}"#;
+
+    #[test]
+    fn to_strip_keeps_alt_text_and_publication_date() {
+        let comic: XkcdComic = serde_json::from_str(SAMPLE).unwrap();
+        let strip = comic.to_strip();
+
+        assert_eq!(strip.date, "#927");
+        assert_eq!(strip.metadata.published.as_deref(), Some("2011-08-17"));
+        assert!(strip.metadata.alt_text.unwrap().starts_with("Fortunately"));
+        assert!(strip.metadata.transcript.is_none());
+        assert!(strip.metadata.safe_title.is_none());
+        assert_eq!(strip.metadata.links.len(), 1);
+        assert!(strip.metadata.links[0].url.ends_with("/927"));
+    }
+
+    #[test]
+    fn to_strip_records_differing_safe_title() {
+        let comic: XkcdComic = serde_json::from_str(
+            r#"{"num": 1, "title": "Barrel - Part 1", "safe_title": "Barrel Part 1",
+                "img": "https://imgs.xkcd.com/comics/barrel_cropped_(1).jpg",
+                "link": "https://example.com", "year": "2006", "month": "1", "day": "1"}"#,
+        )
+        .unwrap();
+        let strip = comic.to_strip();
+
+        assert_eq!(strip.metadata.safe_title.as_deref(), Some("Barrel Part 1"));
+        assert_eq!(strip.clone().into_safe().title, "Barrel Part 1");
+        assert_eq!(strip.metadata.links[0].label, "link");
+        assert_eq!(strip.prev_date, None);
+    }
+}
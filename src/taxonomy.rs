@@ -0,0 +1,275 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::{Comic, Rating};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagKind {
+    #[default]
+    Topic,
+    /// Marks the language a comic is published in
+    Language,
+    /// Marks content some readers may want filtered out
+    Rating,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TagDefinition {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub parent: Option<String>,
+    #[serde(default)]
+    pub synonyms: Vec<String>,
+    #[serde(default)]
+    pub kind: TagKind,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TagNode {
+    pub slug: String,
+    pub name: String,
+    pub description: String,
+    pub kind: TagKind,
+    pub synonyms: Vec<String>,
+    /// Comics tagged with exactly this tag
+    pub count: usize,
+    /// Comics tagged with this tag or any tag below it
+    pub total: usize,
+    pub children: Vec<TagNode>,
+}
+
+/// Tag definitions from `tag_definitions.json`: display names, hierarchy, synonyms and kinds.
+#[derive(Debug, Clone, Default)]
+pub struct Taxonomy {
+    tags: BTreeMap<String, TagDefinition>,
+    aliases: HashMap<String, String>,
+}
+
+impl Taxonomy {
+    pub fn new(tags: BTreeMap<String, TagDefinition>) -> anyhow::Result<Self> {
+        let mut aliases = HashMap::new();
+        for (slug, def) in &tags {
+            if let Some(ref parent) = def.parent
+                && !tags.contains_key(parent)
+            {
+                anyhow::bail!("tag {} has unknown parent {}", slug, parent);
+            }
+            for alias in std::iter::once(slug).chain(&def.synonyms) {
+                let key = alias.to_lowercase();
+                if let Some(other) = aliases.insert(key, slug.clone())
+                    && other != *slug
+                {
+                    anyhow::bail!("{} is used by both {} and {}", alias, other, slug);
+                }
+            }
+        }
+
+        let taxonomy = Self { tags, aliases };
+        for slug in taxonomy.tags.keys() {
+            let mut seen = BTreeSet::new();
+            let mut current = Some(slug.as_str());
+            while let Some(tag) = current {
+                if !seen.insert(tag) {
+                    anyhow::bail!("tag {} is its own ancestor", slug);
+                }
+                current = taxonomy.tags[tag].parent.as_deref();
+            }
+        }
+        Ok(taxonomy)
+    }
+
+    pub fn get(&self, slug: &str) -> Option<&TagDefinition> {
+        self.tags.get(slug)
+    }
+
+    /// Maps a slug or synonym, in any case, to its canonical slug.
+    pub fn resolve(&self, name: &str) -> Option<&str> {
+        self.aliases.get(&name.to_lowercase()).map(String::as_str)
+    }
+
+    pub fn is_kind(&self, slug: &str, kind: TagKind) -> bool {
+        self.tags.get(slug).is_some_and(|d| d.kind == kind)
+    }
+
+    /// Whether a comic may be shown in safe mode: rated `general` and carrying no rating tag.
+    pub fn is_safe(&self, comic: &Comic, tags: &[String]) -> bool {
+        comic.rating == Rating::General && !tags.iter().any(|t| self.is_kind(t, TagKind::Rating))
+    }
+
+    /// The tag itself followed by every tag below it.
+    pub fn descendants(&self, slug: &str) -> Vec<String> {
+        let mut out = vec![slug.to_string()];
+        let mut i = 0;
+        while i < out.len() {
+            let children = self
+                .tags
+                .iter()
+                .filter(|(_, d)| d.parent.as_deref() == Some(out[i].as_str()))
+                .map(|(s, _)| s.clone())
+                .collect::<Vec<_>>();
+            out.extend(children);
+            i += 1;
+        }
+        out
+    }
+
+    /// Tags a `tag` filter should match: the resolved tag and its descendants, or the
+    /// raw value when it is not a known tag.
+    pub fn expand(&self, name: &str) -> Vec<String> {
+        match self.resolve(name) {
+            Some(slug) => self.descendants(slug),
+            None => vec![name.to_lowercase()],
+        }
+    }
+
+    /// Every tag used in `tags` that has no definition, as `(endpoint, tag)` pairs.
+    pub fn undefined_tags<'a>(
+        &self,
+        tags: &'a HashMap<String, Vec<String>>,
+    ) -> Vec<(&'a str, &'a str)> {
+        let mut missing: Vec<(&str, &str)> = tags
+            .iter()
+            .flat_map(|(endpoint, list)| list.iter().map(move |t| (endpoint.as_str(), t.as_str())))
+            .filter(|(_, t)| !self.tags.contains_key(*t))
+            .collect();
+        missing.sort_unstable();
+        missing
+    }
+
+    pub fn tree(&self, tags: &HashMap<String, Vec<String>>) -> Vec<TagNode> {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for list in tags.values() {
+            for tag in list {
+                *counts.entry(tag.as_str()).or_insert(0) += 1;
+            }
+        }
+        self.children_of(None, tags, &counts)
+    }
+
+    fn children_of(
+        &self,
+        parent: Option<&str>,
+        tags: &HashMap<String, Vec<String>>,
+        counts: &HashMap<&str, usize>,
+    ) -> Vec<TagNode> {
+        self.tags
+            .iter()
+            .filter(|(_, d)| d.parent.as_deref() == parent)
+            .map(|(slug, def)| {
+                let below = self.descendants(slug);
+                let total = tags
+                    .values()
+                    .filter(|list| list.iter().any(|t| below.contains(t)))
+                    .count();
+                TagNode {
+                    slug: slug.clone(),
+                    name: def.name.clone(),
+                    description: def.description.clone(),
+                    kind: def.kind,
+                    synonyms: def.synonyms.clone(),
+                    count: counts.get(slug.as_str()).copied().unwrap_or(0),
+                    total,
+                    children: self.children_of(Some(slug), tags, counts),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn def(parent: Option<&str>, synonyms: &[&str], kind: TagKind) -> TagDefinition {
+        TagDefinition {
+            name: String::new(),
+            description: String::new(),
+            parent: parent.map(str::to_string),
+            synonyms: synonyms.iter().map(|s| s.to_string()).collect(),
+            kind,
+        }
+    }
+
+    fn sample() -> Taxonomy {
+        Taxonomy::new(BTreeMap::from([
+            ("family".into(), def(None, &[], TagKind::Topic)),
+            (
+                "kids".into(),
+                def(Some("family"), &["children"], TagKind::Topic),
+            ),
+            ("babies".into(), def(Some("kids"), &[], TagKind::Topic)),
+            (
+                "en-espanol".into(),
+                def(None, &["Spanish"], TagKind::Language),
+            ),
+        ]))
+        .unwrap()
+    }
+
+    #[test]
+    fn resolves_synonyms_and_expands_children() {
+        let taxonomy = sample();
+        assert_eq!(taxonomy.resolve("CHILDREN"), Some("kids"));
+        assert_eq!(taxonomy.resolve("spanish"), Some("en-espanol"));
+        assert_eq!(taxonomy.expand("family"), ["family", "kids", "babies"]);
+        assert_eq!(taxonomy.expand("unknown"), ["unknown"]);
+        assert!(taxonomy.is_kind("en-espanol", TagKind::Language));
+    }
+
+    #[test]
+    fn rejects_broken_definitions() {
+        let cycle = Taxonomy::new(BTreeMap::from([
+            ("a".into(), def(Some("b"), &[], TagKind::Topic)),
+            ("b".into(), def(Some("a"), &[], TagKind::Topic)),
+        ]));
+        assert!(cycle.is_err());
+
+        let clash = Taxonomy::new(BTreeMap::from([
+            ("a".into(), def(None, &["x"], TagKind::Topic)),
+            ("b".into(), def(None, &["x"], TagKind::Topic)),
+        ]));
+        assert!(clash.is_err());
+    }
+
+    #[test]
+    fn tree_counts_include_descendants() {
+        let taxonomy = sample();
+        let tags = HashMap::from([
+            (
+                "a".to_string(),
+                vec!["family".to_string(), "kids".to_string()],
+            ),
+            ("b".to_string(), vec!["babies".to_string()]),
+            ("c".to_string(), vec!["en-espanol".to_string()]),
+        ]);
+        let tree = taxonomy.tree(&tags);
+        let family = tree.iter().find(|n| n.slug == "family").unwrap();
+        assert_eq!(family.count, 1);
+        assert_eq!(family.total, 2);
+        assert_eq!(family.children[0].slug, "kids");
+        assert_eq!(family.children[0].total, 2);
+    }
+
+    #[test]
+    fn safe_mode_hides_rated_comics_and_rating_tags() {
+        let mut taxonomy = sample();
+        taxonomy
+            .tags
+            .insert("nsfw".into(), def(None, &[], TagKind::Rating));
+        let mut comic: Comic = serde_json::from_str(
+            r#"{"endpoint": "a", "title": "A", "author": null, "available": true, "startDate": null}"#,
+        )
+        .unwrap();
+
+        assert!(taxonomy.is_safe(&comic, &["kids".into()]));
+        assert!(!taxonomy.is_safe(&comic, &["nsfw".into()]));
+        comic.rating = Rating::Teen;
+        assert!(!taxonomy.is_safe(&comic, &["kids".into()]));
+    }
+}
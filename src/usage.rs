@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::recommend::CoOccurrence;
+
+const USAGE_FILE: &str = "usage.json";
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+const MAX_BASKETS: usize = 50_000;
+const MAX_BASKET_SIZE: usize = 100;
+/// A session's selection counts as final once it has been left alone this long
+const SESSION_TTL: Duration = Duration::from_secs(60 * 60);
+const MAX_SESSIONS: usize = 10_000;
+
+/// Aggregated, anonymous usage counters. Nothing here identifies a reader.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct UsageSnapshot {
+    #[serde(default)]
+    pub views: HashMap<String, u64>,
+    /// Distinct selection sets, keyed by their sorted endpoints joined with `,`,
+    /// with how many times each was requested
+    #[serde(default)]
+    pub baskets: HashMap<String, u64>,
+}
+
+impl UsageSnapshot {
+    /// Each selection set with how many times it was requested, in key order.
+    pub fn basket_items(&self) -> Vec<(Vec<String>, u64)> {
+        let mut baskets: Vec<(&String, u64)> =
+            self.baskets.iter().map(|(k, &count)| (k, count)).collect();
+        baskets.sort();
+        baskets
+            .into_iter()
+            .map(|(k, count)| (k.split(',').map(str::to_string).collect(), count))
+            .collect()
+    }
+}
+
+fn basket_key(selected: &[&str]) -> Option<(String, Vec<String>)> {
+    let mut items: Vec<String> = selected.iter().map(|s| s.to_string()).collect();
+    items.sort();
+    items.dedup();
+    if items.len() < 2 || items.len() > MAX_BASKET_SIZE {
+        return None;
+    }
+    Some((items.join(","), items))
+}
+
+pub struct UsageStats {
+    path: Option<PathBuf>,
+    data: RwLock<UsageSnapshot>,
+    model: RwLock<Arc<CoOccurrence>>,
+    /// The basket each open session last recorded, only ever kept in memory
+    sessions: Mutex<HashMap<String, (String, Instant)>>,
+    dirty: AtomicBool,
+}
+
+impl UsageStats {
+    pub fn open(data_dir: &str) -> anyhow::Result<Self> {
+        let path = Path::new(data_dir).join(USAGE_FILE);
+        let data = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| anyhow::anyhow!("failed to parse {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => UsageSnapshot::default(),
+            Err(e) => anyhow::bail!("failed to read {}: {}", path.display(), e),
+        };
+        info!(
+            comics = data.views.len(),
+            baskets = data.baskets.len(),
+            "loaded usage stats"
+        );
+        let baskets = data.basket_items();
+        let model = CoOccurrence::build(baskets.iter().map(|(b, count)| (b.as_slice(), *count)));
+        Ok(Self {
+            path: Some(path),
+            data: RwLock::new(data),
+            model: RwLock::new(Arc::new(model)),
+            sessions: Mutex::new(HashMap::new()),
+            dirty: AtomicBool::new(false),
+        })
+    }
+
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            data: RwLock::new(UsageSnapshot::default()),
+            model: RwLock::new(Arc::new(CoOccurrence::default())),
+            sessions: Mutex::new(HashMap::new()),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    pub fn record_view(&self, endpoint: &str) {
+        let mut data = self.data.write().unwrap();
+        *data.views.entry(endpoint.to_string()).or_insert(0) += 1;
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Records a reader's selected comics. Only the set itself is kept, with how often it was
+    /// requested, and every request adds to the co-occurrence model, so readers who pick the
+    /// same feed each count.
+    ///
+    /// A reader builds a feed a comic at a time, so with a `session` (an opaque token the
+    /// page picks when it loads) each selection replaces the one the session made before,
+    /// and only the last counts.
+    pub fn record_selection(&self, session: Option<&str>, selected: &[&str]) {
+        let basket = basket_key(selected);
+        let mut data = self.data.write().unwrap();
+        if let Some(session) = session {
+            let mut sessions = self.sessions.lock().unwrap();
+            let now = Instant::now();
+            if let Some((previous, seen)) = sessions.get_mut(session) {
+                if basket.as_ref().is_some_and(|(key, _)| key == previous) {
+                    *seen = now;
+                    return;
+                }
+                let previous = previous.clone();
+                sessions.remove(session);
+                self.forget_basket(&mut data, &previous);
+            }
+            if sessions.len() >= MAX_SESSIONS {
+                sessions.retain(|_, (_, seen)| now.duration_since(*seen) < SESSION_TTL);
+            }
+            if let Some((key, _)) = &basket
+                && sessions.len() < MAX_SESSIONS
+            {
+                sessions.insert(session.to_string(), (key.clone(), now));
+            }
+        }
+
+        let Some((key, items)) = basket else {
+            return;
+        };
+        if let Some(count) = data.baskets.get_mut(&key) {
+            *count += 1;
+        } else if data.baskets.len() < MAX_BASKETS {
+            data.baskets.insert(key, 1);
+        } else {
+            return;
+        }
+        let mut model = self.model.write().unwrap();
+        Arc::make_mut(&mut model).add_basket(&items, 1);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Takes back one request for the basket `key`, in the counts and in the model.
+    fn forget_basket(&self, data: &mut UsageSnapshot, key: &str) {
+        let Some(count) = data.baskets.get_mut(key) else {
+            return;
+        };
+        *count -= 1;
+        if *count == 0 {
+            data.baskets.remove(key);
+        }
+        let items: Vec<String> = key.split(',').map(str::to_string).collect();
+        let mut model = self.model.write().unwrap();
+        Arc::make_mut(&mut model).remove_basket(&items);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    pub fn co_occurrence(&self) -> Arc<CoOccurrence> {
+        Arc::clone(&self.model.read().unwrap())
+    }
+
+    pub fn views(&self, endpoint: &str) -> u64 {
+        self.data
+            .read()
+            .unwrap()
+            .views
+            .get(endpoint)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn all_views(&self) -> HashMap<String, u64> {
+        self.data.read().unwrap().views.clone()
+    }
+
+    pub fn snapshot(&self) -> UsageSnapshot {
+        self.data.read().unwrap().clone()
+    }
+
+    pub fn flush(&self) -> anyhow::Result<()> {
+        let Some(ref path) = self.path else {
+            return Ok(());
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let json = serde_json::to_string_pretty(&*self.data.read().unwrap())?;
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, json)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn spawn_flusher(self: &Arc<Self>) {
+        let stats = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+            loop {
+                interval.tick().await;
+                let stats = Arc::clone(&stats);
+                let result = tokio::task::spawn_blocking(move || stats.flush()).await;
+                if let Ok(Err(e)) = result {
+                    warn!("failed to write usage stats: {}", e);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn views_survive_flush_and_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().to_str().unwrap();
+
+        let stats = UsageStats::open(data_dir).unwrap();
+        stats.record_view("garfield");
+        stats.record_view("garfield");
+        stats.record_view("peanuts");
+        stats.flush().unwrap();
+
+        let reloaded = UsageStats::open(data_dir).unwrap();
+        assert_eq!(reloaded.views("garfield"), 2);
+        assert_eq!(reloaded.views("peanuts"), 1);
+        assert_eq!(reloaded.views("dilbert"), 0);
+    }
+
+    #[test]
+    fn readers_with_the_same_feed_each_count() {
+        let stats = UsageStats::in_memory();
+        stats.record_selection(Some("reader-1"), &["peanuts", "garfield"]);
+        stats.record_selection(Some("reader-1"), &["garfield", "peanuts"]);
+        stats.record_selection(Some("reader-1"), &["garfield"]);
+        stats.record_selection(Some("reader-2"), &["garfield", "dilbert"]);
+        assert_eq!(stats.co_occurrence().similarity("garfield", "peanuts"), 0.0);
+
+        stats.record_selection(Some("reader-3"), &["garfield", "dilbert"]);
+        assert!(stats.co_occurrence().similarity("garfield", "dilbert") > 0.0);
+        assert_eq!(stats.snapshot().baskets.get("dilbert,garfield"), Some(&2));
+
+        // The model rebuilt from the saved counts matches the one kept up as they came in
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().to_str().unwrap();
+        let saved = UsageStats::open(data_dir).unwrap();
+        saved.record_selection(None, &["garfield", "dilbert"]);
+        saved.record_selection(None, &["garfield", "dilbert"]);
+        saved.flush().unwrap();
+        assert_eq!(
+            UsageStats::open(data_dir).unwrap().co_occurrence(),
+            saved.co_occurrence()
+        );
+    }
+
+    #[test]
+    fn a_session_only_counts_its_last_selection() {
+        let stats = UsageStats::in_memory();
+        stats.record_selection(Some("tab-1"), &["garfield", "peanuts"]);
+        stats.record_selection(Some("tab-1"), &["garfield", "peanuts"]);
+        stats.record_selection(Some("tab-1"), &["garfield", "peanuts", "dilbert"]);
+        stats.record_selection(Some("tab-2"), &["garfield", "peanuts", "dilbert"]);
+        let baskets = stats.snapshot().baskets;
+        assert_eq!(baskets.len(), 1);
+        assert_eq!(baskets.get("dilbert,garfield,peanuts"), Some(&2));
+
+        // Dropping to a single comic takes the session's feed back out
+        stats.record_selection(Some("tab-1"), &["garfield"]);
+        stats.record_selection(Some("tab-2"), &["garfield"]);
+        assert!(stats.snapshot().baskets.is_empty());
+        assert_eq!(stats.co_occurrence(), Arc::new(CoOccurrence::default()));
+    }
+}
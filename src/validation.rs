@@ -0,0 +1,513 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::path::Path;
+
+use chrono::NaiveDate;
+use scraper::Selector;
+use serde::Serialize;
+
+use crate::data;
+use crate::models::Comic;
+use crate::sources::KNOWN_SOURCES;
+use crate::sources::scrape::load_definitions;
+use crate::sources::script::load_scripts;
+use crate::sources::wayback::load_wayback_comics;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Issue {
+    pub severity: Severity,
+    pub file: String,
+    pub subject: String,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(
+            f,
+            "{:<7} {}: {}: {}",
+            label, self.file, self.subject, self.message
+        )
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub comics: usize,
+    pub issues: Vec<Issue>,
+}
+
+impl Report {
+    fn push(&mut self, severity: Severity, file: &str, subject: &str, message: String) {
+        self.issues.push(Issue {
+            severity,
+            file: file.to_string(),
+            subject: subject.to_string(),
+            message,
+        });
+    }
+
+    pub fn errors(&self) -> usize {
+        self.count(Severity::Error)
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.count(Severity::Warning)
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.issues
+            .iter()
+            .filter(|i| i.severity == severity)
+            .count()
+    }
+
+    /// Whether the catalog passes; with `strict`, warnings fail it too.
+    pub fn passed(&self, strict: bool) -> bool {
+        self.errors() == 0 && (!strict || self.warnings() == 0)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for issue in &self.issues {
+            writeln!(f, "{}", issue)?;
+        }
+        write!(
+            f,
+            "{} comics checked: {} errors, {} warnings",
+            self.comics,
+            self.errors(),
+            self.warnings()
+        )
+    }
+}
+
+/// Checks the catalog files in `data_dir` against each other and the badge directory.
+/// Unreadable files are reported as issues rather than returned as errors so one run
+/// shows everything that needs fixing.
+pub fn validate_data_dir(data_dir: &str) -> Report {
+    let mut report = Report::default();
+
+    let comics = match data::load_comics(data_dir) {
+        Ok(comics) => comics,
+        Err(e) => {
+            report.push(Severity::Error, "comics.json", "file", e.to_string());
+            Vec::new()
+        }
+    };
+    report.comics = comics.len();
+    check_comics(&comics, &mut report);
+
+    let tags = match data::load_tags(data_dir) {
+        Ok(tags) => tags,
+        Err(e) => {
+            report.push(Severity::Error, "tags.json", "file", e.to_string());
+            HashMap::new()
+        }
+    };
+    check_tags(&comics, &tags, &mut report);
+
+    // Load the definitions on their own so each undefined tag is reported separately
+    match data::load_taxonomy(data_dir, &HashMap::new()) {
+        Ok(taxonomy) => {
+            for (endpoint, tag) in taxonomy.undefined_tags(&tags) {
+                report.push(
+                    Severity::Error,
+                    "tags.json",
+                    endpoint,
+                    format!("tag {:?} is not in tag_definitions.json", tag),
+                );
+            }
+        }
+        Err(e) => report.push(
+            Severity::Error,
+            "tag_definitions.json",
+            "file",
+            e.to_string(),
+        ),
+    }
+
+    check_badges(data_dir, &comics, &mut report);
+    check_wayback(data_dir, &comics, &mut report);
+    check_scrapers(data_dir, &comics, &mut report);
+    check_scripts(data_dir, &comics, &mut report);
+    report
+}
+
+fn check_comics(comics: &[Comic], report: &mut Report) {
+    let mut seen = BTreeSet::new();
+    for comic in comics {
+        let endpoint = comic.endpoint.as_str();
+        if endpoint.trim().is_empty() {
+            report.push(
+                Severity::Error,
+                "comics.json",
+                &comic.title,
+                "empty endpoint".into(),
+            );
+        }
+        if !seen.insert(endpoint) {
+            report.push(
+                Severity::Error,
+                "comics.json",
+                endpoint,
+                "duplicate endpoint".into(),
+            );
+        }
+        if comic.title.trim().is_empty() {
+            report.push(
+                Severity::Error,
+                "comics.json",
+                endpoint,
+                "empty title".into(),
+            );
+        }
+        if !KNOWN_SOURCES.contains(&comic.source.as_str()) {
+            report.push(
+                Severity::Error,
+                "comics.json",
+                endpoint,
+                format!(
+                    "unknown source {:?} (expected one of {})",
+                    comic.source,
+                    KNOWN_SOURCES.join(", ")
+                ),
+            );
+        }
+        if comic.source == "feed" {
+            match comic.feed {
+                None => report.push(
+                    Severity::Error,
+                    "comics.json",
+                    endpoint,
+                    "source is feed but no feed.url is set".into(),
+                ),
+                Some(ref feed) => {
+                    if reqwest::Url::parse(&feed.url).is_err() {
+                        report.push(
+                            Severity::Error,
+                            "comics.json",
+                            endpoint,
+                            format!("feed.url {:?} is not a URL", feed.url),
+                        );
+                    }
+                    if let Some(ref css) = feed.image_selector
+                        && Selector::parse(css).is_err()
+                    {
+                        report.push(
+                            Severity::Error,
+                            "comics.json",
+                            endpoint,
+                            format!("feed.imageSelector {:?} is not a CSS selector", css),
+                        );
+                    }
+                }
+            }
+        }
+        if let Some(ref start) = comic.start_date
+            && NaiveDate::parse_from_str(start, "%Y-%m-%d").is_err()
+        {
+            report.push(
+                Severity::Error,
+                "comics.json",
+                endpoint,
+                format!("startDate {:?} is not YYYY-MM-DD", start),
+            );
+        }
+    }
+}
+
+fn check_tags(comics: &[Comic], tags: &HashMap<String, Vec<String>>, report: &mut Report) {
+    let endpoints: BTreeSet<&str> = comics.iter().map(|c| c.endpoint.as_str()).collect();
+
+    let mut tagged: Vec<&String> = tags.keys().collect();
+    tagged.sort();
+    for endpoint in tagged {
+        if !endpoints.contains(endpoint.as_str()) {
+            report.push(
+                Severity::Error,
+                "tags.json",
+                endpoint,
+                "not in comics.json".into(),
+            );
+        }
+        let list = &tags[endpoint];
+        let unique: BTreeSet<&String> = list.iter().collect();
+        if unique.len() != list.len() {
+            report.push(
+                Severity::Warning,
+                "tags.json",
+                endpoint,
+                "lists a tag more than once".into(),
+            );
+        }
+    }
+
+    for endpoint in &endpoints {
+        if !tags.contains_key(*endpoint) {
+            report.push(
+                Severity::Warning,
+                "tags.json",
+                endpoint,
+                "comic has no tags".into(),
+            );
+        }
+    }
+}
+
+fn check_wayback(data_dir: &str, comics: &[Comic], report: &mut Report) {
+    let configured = match load_wayback_comics(data_dir) {
+        Ok(configured) => configured,
+        Err(e) => {
+            report.push(Severity::Error, "wayback.json", "file", e.to_string());
+            return;
+        }
+    };
+
+    for comic in comics.iter().filter(|c| c.source == "wayback") {
+        match configured.iter().find(|w| w.endpoint == comic.endpoint) {
+            None => report.push(
+                Severity::Error,
+                "comics.json",
+                &comic.endpoint,
+                "source is wayback but wayback.json has no entry for it".into(),
+            ),
+            Some(wayback) if !wayback.archive_path(data_dir).exists() => report.push(
+                Severity::Warning,
+                "wayback.json",
+                &comic.endpoint,
+                "archive not built yet (run scrape_wayback)".into(),
+            ),
+            Some(_) => {}
+        }
+    }
+    for wayback in &configured {
+        let used = comics
+            .iter()
+            .any(|c| c.endpoint == wayback.endpoint && c.source == "wayback");
+        if !used {
+            report.push(
+                Severity::Warning,
+                "wayback.json",
+                &wayback.endpoint,
+                "no comic in comics.json with source wayback".into(),
+            );
+        }
+    }
+}
+
+fn check_scrapers(data_dir: &str, comics: &[Comic], report: &mut Report) {
+    let definitions = match load_definitions(data_dir) {
+        Ok(definitions) => definitions,
+        Err(e) => {
+            report.push(Severity::Error, "sources", "directory", e.to_string());
+            return;
+        }
+    };
+
+    for comic in comics.iter().filter(|c| c.source == "scrape") {
+        if !definitions
+            .iter()
+            .any(|d| d.definition.endpoint == comic.endpoint)
+        {
+            report.push(
+                Severity::Error,
+                "comics.json",
+                &comic.endpoint,
+                "source is scrape but data/sources has no definition for it".into(),
+            );
+        }
+    }
+    for definition in definitions.iter().map(|d| &d.definition) {
+        let used = comics
+            .iter()
+            .any(|c| c.endpoint == definition.endpoint && c.source == "scrape");
+        if !used {
+            report.push(
+                Severity::Warning,
+                "sources",
+                &definition.endpoint,
+                "no comic in comics.json with source scrape".into(),
+            );
+        }
+    }
+}
+
+fn check_scripts(data_dir: &str, comics: &[Comic], report: &mut Report) {
+    let scripts = match load_scripts(data_dir) {
+        Ok(scripts) => scripts,
+        Err(e) => {
+            report.push(Severity::Error, "scripts", "directory", e.to_string());
+            return;
+        }
+    };
+
+    for comic in comics.iter().filter(|c| c.source == "script") {
+        if !scripts.iter().any(|s| s.endpoint == comic.endpoint) {
+            report.push(
+                Severity::Error,
+                "comics.json",
+                &comic.endpoint,
+                "source is script but data/scripts has no script for it".into(),
+            );
+        }
+    }
+    for script in &scripts {
+        let used = comics
+            .iter()
+            .any(|c| c.endpoint == script.endpoint && c.source == "script");
+        if !used {
+            report.push(
+                Severity::Warning,
+                "scripts",
+                &script.endpoint,
+                "no comic in comics.json with source script".into(),
+            );
+        }
+    }
+}
+
+/// Endpoints that have a PNG in `data_dir/badges`.
+pub fn badge_endpoints(data_dir: &str) -> std::io::Result<BTreeSet<String>> {
+    let mut badges = BTreeSet::new();
+    for entry in std::fs::read_dir(Path::new(data_dir).join("badges"))?.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("png") {
+            continue;
+        }
+        if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+            badges.insert(stem.to_string());
+        }
+    }
+    Ok(badges)
+}
+
+fn check_badges(data_dir: &str, comics: &[Comic], report: &mut Report) {
+    let badges = match badge_endpoints(data_dir) {
+        Ok(badges) => badges,
+        Err(e) => {
+            report.push(
+                Severity::Warning,
+                "badges",
+                "directory",
+                format!("cannot read {}/badges: {}", data_dir, e),
+            );
+            return;
+        }
+    };
+
+    let endpoints: BTreeSet<String> = comics.iter().map(|c| c.endpoint.clone()).collect();
+    for orphan in badges.difference(&endpoints) {
+        report.push(
+            Severity::Warning,
+            "badges",
+            &format!("{}.png", orphan),
+            "no comic with this endpoint".into(),
+        );
+    }
+    for missing in endpoints.difference(&badges) {
+        report.push(
+            Severity::Warning,
+            "badges",
+            missing,
+            "comic has no badge".into(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Rating;
+
+    #[test]
+    fn shipped_catalog_is_clean() {
+        let report = validate_data_dir("data");
+        assert!(report.comics > 100);
+        assert!(report.passed(true), "{:#?}", report.issues);
+    }
+
+    #[test]
+    fn schema_matches_known_sources_and_ratings() {
+        let schema: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string("data/schema/comics.schema.json").unwrap(),
+        )
+        .unwrap();
+        let properties = &schema["items"]["properties"];
+        assert_eq!(
+            properties["source"]["enum"],
+            serde_json::json!(KNOWN_SOURCES)
+        );
+        assert_eq!(
+            properties["rating"]["enum"],
+            serde_json::json!([Rating::General, Rating::Teen, Rating::Mature])
+        );
+    }
+
+    #[test]
+    fn reports_each_kind_of_problem() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::write(
+            root.join("comics.json"),
+            r#"[
+                {"endpoint": "a", "title": "A", "author": null, "available": true, "startDate": "2020-13-01"},
+                {"endpoint": "a", "title": "A again", "author": null, "available": true, "startDate": null},
+                {"endpoint": "b", "title": "B", "author": null, "available": true, "startDate": null, "source": "nowhere"},
+                {"endpoint": "c", "title": "C", "author": null, "available": false, "startDate": null, "source": "wayback"},
+                {"endpoint": "d", "title": "D", "author": null, "available": true, "startDate": null, "source": "feed"},
+                {"endpoint": "e", "title": "E", "author": null, "available": true, "startDate": null, "source": "scrape"},
+                {"endpoint": "f", "title": "F", "author": null, "available": true, "startDate": null, "source": "script"},
+                {"endpoint": "g", "title": "G", "author": null, "available": true, "startDate": null, "source": "feed", "feed": {"url": "https://g.example.com/feed", "imageSelector": "img["}}
+            ]"#,
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("tags.json"),
+            r#"{"a": ["humor", "humor", "no-such-tag"], "ghost": ["humor"]}"#,
+        )
+        .unwrap();
+        std::fs::copy(
+            "data/tag_definitions.json",
+            root.join("tag_definitions.json"),
+        )
+        .unwrap();
+        std::fs::create_dir(root.join("badges")).unwrap();
+        std::fs::write(root.join("badges/a.png"), b"").unwrap();
+        std::fs::write(root.join("badges/old.png"), b"").unwrap();
+
+        let report = validate_data_dir(root.to_str().unwrap());
+        let messages: Vec<String> = report.issues.iter().map(|i| i.to_string()).collect();
+        let has = |needle: &str| messages.iter().any(|m| m.contains(needle));
+
+        assert!(has("a: duplicate endpoint"), "{:#?}", messages);
+        assert!(has("a: startDate \"2020-13-01\""));
+        assert!(has("b: unknown source \"nowhere\""));
+        assert!(has("ghost: not in comics.json"));
+        assert!(has("b: comic has no tags"));
+        assert!(has("a: lists a tag more than once"));
+        assert!(has("a: tag \"no-such-tag\""));
+        assert!(has("old.png: no comic with this endpoint"));
+        assert!(has("b: comic has no badge"));
+        assert!(has("c: source is wayback but wayback.json has no entry"));
+        assert!(has("d: source is feed but no feed.url"));
+        assert!(has("g: feed.imageSelector \"img[\" is not a CSS selector"));
+        assert!(has(
+            "e: source is scrape but data/sources has no definition"
+        ));
+        assert!(has("f: source is script but data/scripts has no script"));
+        assert!(!report.passed(false));
+    }
+}
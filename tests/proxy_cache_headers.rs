@@ -1,15 +1,21 @@
-use std::collections::HashMap;
 use std::sync::Arc;
 
 use async_trait::async_trait;
 use axum::body::Body;
 use axum::http::{Request, StatusCode, header};
 use panels::AppState;
+use panels::bookmarks::BookmarkStore;
+use panels::cache::Caches;
+use panels::catalog::{Catalog, CatalogData};
 use panels::config::PanelsConfig;
 use panels::error::Result;
-use panels::models::ComicStrip;
+use panels::image_hashes::ImageHashStore;
+use panels::imaging::hash::{ImageHashes, Placeholder};
+use panels::models::{ComicStrip, StripMetadata};
 use panels::routes;
+use panels::search::SearchIndex;
 use panels::sources::{ComicSource, SourceRegistry};
+use panels::usage::UsageStats;
 use tower::util::ServiceExt;
 
 struct MockSource;
@@ -33,10 +39,27 @@ impl ComicSource for MockSource {
     }
 
     async fn proxy_image(&self, _image_url: &str) -> Result<(Vec<u8>, String)> {
-        Ok((vec![1, 2, 3], "image/png".to_string()))
+        Ok((two_panel_png(), "image/png".to_string()))
     }
 }
 
+/// A 400x200 white strip with two black 180x180 panels.
+fn two_panel_png() -> Vec<u8> {
+    let mut strip = image::RgbImage::from_pixel(400, 200, image::Rgb([255, 255, 255]));
+    for (x, y, pixel) in strip.enumerate_pixels_mut() {
+        let in_panel =
+            (10..190).contains(&y) && ((10..190).contains(&x) || (210..390).contains(&x));
+        if in_panel {
+            *pixel = image::Rgb([0, 0, 0]);
+        }
+    }
+    let mut png = std::io::Cursor::new(Vec::new());
+    image::DynamicImage::ImageRgb8(strip)
+        .write_to(&mut png, image::ImageFormat::Png)
+        .unwrap();
+    png.into_inner()
+}
+
 fn mock_strip(endpoint: &str, date: &str) -> ComicStrip {
     ComicStrip {
         endpoint: endpoint.to_string(),
@@ -46,20 +69,43 @@ fn mock_strip(endpoint: &str, date: &str) -> ComicStrip {
         source_url: "https://example.com/source".to_string(),
         prev_date: None,
         next_date: None,
+        metadata: StripMetadata::default(),
     }
 }
 
 fn test_app() -> axum::Router {
+    test_app_with(CatalogData::default())
+}
+
+fn test_app_with(catalog: CatalogData) -> axum::Router {
     let state = Arc::new(AppState {
         config: PanelsConfig {
             port: 3000,
             data_dir: "data".to_string(),
             strip_cache_max: 10,
             strip_cache_ttl_secs: 60,
+            image_cache_mb: 1,
+            public_url: None,
+            admin_token: None,
+            catalog_poll_secs: 0,
+            strict_catalog: false,
+            mirror: Vec::new(),
+            mirror_since: None,
+            mirror_sync_secs: 0,
+            history_poll_secs: 0,
+            mirror_offline: false,
+            safe_mode: false,
+            rec_content_weight: 1.0,
+            rec_collaborative_weight: 1.0,
+            rec_popularity_weight: 0.1,
         },
-        comics: vec![],
-        tags: HashMap::new(),
+        catalog: Arc::new(Catalog::new("data", catalog)),
         sources: SourceRegistry::new(vec![Box::new(MockSource)]),
+        caches: Caches::new(10, 60, 1024 * 1024),
+        bookmarks: BookmarkStore::open("data").unwrap(),
+        search: Arc::new(SearchIndex::in_memory().unwrap()),
+        usage: Arc::new(UsageStats::in_memory()),
+        image_hashes: Arc::new(ImageHashStore::in_memory()),
     });
 
     routes::build_router(state)
@@ -102,3 +148,199 @@ async fn deterministic_image_endpoint_keeps_long_cache() {
         "public, max-age=86400, s-maxage=604800"
     );
 }
+
+#[tokio::test]
+async fn resized_image_negotiates_format_from_accept() {
+    let response = test_app()
+        .oneshot(
+            Request::builder()
+                .uri("/api/comics/test/2025-01-01/image?w=100")
+                .header(header::ACCEPT, "image/webp,*/*;q=0.8")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    // WebP is lossless here, so only AVIF is negotiated and this keeps the source's PNG
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get(header::CONTENT_TYPE).unwrap(),
+        "image/png"
+    );
+    assert_eq!(response.headers().get(header::VARY).unwrap(), "Accept");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let resized = image::load_from_memory(&body).unwrap();
+    // 100 snaps up to the 160 preset
+    assert_eq!((resized.width(), resized.height()), (160, 80));
+}
+
+#[tokio::test]
+async fn unknown_fit_is_rejected() {
+    let response = test_app()
+        .oneshot(
+            Request::builder()
+                .uri("/api/comics/test/2025-01-01/image?w=320&fit=squash")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn panels_endpoint_and_single_panel_crop() {
+    let app = test_app();
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/comics/test/2025-01-01/panels")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let layout: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        layout["panels"],
+        serde_json::json!([
+            { "x": 10, "y": 10, "width": 180, "height": 180 },
+            { "x": 210, "y": 10, "width": 180, "height": 180 }
+        ])
+    );
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/comics/test/2025-01-01/image?panel=1")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get(header::CONTENT_TYPE).unwrap(),
+        "image/png"
+    );
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let panel = image::load_from_memory(&body).unwrap();
+    assert_eq!((panel.width(), panel.height()), (180, 180));
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/comics/test/2025-01-01/image?panel=2")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+async fn get(app: &axum::Router, uri: &str) -> (StatusCode, serde_json::Value) {
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&body).unwrap_or_default())
+}
+
+#[tokio::test]
+async fn proxied_images_are_hashed_and_placeholders_reported_missing() {
+    let app = test_app();
+    let (status, _) = get(&app, "/api/comics/test/2025-01-01/image").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, strip) = get(&app, "/api/comics/test/2025-01-01").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        strip["metadata"]["imageHash"]["dhash"]
+            .as_str()
+            .map(str::len),
+        Some(16)
+    );
+
+    let image = image::load_from_memory(&two_panel_png()).unwrap();
+    let app = test_app_with(CatalogData {
+        placeholders: vec![Placeholder {
+            name: "unavailable".to_string(),
+            hashes: ImageHashes::of(&image),
+            note: None,
+        }],
+        ..CatalogData::default()
+    });
+    let (status, _) = get(&app, "/api/comics/test/2025-01-01/image").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = get(&app, "/api/comics/test/2025-01-01").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn export_streams_a_cbz_of_the_range() {
+    let comic: panels::models::Comic = serde_json::from_value(serde_json::json!({
+        "endpoint": "test",
+        "title": "Test Comic",
+        "author": null,
+        "available": true,
+        "startDate": null
+    }))
+    .unwrap();
+    let app = test_app_with(CatalogData {
+        comics: vec![comic],
+        ..CatalogData::default()
+    });
+
+    let (status, _) = get(
+        &app,
+        "/api/comics/test/export?from=2025-01-02&to=2025-01-01",
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/comics/test/export?from=2025-01-01&to=2025-01-02")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get(header::CONTENT_TYPE).unwrap(),
+        "application/vnd.comicbook+zip"
+    );
+    assert_eq!(
+        response.headers().get(header::CONTENT_DISPOSITION).unwrap(),
+        "attachment; filename=\"test-2025-01-01-2025-01-02.cbz\""
+    );
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let archive = zip::ZipArchive::new(std::io::Cursor::new(body.to_vec())).unwrap();
+    let mut names: Vec<&str> = archive.file_names().collect();
+    names.sort();
+    assert_eq!(
+        names,
+        vec!["2025-01-01.png", "2025-01-02.png", "ComicInfo.xml"]
+    );
+}
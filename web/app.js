@@ -6,6 +6,8 @@
     selectedEndpoints: new Set(),
     currentStrip: null,
     activeTags: new Set(),
+    tagCounts: {},
+    tagInfo: {},
     activeLetter: null,
     searchQuery: '',
     recommendations: [],
@@ -55,6 +57,8 @@
   const LS_SIDEBAR = 'panels_sidebar_collapsed';
   const LS_COLLAPSIBLES = 'panels_collapsibles';
   const LS_FAVORITES = 'panels_favorites';
+  // Lets the server count a feed built up a comic at a time once; never stored
+  const SESSION = Math.random().toString(36).slice(2);
 
   const $ = (sel) => document.querySelector(sel);
   const $$ = (sel) => document.querySelectorAll(sel);
@@ -116,21 +120,54 @@
     favEmpty: $('#favEmpty'),
   };
 
+  // Open the app with ?safe=1 to hide comics that are not rated general (e.g. on a lobby display)
+  const SAFE_MODE = new URLSearchParams(window.location.search).get('safe') === '1';
+
+  function withSafe(url) {
+    if (!SAFE_MODE) return url;
+    return url + (url.includes('?') ? '&' : '?') + 'safe=1';
+  }
+
   async function fetchComics() {
-    const res = await fetch('/api/comics');
+    const res = await fetch(withSafe('/api/comics?facets=1'));
     if (!res.ok) throw new Error('Failed to fetch comics');
-    return res.json();
+    const data = await res.json();
+    state.tagCounts = data.facets.tags;
+    return data.results;
   }
 
   async function fetchStrip(endpoint, date) {
-    const res = await fetch(`/api/comics/${encodeURIComponent(endpoint)}/${encodeURIComponent(date)}`);
+    const res = await fetch(withSafe(`/api/comics/${encodeURIComponent(endpoint)}/${encodeURIComponent(date)}`));
     if (!res.ok) throw new Error('Failed to fetch strip');
     return res.json();
   }
 
+  async function fetchTags() {
+    const res = await fetch('/api/tags');
+    if (!res.ok) throw new Error('Failed to fetch tags');
+    const info = {};
+    const walk = (nodes) => {
+      nodes.forEach((node) => {
+        walk(node.children);
+        const below = node.children.flatMap((child) => info[child.slug].descendants);
+        info[node.slug] = { name: node.name, kind: node.kind, descendants: [node.slug, ...below] };
+      });
+    };
+    walk(await res.json());
+    state.tagInfo = info;
+  }
+
+  function isLanguageTag(tag) {
+    return state.tagInfo[tag] ? state.tagInfo[tag].kind === 'language' : false;
+  }
+
+  function tagWithDescendants(tag) {
+    return state.tagInfo[tag] ? state.tagInfo[tag].descendants : [tag];
+  }
+
   async function fetchRecommendations(endpoints, limit) {
     const selected = Array.from(endpoints).join(',');
-    const res = await fetch(`/api/recommendations?selected=${encodeURIComponent(selected)}&limit=${limit}`);
+    const res = await fetch(withSafe(`/api/recommendations?selected=${encodeURIComponent(selected)}&limit=${limit}&session=${SESSION}`));
     if (!res.ok) throw new Error('Failed to fetch recommendations');
     return res.json();
   }
@@ -303,7 +340,8 @@
       const color = hashColor(tag);
       const chip = document.createElement('button');
       chip.className = 'tag-chip';
-      chip.textContent = tag;
+      const label = state.tagInfo[tag] ? state.tagInfo[tag].name : tag;
+      chip.textContent = state.tagCounts[tag] ? `${label} (${state.tagCounts[tag]})` : label;
       chip.dataset.tag = tag;
       chip.dataset.color = color;
 
@@ -398,7 +436,7 @@
 
     els.luckyBtn.addEventListener('click', () => {
       const available = state.allComics.filter(
-        (c) => c.available && !c.tags.some((t) => t === 'en-espanol')
+        (c) => c.available && !c.tags.some(isLanguageTag)
       );
       if (available.length === 0) return;
       const count = Math.floor(Math.random() * 5) + 3;
@@ -426,7 +464,10 @@
   }
 
   function matchesTagAndAlphabet(c) {
-    if (state.activeTags.size > 0 && !c.tags.some((t) => state.activeTags.has(t))) return false;
+    if (state.activeTags.size > 0) {
+      const wanted = new Set([...state.activeTags].flatMap(tagWithDescendants));
+      if (!c.tags.some((t) => wanted.has(t))) return false;
+    }
     if (state.activeLetter === '#' && !/^\d/.test(c.title)) return false;
     if (state.activeLetter && state.activeLetter !== '#' && !c.title.toUpperCase().startsWith(state.activeLetter)) return false;
     return true;
@@ -471,7 +512,7 @@
     const selectedComics = state.allComics.filter((c) => state.selectedEndpoints.has(c.endpoint));
     const recEndpoints = new Set(state.recommendations.map((r) => r.endpoint));
     const recommendedComics = state.recommendEnabled
-      ? state.allComics.filter((c) => recEndpoints.has(c.endpoint) && !state.selectedEndpoints.has(c.endpoint))
+      ? state.recommendations.filter((r) => !state.selectedEndpoints.has(r.endpoint))
       : [];
     const selectedSet = new Set([...state.selectedEndpoints, ...recEndpoints]);
     const allComics = state.allComics.filter((c) => !selectedSet.has(c.endpoint) && matchesTagAndAlphabet(c));
@@ -531,7 +572,7 @@
       img.className = 'badge-img';
       img.src = `/api/badges/${comic.endpoint}.png`;
       img.alt = comic.title;
-      img.title = comic.title;
+      img.title = comic.reasons && comic.reasons.length ? `${comic.title}\n${comic.reasons.join('\n')}` : comic.title;
       img.loading = 'lazy';
       img.onerror = function () {
         this.style.display = 'none';
@@ -584,7 +625,8 @@
     const comic = state.allComics.find((c) => c.endpoint === strip.endpoint);
     const author = comic && comic.author ? ` by ${comic.author}` : '';
     els.comicMeta.textContent = `[ ${strip.title} ]${author} — ${strip.date}`;
-    els.comicImage.src = `/api/comics/${encodeURIComponent(strip.endpoint)}/${encodeURIComponent(strip.date)}/image`;
+    els.comicImage.src = withSafe(`/api/comics/${encodeURIComponent(strip.endpoint)}/${encodeURIComponent(strip.date)}/image`);
+    els.comicImage.title = (strip.metadata && strip.metadata.altText) || '';
     resetZoom();
 
     updateNavVisibility();
@@ -902,8 +944,29 @@
     handleRoute();
   }
 
+  function permalinkFromPath() {
+    const match = location.pathname.match(/^\/c\/([^/]+)\/([^/]+)\/?$/);
+    if (!match) return null;
+    return { endpoint: decodeURIComponent(match[1]), date: decodeURIComponent(match[2]) };
+  }
+
+  async function openPermalink(link) {
+    showLoading();
+    try {
+      const strip = await fetchStrip(link.endpoint, link.date);
+      showComic(strip);
+    } catch (e) {
+      showError();
+    }
+    state.isLoading = false;
+  }
+
   function handleRoute() {
-    if (location.pathname === '/feed') {
+    const permalink = permalinkFromPath();
+    if (permalink) {
+      switchToPanel();
+      openPermalink(permalink);
+    } else if (location.pathname === '/feed') {
       switchToFeed();
     } else if (location.pathname === '/favorites') {
       switchToFavorites();
@@ -1002,7 +1065,7 @@
         const card = document.createElement('div');
         card.className = 'fav-card';
 
-        const imgSrc = `/api/comics/${encodeURIComponent(fav.endpoint)}/${encodeURIComponent(fav.date)}/image`;
+        const imgSrc = withSafe(`/api/comics/${encodeURIComponent(fav.endpoint)}/${encodeURIComponent(fav.date)}/image`);
 
         const img = document.createElement('img');
         img.className = 'fav-card-img';
@@ -1085,7 +1148,7 @@
         '</button>' +
       '</div>' +
       '<div class="feed-card-image">' +
-        '<img src="/api/comics/' + encodeURIComponent(strip.endpoint) + '/' + encodeURIComponent(strip.date) + '/image" alt="' + escapeHtml(strip.title) + '" loading="lazy">' +
+        '<img src="' + withSafe('/api/comics/' + encodeURIComponent(strip.endpoint) + '/' + encodeURIComponent(strip.date) + '/image') + '" alt="' + escapeHtml(strip.title) + '" loading="lazy">' +
       '</div>';
 
     const cardImg = card.querySelector('.feed-card-image img');
@@ -1424,7 +1487,8 @@
     initFeedZoom();
 
     try {
-      state.allComics = await fetchComics();
+      const [comics] = await Promise.all([fetchComics(), fetchTags().catch(() => {})]);
+      state.allComics = comics;
       buildFuseIndex(state.allComics);
       buildTagFilters();
       buildAlphaBar();